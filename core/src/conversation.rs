@@ -0,0 +1,510 @@
+use crate::{
+    messages::{ChatMessage, Feedback, Role, IMAGE_TOKENS},
+    redaction::redact,
+};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "tiktoken")]
+use std::sync::OnceLock;
+use std::{borrow::Cow, collections::HashMap};
+#[cfg(feature = "tiktoken")]
+use tiktoken_rs::{cl100k_base, CoreBPE};
+
+/// start making room once a conversation uses this share of the context window
+const CONTEXT_TRIM_THRESHOLD: f64 = 0.8;
+/// trimming stops once the conversation is down to this share
+const CONTEXT_TRIM_TARGET: f64 = 0.5;
+/// the newest messages are never trimmed automatically
+const KEEP_RECENT_MESSAGES: usize = 4;
+
+/// What happens when a conversation gets close to the token limit
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextOverflow {
+    /// drop the oldest messages
+    Truncate,
+    /// replace the oldest messages with a summary
+    #[default]
+    Summarize,
+    /// refuse to send until messages are removed with /trim
+    Error,
+}
+
+/// Messages that have to go before the next question fits in the context window
+pub struct ContextTrim {
+    /// tokens the conversation would use with the next question
+    pub used: i64,
+    /// indices of the oldest messages
+    pub indices: Vec<usize>,
+    /// tokens freed by removing them
+    pub tokens: usize,
+}
+
+/// tokenizer used by gpt-3.5-turbo and gpt-4, loading it is slow so it's only done once
+#[cfg(feature = "tiktoken")]
+fn tokenizer() -> &'static CoreBPE {
+    static TOKENIZER: OnceLock<CoreBPE> = OnceLock::new();
+    TOKENIZER.get_or_init(|| cl100k_base().expect("Failed to load cl100k_base"))
+}
+
+/// Use local tokenizer to estimate the number of tokens in a text
+#[cfg(feature = "tiktoken")]
+pub fn count_text_tokens(text: &str) -> usize {
+    tokenizer().encode_with_special_tokens(text).len()
+}
+
+/// Estimate the number of tokens in a text without the tokenizer
+///
+/// English averages about four characters per token
+#[cfg(not(feature = "tiktoken"))]
+pub fn count_text_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// logit_bias of a request, biases per word become biases for the token of the word
+///
+/// words are looked up as written, capitalized and after a space the way they appear
+/// mid-sentence and only variants that are a single token are biased. biasing every
+/// token of a longer word or phrase would also change common pieces like `un` or ` the`,
+/// so words without a single token variant are refused. later words win when they share a token
+#[cfg(feature = "tiktoken")]
+pub fn logit_bias<'a>(
+    words: impl IntoIterator<Item = (&'a str, i32)>,
+) -> anyhow::Result<HashMap<String, serde_json::Value>> {
+    let mut logit_bias = HashMap::new();
+    for (word, bias) in words {
+        if !(-100..=100).contains(&bias) {
+            anyhow::bail!("Bias of {word} is {bias}, logit_bias has to be between -100 and 100");
+        }
+        let tokens: Vec<usize> = word_variants(word)
+            .iter()
+            .map(|variant| tokenizer().encode_with_special_tokens(variant))
+            .filter(|tokens| tokens.len() == 1)
+            .map(|tokens| tokens[0])
+            .collect();
+        if tokens.is_empty() {
+            anyhow::bail!(
+                "{word} is more than one token, logit_bias and banned_words only take words that are a single token"
+            );
+        }
+        for token in tokens {
+            logit_bias.insert(token.to_string(), serde_json::Value::from(bias));
+        }
+    }
+    Ok(logit_bias)
+}
+
+/// token ids depend on the tokenizer, without it no bias can be sent
+#[cfg(not(feature = "tiktoken"))]
+pub fn logit_bias<'a>(
+    words: impl IntoIterator<Item = (&'a str, i32)>,
+) -> anyhow::Result<HashMap<String, serde_json::Value>> {
+    if words.into_iter().next().is_some() {
+        anyhow::bail!(
+            "logit_bias and banned_words are not available, gpt-cli was built without the tiktoken feature. Rebuild with `--features tiktoken`"
+        );
+    }
+    Ok(HashMap::new())
+}
+
+#[cfg(feature = "tiktoken")]
+fn word_variants(word: &str) -> Vec<String> {
+    let mut characters = word.chars();
+    let capitalized = match characters.next() {
+        Some(first) => first.to_uppercase().chain(characters).collect(),
+        None => String::new(),
+    };
+    let mut variants = vec![
+        word.to_owned(),
+        format!(" {word}"),
+        capitalized.clone(),
+        format!(" {capitalized}"),
+    ];
+    variants.sort_unstable();
+    variants.dedup();
+    variants
+}
+
+/// user message as it's sent to the model
+pub fn decorate_question(question: &str, prefix: Option<&str>, suffix: Option<&str>) -> String {
+    let mut decorated = String::new();
+    if let Some(prefix) = prefix {
+        decorated.push_str(prefix);
+        decorated.push_str("\n\n");
+    }
+    decorated.push_str(question);
+    if let Some(suffix) = suffix {
+        decorated.push_str("\n\n");
+        decorated.push_str(suffix);
+    }
+    decorated
+}
+
+/// Lowercase text and drop punctuation and repeated whitespace
+pub fn normalize_text(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|character| character.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Similarity between 0 and 1 based on edit distance
+pub fn text_similarity(a: &str, b: &str) -> f32 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+
+    // levenshtein distance keeping only one row
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut previous_diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous_diagonal + usize::from(a_char != b_char);
+            previous_diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    1.0 - row[b.len()] as f32 / longest as f32
+}
+
+/// Messages of a conversation, the first one holds the system prompt
+///
+/// Token budgeting and request building without any IO, so every frontend shares them.
+/// Frontends send the messages it builds and add the answers back.
+/// Serialized as the plain list of messages.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(transparent)]
+pub struct Conversation {
+    messages: Vec<ChatMessage>,
+}
+
+impl Conversation {
+    pub fn new(system_prompt: impl Into<String>) -> Self {
+        Self {
+            messages: vec![ChatMessage::new(Role::System, system_prompt)],
+        }
+    }
+
+    /// conversation made of these messages as they are
+    pub fn from_messages(messages: Vec<ChatMessage>) -> Self {
+        Self { messages }
+    }
+
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    pub fn push(&mut self, message: ChatMessage) {
+        self.messages.push(message);
+    }
+
+    /// pop and return the last message
+    pub fn pop(&mut self) -> Option<ChatMessage> {
+        self.messages.pop()
+    }
+
+    pub fn add_note(&mut self, index: usize, note: &str) {
+        if let Some(message) = self.messages.get_mut(index) {
+            message.notes.push(note.to_owned());
+        }
+    }
+
+    pub fn set_feedback(&mut self, index: usize, feedback: Feedback) {
+        if let Some(message) = self.messages.get_mut(index) {
+            message.feedback = Some(feedback);
+        }
+    }
+
+    /// keep the first `len` messages, the system prompt always stays
+    pub fn truncate(&mut self, len: usize) {
+        self.messages.truncate(len.max(1));
+    }
+
+    /// replace the system prompt for the following requests
+    pub fn set_system_prompt(&mut self, system_prompt: impl Into<String>) {
+        let system_message = ChatMessage::new(Role::System, system_prompt);
+        match self.messages.first_mut() {
+            Some(first) if first.role.is_instruction() => *first = system_message,
+            _ => self.messages.insert(0, system_message),
+        }
+    }
+
+    /// put few-shot examples right after the system prompt, replacing the ones there before
+    pub fn set_examples(&mut self, examples: Vec<ChatMessage>) {
+        self.messages.retain(|message| !message.example);
+        let position = match self.messages.first() {
+            Some(first) if first.role.is_instruction() => 1,
+            _ => 0,
+        };
+        self.messages.splice(position..position, examples);
+    }
+
+    /// Use local tokenizer library to estimate token usage
+    ///
+    /// This can be imprecise if we have different tokenization rules than the model
+    pub fn count_tokens(&self) -> i64 {
+        // based on this https://github.com/openai/openai-cookbook/blob/main/examples/How_to_count_tokens_with_tiktoken.ipynb
+        // but there some weird hacks because the counts weren't lining up
+
+        // Start with -1 because somehow we always had 1 extra token
+        let mut token_count = -1_i64;
+        for message in &self.messages {
+            // each message adds 4 tokens
+            // because every message follows <im_start>{role/name}\n{content}<im_end>\n
+            match message.role {
+                Role::User | Role::Tool | Role::Function => {
+                    token_count += 4;
+                    if let Some(name) = &message.name {
+                        // example says "if there's a name, the role is omitted"
+                        // but it says "role is always required and always 1 token"
+                        // so I don't know
+                        token_count -= 1;
+                        // add name to count
+                        token_count += count_text_tokens(name) as i64;
+                    }
+                }
+                Role::System | Role::Developer => {
+                    token_count += 4;
+                }
+                Role::Assistant => {
+                    // Assistant messages should be primed with <im_start>assistant
+                    // so that'd be 2. But from my testing it looks like there are still 4
+                    token_count += 4;
+                }
+            }
+
+            // add role to count
+            token_count += count_text_tokens(message.role.as_str()) as i64;
+
+            // add message to count
+            token_count += count_text_tokens(&message.content) as i64;
+            for tool_call in &message.tool_calls {
+                token_count += count_text_tokens(&tool_call.name) as i64;
+                token_count += count_text_tokens(&tool_call.arguments) as i64;
+            }
+            token_count += (message.images.len() * IMAGE_TOKENS) as i64;
+        }
+        token_count
+    }
+
+    /// estimated tokens of one message including the per message overhead
+    pub fn message_tokens(&self, index: usize) -> usize {
+        self.messages
+            .get(index)
+            .map(|message| {
+                count_text_tokens(&message.content) + message.images.len() * IMAGE_TOKENS + 4
+            })
+            .unwrap_or_default()
+    }
+
+    /// find an earlier user message that is near-identical to this question
+    ///
+    /// returns index of the message
+    pub fn find_similar_question(&self, question: &str) -> Option<usize> {
+        // edit distance is quadratic so leave very long messages to exact matches
+        const MAX_FUZZY_LENGTH: usize = 2000;
+        const SIMILARITY_THRESHOLD: f32 = 0.9;
+
+        let question = normalize_text(question);
+        if question.is_empty() {
+            return None;
+        }
+        self.messages
+            .iter()
+            .enumerate()
+            .filter(|(_, message)| matches!(message.role, Role::User) && !message.example)
+            .find(|(_, message)| {
+                let previous = normalize_text(&message.content);
+                if previous == question {
+                    return true;
+                }
+                previous.len() <= MAX_FUZZY_LENGTH
+                    && question.len() <= MAX_FUZZY_LENGTH
+                    && text_similarity(&previous, &question) >= SIMILARITY_THRESHOLD
+            })
+            .map(|(index, _)| index)
+    }
+
+    /// answer that followed the user message at this index
+    pub fn answer_to(&self, index: usize) -> Option<&str> {
+        self.messages
+            .get(index + 1)
+            .filter(|message| matches!(message.role, Role::Assistant))
+            .map(|message| message.content.as_str())
+    }
+
+    /// remove messages at these indices, the system prompt is always kept
+    pub fn remove_messages(&mut self, indices: &[usize]) {
+        let mut index = 0;
+        self.messages.retain(|_| {
+            let keep = index == 0 || !indices.contains(&index);
+            index += 1;
+            keep
+        });
+    }
+
+    /// messages to trim before `next_tokens` more are sent to a model with this limit
+    ///
+    /// None while the context window has room
+    pub fn context_trim(&self, token_limit: u32, next_tokens: usize) -> Option<ContextTrim> {
+        let limit = token_limit as f64;
+        let used = self.count_tokens() + next_tokens as i64;
+        if (used as f64) < limit * CONTEXT_TRIM_THRESHOLD {
+            return None;
+        }
+        let indices = self.oldest_messages(used - (limit * CONTEXT_TRIM_TARGET) as i64);
+        if indices.is_empty() {
+            return None;
+        }
+        let tokens = indices
+            .iter()
+            .map(|index| self.message_tokens(*index))
+            .sum();
+        Some(ContextTrim {
+            used,
+            indices,
+            tokens,
+        })
+    }
+
+    /// oldest messages worth at least `tokens`, never the system prompt, examples or the newest messages
+    fn oldest_messages(&self, tokens: i64) -> Vec<usize> {
+        let trimmable = self.messages.len().saturating_sub(KEEP_RECENT_MESSAGES);
+        let mut indices = vec![];
+        let mut freed = 0;
+        for index in 1..trimmable {
+            if self.messages[index].example {
+                continue;
+            }
+            // tool results can't be sent without the call they answer
+            let answers_removed_call =
+                self.messages[index].role == Role::Tool && indices.last() == Some(&(index - 1));
+            if freed >= tokens && !answers_removed_call {
+                break;
+            }
+            freed += self.message_tokens(index) as i64;
+            indices.push(index);
+        }
+        indices
+    }
+
+    /// request asking a model to summarize the messages at these indices
+    pub fn summary_request(&self, indices: &[usize]) -> ChatMessage {
+        let transcript = self
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| indices.contains(index))
+            .map(|(_, message)| format!("{}: {}", message.role.label(), message.content))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        ChatMessage::new(
+            Role::User,
+            format!(
+                "Summarize this part of a conversation as briefly as possible. Keep every fact, decision and piece of code that may matter later.\n\n{transcript}"
+            ),
+        )
+    }
+
+    /// request asking a model to fold the messages from `start` on into an earlier summary
+    ///
+    /// only the new messages are sent so updating the summary stays cheap
+    pub fn progressive_summary_request(&self, previous: Option<&str>, start: usize) -> ChatMessage {
+        let transcript = self
+            .messages
+            .iter()
+            .skip(start)
+            .filter(|message| !message.role.is_instruction())
+            .map(|message| format!("{}: {}", message.role.label(), message.content))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let previous = previous
+            .map(|summary| format!("Summary so far: {summary}\n\n"))
+            .unwrap_or_default();
+        ChatMessage::new(
+            Role::User,
+            format!(
+                "Write where this conversation stands in at most three short sentences: the topic, what was decided and what is still open. Answer with the summary only.\n\n{previous}New messages:\n\n{transcript}"
+            ),
+        )
+    }
+
+    /// replace the messages at these indices with the summary in place of the first one
+    pub fn replace_with_summary(&mut self, indices: &[usize], summary: &str) {
+        let Some(&first) = indices.iter().filter(|index| **index > 0).min() else {
+            return;
+        };
+        self.remove_messages(indices);
+        self.messages.insert(
+            first.min(self.messages.len()),
+            ChatMessage::new(
+                Role::System,
+                format!("Summary of earlier messages: {summary}"),
+            ),
+        );
+    }
+
+    /// replace secrets and personal data in every message with placeholders
+    pub fn redact(&mut self) {
+        for message in &mut self.messages {
+            if let Cow::Owned(redacted) = redact(&message.content) {
+                message.content = redacted;
+            }
+        }
+    }
+
+    /// messages as they are sent with user messages decorated
+    pub fn request_messages(&self, prefix: Option<&str>, suffix: Option<&str>) -> Vec<ChatMessage> {
+        let mut messages = self.messages.clone();
+        if prefix.is_some() || suffix.is_some() {
+            for message in &mut messages {
+                if matches!(message.role, Role::User) {
+                    message.content = decorate_question(&message.content, prefix, suffix);
+                }
+            }
+        }
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn similarity_follows_edit_distance() {
+        assert_eq!(text_similarity("same", "same"), 1.0);
+        assert_eq!(text_similarity("", ""), 1.0);
+        assert_eq!(text_similarity("abc", ""), 0.0);
+        assert!((text_similarity("kitten", "sitting") - (1.0 - 3.0 / 7.0)).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn normalizes_case_and_punctuation() {
+        assert_eq!(normalize_text("  What's a HASH map?! "), "whats a hash map");
+    }
+
+    #[cfg(feature = "tiktoken")]
+    #[test]
+    fn biases_single_tokens_and_refuses_phrases() {
+        // the words of the README example
+        let biases = logit_bias([("certainly", -100), ("delve", -100)]).unwrap();
+        assert!(biases.len() >= 2);
+        assert!(biases
+            .values()
+            .all(|bias| bias == &serde_json::Value::from(-100)));
+
+        let error = logit_bias([("as an AI language model", -100)]).unwrap_err();
+        assert!(error.to_string().contains("more than one token"), "{error}");
+    }
+}
@@ -0,0 +1,8 @@
+//! Conversation engine shared by every gpt-cli frontend
+//!
+//! Message history, token budgeting and request building without any IO, so it builds without
+//! tokio or a terminal. The `gpt_cli` crate re-exports these modules under the same names.
+
+pub mod conversation;
+pub mod messages;
+pub mod redaction;
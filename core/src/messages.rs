@@ -0,0 +1,202 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::PathBuf};
+
+/// Author of a message, covers every role of the chat API
+///
+/// serialized like the API so conversations saved before this type existed keep loading
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    /// newer name for system instructions used by reasoning models
+    Developer,
+    User,
+    Assistant,
+    /// result of a tool call
+    Tool,
+    /// result of a legacy function call
+    Function,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::Developer => "developer",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+            Role::Function => "function",
+        }
+    }
+
+    /// name shown in transcripts
+    pub fn label(&self) -> &'static str {
+        match self {
+            Role::System => "System",
+            Role::Developer => "Developer",
+            Role::User => "User",
+            Role::Assistant => "ChatGPT",
+            Role::Tool => "Tool",
+            Role::Function => "Function",
+        }
+    }
+
+    /// instructions for the model rather than part of the conversation
+    pub fn is_instruction(&self) -> bool {
+        matches!(self, Role::System | Role::Developer)
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One message of a conversation
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+    /// speaker for user messages or the tool that produced a tool message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// tool call a tool message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// tools an assistant message asked to run
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// whole content of a response too long to keep in history, content is an excerpt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_file: Option<PathBuf>,
+    /// private annotations added with /note, never sent to the API
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    /// rating of an answer from /good or /bad
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feedback: Option<Feedback>,
+    /// images sent as content parts after the text
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<Image>,
+    /// answer cut short by a timeout or Ctrl-C
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// versions of the git tracked files attached to the message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<FileVersion>,
+    /// few-shot exchange seeded from the persona, sent like any message but collapsed in /history
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub example: bool,
+}
+
+/// Whether an answer was any good
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+    Good,
+    Bad,
+}
+
+impl Rating {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rating::Good => "good",
+            Rating::Bad => "bad",
+        }
+    }
+}
+
+/// Rating given to an answer
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Feedback {
+    pub rating: Rating,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// tokens a high detail image costs at its usual size, only used to estimate context use
+pub const IMAGE_TOKENS: usize = 765;
+/// characters of the blob hash shown next to a file
+const SHORT_BLOB_LENGTH: usize = 7;
+
+/// Image attached with /image or --image
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Image {
+    /// path or URL it was attached from, shown in history
+    pub source: String,
+    /// URL or base64 data URL the model fetches it from
+    pub url: String,
+}
+
+/// Version of a git tracked file when it was attached
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FileVersion {
+    /// top level of the work tree
+    pub repository: PathBuf,
+    /// path inside the repository with forward slashes
+    pub path: String,
+    /// blob hash of the attached content, `git show <blob>` prints it again
+    pub blob: String,
+}
+
+impl FileVersion {
+    /// path with the abbreviated blob, e.g. src/main.rs@1a2b3c4
+    pub fn label(&self) -> String {
+        let short_blob = self.blob.get(..SHORT_BLOB_LENGTH).unwrap_or(&self.blob);
+        format!("{}@{short_blob}", self.path)
+    }
+}
+
+/// Tool the model asked to run
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    /// JSON encoded arguments
+    pub arguments: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            name: None,
+            tool_call_id: None,
+            tool_calls: vec![],
+            content_file: None,
+            notes: vec![],
+            feedback: None,
+            images: vec![],
+            truncated: false,
+            files: vec![],
+            example: false,
+        }
+    }
+
+    /// tool result as a user message naming the tool
+    pub fn tool_output_text(&self) -> String {
+        let tool = self
+            .name
+            .as_deref()
+            .or(self.tool_call_id.as_deref())
+            .unwrap_or("tool");
+        format!("Output of {tool}:\n{}", self.content)
+    }
+}
+
+/// the API only accepts names made of letters, digits, `_` and `-`
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.len() > 64
+        || !name
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || matches!(character, '_' | '-'))
+    {
+        anyhow::bail!("Name {name:?} may only contain up to 64 letters, digits, _ and -");
+    }
+    Ok(())
+}
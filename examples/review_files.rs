@@ -0,0 +1,37 @@
+//! Ask for a short review of every file given on the command line in one conversation
+//!
+//! `cargo run --example review_files -- src/main.rs src/lib.rs`
+
+use gpt_cli::ChatSession;
+use std::io::Write;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut session = ChatSession::new()?;
+    session.parameters_mut().temperature = Some(0.2);
+
+    for path in std::env::args().skip(1) {
+        let content = std::fs::read_to_string(&path)?;
+        println!("## {path}\n");
+        session
+            .stream(
+                &format!(
+                    "Review this file in three bullet points.\n\nFile: {path}\n```\n{content}\n```"
+                ),
+                |content| {
+                    print!("{content}");
+                    std::io::stdout().flush()?;
+                    Ok(())
+                },
+            )
+            .await?;
+        println!("\n");
+    }
+
+    let path = session.save().await?;
+    println!(
+        "Conversation saved, resume it with gpt-cli --file {}",
+        path.display()
+    );
+    Ok(())
+}
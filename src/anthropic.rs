@@ -0,0 +1,309 @@
+use crate::{
+    chat_client::{
+        model_ids, server_sent_events, ChatBackend, ChatCompletionStream, MessageImages,
+    },
+    images::data_url_parts,
+    messages::Image,
+};
+use anyhow::{Context, Result};
+use async_openai::types::{
+    CreateChatCompletionRequest, CreateChatCompletionResponse, CreateChatCompletionStreamResponse,
+};
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+/// the Messages API only samples up to 1, OpenAI goes up to 2
+const MAX_TEMPERATURE: f64 = 1.0;
+
+/// Anthropic account used with `--provider anthropic`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AnthropicConfig {
+    pub api_key: String,
+    #[serde(default = "default_base_url")]
+    pub base_url: String,
+    /// longest answer when --max-tokens isn't given, the API requires a limit
+    #[serde(default = "default_max_tokens")]
+    pub max_tokens: u32,
+}
+
+fn default_base_url() -> String {
+    String::from("https://api.anthropic.com")
+}
+
+fn default_max_tokens() -> u32 {
+    4096
+}
+
+// there is no Anthropic support in async-openai so requests are made by hand
+// and converted from and to the OpenAI types
+#[derive(Clone)]
+pub struct AnthropicClient {
+    http: reqwest::Client,
+    config: AnthropicConfig,
+}
+
+impl AnthropicClient {
+    pub fn new(config: &AnthropicConfig) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            config: config.clone(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/v1/{path}", self.config.base_url.trim_end_matches('/'))
+    }
+
+    fn authenticate(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        request
+            .header("x-api-key", &self.config.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+    }
+
+    /// Messages API request for a chat completion request
+    ///
+    /// system messages become the system prompt, consecutive messages of one role
+    /// are merged because roles have to alternate starting with the user
+    fn messages_request(
+        &self,
+        request: &CreateChatCompletionRequest,
+        images: &MessageImages,
+        stream: bool,
+    ) -> Result<Value> {
+        let request = serde_json::to_value(request)?;
+        let mut system = vec![];
+        let mut messages: Vec<Value> = vec![];
+        for (index, message) in request["messages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            let content = message["content"].as_str().unwrap_or_default();
+            let role = match message["role"].as_str() {
+                Some("system") => {
+                    if !content.is_empty() {
+                        system.push(content.to_owned());
+                    }
+                    continue;
+                }
+                Some("assistant") => "assistant",
+                _ => "user",
+            };
+            // there are no names, keep the speaker in the text
+            let content = match message["name"].as_str() {
+                Some(name) if role == "user" => format!("{name}: {content}"),
+                _ => content.to_owned(),
+            };
+            let content = match images.iter().find(|(message, _)| *message == index) {
+                Some((_, images)) => {
+                    let mut blocks: Vec<Value> = images.iter().map(image_block).collect();
+                    blocks.push(json!({ "type": "text", "text": content }));
+                    blocks.into()
+                }
+                None => content.into(),
+            };
+            match messages.last_mut() {
+                Some(last) if last["role"] == role => {
+                    last["content"] = merge_content(&last["content"], content);
+                }
+                _ => messages.push(json!({ "role": role, "content": content })),
+            }
+        }
+        if !matches!(messages.first(), Some(first) if first["role"] == "user") {
+            messages.insert(
+                0,
+                json!({ "role": "user", "content": "(conversation continues)" }),
+            );
+        }
+
+        let mut body = json!({
+            "model": request["model"],
+            "max_tokens": request["max_tokens"].as_u64().unwrap_or(self.config.max_tokens as u64),
+            "messages": messages,
+            "stream": stream,
+        });
+        if !system.is_empty() {
+            body["system"] = system.join("\n\n").into();
+        }
+        if let Some(temperature) = request["temperature"].as_f64() {
+            body["temperature"] = temperature.min(MAX_TEMPERATURE).into();
+        }
+        if let Some(top_p) = request["top_p"].as_f64() {
+            body["top_p"] = top_p.into();
+        }
+        Ok(body)
+    }
+
+    async fn send(&self, body: &Value) -> Result<reqwest::Response> {
+        let response = self
+            .authenticate(self.http.post(self.url("messages")))
+            .json(body)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await?;
+            anyhow::bail!("Anthropic returned {status}: {body}");
+        }
+        Ok(response)
+    }
+}
+
+/// image content block, files are sent as base64 and URLs are fetched by Anthropic
+fn image_block(image: &Image) -> Value {
+    match data_url_parts(&image.url) {
+        Some((media_type, data)) => json!({
+            "type": "image",
+            "source": { "type": "base64", "media_type": media_type, "data": data },
+        }),
+        None => json!({
+            "type": "image",
+            "source": { "type": "url", "url": image.url },
+        }),
+    }
+}
+
+/// text stays text, once there are images both become lists of blocks
+fn merge_content(previous: &Value, next: Value) -> Value {
+    if let (Some(previous), Some(next)) = (previous.as_str(), next.as_str()) {
+        return format!("{previous}\n\n{next}").into();
+    }
+    let mut blocks = content_blocks(previous);
+    blocks.extend(content_blocks(&next));
+    blocks.into()
+}
+
+fn content_blocks(content: &Value) -> Vec<Value> {
+    match content {
+        Value::String(text) => vec![json!({ "type": "text", "text": text })],
+        Value::Array(blocks) => blocks.clone(),
+        _ => vec![],
+    }
+}
+
+/// OpenAI name of the reason an answer stopped
+fn finish_reason(stop_reason: &Value) -> Value {
+    match stop_reason.as_str() {
+        Some("max_tokens") => "length".into(),
+        Some(_) => "stop".into(),
+        None => Value::Null,
+    }
+}
+
+fn created() -> u32 {
+    chrono::Utc::now().timestamp() as u32
+}
+
+#[async_trait]
+impl ChatBackend for AnthropicClient {
+    async fn create(
+        &self,
+        request: CreateChatCompletionRequest,
+        images: MessageImages,
+    ) -> Result<CreateChatCompletionResponse> {
+        let response: Value = self
+            .send(&self.messages_request(&request, &images, false)?)
+            .await?
+            .json()
+            .await
+            .context("failed to parse Anthropic response")?;
+        let content: String = response["content"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|block| block["text"].as_str())
+            .collect();
+        let prompt_tokens = response["usage"]["input_tokens"]
+            .as_u64()
+            .unwrap_or_default();
+        let completion_tokens = response["usage"]["output_tokens"]
+            .as_u64()
+            .unwrap_or_default();
+        serde_json::from_value(json!({
+            "id": response["id"],
+            "object": "chat.completion",
+            "created": created(),
+            "model": response["model"],
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": content },
+                "finish_reason": finish_reason(&response["stop_reason"]),
+            }],
+            "usage": {
+                "prompt_tokens": prompt_tokens,
+                "completion_tokens": completion_tokens,
+                "total_tokens": prompt_tokens + completion_tokens,
+            },
+        }))
+        .context("failed to convert Anthropic response")
+    }
+
+    async fn create_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+        images: MessageImages,
+    ) -> Result<ChatCompletionStream> {
+        let response = self
+            .send(&self.messages_request(&request, &images, true)?)
+            .await?;
+        let model = request.model;
+        // the response ends after the message_stop event
+        Ok(server_sent_events(response)
+            .filter_map(move |data| {
+                let model = model.clone();
+                async move {
+                    let event: Value = match data.and_then(|data| Ok(serde_json::from_str(&data)?))
+                    {
+                        Ok(event) => event,
+                        Err(error) => return Some(Err(error)),
+                    };
+                    let (content, stop) = match event["type"].as_str() {
+                        Some("content_block_delta") => {
+                            (event["delta"]["text"].clone(), Value::Null)
+                        }
+                        Some("message_delta") => {
+                            (Value::Null, finish_reason(&event["delta"]["stop_reason"]))
+                        }
+                        Some("error") => {
+                            return Some(Err(anyhow::anyhow!(
+                                "Anthropic stream failed: {}",
+                                event["error"]["message"]
+                            )))
+                        }
+                        // message start, pings and block boundaries carry no text
+                        _ => return None,
+                    };
+                    let chunk =
+                        serde_json::from_value::<CreateChatCompletionStreamResponse>(json!({
+                            "id": "",
+                            "object": "chat.completion.chunk",
+                            "created": created(),
+                            "model": model,
+                            "choices": [{
+                                "index": 0,
+                                "delta": { "content": content },
+                                "finish_reason": stop,
+                            }],
+                        }));
+                    Some(chunk.context("failed to convert Anthropic stream"))
+                }
+            })
+            .boxed())
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let response = self
+            .authenticate(self.http.get(self.url("models")))
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!("Anthropic returned {status}: {}", response.text().await?);
+        }
+        model_ids(response).await
+    }
+}
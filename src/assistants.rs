@@ -0,0 +1,253 @@
+use crate::{
+    chat_client::openai_http_client, configuration::AppConfig, fine_tuning::parse_response,
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// async-openai doesn't cover the assistants API either
+const ASSISTANTS_BETA_HEADER: (&str, &str) = ("OpenAI-Beta", "assistants=v2");
+const RUN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Which API conversations are sent to
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// stateless chat completions, the whole history is sent every time
+    #[default]
+    ChatCompletions,
+    /// conversations live server-side in assistant threads
+    Assistants,
+}
+
+#[derive(Debug, Deserialize)]
+struct Object {
+    id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateAssistantRequest<'a> {
+    model: &'a str,
+    name: &'a str,
+    instructions: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThreadMessage<'a> {
+    pub role: &'a str,
+    pub content: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateThreadRequest<'a> {
+    messages: &'a [ThreadMessage<'a>],
+}
+
+#[derive(Debug, Serialize)]
+struct ThreadMetadata<'a> {
+    title: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct ModifyThreadRequest<'a> {
+    metadata: ThreadMetadata<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateRunRequest<'a> {
+    assistant_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct RunError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Run {
+    id: String,
+    status: String,
+    last_error: Option<RunError>,
+    pub usage: Option<RunUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum MessageContent {
+    Text {
+        text: MessageText,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageText {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    content: Vec<MessageContent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageList {
+    data: Vec<Message>,
+}
+
+pub struct AssistantsClient {
+    http: reqwest::Client,
+    api_base: String,
+    api_key: String,
+}
+
+impl AssistantsClient {
+    pub fn new(config: &AppConfig) -> Result<Self> {
+        Ok(Self {
+            http: openai_http_client(config)?,
+            api_base: config.api_base().to_owned(),
+            api_key: config.open_ai_api_key.clone(),
+        })
+    }
+
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        self.http
+            .post(format!("{}{path}", self.api_base))
+            .bearer_auth(&self.api_key)
+            .header(ASSISTANTS_BETA_HEADER.0, ASSISTANTS_BETA_HEADER.1)
+    }
+
+    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        self.http
+            .get(format!("{}{path}", self.api_base))
+            .bearer_auth(&self.api_key)
+            .header(ASSISTANTS_BETA_HEADER.0, ASSISTANTS_BETA_HEADER.1)
+    }
+
+    /// create assistant for a persona, returns assistant id
+    pub async fn create_assistant(
+        &self,
+        model: &str,
+        name: &str,
+        instructions: &str,
+    ) -> Result<String> {
+        let request = CreateAssistantRequest {
+            model,
+            name,
+            instructions,
+        };
+        let response = self.post("/assistants").json(&request).send().await?;
+        let assistant: Object = parse_response(response).await?;
+        Ok(assistant.id)
+    }
+
+    /// create thread seeded with existing messages, returns thread id
+    pub async fn create_thread(&self, messages: &[ThreadMessage<'_>]) -> Result<String> {
+        let request = CreateThreadRequest { messages };
+        let response = self.post("/threads").json(&request).send().await?;
+        let thread: Object = parse_response(response).await?;
+        Ok(thread.id)
+    }
+
+    pub async fn set_thread_title(&self, thread_id: &str, title: &str) -> Result<()> {
+        let request = ModifyThreadRequest {
+            metadata: ThreadMetadata { title },
+        };
+        let response = self
+            .post(&format!("/threads/{thread_id}"))
+            .json(&request)
+            .send()
+            .await?;
+        let _: Object = parse_response(response).await?;
+        Ok(())
+    }
+
+    pub async fn add_user_message(&self, thread_id: &str, content: &str) -> Result<()> {
+        let message = ThreadMessage {
+            role: "user",
+            content,
+        };
+        let response = self
+            .post(&format!("/threads/{thread_id}/messages"))
+            .json(&message)
+            .send()
+            .await?;
+        let _: Object = parse_response(response).await?;
+        Ok(())
+    }
+
+    /// start a run on the thread and poll until it's done
+    pub async fn run_to_completion(
+        &self,
+        thread_id: &str,
+        assistant_id: &str,
+        temperature: Option<f32>,
+        top_p: Option<f32>,
+    ) -> Result<Run> {
+        let request = CreateRunRequest {
+            assistant_id,
+            temperature,
+            top_p,
+        };
+        let response = self
+            .post(&format!("/threads/{thread_id}/runs"))
+            .json(&request)
+            .send()
+            .await?;
+        let mut run: Run = parse_response(response).await?;
+
+        while matches!(run.status.as_str(), "queued" | "in_progress") {
+            tokio::time::sleep(RUN_POLL_INTERVAL).await;
+            let response = self
+                .get(&format!("/threads/{thread_id}/runs/{}", run.id))
+                .send()
+                .await?;
+            run = parse_response(response).await?;
+        }
+
+        if run.status != "completed" {
+            let reason = run
+                .last_error
+                .map(|error| error.message)
+                .unwrap_or_default();
+            anyhow::bail!("Assistant run ended with status {}: {reason}", run.status);
+        }
+        Ok(run)
+    }
+
+    /// text of the newest message on the thread
+    pub async fn latest_message(&self, thread_id: &str) -> Result<String> {
+        let response = self
+            .get(&format!("/threads/{thread_id}/messages"))
+            .query(&[("limit", "1"), ("order", "desc")])
+            .send()
+            .await?;
+        let list: MessageList = parse_response(response).await?;
+        let message = list
+            .data
+            .into_iter()
+            .next()
+            .context("Thread has no messages")?;
+        Ok(message
+            .content
+            .into_iter()
+            .filter_map(|content| match content {
+                MessageContent::Text { text } => Some(text.value),
+                MessageContent::Other => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
@@ -0,0 +1,604 @@
+use crate::{
+    citations,
+    conversation::count_text_tokens,
+    file_versions::{self, FileVersion},
+    injection,
+};
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+/// larger files would blow the context anyway
+const DEFAULT_MAX_FILE_KIB: usize = 1024;
+const DEFAULT_MAX_TOTAL_KIB: usize = 4 * 1024;
+/// ask before attaching more than this many tokens at once
+pub const CONFIRM_TOKEN_THRESHOLD: usize = 4000;
+
+/// What happens to a file or page over `max_file_kib`
+///
+/// nothing is summarized, content past the limit never reaches the model
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizedAttachment {
+    #[default]
+    Refuse,
+    /// attach as many whole lines from the start as fit and cut the rest,
+    /// a note tells the model how much is missing
+    KeepStart,
+}
+
+/// Size limits for attached files and pages
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AttachmentConfig {
+    /// largest file or page in KiB
+    #[serde(default = "default_max_file_kib")]
+    pub max_file_kib: usize,
+    /// most KiB one /attach or --attach adds, globs skip files past it
+    #[serde(default = "default_max_total_kib")]
+    pub max_total_kib: usize,
+    #[serde(default)]
+    pub oversized: OversizedAttachment,
+}
+
+fn default_max_file_kib() -> usize {
+    DEFAULT_MAX_FILE_KIB
+}
+
+fn default_max_total_kib() -> usize {
+    DEFAULT_MAX_TOTAL_KIB
+}
+
+impl Default for AttachmentConfig {
+    fn default() -> Self {
+        Self {
+            max_file_kib: DEFAULT_MAX_FILE_KIB,
+            max_total_kib: DEFAULT_MAX_TOTAL_KIB,
+            oversized: OversizedAttachment::default(),
+        }
+    }
+}
+
+impl AttachmentConfig {
+    fn max_file_bytes(&self) -> usize {
+        self.max_file_kib * 1024
+    }
+
+    pub fn max_total_bytes(&self) -> usize {
+        self.max_total_kib * 1024
+    }
+
+    fn check_size(&self, size: usize) -> Result<()> {
+        if size > self.max_file_bytes() {
+            anyhow::bail!(
+                "Attachment is larger than {} KiB, attach a smaller file or a line range or raise attachments.max_file_kib",
+                self.max_file_kib
+            );
+        }
+        Ok(())
+    }
+}
+
+/// File or web page added to the conversation
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    /// relative path or URL
+    pub source: String,
+    pub content: String,
+    pub tokens: usize,
+    /// fence tag for the content
+    pub language: Option<&'static str>,
+    /// first and last attached line when only part of a file is attached
+    pub lines: Option<(usize, usize)>,
+    /// git blob of a file from a repository
+    pub version: Option<FileVersion>,
+    /// earlier version the content is a diff against
+    pub diff_base: Option<FileVersion>,
+}
+
+impl Attachment {
+    fn new(source: String, content: String) -> Self {
+        let tokens = count_text_tokens(&content);
+        let language = detect_language(&source, &content);
+        Self {
+            source,
+            content,
+            tokens,
+            language,
+            lines: None,
+            version: None,
+            diff_base: None,
+        }
+    }
+
+    /// source with line range
+    pub fn label(&self) -> String {
+        match self.lines {
+            Some((start, end)) => format!("{}:{start}-{end}", self.source),
+            None => self.source.clone(),
+        }
+    }
+
+    /// prefix every line with its number so answers can cite it, diffs keep their own
+    pub fn number_lines(&mut self) {
+        if self.diff_base.is_some() {
+            return;
+        }
+        let first_line = self.lines.map(|(start, _)| start).unwrap_or(1);
+        self.content = citations::number_lines(&self.content, first_line);
+        self.tokens = count_text_tokens(&self.content);
+    }
+
+    /// replace the content with a diff against the version attached earlier
+    ///
+    /// only done when the diff is smaller than the file, returns whether it was
+    pub fn diff_against(&mut self, earlier: &FileVersion, earlier_content: &str) -> bool {
+        if self.lines.is_some() {
+            return false;
+        }
+        let patch = diffy::create_patch(earlier_content, &self.content).to_string();
+        let tokens = count_text_tokens(&patch);
+        if tokens >= self.tokens {
+            return false;
+        }
+        self.content = patch;
+        self.tokens = tokens;
+        self.diff_base = Some(earlier.clone());
+        true
+    }
+
+    /// text included in the message, models answer code questions better
+    /// when they know the path and language of the file
+    pub fn to_message(&self) -> String {
+        // fence has to be longer than any backtick run inside the content
+        let longest_backtick_run = self
+            .content
+            .split(|character| character != '`')
+            .map(str::len)
+            .max()
+            .unwrap_or_default();
+        let fence = "`".repeat(longest_backtick_run.max(2) + 1);
+        let header = match (&self.diff_base, self.lines) {
+            (Some(earlier), _) => format!(
+                "File: {} (changes since the version attached earlier, {})",
+                self.source,
+                earlier.label()
+            ),
+            (None, Some((start, end))) => format!("File: {} (lines {start}-{end})", self.source),
+            (None, None) => format!("File: {}", self.source),
+        };
+        let language = match self.diff_base {
+            Some(_) => "diff",
+            None => self.language.unwrap_or_default(),
+        };
+        let message = format!(
+            "{header}\n{fence}{language}\n{}\n{fence}",
+            self.content.trim_end()
+        );
+        if self.is_untrusted() {
+            injection::wrap_untrusted(&self.source, &message)
+        } else {
+            message
+        }
+    }
+
+    /// fetched from the web, the page could try to instruct the model
+    pub fn is_untrusted(&self) -> bool {
+        is_url(&self.source)
+    }
+}
+
+/// guess language from extension, file name or shebang line
+fn detect_language(source: &str, content: &str) -> Option<&'static str> {
+    // URLs can carry a query or fragment after the file name
+    let path = source.split(['?', '#']).next().unwrap_or(source);
+    let file_name = path.rsplit('/').next().unwrap_or(path).to_lowercase();
+    let by_name = match file_name.as_str() {
+        "dockerfile" => Some("dockerfile"),
+        "makefile" => Some("makefile"),
+        "cmakelists.txt" => Some("cmake"),
+        _ => None,
+    };
+    if by_name.is_some() {
+        return by_name;
+    }
+
+    let extension = file_name.rsplit_once('.').map(|(_, extension)| extension);
+    let by_extension = match extension {
+        Some("rs") => Some("rust"),
+        Some("py") => Some("python"),
+        Some("js" | "mjs" | "cjs") => Some("javascript"),
+        Some("ts" | "mts") => Some("typescript"),
+        Some("tsx") => Some("tsx"),
+        Some("jsx") => Some("jsx"),
+        Some("go") => Some("go"),
+        Some("c" | "h") => Some("c"),
+        Some("cpp" | "cc" | "cxx" | "hpp" | "hh") => Some("cpp"),
+        Some("cs") => Some("csharp"),
+        Some("java") => Some("java"),
+        Some("kt" | "kts") => Some("kotlin"),
+        Some("swift") => Some("swift"),
+        Some("rb") => Some("ruby"),
+        Some("php") => Some("php"),
+        Some("sh" | "bash" | "zsh") => Some("bash"),
+        Some("ps1") => Some("powershell"),
+        Some("sql") => Some("sql"),
+        Some("html" | "htm") => Some("html"),
+        Some("css") => Some("css"),
+        Some("scss") => Some("scss"),
+        Some("json") => Some("json"),
+        Some("yaml" | "yml") => Some("yaml"),
+        Some("toml") => Some("toml"),
+        Some("xml") => Some("xml"),
+        Some("md" | "markdown") => Some("markdown"),
+        Some("lua") => Some("lua"),
+        Some("nix") => Some("nix"),
+        Some("proto") => Some("protobuf"),
+        Some("tf") => Some("hcl"),
+        Some("vue") => Some("vue"),
+        Some("zig") => Some("zig"),
+        Some("hs") => Some("haskell"),
+        Some("ex" | "exs") => Some("elixir"),
+        Some("scala") => Some("scala"),
+        Some("dart") => Some("dart"),
+        _ => None,
+    };
+    if by_extension.is_some() {
+        return by_extension;
+    }
+
+    let shebang = content.lines().next()?.strip_prefix("#!")?;
+    if shebang.contains("python") {
+        Some("python")
+    } else if shebang.contains("node") {
+        Some("javascript")
+    } else if shebang.contains("bash") || shebang.ends_with("/sh") || shebang.contains(" sh") {
+        Some("bash")
+    } else {
+        None
+    }
+}
+
+fn is_url(target: &str) -> bool {
+    target.starts_with("https://") || target.starts_with("http://")
+}
+
+fn is_glob(target: &str) -> bool {
+    target.contains(['*', '?', '['])
+}
+
+/// Attachments loaded from one target
+#[derive(Debug, Default)]
+pub struct LoadedTarget {
+    pub attachments: Vec<Attachment>,
+    /// files matched by a glob that couldn't be attached and why
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+/// load a file, URL or every file matching a glob
+///
+/// globs skip gitignored files and files that can't be attached like binaries
+pub async fn load_target(target: &str, limits: &AttachmentConfig) -> Result<LoadedTarget> {
+    if !is_glob(target) || is_url(target) {
+        return Ok(LoadedTarget {
+            attachments: vec![load(target, limits).await?],
+            skipped: vec![],
+        });
+    }
+
+    let mut loaded = LoadedTarget::default();
+    let mut total_bytes = 0;
+    for path in expand_glob(target)? {
+        match read_file(&path, limits) {
+            Ok(attachment) if total_bytes + attachment.content.len() > limits.max_total_bytes() => {
+                let reason = format!(
+                    "over the {} KiB total attachment limit",
+                    limits.max_total_kib
+                );
+                loaded.skipped.push((path, reason));
+            }
+            Ok(attachment) => {
+                total_bytes += attachment.content.len();
+                loaded.attachments.push(attachment);
+            }
+            Err(error) => loaded.skipped.push((path, error.to_string())),
+        }
+    }
+    if loaded.attachments.is_empty() && loaded.skipped.is_empty() {
+        anyhow::bail!("No files match {target}");
+    }
+    Ok(loaded)
+}
+
+/// files matching the pattern, `*` stays within a directory and `**` crosses them
+fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    let matcher = globset::GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()?
+        .compile_matcher();
+    // only walk the part of the tree the pattern can match
+    let base: PathBuf = Path::new(pattern)
+        .components()
+        .take_while(|component| !is_glob(&component.as_os_str().to_string_lossy()))
+        .collect();
+    let base = if base.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        base
+    };
+
+    let mut files = vec![];
+    for entry in ignore::WalkBuilder::new(&base).build() {
+        let entry = entry?;
+        if !entry
+            .file_type()
+            .is_some_and(|file_type| file_type.is_file())
+        {
+            continue;
+        }
+        let path = entry.path().strip_prefix("./").unwrap_or(entry.path());
+        if matcher.is_match(path) {
+            files.push(path.to_owned());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// load a local file, a line range of it or fetch a URL
+async fn load(target: &str, limits: &AttachmentConfig) -> Result<Attachment> {
+    if is_url(target) {
+        return fetch_url(target, limits).await;
+    }
+    match parse_line_range(target) {
+        // file names can contain colons so prefer an existing file
+        Some((path, start, end)) if !Path::new(target).exists() => {
+            read_line_range(Path::new(path), start, end, limits)
+        }
+        _ => read_file(Path::new(target), limits),
+    }
+}
+
+/// file a local target reads, without its line range
+pub fn target_file(target: &str) -> &Path {
+    match parse_line_range(target) {
+        Some((path, _, _)) if !Path::new(target).exists() => Path::new(path),
+        _ => Path::new(target),
+    }
+}
+
+/// split `path:start-end` or `path:line`, lines are 1-based and inclusive
+fn parse_line_range(target: &str) -> Option<(&str, usize, usize)> {
+    let (path, range) = target.rsplit_once(':')?;
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+        None => {
+            let line = range.parse().ok()?;
+            (line, line)
+        }
+    };
+    Some((path, start, end))
+}
+
+fn read_line_range(
+    path: &Path,
+    start: usize,
+    end: usize,
+    limits: &AttachmentConfig,
+) -> Result<Attachment> {
+    if start == 0 || start > end {
+        anyhow::bail!("Invalid line range {start}-{end}, lines start at 1");
+    }
+    let bytes =
+        std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let content = decode_text(bytes, None)?;
+    let line_count = content.lines().count();
+    if start > line_count {
+        anyhow::bail!("{} only has {line_count} lines", path.display());
+    }
+    let end = end.min(line_count);
+    let snippet = content
+        .lines()
+        .skip(start - 1)
+        .take(end - start + 1)
+        .collect::<Vec<_>>()
+        .join("\n");
+    limits.check_size(snippet.len())?;
+
+    let mut attachment = Attachment::new(relative_path(path), snippet);
+    attachment.lines = Some((start, end));
+    attachment.version = file_versions::file_version(path);
+    Ok(attachment)
+}
+
+fn read_file(path: &Path, limits: &AttachmentConfig) -> Result<Attachment> {
+    let size = std::fs::metadata(path)
+        .with_context(|| format!("failed to read {}", path.display()))?
+        .len() as usize;
+    if limits.oversized == OversizedAttachment::Refuse {
+        limits.check_size(size)?;
+    }
+    let mut bytes = vec![];
+    std::fs::File::open(path)
+        .with_context(|| format!("failed to read {}", path.display()))?
+        .take(limits.max_file_bytes() as u64 + 1)
+        .read_to_end(&mut bytes)?;
+    let content = truncated_text(bytes, None, size, limits)?;
+    let mut attachment = Attachment::new(relative_path(path), content);
+    attachment.version = file_versions::file_version(path);
+    Ok(attachment)
+}
+
+/// relative paths are shorter and don't leak the home directory
+fn relative_path(path: &Path) -> String {
+    std::env::current_dir()
+        .ok()
+        .and_then(|current_dir| path.strip_prefix(current_dir).ok().map(Path::to_owned))
+        .unwrap_or_else(|| path.to_owned())
+        .display()
+        .to_string()
+}
+
+async fn fetch_url(url: &str, limits: &AttachmentConfig) -> Result<Attachment> {
+    let content = fetch_limited(url, limits).await?;
+    Ok(Attachment::new(url.to_owned(), content))
+}
+
+/// download a text document, refusing binary content and anything over the default size limit
+pub async fn fetch_text(url: &str) -> Result<String> {
+    fetch_limited(url, &AttachmentConfig::default()).await
+}
+
+async fn fetch_limited(url: &str, limits: &AttachmentConfig) -> Result<String> {
+    let response = reqwest::get(url)
+        .await
+        .with_context(|| format!("failed to fetch {url}"))?
+        .error_for_status()?;
+
+    let content_length = response.content_length().map(|size| size as usize);
+    if let Some(size) = content_length {
+        if limits.oversized == OversizedAttachment::Refuse {
+            limits.check_size(size)?;
+        }
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(|content_type| content_type.to_owned());
+    if let Some(content_type) = content_type.as_deref() {
+        if !is_text_content_type(content_type) {
+            anyhow::bail!(
+                "{url} is {content_type}, only text can be attached. Download and convert it to text first"
+            );
+        }
+    }
+
+    // servers don't always send content length so enforce the limit while downloading
+    let mut bytes = vec![];
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        bytes.extend_from_slice(&chunk?);
+        if bytes.len() > limits.max_file_bytes() {
+            if limits.oversized == OversizedAttachment::Refuse {
+                limits.check_size(bytes.len())?;
+            }
+            break;
+        }
+    }
+
+    let size = content_length.unwrap_or(bytes.len()).max(bytes.len());
+    truncated_text(bytes, content_type.as_deref(), size, limits)
+}
+
+/// text of the first `max_file_kib`, cut after the last whole line with a note about the rest
+fn truncated_text(
+    mut bytes: Vec<u8>,
+    content_type: Option<&str>,
+    size: usize,
+    limits: &AttachmentConfig,
+) -> Result<String> {
+    if bytes.len() <= limits.max_file_bytes() {
+        return decode_text(bytes, content_type);
+    }
+    bytes.truncate(limits.max_file_bytes());
+    // a cut in the middle of a character wouldn't decode
+    if let Some(last_newline) = bytes.iter().rposition(|byte| *byte == b'\n') {
+        bytes.truncate(last_newline + 1);
+    }
+    let attached = bytes.len();
+    let mut content = decode_text(bytes, content_type)?;
+    content.push_str(&format!(
+        "\n[{} KiB more not attached, over the {} KiB limit]",
+        size.saturating_sub(attached).div_ceil(1024),
+        limits.max_file_kib
+    ));
+    Ok(content)
+}
+
+/// kind of a binary file recognized by its first bytes
+///
+/// short signatures that plain text could start with are left to the NUL and UTF-8 checks
+fn binary_kind(bytes: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "a PNG image"),
+        (b"\xff\xd8\xff", "a JPEG image"),
+        (b"GIF87a", "a GIF image"),
+        (b"GIF89a", "a GIF image"),
+        (b"%PDF-", "a PDF document"),
+        (b"PK\x03\x04", "a ZIP archive or office document"),
+        (b"\x1f\x8b", "gzip compressed"),
+        (b"\xfd7zXZ\x00", "xz compressed"),
+        (b"7z\xbc\xaf\x27\x1c", "a 7z archive"),
+        (b"\x7fELF", "an ELF executable"),
+        (b"\xcf\xfa\xed\xfe", "a Mach-O executable"),
+        (b"\x00asm", "a WebAssembly module"),
+        (b"SQLite format 3\x00", "an SQLite database"),
+        (b"RIFF", "a RIFF media file"),
+        (b"OggS", "an Ogg media file"),
+    ];
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| bytes.starts_with(signature))
+        .map(|(_, kind)| *kind)
+}
+
+fn is_text_content_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime.as_str(),
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/x-yaml"
+                | "application/yaml"
+                | "application/toml"
+                | "application/x-sh"
+        )
+}
+
+fn decode_text(bytes: Vec<u8>, content_type: Option<&str>) -> Result<String> {
+    if let Some(kind) = binary_kind(&bytes) {
+        anyhow::bail!("Content is {kind}, only text can be attached");
+    }
+    // NUL bytes don't show up in text files
+    let binary_error = || {
+        anyhow::anyhow!(
+            "Content looks binary{}, only text can be attached",
+            content_type
+                .map(|content_type| format!(" ({content_type})"))
+                .unwrap_or_default()
+        )
+    };
+    if bytes.contains(&0) {
+        return Err(binary_error());
+    }
+    String::from_utf8(bytes).map_err(|_| binary_error())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stars_stay_within_a_directory() {
+        let files = expand_glob("*.toml").expect("pattern is valid");
+        assert!(files.contains(&PathBuf::from("Cargo.toml")));
+        assert!(!files.contains(&PathBuf::from("core/Cargo.toml")));
+
+        let files = expand_glob("**/Cargo.toml").expect("pattern is valid");
+        assert!(files.contains(&PathBuf::from("Cargo.toml")));
+        assert!(files.contains(&PathBuf::from("core/Cargo.toml")));
+    }
+}
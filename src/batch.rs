@@ -0,0 +1,243 @@
+use crate::{
+    chat_client::{openai_http_client, to_request_messages, ToRequestMessage},
+    chat_manager::ChatHistory,
+    configuration::AppConfig,
+    fine_tuning::{parse_response, UploadedFile},
+    messages::{ChatMessage, Role},
+    utils::TimestampConfig,
+};
+use anyhow::{Context, Result};
+use async_openai::types::{CreateChatCompletionRequest, CreateChatCompletionRequestArgs};
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+
+/// every request of a batch goes to chat completions
+const BATCH_ENDPOINT: &str = "/v1/chat/completions";
+/// the only window the Batch API offers
+const COMPLETION_WINDOW: &str = "24h";
+
+#[derive(Debug, Deserialize)]
+pub struct Batch {
+    pub id: String,
+    pub status: String,
+    pub created_at: i64,
+    pub output_file_id: Option<String>,
+    pub error_file_id: Option<String>,
+    pub request_counts: Option<RequestCounts>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequestCounts {
+    pub total: u64,
+    pub completed: u64,
+    pub failed: u64,
+}
+
+impl Batch {
+    pub fn summary(&self, timestamps: &TimestampConfig) -> String {
+        let created = Local
+            .timestamp_opt(self.created_at, 0)
+            .single()
+            .map(|created| timestamps.display(&created))
+            .unwrap_or_default();
+        let mut summary = format!("{} {} created {created}", self.id, self.status);
+        if let Some(counts) = &self.request_counts {
+            summary.push_str(&format!(
+                " {}/{} done, {} failed",
+                counts.completed, counts.total, counts.failed
+            ));
+        }
+        summary
+    }
+
+    fn is_done(&self) -> bool {
+        matches!(
+            self.status.as_str(),
+            "completed" | "failed" | "expired" | "cancelled"
+        )
+    }
+}
+
+/// Answer to one prompt of a finished batch
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    /// `prompt-<n>` for the nth line of the submitted file
+    pub custom_id: String,
+    pub answer: Option<String>,
+    pub error: Option<String>,
+}
+
+impl BatchResult {
+    fn parse(line: &str) -> Result<Self> {
+        let value: serde_json::Value =
+            serde_json::from_str(line).context("failed to parse batch result")?;
+        let body = &value["response"]["body"];
+        let error = value["error"]["message"]
+            .as_str()
+            .or_else(|| body["error"]["message"].as_str());
+        Ok(Self {
+            custom_id: value["custom_id"].as_str().unwrap_or_default().to_owned(),
+            answer: body["choices"][0]["message"]["content"]
+                .as_str()
+                .map(str::to_owned),
+            error: error.map(str::to_owned),
+        })
+    }
+
+    /// line of the submitted file, results are sorted by it
+    fn line(&self) -> usize {
+        self.custom_id
+            .strip_prefix("prompt-")
+            .and_then(|line| line.parse().ok())
+            .unwrap_or(usize::MAX)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BatchRequest<'a> {
+    custom_id: String,
+    method: &'static str,
+    url: &'static str,
+    body: &'a CreateChatCompletionRequest,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateBatchRequest<'a> {
+    input_file_id: &'a str,
+    endpoint: &'static str,
+    completion_window: &'static str,
+}
+
+/// JSONL input of a batch with one request per non-empty line of `prompts`
+///
+/// each prompt starts a conversation like `chat_history`, requests are numbered by their line
+pub fn batch_input(
+    prompts: &str,
+    chat_history: &ChatHistory,
+    temperature: Option<f32>,
+) -> Result<String> {
+    let mut jsonl = String::new();
+    for (index, prompt) in prompts.lines().enumerate() {
+        if prompt.trim().is_empty() {
+            continue;
+        }
+        let mut messages = to_request_messages(chat_history.messages())?;
+        messages.push(ChatMessage::new(Role::User, prompt.trim()).to_request_message()?);
+        let mut body = CreateChatCompletionRequestArgs::default();
+        body.model(&chat_history.model_metadata().name)
+            .messages(messages);
+        if let Some(temperature) = temperature {
+            body.temperature(temperature);
+        }
+        let request = BatchRequest {
+            custom_id: format!("prompt-{}", index + 1),
+            method: "POST",
+            url: BATCH_ENDPOINT,
+            body: &body.build()?,
+        };
+        jsonl.push_str(&serde_json::to_string(&request)?);
+        jsonl.push('\n');
+    }
+    if jsonl.is_empty() {
+        anyhow::bail!("No prompts to submit, write one per line");
+    }
+    Ok(jsonl)
+}
+
+/// async-openai doesn't cover the Batch API so we talk to it directly
+pub struct BatchClient {
+    http: reqwest::Client,
+    api_base: String,
+    api_key: String,
+}
+
+impl BatchClient {
+    pub fn new(config: &AppConfig) -> Result<Self> {
+        Ok(Self {
+            http: openai_http_client(config)?,
+            api_base: config.api_base().to_owned(),
+            api_key: config.open_ai_api_key.clone(),
+        })
+    }
+
+    /// upload the JSONL requests and start the batch, answers are ready within a day
+    pub async fn submit(&self, file_name: &str, jsonl: String) -> Result<Batch> {
+        let part = reqwest::multipart::Part::text(jsonl)
+            .file_name(file_name.to_owned())
+            .mime_str("application/jsonl")?;
+        let form = reqwest::multipart::Form::new()
+            .text("purpose", "batch")
+            .part("file", part);
+        let response = self
+            .http
+            .post(format!("{}/files", self.api_base))
+            .bearer_auth(&self.api_key)
+            .multipart(form)
+            .send()
+            .await?;
+        let uploaded: UploadedFile = parse_response(response).await?;
+
+        let request = CreateBatchRequest {
+            input_file_id: &uploaded.id,
+            endpoint: BATCH_ENDPOINT,
+            completion_window: COMPLETION_WINDOW,
+        };
+        let response = self
+            .http
+            .post(format!("{}/batches", self.api_base))
+            .bearer_auth(&self.api_key)
+            .json(&request)
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+
+    pub async fn status(&self, batch_id: &str) -> Result<Batch> {
+        let response = self
+            .http
+            .get(format!("{}/batches/{batch_id}", self.api_base))
+            .bearer_auth(&self.api_key)
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+
+    /// answers and errors of a finished batch in the order of the prompts
+    pub async fn fetch(&self, batch_id: &str) -> Result<Vec<BatchResult>> {
+        let batch = self.status(batch_id).await?;
+        if !batch.is_done() {
+            anyhow::bail!(
+                "Batch {batch_id} is {}, check again with gpt-cli batch status {batch_id}",
+                batch.status
+            );
+        }
+        let mut results = vec![];
+        for file_id in [&batch.output_file_id, &batch.error_file_id]
+            .into_iter()
+            .flatten()
+        {
+            for line in self.file_content(file_id).await?.lines() {
+                if !line.trim().is_empty() {
+                    results.push(BatchResult::parse(line)?);
+                }
+            }
+        }
+        results.sort_by_key(BatchResult::line);
+        Ok(results)
+    }
+
+    async fn file_content(&self, file_id: &str) -> Result<String> {
+        let response = self
+            .http
+            .get(format!("{}/files/{file_id}/content", self.api_base))
+            .bearer_auth(&self.api_key)
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            anyhow::bail!("OpenAI API returned {status}: {body}");
+        }
+        Ok(body)
+    }
+}
@@ -0,0 +1,504 @@
+use crate::{
+    anthropic::AnthropicClient,
+    configuration::AppConfig,
+    messages::{ChatMessage, Image, Role},
+};
+use anyhow::{Context, Result};
+use async_openai::{
+    types::{
+        ChatCompletionRequestMessage, ChatCompletionRequestMessageArgs,
+        CreateChatCompletionRequest, CreateChatCompletionResponse,
+        CreateChatCompletionStreamResponse, Role as ApiRole,
+    },
+    Client,
+};
+use async_trait::async_trait;
+use futures::{stream::BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, sync::Arc};
+
+/// Streamed chunks of a chat completion
+pub type ChatCompletionStream = BoxStream<'static, Result<CreateChatCompletionStreamResponse>>;
+
+/// images of the request messages by message index, the OpenAI types only carry text
+pub type MessageImages = Vec<(usize, Vec<Image>)>;
+
+/// role of a message the API client returned
+pub fn role_from_api(role: ApiRole) -> Role {
+    match role {
+        ApiRole::System => Role::System,
+        ApiRole::User => Role::User,
+        ApiRole::Assistant => Role::Assistant,
+    }
+}
+
+/// Message in the form the API client can send
+///
+/// conversion lives with the client so the core messages don't depend on it
+pub trait ToRequestMessage {
+    fn to_request_message(&self) -> Result<ChatCompletionRequestMessage>;
+}
+
+impl ToRequestMessage for ChatMessage {
+    /// the client predates developer and tool messages, developer messages are sent as
+    /// system messages, tool calls are described in the assistant message
+    /// and tool results are sent as user messages naming the tool
+    fn to_request_message(&self) -> Result<ChatCompletionRequestMessage> {
+        let mut builder = ChatCompletionRequestMessageArgs::default();
+        match self.role {
+            Role::System | Role::Developer => {
+                builder.role(ApiRole::System).content(&self.content);
+            }
+            Role::User => {
+                builder.role(ApiRole::User).content(&self.content);
+            }
+            Role::Assistant => {
+                let mut content = self.content.clone();
+                for tool_call in &self.tool_calls {
+                    if !content.is_empty() {
+                        content.push('\n');
+                    }
+                    content.push_str(&format!(
+                        "Called {} with {}",
+                        tool_call.name, tool_call.arguments
+                    ));
+                }
+                builder.role(ApiRole::Assistant).content(content);
+            }
+            Role::Tool | Role::Function => {
+                builder.role(ApiRole::User).content(self.tool_output_text());
+                return Ok(builder.build()?);
+            }
+        }
+        if let Some(name) = &self.name {
+            builder.name(name);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// convert a whole history for a request
+pub fn to_request_messages(messages: &[ChatMessage]) -> Result<Vec<ChatCompletionRequestMessage>> {
+    messages
+        .iter()
+        .map(ChatMessage::to_request_message)
+        .collect()
+}
+
+/// request as JSON with the messages that have images turned into content parts
+pub fn request_body(
+    request: &CreateChatCompletionRequest,
+    images: &MessageImages,
+) -> Result<Value> {
+    let mut body = serde_json::to_value(request)?;
+    for (index, images) in images {
+        let Some(message) = body["messages"].get_mut(*index) else {
+            continue;
+        };
+        let mut parts = vec![json!({ "type": "text", "text": message["content"] })];
+        parts.extend(
+            images
+                .iter()
+                .map(|image| json!({ "type": "image_url", "image_url": { "url": image.url } })),
+        );
+        message["content"] = parts.into();
+    }
+    Ok(body)
+}
+
+/// How requests to Azure authenticate
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AzureAuth {
+    /// resource key in the `api-key` header
+    #[default]
+    ApiKey,
+    /// Microsoft Entra ID token as a bearer token
+    Bearer,
+}
+
+/// Azure OpenAI resource used instead of api.openai.com
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AzureConfig {
+    /// e.g. https://my-resource.openai.azure.com
+    pub endpoint: String,
+    pub api_version: String,
+    #[serde(default)]
+    pub auth: AzureAuth,
+    /// key or token, open_ai_api_key is used when missing
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// deployment for each model name, models that aren't listed use `deployment`
+    #[serde(default)]
+    pub deployments: HashMap<String, String>,
+    /// deployment for models without their own, defaults to the model name
+    #[serde(default)]
+    pub deployment: Option<String>,
+}
+
+/// Company whose API answers chat requests
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Provider {
+    /// api.openai.com or the Azure resource from the `azure` config section
+    #[default]
+    OpenAi,
+    /// Messages API configured in the `anthropic` config section
+    Anthropic,
+}
+
+/// Chat completions from one provider
+///
+/// requests and responses use the OpenAI types, other providers convert them,
+/// images are passed next to the request because its messages only hold text
+#[async_trait]
+pub trait ChatBackend: Send + Sync {
+    async fn create(
+        &self,
+        request: CreateChatCompletionRequest,
+        images: MessageImages,
+    ) -> Result<CreateChatCompletionResponse>;
+
+    async fn create_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+        images: MessageImages,
+    ) -> Result<ChatCompletionStream>;
+
+    /// ids of the models the endpoint serves
+    async fn list_models(&self) -> Result<Vec<String>>;
+
+    /// fail early when the key is wrong
+    async fn check_credentials(&self) -> Result<()> {
+        self.list_models().await?;
+        Ok(())
+    }
+}
+
+/// HTTP client sending the configured organization and project with every request to OpenAI
+pub fn openai_http_client(config: &AppConfig) -> Result<reqwest::Client> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in config.openai_headers() {
+        let value = reqwest::header::HeaderValue::from_str(value)
+            .with_context(|| format!("{value} isn't a valid {name} header"))?;
+        headers.insert(name, value);
+    }
+    Ok(reqwest::Client::builder()
+        .default_headers(headers)
+        .build()?)
+}
+
+// async-openai only sends text content so requests with images are made by hand
+#[derive(Clone)]
+pub struct OpenAiClient {
+    client: Client,
+    http: reqwest::Client,
+    api_base: String,
+    api_key: String,
+}
+
+impl OpenAiClient {
+    fn new(config: &AppConfig) -> Result<Self> {
+        let api_base = config.api_base().to_owned();
+        let http = openai_http_client(config)?;
+        Ok(Self {
+            client: Client::new()
+                .with_api_key(&config.open_ai_api_key)
+                .with_api_base(&api_base)
+                .with_http_client(http.clone()),
+            http,
+            api_base,
+            api_key: config.open_ai_api_key.clone(),
+        })
+    }
+
+    async fn send(&self, body: &Value) -> Result<reqwest::Response> {
+        let response = self
+            .http
+            .post(format!("{}/chat/completions", self.api_base))
+            .bearer_auth(&self.api_key)
+            .json(body)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await?;
+            anyhow::bail!("OpenAI returned {status}: {body}");
+        }
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl ChatBackend for OpenAiClient {
+    async fn create(
+        &self,
+        request: CreateChatCompletionRequest,
+        images: MessageImages,
+    ) -> Result<CreateChatCompletionResponse> {
+        if images.is_empty() {
+            return Ok(self.client.chat().create(request).await?);
+        }
+        self.send(&request_body(&request, &images)?)
+            .await?
+            .json()
+            .await
+            .context("failed to parse OpenAI response")
+    }
+
+    async fn create_stream(
+        &self,
+        mut request: CreateChatCompletionRequest,
+        images: MessageImages,
+    ) -> Result<ChatCompletionStream> {
+        if images.is_empty() {
+            return Ok(self
+                .client
+                .chat()
+                .create_stream(request)
+                .await?
+                .map(|chunk| chunk.map_err(anyhow::Error::from))
+                .boxed());
+        }
+        request.stream = Some(true);
+        let response = self.send(&request_body(&request, &images)?).await?;
+        Ok(chat_completion_chunks(response))
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let models = self.client.models().list().await?;
+        Ok(models.data.into_iter().map(|model| model.id).collect())
+    }
+}
+
+/// Chat completions from whichever provider is configured
+#[derive(Clone)]
+pub struct ChatClient {
+    backend: Arc<dyn ChatBackend>,
+}
+
+impl ChatClient {
+    /// OpenAI goes to Azure when it's configured
+    pub fn new(config: &AppConfig, provider: Provider) -> Result<Self> {
+        let backend: Arc<dyn ChatBackend> = match (provider, &config.azure) {
+            (Provider::OpenAi, Some(azure)) => {
+                Arc::new(AzureClient::new(azure, &config.open_ai_api_key))
+            }
+            (Provider::OpenAi, None) => Arc::new(OpenAiClient::new(config)?),
+            // the Messages API has no logit_bias so it would be dropped without a word
+            (Provider::Anthropic, _)
+                if !(config.logit_bias.is_empty() && config.banned_words.is_empty()) =>
+            {
+                anyhow::bail!("logit_bias and banned_words aren't supported with Anthropic")
+            }
+            (Provider::Anthropic, _) => {
+                Arc::new(AnthropicClient::new(config.anthropic.as_ref().context(
+                    "The anthropic provider needs an anthropic section with api_key in config",
+                )?))
+            }
+        };
+        Ok(Self { backend })
+    }
+
+    pub async fn create(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse> {
+        self.backend.create(request, vec![]).await
+    }
+
+    pub async fn create_with_images(
+        &self,
+        request: CreateChatCompletionRequest,
+        images: MessageImages,
+    ) -> Result<CreateChatCompletionResponse> {
+        self.backend.create(request, images).await
+    }
+
+    pub async fn create_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<ChatCompletionStream> {
+        self.backend.create_stream(request, vec![]).await
+    }
+
+    pub async fn create_stream_with_images(
+        &self,
+        request: CreateChatCompletionRequest,
+        images: MessageImages,
+    ) -> Result<ChatCompletionStream> {
+        self.backend.create_stream(request, images).await
+    }
+
+    /// fail early when the key is wrong
+    pub async fn check_credentials(&self) -> Result<()> {
+        self.backend.check_credentials().await
+    }
+
+    pub async fn list_models(&self) -> Result<Vec<String>> {
+        self.backend.list_models().await
+    }
+}
+
+/// ids in a `/models` response, every provider lists them in the same shape
+pub async fn model_ids(response: reqwest::Response) -> Result<Vec<String>> {
+    let models: serde_json::Value = response
+        .json()
+        .await
+        .context("failed to parse model list")?;
+    Ok(models["data"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|model| model["id"].as_str())
+        .map(str::to_owned)
+        .collect())
+}
+
+/// `data` of every server-sent event in a response
+pub fn server_sent_events(response: reqwest::Response) -> BoxStream<'static, Result<String>> {
+    let bytes = response
+        .bytes_stream()
+        .map(|chunk| chunk.map(|bytes| bytes.to_vec()))
+        .boxed();
+    futures::stream::unfold((bytes, vec![]), |(mut bytes, mut buffer)| async move {
+        loop {
+            // one `data:` line per event, other lines only name the event
+            if let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                let Some(data) = line.trim().strip_prefix("data:") else {
+                    continue;
+                };
+                return Some((Ok(data.trim().to_owned()), (bytes, buffer)));
+            }
+            match bytes.next().await? {
+                Ok(chunk) => buffer.extend(chunk),
+                Err(error) => return Some((Err(error.into()), (bytes, buffer))),
+            }
+        }
+    })
+    .boxed()
+}
+
+/// chunks of an OpenAI style stream, it ends with a `[DONE]` event
+fn chat_completion_chunks(response: reqwest::Response) -> ChatCompletionStream {
+    server_sent_events(response)
+        .take_while(|data| futures::future::ready(!matches!(data, Ok(data) if data == "[DONE]")))
+        .filter_map(|data| async move {
+            let data = match data {
+                Ok(data) => data,
+                Err(error) => return Some(Err(error)),
+            };
+            match serde_json::from_str::<CreateChatCompletionStreamResponse>(&data) {
+                // Azure sends content filter results as chunks without choices
+                Ok(chunk) if chunk.choices.is_empty() => None,
+                chunk => Some(chunk.context("failed to parse chat completion stream")),
+            }
+        })
+        .boxed()
+}
+
+// async-openai can't add the api-version query Azure needs so these requests are made by hand
+#[derive(Clone)]
+pub struct AzureClient {
+    http: reqwest::Client,
+    config: AzureConfig,
+    api_key: String,
+}
+
+impl AzureClient {
+    fn new(config: &AzureConfig, fallback_api_key: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            config: config.clone(),
+            api_key: config
+                .api_key
+                .clone()
+                .unwrap_or_else(|| fallback_api_key.to_owned()),
+        }
+    }
+
+    fn deployment<'a>(&'a self, model: &'a str) -> &'a str {
+        self.config
+            .deployments
+            .get(model)
+            .or(self.config.deployment.as_ref())
+            .map(String::as_str)
+            .unwrap_or(model)
+    }
+
+    fn authenticate(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self.config.auth {
+            AzureAuth::ApiKey => request.header("api-key", &self.api_key),
+            AzureAuth::Bearer => request.bearer_auth(&self.api_key),
+        }
+    }
+
+    async fn send(
+        &self,
+        request: &CreateChatCompletionRequest,
+        images: &MessageImages,
+    ) -> Result<reqwest::Response> {
+        let url = format!(
+            "{}/openai/deployments/{}/chat/completions",
+            self.config.endpoint.trim_end_matches('/'),
+            self.deployment(&request.model)
+        );
+        let response = self
+            .authenticate(self.http.post(url))
+            .query(&[("api-version", &self.config.api_version)])
+            .json(&request_body(request, images)?)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await?;
+            anyhow::bail!("Azure OpenAI returned {status}: {body}");
+        }
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl ChatBackend for AzureClient {
+    async fn create(
+        &self,
+        request: CreateChatCompletionRequest,
+        images: MessageImages,
+    ) -> Result<CreateChatCompletionResponse> {
+        let response = self.send(&request, &images).await?;
+        response
+            .json()
+            .await
+            .context("failed to parse Azure OpenAI response")
+    }
+
+    async fn create_stream(
+        &self,
+        mut request: CreateChatCompletionRequest,
+        images: MessageImages,
+    ) -> Result<ChatCompletionStream> {
+        request.stream = Some(true);
+        let response = self.send(&request, &images).await?;
+        Ok(chat_completion_chunks(response))
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let url = format!(
+            "{}/openai/models",
+            self.config.endpoint.trim_end_matches('/')
+        );
+        let response = self
+            .authenticate(self.http.get(url))
+            .query(&[("api-version", &self.config.api_version)])
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!("Azure OpenAI returned {status}: {}", response.text().await?);
+        }
+        model_ids(response).await
+    }
+}
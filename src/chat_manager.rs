@@ -1,48 +1,416 @@
 use crate::{
+    assistants::{AssistantsClient, ThreadMessage},
+    chat_client::{
+        role_from_api, to_request_messages, ChatClient, MessageImages, ToRequestMessage,
+    },
+    citations::CITATION_INSTRUCTIONS,
+    code_blocks::{extract_code_blocks, CodeBlock},
     configuration::get_project_dirs,
-    utils::{INCREASING_TREND_EMOJI, QUESTION_MARK_EMOJI, ROBOT_EMOJI, SYSTEM_EMOJI},
-};
-use anyhow::{Context, Result};
-use async_openai::{
-    types::{
-        ChatCompletionRequestMessage, ChatCompletionRequestMessageArgs,
-        CreateChatCompletionRequestArgs, Role, Usage,
+    conversation::{decorate_question, ContextOverflow, ContextTrim, Conversation},
+    export::{self, ExportFormat},
+    file_versions::{self, FileVersion},
+    interrupt::Stoppable,
+    messages::{ChatMessage, Feedback, Image, Rating, Role},
+    projects,
+    redaction::{self, SensitiveKind},
+    render::{render_text, RenderOptions, ResponseRenderer},
+    response_buffer::{self, ResponseBuffer},
+    response_cache::ResponseCache,
+    storage::{self, StorageFormat},
+    todos,
+    tools::{ToolRegistry, ToolsClient, MAX_TOOL_ROUNDS},
+    utils::{
+        progress_bar, TimestampConfig, IMAGE_EMOJI, INCREASING_TREND_EMOJI, NOTE_EMOJI,
+        QUESTION_MARK_EMOJI, ROBOT_EMOJI, STOPWATCH_EMOJI, SYSTEM_EMOJI,
     },
-    Client,
 };
+use anyhow::{Context, Result};
+use async_openai::types::{CreateChatCompletionRequest, CreateChatCompletionRequestArgs, Usage};
 use chrono::prelude::{DateTime, Local};
 use dialoguer::console::Term;
-use futures::StreamExt;
+use futures::{FutureExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
-use tiktoken_rs::cl100k_base;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+/// Response streamed into history
+struct StreamedResponse {
+    /// content as it's kept in history
+    content: String,
+    /// cut short by the response timeout
+    timed_out: bool,
+    /// stopped with Ctrl-C
+    stopped: bool,
+    /// file holding the whole response when it was too long to keep in memory
+    content_file: Option<PathBuf>,
+}
+
+/// Result of a request made in a background task
+pub struct Pending<T> {
+    handle: tokio::task::JoinHandle<Result<T>>,
+}
+
+/// Title being generated in a background task
+pub type PendingTitle = Pending<String>;
+
+/// Summary being updated in a background task
+pub type PendingSummary = Pending<ConversationSummary>;
+
+impl<T> Pending<T> {
+    /// the result if it's ready, never waits
+    pub fn try_take(&mut self) -> Option<Result<T>> {
+        if !self.handle.is_finished() {
+            return None;
+        }
+        (&mut self.handle)
+            .now_or_never()
+            .map(|joined| joined.context("background task failed")?)
+    }
+
+    pub async fn wait(self) -> Result<T> {
+        self.handle.await.context("background task failed")?
+    }
+
+    pub fn cancel(self) {
+        self.handle.abort();
+    }
+}
+
+/// Short summary of where a conversation stands, pinned above the transcript in `view` and `history`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConversationSummary {
+    pub text: String,
+    /// messages of the history the summary covers
+    pub messages: usize,
+}
+
+/// Progressive summaries updated in the background during a chat
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SummaryConfig {
+    /// user turns between updates, 0 turns summaries off
+    #[serde(default = "default_summary_turns")]
+    pub every_turns: usize,
+    /// cheaper model used for summaries, the conversation's model when not set
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+impl Default for SummaryConfig {
+    fn default() -> Self {
+        Self {
+            every_turns: default_summary_turns(),
+            model: None,
+        }
+    }
+}
+
+fn default_summary_turns() -> usize {
+    4
+}
+
+/// Optional parameters applied to every completion request
+#[derive(Debug, Clone, Default)]
+pub struct CompletionParameters {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    /// longest answer in tokens
+    pub max_tokens: Option<u16>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    /// abort the response after this long
+    pub response_timeout: Option<Duration>,
+    pub response_cache: Option<ResponseCache>,
+    /// added before every user message when it's sent, never saved in history
+    pub question_prefix: Option<String>,
+    /// added after every user message when it's sent, never saved in history
+    pub question_suffix: Option<String>,
+    /// how room is made when the context window fills up
+    pub context_overflow: ContextOverflow,
+    /// bias per token id, see `conversation::logit_bias`
+    pub logit_bias: HashMap<String, serde_json::Value>,
+    /// ask for source markers on claims from attached files, the instructions are added
+    /// to the system prompt when it's sent and never saved
+    pub citations: bool,
+}
+
+/// Sampling parameter that can be tuned during a conversation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplingParameter {
+    Temperature,
+    TopP,
+    MaxTokens,
+    PresencePenalty,
+    FrequencyPenalty,
+}
+
+impl SamplingParameter {
+    pub const ALL: [SamplingParameter; 5] = [
+        SamplingParameter::Temperature,
+        SamplingParameter::TopP,
+        SamplingParameter::MaxTokens,
+        SamplingParameter::PresencePenalty,
+        SamplingParameter::FrequencyPenalty,
+    ];
+
+    /// name used by the API
+    pub fn name(&self) -> &'static str {
+        match self {
+            SamplingParameter::Temperature => "temperature",
+            SamplingParameter::TopP => "top_p",
+            SamplingParameter::MaxTokens => "max_tokens",
+            SamplingParameter::PresencePenalty => "presence_penalty",
+            SamplingParameter::FrequencyPenalty => "frequency_penalty",
+        }
+    }
+
+    /// lowest and highest value the API accepts
+    pub fn range(&self) -> (f32, f32) {
+        match self {
+            SamplingParameter::Temperature => (0.0, 2.0),
+            SamplingParameter::TopP => (0.0, 1.0),
+            SamplingParameter::MaxTokens => (1.0, u16::MAX as f32),
+            SamplingParameter::PresencePenalty | SamplingParameter::FrequencyPenalty => (-2.0, 2.0),
+        }
+    }
+}
+
+impl CompletionParameters {
+    /// current value of a sampling parameter, None uses the API default
+    pub fn sampling_parameter(&self, parameter: SamplingParameter) -> Option<f32> {
+        match parameter {
+            SamplingParameter::Temperature => self.temperature,
+            SamplingParameter::TopP => self.top_p,
+            SamplingParameter::MaxTokens => self.max_tokens.map(f32::from),
+            SamplingParameter::PresencePenalty => self.presence_penalty,
+            SamplingParameter::FrequencyPenalty => self.frequency_penalty,
+        }
+    }
+
+    /// change a sampling parameter for the following requests
+    pub fn set_sampling_parameter(&mut self, parameter: SamplingParameter, value: Option<f32>) {
+        match parameter {
+            SamplingParameter::Temperature => self.temperature = value,
+            SamplingParameter::TopP => self.top_p = value,
+            SamplingParameter::MaxTokens => self.max_tokens = value.map(|value| value as u16),
+            SamplingParameter::PresencePenalty => self.presence_penalty = value,
+            SamplingParameter::FrequencyPenalty => self.frequency_penalty = value,
+        }
+    }
+
+    /// user message as it's sent to the model
+    fn decorate_question(&self, question: &str) -> String {
+        decorate_question(
+            question,
+            self.question_prefix.as_deref(),
+            self.question_suffix.as_deref(),
+        )
+    }
+}
+
+fn cached_response(response_cache: Option<&ResponseCache>, key: &str) -> Result<Option<String>> {
+    let cached = match response_cache {
+        Some(response_cache) => response_cache.get(key)?,
+        None => None,
+    };
+    tracing::Span::current().record("cached", cached.is_some());
+    Ok(cached)
+}
+
+#[tracing::instrument(skip_all, fields(model = %request.model, cached))]
+async fn request_title(
+    client: &ChatClient,
+    request: CreateChatCompletionRequest,
+    response_cache: Option<&ResponseCache>,
+) -> Result<String> {
+    let cache_key = ResponseCache::key(&request)?;
+    if let Some(title) = cached_response(response_cache, &cache_key)? {
+        return Ok(title);
+    }
+
+    let response = client.create(request).await?;
+
+    // models sometimes quote the title or explain it on the following lines
+    let title = response
+        .choices
+        .first()
+        .context("No first choice on response")?
+        .message
+        .content
+        .trim()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim_matches(|character: char| character.is_whitespace() || "\"'`.".contains(character))
+        .to_owned();
+    if let Some(response_cache) = response_cache {
+        response_cache.put(&cache_key, &title)?;
+    }
+    Ok(title)
+}
+
+/// title part of a file named `<start time>_<title>.<extension>`, None for untitled conversations
+fn saved_title(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?;
+    let (_, title) = file_name.split_once('_')?;
+    let title = title.split('.').next()?;
+    (!title.is_empty()).then(|| title.to_owned())
+}
+
+/// put token counts reported by the API on the span of the current request
+fn record_usage(usage: Option<&Usage>) {
+    if let Some(usage) = usage {
+        let span = tracing::Span::current();
+        span.record("prompt_tokens", usage.prompt_tokens);
+        span.record("completion_tokens", usage.completion_tokens);
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ModelMetadata {
     pub name: String,
     pub token_limit: u32,
+    #[serde(default)]
+    pub pricing: Option<ModelPricing>,
+    /// accepts images from /image and --image
+    #[serde(default)]
+    pub vision: bool,
+}
+
+/// Price in USD per million tokens
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct ModelPricing {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl ModelPricing {
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.prompt
+            + usage.completion_tokens as f64 * self.completion)
+            / 1_000_000.0
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AssistantMetadata {
+    /// persona name, empty for conversations saved before personas had names
+    #[serde(default)]
+    pub name: String,
     pub system_prompt: String,
+    /// exchanges new conversations start with
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<ExampleExchange>,
 }
 
 impl AssistantMetadata {
-    pub fn new(system_prompt: String) -> Self {
-        Self { system_prompt }
+    pub fn new(name: &str, system_prompt: String) -> Self {
+        Self {
+            name: name.to_owned(),
+            system_prompt,
+            examples: vec![],
+        }
+    }
+
+    /// system prompt followed by the example exchanges
+    fn conversation(&self) -> Conversation {
+        let mut conversation = Conversation::new(self.system_prompt.clone());
+        conversation.set_examples(self.example_messages());
+        conversation
+    }
+
+    fn example_messages(&self) -> Vec<ChatMessage> {
+        self.examples
+            .iter()
+            .flat_map(|example| {
+                [
+                    ChatMessage::new(Role::User, example.user.clone()),
+                    ChatMessage::new(Role::Assistant, example.assistant.clone()),
+                ]
+            })
+            .map(|mut message| {
+                message.example = true;
+                message
+            })
+            .collect()
     }
 }
 
+/// Question and answer showing the model how to respond
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ExampleExchange {
+    pub user: String,
+    pub assistant: String,
+}
+
+/// Server-side thread mirroring this conversation in the assistants backend
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssistantThread {
+    assistant_id: String,
+    thread_id: String,
+    /// how many messages of the local history the thread contains
+    synced_messages: usize,
+}
+
 /// Manager for conversations
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatHistory {
-    history: Vec<ChatCompletionRequestMessage>,
+    /// stays the same when the conversation is renamed, conversations saved without one get a new one
+    #[serde(default = "storage::new_conversation_id")]
+    id: String,
+    #[serde(rename = "history")]
+    conversation: Conversation,
     token_usage: Option<Usage>,
     conversation_start: Option<DateTime<Local>>,
     conversation_title: Option<String>,
     model_metadata: ModelMetadata,
     assistant_metadata: AssistantMetadata,
+    /// everything typed into the prompt during this conversation
+    #[serde(default)]
+    input_history: Vec<String>,
+    /// only set for conversations using the assistants backend
+    #[serde(default)]
+    assistant_thread: Option<AssistantThread>,
+    /// labels for finding the conversation later
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// gpg key the saved file is encrypted for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted_for: Option<String>,
+    /// directory the conversation was started in, ties it to the project it's about
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    working_directory: Option<PathBuf>,
+    /// project namespace the conversation is saved under
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    project: Option<String>,
+    /// where the conversation stands, see `spawn_summary`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    summary: Option<ConversationSummary>,
+    /// name put on new user messages, whoever resumes the conversation sets their own
+    #[serde(skip)]
+    user_name: Option<String>,
+    /// images sent with the next user message
+    #[serde(skip)]
+    pending_images: Vec<Image>,
+    /// versions of the attached files, recorded on the next user message
+    #[serde(skip)]
+    pending_files: Vec<FileVersion>,
+    /// model picked by --auto-model for the current turn, the saved model stays as it was
+    #[serde(skip)]
+    turn_model: Option<ModelMetadata>,
+    /// why the last answer ended as reported by the API
+    #[serde(skip)]
+    finish_reason: Option<String>,
+    /// loaded conversations keep the format of their file
+    #[serde(skip)]
+    storage_format: StorageFormat,
+    /// file the conversation was loaded from or first saved to, later saves write there
+    #[serde(skip)]
+    saved_path: Option<PathBuf>,
 }
 
 impl ChatHistory {
@@ -50,21 +418,229 @@ impl ChatHistory {
         model_metadata: ModelMetadata,
         assistant_metadata: AssistantMetadata,
     ) -> anyhow::Result<Self> {
-        let history = vec![ChatCompletionRequestMessageArgs::default()
-            .content(assistant_metadata.system_prompt.clone())
-            .role(Role::System)
-            .build()?];
         let dt: DateTime<Local> = Local::now();
         Ok(Self {
-            history,
+            id: storage::new_conversation_id(),
+            conversation: assistant_metadata.conversation(),
             token_usage: None,
             conversation_start: Some(dt),
             conversation_title: None,
             model_metadata,
             assistant_metadata,
+            input_history: vec![],
+            assistant_thread: None,
+            tags: vec![],
+            encrypted_for: storage::profile_recipient(),
+            working_directory: std::env::current_dir().ok(),
+            project: None,
+            summary: None,
+            user_name: None,
+            pending_images: vec![],
+            pending_files: vec![],
+            turn_model: None,
+            finish_reason: None,
+            storage_format: StorageFormat::default(),
+            saved_path: None,
         })
     }
 
+    /// conversation made of messages exported elsewhere
+    ///
+    /// a leading system message becomes the system prompt
+    pub fn from_messages(
+        model_metadata: ModelMetadata,
+        persona: &str,
+        mut messages: Vec<ChatMessage>,
+    ) -> anyhow::Result<Self> {
+        let system_prompt = messages
+            .first()
+            .filter(|message| message.role.is_instruction())
+            .map(|message| message.content.clone());
+        let mut chat_history = Self::new(
+            model_metadata,
+            AssistantMetadata::new(persona, system_prompt.clone().unwrap_or_default()),
+        )?;
+        if system_prompt.is_none() {
+            messages.insert(0, ChatMessage::new(Role::System, String::new()));
+        }
+        chat_history.conversation = Conversation::from_messages(messages);
+        Ok(chat_history)
+    }
+
+    /// start over with the same model and persona
+    ///
+    /// the conversation so far stays in its file, the next save starts a new one
+    pub fn clear(&mut self) {
+        self.id = storage::new_conversation_id();
+        self.saved_path = None;
+        self.conversation = self.assistant_metadata.conversation();
+        self.token_usage = None;
+        self.conversation_start = Some(Local::now());
+        self.conversation_title = None;
+        self.input_history.clear();
+        self.assistant_thread = None;
+        self.tags.clear();
+        self.encrypted_for = storage::profile_recipient();
+        self.working_directory = std::env::current_dir().ok();
+        self.summary = None;
+        self.pending_images.clear();
+        self.pending_files.clear();
+    }
+
+    /// name shown for the following user messages in shared transcripts
+    pub fn set_user_name(&mut self, user_name: Option<String>) {
+        self.user_name = user_name;
+    }
+
+    /// how the conversation is written by the following saves
+    pub fn set_storage_format(&mut self, storage_format: StorageFormat) {
+        self.storage_format = storage_format;
+    }
+
+    /// encrypt the following saves for this gpg key
+    ///
+    /// they go to a new file with the encrypted extension, the plain one is left to the caller
+    pub fn encrypt_for(&mut self, recipient: String) {
+        self.encrypted_for = Some(recipient);
+        self.saved_path = None;
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted_for.is_some()
+    }
+
+    /// kinds of secrets and personal data in the messages from `start` on
+    pub fn sensitive_data(&self, start: usize) -> Vec<SensitiveKind> {
+        let mut kinds = vec![];
+        for message in self.messages().iter().skip(start) {
+            for kind in redaction::detect(&message.content) {
+                if !kinds.contains(&kind) {
+                    kinds.push(kind);
+                }
+            }
+        }
+        kinds
+    }
+
+    /// replace secrets and personal data with placeholders, also in the questions typed
+    pub fn redact(&mut self) {
+        self.conversation.redact();
+        for line in &mut self.input_history {
+            *line = redaction::redact(line).into_owned();
+        }
+    }
+
+    pub fn input_history(&self) -> &[String] {
+        &self.input_history
+    }
+
+    /// remember a line typed into the prompt so it can be recalled when resuming
+    pub fn record_input(&mut self, line: &str) {
+        self.input_history.push(line.to_owned());
+    }
+
+    pub fn messages(&self) -> &[ChatMessage] {
+        self.conversation.messages()
+    }
+
+    pub fn conversation_title(&self) -> Option<&str> {
+        self.conversation_title.as_deref()
+    }
+
+    /// a saved conversation stays in its file, `rename` moves it
+    pub fn set_conversation_title(&mut self, title: String) {
+        self.conversation_title = Some(title);
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// label the conversation, false when it already has the tag
+    pub fn add_tag(&mut self, tag: &str) -> bool {
+        let tag = normalize_tag(tag);
+        if tag.is_empty() || self.has_tag(&tag) {
+            return false;
+        }
+        self.tags.push(tag);
+        self.tags.sort_unstable();
+        true
+    }
+
+    /// false when the conversation didn't have the tag
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let tag = normalize_tag(tag);
+        let count = self.tags.len();
+        self.tags.retain(|existing| *existing != tag);
+        self.tags.len() != count
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        let tag = normalize_tag(tag);
+        self.tags.contains(&tag)
+    }
+
+    pub fn conversation_start(&self) -> Option<DateTime<Local>> {
+        self.conversation_start
+    }
+
+    pub fn working_directory(&self) -> Option<&Path> {
+        self.working_directory.as_deref()
+    }
+
+    pub fn project(&self) -> Option<&str> {
+        self.project.as_deref()
+    }
+
+    pub fn user_name(&self) -> Option<&str> {
+        self.user_name.as_deref()
+    }
+
+    /// save new files under this project's folder
+    pub fn set_project(&mut self, project: Option<String>) {
+        self.project = project;
+    }
+
+    /// started in this directory or one below it
+    pub fn started_in(&self, directory: &Path) -> bool {
+        self.working_directory
+            .as_deref()
+            .is_some_and(|working_directory| working_directory.starts_with(directory))
+    }
+
+    /// model the next requests go to
+    pub fn model_metadata(&self) -> &ModelMetadata {
+        self.turn_model.as_ref().unwrap_or(&self.model_metadata)
+    }
+
+    /// switch model for the following requests
+    pub fn set_model_metadata(&mut self, model_metadata: ModelMetadata) {
+        self.model_metadata = model_metadata;
+        self.turn_model = None;
+    }
+
+    /// send the requests of this turn to another model without saving it with the conversation
+    pub fn route_turn(&mut self, model_metadata: ModelMetadata) {
+        self.turn_model = Some(model_metadata);
+    }
+
+    pub fn assistant_metadata(&self) -> &AssistantMetadata {
+        &self.assistant_metadata
+    }
+
+    /// replace the system prompt for the following requests
+    ///
+    /// the examples of the old persona are replaced with the new one's
+    pub fn set_persona(&mut self, assistant_metadata: AssistantMetadata) {
+        self.conversation
+            .set_system_prompt(assistant_metadata.system_prompt.clone());
+        self.conversation
+            .set_examples(assistant_metadata.example_messages());
+        self.assistant_metadata = assistant_metadata;
+        // the server-side assistant carries the old instructions
+        self.assistant_thread = None;
+    }
+
     /// Get Usage as reported by the API
     ///
     /// Usage is not reported in streaming mode for some reason
@@ -72,207 +648,1105 @@ impl ChatHistory {
         self.token_usage.clone()
     }
 
+    /// why the last answer ended, e.g. stop or length
+    ///
+    /// answers cut short by the response timeout or Ctrl-C end with timeout or stopped
+    pub fn finish_reason(&self) -> Option<&str> {
+        self.finish_reason.as_deref()
+    }
+
     /// Use local tokenizer library to estimate token usage
     ///
     /// This can be imprecise if we have different tokenization rules than the model
     pub fn count_tokens(&self) -> i64 {
-        // based on this https://github.com/openai/openai-cookbook/blob/main/examples/How_to_count_tokens_with_tiktoken.ipynb
-        // but there some weird hacks because the counts weren't lining up
-
-        // used by gpt-3.5-turbo-0301
-        let bpe = cl100k_base().expect("Failed to load cl100k_base");
-        // Start with -1 because somehow we always had 1 extra token
-        let mut token_count = -1_i64;
-        for message in &self.history {
-            // each message adds 4 tokens
-            // because every message follows <im_start>{role/name}\n{content}<im_end>\n
-            match message.role {
-                Role::User => {
-                    token_count += 4;
-                    if let Some(name) = &message.name {
-                        // example says "if there's a name, the role is omitted"
-                        // but it says "role is always required and always 1 token"
-                        // so I don't know
-                        token_count -= 1;
-                        // add name to count
-                        token_count += bpe.encode_with_special_tokens(name).len() as i64;
-                    }
-                }
-                Role::System => {
-                    token_count += 4;
-                }
-                Role::Assistant => {
-                    // Assistant messages should be primed with <im_start>assistant
-                    // so that'd be 2. But from my testing it looks like there are still 4
-                    token_count += 4;
-                }
-            }
-
-            // add role to count
-            token_count += bpe
-                .encode_with_special_tokens(&message.role.to_string())
-                .len() as i64;
-
-            // add message to count
-            token_count += bpe.encode_with_special_tokens(&message.content).len() as i64;
-        }
-        token_count
+        self.conversation.count_tokens()
     }
 
     /// fun attempt at generating titles for chats
-    /// would be great if this could be async
-    async fn populate_title_if_empty(&mut self, client: &Client) -> Result<()> {
+    ///
+    /// waits for the title, interactive sessions use `spawn_title` instead
+    pub async fn populate_title_if_empty(
+        &mut self,
+        client: &ChatClient,
+        parameters: &CompletionParameters,
+    ) -> Result<()> {
         if self.conversation_title.is_none() {
-            self.populate_title(client).await?;
+            self.generate_title(client, parameters.response_cache.as_ref())
+                .await?;
         }
         Ok(())
     }
 
     /// create a new title for the chat using special ChatGPT query
-    pub async fn populate_title(&mut self, client: &Client) -> Result<()> {
-        let mut history_copy = self.history.clone();
+    pub async fn populate_title(&mut self, client: &ChatClient) -> Result<()> {
+        self.conversation_title = Some(self.generate_new_title(client).await?);
+        Ok(())
+    }
+
+    /// ask for a title without setting it, pass it to `rename` to use it
+    pub async fn generate_new_title(&self, client: &ChatClient) -> Result<String> {
+        // asking for a new title should never return the old one
+        request_title(client, self.title_request()?, None).await
+    }
+
+    /// generate a title for an untitled conversation in a background task
+    ///
+    /// the terminal title is set as soon as it arrives, hand it to `rename` afterwards
+    pub fn spawn_title(
+        &self,
+        client: &ChatClient,
+        parameters: &CompletionParameters,
+        term: &Term,
+    ) -> Result<Option<PendingTitle>> {
+        if self.conversation_title.is_some() {
+            return Ok(None);
+        }
+        let request = self.title_request()?;
+        let client = client.clone();
+        let response_cache = parameters.response_cache.clone();
+        let term = term.clone();
+        let handle = tokio::spawn(async move {
+            let title = request_title(&client, request, response_cache.as_ref()).await?;
+            term.set_title(title.replace('_', " "));
+            Ok(title)
+        });
+        Ok(Some(PendingTitle { handle }))
+    }
+
+    pub fn summary(&self) -> Option<&ConversationSummary> {
+        self.summary.as_ref()
+    }
+
+    pub fn set_summary(&mut self, summary: ConversationSummary) {
+        self.summary = Some(summary);
+    }
+
+    /// update the summary in a background task once `every_turns` questions were asked since the last one
+    ///
+    /// only the previous summary and the new messages are sent
+    pub fn spawn_summary(
+        &self,
+        client: &ChatClient,
+        config: &SummaryConfig,
+    ) -> Result<Option<PendingSummary>> {
+        if config.every_turns == 0 {
+            return Ok(None);
+        }
+        let messages = self.messages();
+        // summarized messages may have been removed to free up context
+        let start = self
+            .summary
+            .as_ref()
+            .map(|summary| summary.messages)
+            .filter(|covered| *covered <= messages.len())
+            .unwrap_or_default();
+        let turns = messages[start..]
+            .iter()
+            .filter(|message| message.role == Role::User)
+            .count();
+        if turns < config.every_turns {
+            return Ok(None);
+        }
+        let previous = self.summary.as_ref().map(|summary| summary.text.as_str());
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(
+                config
+                    .model
+                    .as_deref()
+                    .unwrap_or(&self.model_metadata().name),
+            )
+            .messages([self
+                .conversation
+                .progressive_summary_request(previous, start)
+                .to_request_message()?])
+            .build()?;
+        let covered = messages.len();
+        let client = client.clone();
+        let handle = tokio::spawn(async move {
+            let response = client.create(request).await?;
+            let text = response
+                .choices
+                .first()
+                .context("summary response has no choices")?
+                .message
+                .content
+                .trim()
+                .to_owned();
+            Ok(ConversationSummary {
+                text,
+                messages: covered,
+            })
+        });
+        Ok(Some(PendingSummary { handle }))
+    }
+
+    /// give the conversation a title, a saved conversation moves to the titled file
+    pub fn rename(&mut self, title: String, save: bool) -> Result<()> {
+        self.conversation_title = Some(title);
+        let Some(previous_path) = self.saved_path.take() else {
+            return Ok(());
+        };
+        if !previous_path.exists() {
+            // nothing to move, the next save derives the path again
+            return Ok(());
+        }
+        let titled_path = self.new_file_path()?;
+        if !save || titled_path == previous_path {
+            self.saved_path = Some(previous_path);
+            return Ok(());
+        }
+        self.saved_path = Some(titled_path);
+        self.save_to_file()?;
+        std::fs::remove_file(&previous_path)
+            .with_context(|| format!("failed to remove {}", previous_path.display()))?;
+        Ok(())
+    }
+
+    /// show the title as the terminal window title
+    pub fn show_title(&self, term: &Term) {
+        if let Some(title) = &self.conversation_title {
+            term.set_title(title.replace('_', " "));
+        }
+    }
+
+    async fn generate_title(
+        &mut self,
+        client: &ChatClient,
+        response_cache: Option<&ResponseCache>,
+    ) -> Result<()> {
+        let title = request_title(client, self.title_request()?, response_cache).await?;
+        self.conversation_title = Some(title);
+        Ok(())
+    }
+
+    fn title_request(&self) -> Result<CreateChatCompletionRequest> {
+        let mut history_copy = to_request_messages(self.messages())?;
         let message =
                 "How would you title this conversation up until before this message? Answer in all lowercase with underscores 
 \"_\" between words so that it can be used as a file name. Be concise.";
 
-        let user_message = ChatCompletionRequestMessageArgs::default()
-            .content(message)
-            .role(Role::User)
+        history_copy.push(ChatMessage::new(Role::User, message).to_request_message()?);
+
+        Ok(CreateChatCompletionRequestArgs::default()
+            .model(&self.model_metadata().name)
+            .messages(history_copy)
+            .build()?)
+    }
+
+    /// ask a second model to critique the last response
+    ///
+    /// the critique is only returned and never added to the history
+    #[tracing::instrument(skip_all, fields(model = %critic_model.name, prompt_tokens, completion_tokens))]
+    pub async fn verify_last_response(
+        &self,
+        client: &ChatClient,
+        critic_model: &ModelMetadata,
+    ) -> Result<String> {
+        let answer = self
+            .messages()
+            .last()
+            .filter(|message| matches!(message.role, Role::Assistant))
+            .context("No response to verify")?;
+        let question = self
+            .messages()
+            .iter()
+            .rev()
+            .skip(1)
+            .find(|message| matches!(message.role, Role::User))
+            .map(|message| message.content.as_str())
+            .unwrap_or_default();
+
+        let system_message =
+            "You are a critical reviewer. Check the answer to the question for factual errors, 
+faulty reasoning and unsupported claims. Start with a confidence rating (high, medium or low) 
+followed by at most three short sentences of critique.";
+
+        let messages = to_request_messages(&[
+            ChatMessage::new(Role::System, system_message),
+            ChatMessage::new(
+                Role::User,
+                format!("Question:\n{question}\n\nAnswer:\n{}", answer.content),
+            ),
+        ])?;
+
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(&critic_model.name)
+            .messages(messages)
             .build()?;
 
-        history_copy.push(user_message);
+        let response = client.create(request).await?;
+        record_usage(response.usage.as_ref());
+
+        Ok(response
+            .choices
+            .first()
+            .context("No first choice on response")?
+            .message
+            .content
+            .trim()
+            .to_owned())
+    }
+
+    /// ask the model for the actionable items of the conversation, one per line
+    #[tracing::instrument(skip_all, fields(model = %self.model_metadata().name, prompt_tokens, completion_tokens))]
+    pub async fn extract_todos(&self, client: &ChatClient) -> Result<Vec<String>> {
+        let message = format!(
+            "List the actionable tasks and reminders from this conversation up until before this message. 
+Write one short imperative task per line without numbering or commentary. Answer {} when there are none.",
+            todos::NO_TODOS
+        );
+        let mut messages = to_request_messages(self.messages())?;
+        messages.push(ChatMessage::new(Role::User, message).to_request_message()?);
 
         let request = CreateChatCompletionRequestArgs::default()
-            .model(&self.model_metadata.name)
-            .messages(history_copy)
+            .model(&self.model_metadata().name)
+            .messages(messages)
             .build()?;
+        let response = client.create(request).await?;
+        record_usage(response.usage.as_ref());
+
+        let answer = &response
+            .choices
+            .first()
+            .context("No first choice on response")?
+            .message
+            .content;
+        Ok(todos::parse_todos(answer))
+    }
 
-        let response = client.chat().create(request).await?;
+    /// fenced code blocks of the last answer
+    pub fn last_code_blocks(&self) -> Vec<CodeBlock> {
+        self.messages()
+            .iter()
+            .rev()
+            .find(|message| message.role == Role::Assistant)
+            .map(|message| extract_code_blocks(&message.content))
+            .unwrap_or_default()
+    }
 
-        let title = response.choices[0].message.content.trim().to_owned();
-        self.conversation_title = Some(title);
+    /// drop every message after message `number` as numbered by /history
+    ///
+    /// returns the conversation as it was before, titled so it's saved as a file of its own
+    pub fn rollback(&mut self, number: usize) -> Result<ChatHistory> {
+        if self.assistant_thread.is_some() {
+            anyhow::bail!("Conversations on the assistants backend can't be rolled back");
+        }
+        let last = self.messages().len().saturating_sub(1);
+        if number >= last {
+            anyhow::bail!("Message {number} is already the last one, pick one below {last}");
+        }
+        let mut backup = self.clone();
+        backup.id = storage::new_conversation_id();
+        backup.saved_path = None;
+        backup.conversation_title = Some(format!(
+            "{}_before_rollback_{last}",
+            self.conversation_title.as_deref().unwrap_or("untitled")
+        ));
+        self.conversation.truncate(number + 1);
+        Ok(backup)
+    }
+
+    /// annotate the last message, returns its number as shown by /history
+    pub fn add_note(&mut self, note: &str) -> Option<usize> {
+        let number = self.messages().len().checked_sub(1)?;
+        self.conversation.add_note(number, note);
+        Some(number)
+    }
+
+    /// rate the last answer, returns its number as shown by /history
+    pub fn rate_last_answer(&mut self, rating: Rating, reason: Option<String>) -> Option<usize> {
+        let number = self
+            .messages()
+            .iter()
+            .rposition(|message| message.role == Role::Assistant)?;
+        self.conversation
+            .set_feedback(number, Feedback { rating, reason });
+        Some(number)
+    }
+
+    /// how many answers were rated like this
+    pub fn count_ratings(&self, rating: Rating) -> usize {
+        self.messages()
+            .iter()
+            .filter(|message| {
+                message
+                    .feedback
+                    .as_ref()
+                    .is_some_and(|feedback| feedback.rating == rating)
+            })
+            .count()
+    }
+
+    /// add assistant message to history, used for answers that didn't come from completions
+    pub fn add_assistant_message(&mut self, content: &str) -> Result<()> {
+        self.conversation
+            .push(ChatMessage::new(Role::Assistant, content));
         Ok(())
     }
 
-    /// pop and return the last message in history
-    pub fn pop_last_message(&mut self) -> Option<ChatCompletionRequestMessage> {
-        self.history.pop()
+    /// note in the transcript about a change to the session, sent as a system message
+    pub fn add_system_note(&mut self, note: &str) {
+        self.conversation.push(ChatMessage::new(Role::System, note));
     }
 
-    /// generate next message
-    // maybe temperature and top_p should be part of the struct
-    pub async fn next_message(
+    /// add user message to history without sending it, pending images and file versions go with it
+    pub fn add_user_message(&mut self, user_message: &str) -> Result<()> {
+        let mut user_message = ChatMessage::new(Role::User, user_message);
+        user_message.name = self.user_name.clone();
+        user_message.images = std::mem::take(&mut self.pending_images);
+        user_message.files = std::mem::take(&mut self.pending_files);
+        self.conversation.push(user_message);
+        Ok(())
+    }
+
+    /// take back the last question when it got no answer or is asked again,
+    /// its images and attached files go with the next question instead
+    pub fn unsend_user_message(&mut self) -> Option<String> {
+        if !matches!(self.conversation.messages().last(), Some(message) if message.role == Role::User)
+        {
+            return None;
+        }
+        let message = self.conversation.pop()?;
+        self.pending_images = message.images;
+        self.pending_files = message.files;
+        Some(message.content)
+    }
+
+    /// send an image with the next question
+    pub fn attach_image(&mut self, image: Image) {
+        self.pending_images.push(image);
+    }
+
+    pub fn pending_images(&self) -> &[Image] {
+        &self.pending_images
+    }
+
+    /// record the version of an attached file on the next question
+    pub fn attach_file_version(&mut self, version: FileVersion) {
+        self.pending_files.retain(|pending| pending != &version);
+        self.pending_files.push(version);
+    }
+
+    /// the draft the files were attached to was thrown away
+    pub fn discard_file_versions(&mut self) {
+        self.pending_files.clear();
+    }
+
+    /// version of the same file the conversation attached last
+    pub fn attached_version(&self, version: &FileVersion) -> Option<&FileVersion> {
+        self.conversation
+            .messages()
+            .iter()
+            .flat_map(|message| &message.files)
+            .chain(&self.pending_files)
+            .rfind(|attached| {
+                attached.repository == version.repository && attached.path == version.path
+            })
+    }
+
+    /// files that changed since the conversation last attached them
+    pub fn changed_files(&self) -> Vec<&FileVersion> {
+        let mut latest: Vec<&FileVersion> = vec![];
+        for version in self
+            .conversation
+            .messages()
+            .iter()
+            .flat_map(|message| &message.files)
+        {
+            latest.retain(|attached| {
+                attached.repository != version.repository || attached.path != version.path
+            });
+            latest.push(version);
+        }
+        latest.retain(|version| file_versions::has_changed(version) == Some(true));
+        latest
+    }
+
+    /// find an earlier user message that is near-identical to this question
+    ///
+    /// returns index of the message in history
+    pub fn find_similar_question(&self, question: &str) -> Option<usize> {
+        self.conversation.find_similar_question(question)
+    }
+
+    /// answer that followed the user message at this index
+    pub fn answer_to(&self, index: usize) -> Option<&str> {
+        self.conversation.answer_to(index)
+    }
+
+    /// estimated tokens of one message including the per message overhead
+    pub fn message_tokens(&self, index: usize) -> usize {
+        self.conversation.message_tokens(index)
+    }
+
+    /// remove messages at these indices, the system prompt is always kept
+    pub fn remove_messages(&mut self, indices: &[usize]) {
+        self.conversation.remove_messages(indices);
+    }
+
+    /// replace the messages at these indices with one summary in place of the first one
+    #[tracing::instrument(skip_all, fields(model = %self.model_metadata().name, messages = indices.len(), prompt_tokens, completion_tokens))]
+    pub async fn summarize_messages(
         &mut self,
-        user_message: &str,
-        client: &Client,
-        temperature: Option<f32>,
-        top_p: Option<f32>,
-    ) -> anyhow::Result<String> {
-        let user_message = ChatCompletionRequestMessageArgs::default()
-            .content(user_message)
-            .role(Role::User)
+        indices: &[usize],
+        client: &ChatClient,
+    ) -> Result<()> {
+        if !indices.iter().any(|index| *index > 0) {
+            return Ok(());
+        }
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(&self.model_metadata().name)
+            .messages([self
+                .conversation
+                .summary_request(indices)
+                .to_request_message()?])
             .build()?;
+        let response = client.create(request).await?;
+        record_usage(response.usage.as_ref());
+        let summary = response
+            .choices
+            .first()
+            .context("summary response has no choices")?
+            .message
+            .content
+            .trim()
+            .to_owned();
+
+        self.conversation.replace_with_summary(indices, &summary);
+        Ok(())
+    }
 
-        self.history.push(user_message);
+    /// make room before `next_tokens` more are sent if the context window would get too full
+    ///
+    /// returns what was done so it can be shown
+    pub async fn fit_context(
+        &mut self,
+        next_tokens: usize,
+        client: &ChatClient,
+        parameters: &CompletionParameters,
+    ) -> Result<Option<String>> {
+        let Some(ContextTrim {
+            used,
+            indices,
+            tokens,
+        }) = self
+            .conversation
+            .context_trim(self.model_metadata().token_limit, next_tokens)
+        else {
+            return Ok(None);
+        };
+        let action = match parameters.context_overflow {
+            ContextOverflow::Error => anyhow::bail!(
+                "Conversation would use {used}/{} tokens, free up context with /trim",
+                self.model_metadata().token_limit
+            ),
+            ContextOverflow::Truncate => {
+                self.remove_messages(&indices);
+                "Dropped"
+            }
+            ContextOverflow::Summarize => {
+                self.summarize_messages(&indices, client).await?;
+                "Summarized"
+            }
+        };
+        Ok(Some(format!(
+            "{action} {} oldest messages ({tokens} tokens) to stay within the context window",
+            indices.len()
+        )))
+    }
+
+    /// pop and return the last message in history
+    pub fn pop_last_message(&mut self) -> Option<ChatMessage> {
+        self.conversation.pop()
+    }
 
+    /// history as it's sent with questions decorated
+    fn request_history(&self, parameters: &CompletionParameters) -> Vec<ChatMessage> {
+        let mut messages = self.conversation.request_messages(
+            parameters.question_prefix.as_deref(),
+            parameters.question_suffix.as_deref(),
+        );
+        if parameters.citations {
+            if let Some(system_message) = messages
+                .first_mut()
+                .filter(|message| message.role.is_instruction())
+            {
+                system_message.content = format!(
+                    "{}\n\n{CITATION_INSTRUCTIONS}",
+                    system_message.content.trim_end()
+                );
+            }
+        }
+        messages
+    }
+
+    /// images of the request messages, models without vision don't get any
+    fn request_images(&self) -> MessageImages {
+        if !self.model_metadata().vision {
+            return vec![];
+        }
+        self.conversation
+            .messages()
+            .iter()
+            .enumerate()
+            .filter(|(_, message)| !message.images.is_empty())
+            .map(|(index, message)| (index, message.images.clone()))
+            .collect()
+    }
+
+    fn build_request(
+        &self,
+        parameters: &CompletionParameters,
+    ) -> Result<CreateChatCompletionRequest> {
         // request builder setup is a bit more complicated because of the optional parameters
         let mut request_builder = CreateChatCompletionRequestArgs::default();
 
         request_builder
-            .model(&self.model_metadata.name)
-            .messages(self.history.clone());
+            .model(&self.model_metadata().name)
+            .messages(to_request_messages(&self.request_history(parameters))?);
 
-        if let Some(temperature) = temperature {
+        if let Some(temperature) = parameters.temperature {
             request_builder.temperature(temperature);
         }
 
-        if let Some(top_p) = top_p {
+        if let Some(top_p) = parameters.top_p {
             request_builder.top_p(top_p);
         }
 
-        let request = request_builder.build()?;
-
-        let response = client.chat().create(request).await?;
+        if let Some(max_tokens) = parameters.max_tokens {
+            request_builder.max_tokens(max_tokens);
+        }
 
-        let added_response = ChatCompletionRequestMessageArgs::default()
-            .content(response.choices[0].message.content.clone())
-            .role(response.choices[0].message.role.clone())
-            .build()?;
+        if let Some(presence_penalty) = parameters.presence_penalty {
+            request_builder.presence_penalty(presence_penalty);
+        }
 
-        self.history.push(added_response);
-        self.token_usage = response.usage;
+        if let Some(frequency_penalty) = parameters.frequency_penalty {
+            request_builder.frequency_penalty(frequency_penalty);
+        }
 
-        self.populate_title_if_empty(client).await?;
+        if !parameters.logit_bias.is_empty() {
+            request_builder.logit_bias(parameters.logit_bias.clone());
+        }
 
-        Ok(response.choices[0].message.content.clone())
+        Ok(request_builder.build()?)
     }
 
-    /// stream next message to terminal
-    // maybe temperature and top_p should be part of the struct
-    pub async fn next_message_stream_stdout(
+    /// generate next message
+    ///
+    /// the conversation isn't titled here, see `spawn_title` and `populate_title_if_empty`
+    #[tracing::instrument(skip_all, fields(model = %self.model_metadata().name, estimated_tokens, prompt_tokens, completion_tokens, cached))]
+    pub async fn next_message(
         &mut self,
         user_message: &str,
-        client: &Client,
-        term: &Term,
-        temperature: Option<f32>,
-        top_p: Option<f32>,
+        client: &ChatClient,
+        parameters: &CompletionParameters,
     ) -> anyhow::Result<String> {
-        // this probably shouldn't leak abstraction to terminal
-        // but until I have a use case where the abstriction helps this is okay....ish
-        let user_message = ChatCompletionRequestMessageArgs::default()
-            .content(user_message)
-            .role(Role::User)
-            .build()?;
+        self.add_user_message(user_message)?;
+
+        let request = self.build_request(parameters)?;
+        let images = self.request_images();
+        tracing::Span::current().record("estimated_tokens", self.count_tokens());
+
+        // the key only covers text so answers about images aren't cached
+        let response_cache = parameters
+            .response_cache
+            .as_ref()
+            .filter(|_| images.is_empty());
+        let cache_key = ResponseCache::key(&request)?;
+        if let Some(content) = cached_response(response_cache, &cache_key)? {
+            self.add_assistant_message(&content)?;
+            self.finish_reason = Some(String::from("stop"));
+            return Ok(content);
+        }
 
-        self.history.push(user_message);
+        let response = client.create_with_images(request, images);
+        let response = if let Some(response_timeout) = parameters.response_timeout {
+            tokio::time::timeout(response_timeout, response)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(anyhow::anyhow!(
+                        "Response timed out after {} seconds",
+                        response_timeout.as_secs()
+                    ))
+                })
+        } else {
+            response.await
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(error) => {
+                // nothing arrived so keep history as it was before the question
+                self.unsend_user_message();
+                return Err(error);
+            }
+        };
+
+        let Some(choice) = response.choices.first() else {
+            self.unsend_user_message();
+            anyhow::bail!("No first choice on response");
+        };
+        self.conversation.push(ChatMessage::new(
+            role_from_api(choice.message.role.clone()),
+            choice.message.content.clone(),
+        ));
+        self.token_usage = response.usage;
+        self.finish_reason = choice.finish_reason.clone();
+        record_usage(self.token_usage.as_ref());
 
-        // request builder setup is a bit more complicated because of the optional parameters
-        let mut request_builder = CreateChatCompletionRequestArgs::default();
+        if let Some(response_cache) = response_cache {
+            response_cache.put(&cache_key, &choice.message.content)?;
+        }
 
-        request_builder
-            .model(&self.model_metadata.name)
-            .messages(self.history.clone());
+        Ok(choice.message.content.clone())
+    }
 
-        if let Some(temperature) = temperature {
-            request_builder.temperature(temperature);
+    /// generate next message, running the tools the model calls until it answers
+    ///
+    /// tool calls and their output are kept in history, when no answer arrives
+    /// they are dropped and the question is taken back
+    #[tracing::instrument(skip_all, fields(model = %self.model_metadata().name, estimated_tokens, prompt_tokens, completion_tokens))]
+    pub async fn next_message_tools(
+        &mut self,
+        user_message: &str,
+        tools_client: &ToolsClient,
+        tools: &ToolRegistry,
+        parameters: &CompletionParameters,
+    ) -> anyhow::Result<String> {
+        self.add_user_message(user_message)?;
+        let question_length = self.conversation.messages().len();
+        let response = self.run_tool_rounds(tools_client, tools, parameters).await;
+        if response.is_err() {
+            self.conversation.truncate(question_length);
+            self.unsend_user_message();
         }
+        response
+    }
 
-        if let Some(top_p) = top_p {
-            request_builder.top_p(top_p);
+    /// answer to the last message, see `next_message_tools`
+    async fn run_tool_rounds(
+        &mut self,
+        tools_client: &ToolsClient,
+        tools: &ToolRegistry,
+        parameters: &CompletionParameters,
+    ) -> anyhow::Result<String> {
+        for _ in 0..MAX_TOOL_ROUNDS {
+            tracing::Span::current().record("estimated_tokens", self.count_tokens());
+            let messages = self.request_history(parameters);
+            let completion =
+                tools_client.complete(&self.model_metadata().name, &messages, tools, parameters);
+            let completion = if let Some(response_timeout) = parameters.response_timeout {
+                tokio::time::timeout(response_timeout, completion)
+                    .await
+                    .map_err(|_| {
+                        anyhow::anyhow!(
+                            "Response timed out after {} seconds",
+                            response_timeout.as_secs()
+                        )
+                    })??
+            } else {
+                completion.await?
+            };
+            self.token_usage = completion.usage;
+            record_usage(self.token_usage.as_ref());
+
+            let mut message = ChatMessage::new(Role::Assistant, completion.content.clone());
+            message.tool_calls = completion.tool_calls.clone();
+            self.conversation.push(message);
+            if completion.tool_calls.is_empty() {
+                return Ok(completion.content);
+            }
+
+            for tool_call in &completion.tool_calls {
+                let mut message = ChatMessage::new(Role::Tool, tools.call(tool_call).await);
+                message.name = Some(tool_call.name.clone());
+                message.tool_call_id = Some(tool_call.id.clone());
+                self.conversation.push(message);
+            }
+        }
+        anyhow::bail!("Model was still calling tools after {MAX_TOOL_ROUNDS} rounds")
+    }
+
+    /// generate next message using a server-side assistant thread
+    ///
+    /// the local history stays the source of truth, if it diverged from the thread
+    /// (regenerated answers, read-only notes) a new thread is seeded from it
+    #[tracing::instrument(skip_all, fields(model = %self.model_metadata().name, prompt_tokens, completion_tokens))]
+    pub async fn next_message_assistants(
+        &mut self,
+        user_message: &str,
+        client: &ChatClient,
+        assistants_client: &AssistantsClient,
+        parameters: &CompletionParameters,
+    ) -> anyhow::Result<String> {
+        let assistant_id = match &self.assistant_thread {
+            Some(assistant_thread) => assistant_thread.assistant_id.clone(),
+            None => {
+                assistants_client
+                    .create_assistant(
+                        &self.model_metadata().name,
+                        &self.assistant_metadata.name,
+                        &self.assistant_metadata.system_prompt,
+                    )
+                    .await?
+            }
+        };
+
+        let thread_id = match &self.assistant_thread {
+            Some(assistant_thread) if assistant_thread.synced_messages == self.messages().len() => {
+                assistants_client
+                    .add_user_message(
+                        &assistant_thread.thread_id,
+                        &parameters.decorate_question(user_message),
+                    )
+                    .await?;
+                assistant_thread.thread_id.clone()
+            }
+            _ => {
+                // system prompt lives on the assistant so only conversation is seeded
+                let seeded: Vec<(&str, String)> = self
+                    .messages()
+                    .iter()
+                    .filter_map(|message| match message.role {
+                        Role::System | Role::Developer => None,
+                        Role::User => {
+                            Some(("user", parameters.decorate_question(&message.content)))
+                        }
+                        Role::Assistant => Some(("assistant", message.content.clone())),
+                        // threads only take user and assistant messages
+                        Role::Tool | Role::Function => {
+                            Some(("user", message.to_request_message().ok()?.content))
+                        }
+                    })
+                    .chain(std::iter::once((
+                        "user",
+                        parameters.decorate_question(user_message),
+                    )))
+                    .collect();
+                let messages: Vec<_> = seeded
+                    .iter()
+                    .map(|(role, content)| ThreadMessage { role, content })
+                    .collect();
+                assistants_client.create_thread(&messages).await?
+            }
+        };
+        self.add_user_message(user_message)?;
+
+        let run = assistants_client.run_to_completion(
+            &thread_id,
+            &assistant_id,
+            parameters.temperature,
+            parameters.top_p,
+        );
+        let run = if let Some(response_timeout) = parameters.response_timeout {
+            tokio::time::timeout(response_timeout, run)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(anyhow::anyhow!(
+                        "Response timed out after {} seconds",
+                        response_timeout.as_secs()
+                    ))
+                })
+        } else {
+            run.await
+        };
+        let answer = match run {
+            Ok(run) => assistants_client
+                .latest_message(&thread_id)
+                .await
+                .map(|content| (run, content)),
+            Err(error) => Err(error),
+        };
+        let (run, content) = match answer {
+            Ok(answer) => answer,
+            Err(error) => {
+                self.unsend_user_message();
+                // the thread may still get an answer so don't reuse it
+                self.assistant_thread = None;
+                return Err(error);
+            }
+        };
+        self.add_assistant_message(&content)?;
+        if let Some(usage) = run.usage {
+            self.token_usage = Some(Usage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+                total_tokens: usage.total_tokens,
+            });
+        }
+        record_usage(self.token_usage.as_ref());
+        self.assistant_thread = Some(AssistantThread {
+            assistant_id,
+            thread_id,
+            synced_messages: self.messages().len(),
+        });
+
+        let untitled = self.conversation_title.is_none();
+        self.populate_title_if_empty(client, parameters).await?;
+        if let (true, Some(title), Some(assistant_thread)) =
+            (untitled, &self.conversation_title, &self.assistant_thread)
+        {
+            assistants_client
+                .set_thread_title(&assistant_thread.thread_id, title)
+                .await?;
         }
 
-        let request = request_builder.build()?;
+        Ok(content)
+    }
+
+    /// stream response into history, content is passed to `on_content` as it arrives
+    ///
+    /// responses too long to keep in memory continue in a file and stop being passed on,
+    /// cancelling `stop` ends the response and keeps what arrived so far.
+    /// the question is taken back when no answer arrives
+    async fn stream_response(
+        &mut self,
+        user_message: &str,
+        client: &ChatClient,
+        parameters: &CompletionParameters,
+        stop: Option<&CancellationToken>,
+        on_content: impl FnMut(&str) -> Result<()>,
+    ) -> anyhow::Result<StreamedResponse> {
+        self.add_user_message(user_message)?;
+        let response = self
+            .stream_answer(client, parameters, stop, on_content)
+            .await;
+        if response.is_err() {
+            self.unsend_user_message();
+        }
+        response
+    }
 
-        let mut stream = client.chat().create_stream(request).await?;
+    /// answer to the last message, see `stream_response`
+    #[tracing::instrument(skip_all, fields(model = %self.model_metadata().name, estimated_tokens, prompt_tokens, completion_tokens, cached, timed_out, stopped))]
+    async fn stream_answer(
+        &mut self,
+        client: &ChatClient,
+        parameters: &CompletionParameters,
+        stop: Option<&CancellationToken>,
+        mut on_content: impl FnMut(&str) -> Result<()>,
+    ) -> anyhow::Result<StreamedResponse> {
+        let request = self.build_request(parameters)?;
+        let images = self.request_images();
+        tracing::Span::current().record("estimated_tokens", self.count_tokens());
 
         let mut response_role = None;
-        let mut response_content_buffer = String::new();
+        let mut response_content_buffer = ResponseBuffer::default();
+        let mut timed_out = false;
+        let mut stopped = false;
+        let mut finish_reason = None;
+
+        // the key only covers text so answers about images aren't cached
+        let response_cache = parameters
+            .response_cache
+            .as_ref()
+            .filter(|_| images.is_empty());
+        let cache_key = ResponseCache::key(&request)?;
+        if let Some(content) = cached_response(response_cache, &cache_key)? {
+            on_content(&content)?;
+            response_content_buffer.push(&content)?;
+            finish_reason = Some(String::from("stop"));
+        } else {
+            // the deadline covers the whole response, dropping the stream cancels the request
+            let deadline = parameters
+                .response_timeout
+                .map(|response_timeout| Instant::now() + response_timeout);
+
+            // connecting and waiting for the headers count against the deadline too
+            let stream = client.create_stream_with_images(request, images);
+            let stream = match deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline, stream).await {
+                    Ok(stream) => stream,
+                    Err(_) => {
+                        anyhow::bail!(
+                            "Response timed out after {} seconds",
+                            parameters.response_timeout.unwrap_or_default().as_secs()
+                        );
+                    }
+                },
+                None => stream.await,
+            };
+            let mut stream = stream?;
+            let stopped_by_user = async {
+                match stop {
+                    Some(stop) => stop.cancelled().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::pin!(stopped_by_user);
+
+            // For reasons not documented in OpenAI docs / OpenAPI spec, the response of streaming call is different and doesn't include all the same fields.
+            loop {
+                let chunk = async {
+                    match deadline {
+                        Some(deadline) => tokio::time::timeout_at(deadline, stream.next()).await,
+                        None => Ok(stream.next().await),
+                    }
+                };
+                let next = tokio::select! {
+                    chunk = chunk => match chunk {
+                        Ok(next) => next,
+                        Err(_) => {
+                            timed_out = true;
+                            break;
+                        }
+                    },
+                    _ = &mut stopped_by_user => {
+                        stopped = true;
+                        break;
+                    }
+                };
+                let Some(result) = next else {
+                    break;
+                };
+                let response = result?;
+                if let Some(new_usage) = response.usage {
+                    self.token_usage = Some(new_usage);
+                }
 
-        term.hide_cursor()?;
+                // this ignores if there are multiple choices on the answer
+                let choice = response
+                    .choices
+                    .first()
+                    .context("No first choice on response")?;
+                if let Some(reason) = &choice.finish_reason {
+                    finish_reason = Some(reason.clone());
+                }
+                let delta = &choice.delta;
+                // role and content are not guaranteed to be set on all deltas
+
+                if let Some(role) = &delta.role {
+                    response_role = Some(role.clone());
+                }
 
-        // For reasons not documented in OpenAI docs / OpenAPI spec, the response of streaming call is different and doesn't include all the same fields.
-        while let Some(result) = stream.next().await {
-            let response = result?;
-            if let Some(new_usage) = response.usage {
-                self.token_usage = Some(new_usage);
+                if let Some(delta_content) = &delta.content {
+                    if response_content_buffer.spill_file().is_none() {
+                        on_content(delta_content)?;
+                    }
+                    if response_content_buffer.push(delta_content)? {
+                        on_content(
+                            "\n\n[Response is too long to show here, it continues in a file]",
+                        )?;
+                    }
+                }
             }
 
-            // this ignores if there are multiple choices on the answer
-            let delta = &response
-                .choices
-                .first()
-                .context("No first choice on response")?
-                .delta;
-            // role and content are not guaranteed to be set on all deltas
+            // close connection right away in case the stream was aborted
+            drop(stream);
+            record_usage(self.token_usage.as_ref());
+            tracing::Span::current().record("timed_out", timed_out);
+            tracing::Span::current().record("stopped", stopped);
 
-            if let Some(role) = &delta.role {
-                response_role = Some(role.clone());
+            // partial and spilled responses are not worth caching
+            if !timed_out && !stopped && response_content_buffer.spill_file().is_none() {
+                if let Some(response_cache) = response_cache {
+                    response_cache.put(&cache_key, response_content_buffer.content())?;
+                }
             }
+        }
 
-            if let Some(delta_content) = &delta.content {
-                response_content_buffer.push_str(delta_content);
-                term.write_str(delta_content)?;
+        let (content, content_file) = response_content_buffer.finish()?;
+        let mut message = ChatMessage::new(
+            response_role.map(role_from_api).unwrap_or(Role::Assistant),
+            content.clone(),
+        );
+        message.content_file = content_file.clone();
+        message.truncated = timed_out || stopped;
+        self.conversation.push(message);
+        self.finish_reason = if timed_out {
+            Some(String::from("timeout"))
+        } else if stopped {
+            Some(String::from("stopped"))
+        } else {
+            finish_reason
+        };
+
+        Ok(StreamedResponse {
+            content,
+            timed_out,
+            stopped,
+            content_file,
+        })
+    }
+
+    /// stream next message, content is passed to `on_content` as it arrives
+    pub async fn next_message_stream(
+        &mut self,
+        user_message: &str,
+        client: &ChatClient,
+        parameters: &CompletionParameters,
+        on_content: impl FnMut(&str) -> Result<()>,
+    ) -> anyhow::Result<String> {
+        let response = self
+            .stream_response(user_message, client, parameters, None, on_content)
+            .await?;
+        self.populate_title_if_empty(client, parameters).await?;
+        Ok(response.content)
+    }
+
+    /// stream next message to terminal
+    ///
+    /// the conversation isn't titled here so the caller decides whether to wait for it,
+    /// Ctrl-C or typing /stop stops the answer and keeps the part that was already shown
+    pub async fn next_message_stream_stdout(
+        &mut self,
+        user_message: &str,
+        client: &ChatClient,
+        term: &Term,
+        render_options: &RenderOptions,
+        parameters: &CompletionParameters,
+    ) -> anyhow::Result<String> {
+        // this probably shouldn't leak abstraction to terminal
+        // but until I have a use case where the abstriction helps this is okay....ish
+        let mut renderer = ResponseRenderer::new(term, render_options);
+
+        let stoppable = if term.is_term() {
+            Stoppable::new().with_stop_command()
+        } else {
+            Stoppable::new()
+        };
+
+        term.hide_cursor()?;
+
+        let response = self
+            .stream_response(
+                user_message,
+                client,
+                parameters,
+                Some(stoppable.token()),
+                |content| renderer.write(content),
+            )
+            .await
+            .and_then(|response| renderer.finish().map(|_| response));
+        drop(stoppable);
+        // the cursor comes back on errors too, the question was already taken back
+        let response = match response {
+            Ok(response) => response,
+            Err(error) => {
+                term.show_cursor()?;
+                return Err(error);
             }
+        };
+
+        if let Some(content_file) = &response.content_file {
+            term.write_line("\n")?;
+            if term.is_term() {
+                response_buffer::page(content_file)?;
+            }
+            term.write_str(&format!(
+                "{SYSTEM_EMOJI} Whole response saved to {}",
+                content_file.display()
+            ))?;
+        }
+
+        if response.timed_out {
+            term.write_str(&format!(
+                "\n\n{STOPWATCH_EMOJI} Response timed out after {} seconds",
+                parameters.response_timeout.unwrap_or_default().as_secs()
+            ))?;
+        }
+        if response.stopped {
+            term.write_str(&format!("\n\n{SYSTEM_EMOJI} Response stopped"))?;
         }
 
         // empty new line after stream is done
@@ -286,34 +1760,135 @@ impl ChatHistory {
 
         term.show_cursor()?;
 
-        let added_response = ChatCompletionRequestMessageArgs::default()
-            .content(&response_content_buffer)
-            .role(response_role.unwrap_or(Role::Assistant))
-            .build()?;
-
-        self.history.push(added_response);
-
-        self.populate_title_if_empty(client).await?;
+        Ok(response.content)
+    }
 
-        if let Some(title) = &self.conversation_title {
-            term.set_title(title.replace('_', " "));
+    /// print summary of the conversation context to terminal
+    pub fn print_banner(
+        &self,
+        term: &Term,
+        parameters: &CompletionParameters,
+        timestamps: &TimestampConfig,
+    ) -> Result<()> {
+        let persona = if self.assistant_metadata.name.is_empty() {
+            "unknown"
+        } else {
+            self.assistant_metadata.name.as_str()
+        };
+        let format_parameter = |value: Option<f32>| {
+            value
+                .map(|value| value.to_string())
+                .unwrap_or_else(|| String::from("default"))
+        };
+        term.write_line(&format!(
+            "{ROBOT_EMOJI} Model {} ({} tokens), persona {persona}, temperature {}, top_p {}",
+            self.model_metadata().name,
+            self.model_metadata().token_limit,
+            format_parameter(parameters.temperature),
+            format_parameter(parameters.top_p),
+        ))?;
+
+        let started = self
+            .conversation_start
+            .map(|start| timestamps.display(&start))
+            .unwrap_or_else(|| String::from("unknown date"));
+        let restored_messages = self
+            .messages()
+            .iter()
+            .filter(|message| !message.role.is_instruction())
+            .count();
+        match &self.conversation_title {
+            Some(title) => term.write_line(&format!(
+                "{SYSTEM_EMOJI} {} started {started}, restored {restored_messages} messages",
+                title.replace('_', " ")
+            ))?,
+            None if restored_messages > 0 => term.write_line(&format!(
+                "{SYSTEM_EMOJI} Untitled conversation started {started}, restored {restored_messages} messages"
+            ))?,
+            None => term.write_line(&format!("{SYSTEM_EMOJI} New conversation"))?,
         }
-
-        Ok(response_content_buffer)
+        // answers about an older version of the code may no longer apply
+        for version in self.changed_files() {
+            term.write_line(&format!(
+                "{NOTE_EMOJI} {} changed since it was attached as {}",
+                version.path,
+                version.label()
+            ))?;
+        }
+        Ok(())
     }
 
     /// print history of chat to terminal
-    pub fn print_history(&self, term: &Term) -> Result<()> {
+    pub fn print_history(&self, term: &Term, render_options: &RenderOptions) -> Result<()> {
         // this should probably not live here
         term.write_line("---------------------------------")?;
         term.write_line("Conversation so far:")?;
-        for message in &self.history {
-            match message.role {
-                Role::System => term.write_line(&format!("{SYSTEM_EMOJI} System:\n"))?,
-                Role::Assistant => term.write_line(&format!("{ROBOT_EMOJI} ChatGPT:\n"))?,
-                Role::User => term.write_line(&format!("{QUESTION_MARK_EMOJI} User:\n"))?,
+        // numbered for /rollback
+        for (number, message) in self.messages().iter().enumerate() {
+            if message.example {
+                if number == 0 || !self.messages()[number - 1].example {
+                    let examples = self.messages()[number..]
+                        .iter()
+                        .take_while(|message| message.example)
+                        .count();
+                    term.write_line(&format!(
+                        "{NOTE_EMOJI} {number}-{}. {} example messages from persona {}\n",
+                        number + examples - 1,
+                        examples,
+                        self.assistant_metadata.name
+                    ))?;
+                }
+                continue;
+            }
+            let emoji = match message.role {
+                Role::System | Role::Developer | Role::Tool | Role::Function => SYSTEM_EMOJI,
+                Role::Assistant => ROBOT_EMOJI,
+                Role::User => QUESTION_MARK_EMOJI,
+            };
+            match &message.name {
+                Some(name) => term.write_line(&format!(
+                    "{emoji} {number}. {} ({name}):\n",
+                    message.role.label()
+                ))?,
+                None => {
+                    term.write_line(&format!("{emoji} {number}. {}:\n", message.role.label()))?
+                }
+            }
+            if matches!(message.role, Role::Assistant) {
+                term.write_line(&render_text(&message.content, term, render_options))?;
+            } else {
+                term.write_line(&message.content)?;
+            }
+            if let Some(feedback) = &message.feedback {
+                match &feedback.reason {
+                    Some(reason) => term.write_line(&format!(
+                        "{NOTE_EMOJI} Rated {}: {reason}",
+                        feedback.rating.as_str()
+                    ))?,
+                    None => term
+                        .write_line(&format!("{NOTE_EMOJI} Rated {}", feedback.rating.as_str()))?,
+                }
+            }
+            for image in &message.images {
+                term.write_line(&format!("{IMAGE_EMOJI} [image: {}]", image.source))?;
+            }
+            for version in &message.files {
+                let changed = if file_versions::has_changed(version) == Some(true) {
+                    ", changed since"
+                } else {
+                    ""
+                };
+                term.write_line(&format!(
+                    "{NOTE_EMOJI} Attached {}{changed}",
+                    version.label()
+                ))?;
+            }
+            if message.truncated {
+                term.write_line(&format!("{NOTE_EMOJI} Truncated"))?;
+            }
+            for note in &message.notes {
+                term.write_line(&format!("{NOTE_EMOJI} Note: {note}"))?;
             }
-            term.write_line(&message.content)?;
         }
 
         term.write_line("")?;
@@ -327,27 +1902,84 @@ impl ChatHistory {
         Ok(())
     }
 
-    /// save chat history file
-    pub fn save_to_file(&self) -> Result<()> {
+    /// where the conversation is saved
+    ///
+    /// the file it was loaded from or first saved to, for unsaved conversations where the
+    /// first save will write
+    pub fn file_path(&self) -> Result<PathBuf> {
+        match &self.saved_path {
+            Some(saved_path) => Ok(saved_path.clone()),
+            None => self.new_file_path(),
+        }
+    }
+
+    /// file named after start time and title, numbered when another conversation has that name
+    fn new_file_path(&self) -> Result<PathBuf> {
         // TODO(David): Extract this outside
         let project_dirs = get_project_dirs()?;
         let cache_dir = project_dirs.cache_dir();
-
-        std::fs::create_dir_all(cache_dir).context("failed to crate user cache directory")?;
+        let directory = match &self.project {
+            Some(project) => cache_dir
+                .join(projects::PROJECTS_DIR_NAME)
+                .join(projects::project_dir_name(project)),
+            None => cache_dir.to_owned(),
+        };
 
         let time = self
             .conversation_start
             .unwrap_or_else(Local::now)
             .to_rfc3339();
+        let slug = storage::slugify(self.conversation_title.as_deref().unwrap_or_default());
+        let mut extension = self.storage_format.extension().to_owned();
+        if self.encrypted_for.is_some() {
+            extension.push_str(&format!(".{}", storage::ENCRYPTED_EXTENSION));
+        }
+
+        let mut file_path = directory.join(format!("{time}_{slug}.{extension}"));
+        let mut number = 2;
+        while file_path.exists() && !self.is_saved_in(&file_path) {
+            file_path = directory.join(format!("{time}_{slug}_{number}.{extension}"));
+            number += 1;
+        }
+        Ok(file_path)
+    }
 
-        let title = self.conversation_title.as_deref().unwrap_or_default();
-        let file_path = cache_dir.join(format!("{time}_{title}.yaml"));
+    /// whether the file holds this conversation, e.g. saved by an earlier session
+    fn is_saved_in(&self, path: &Path) -> bool {
+        // decrypting would ask for the passphrase just to pick a name
+        !storage::is_encrypted(path)
+            && ChatHistory::load_from_file(path).is_ok_and(|saved| saved.id == self.id)
+    }
+
+    /// save chat history file
+    pub fn save_to_file(&mut self) -> Result<()> {
+        let file_path = self.file_path()?;
+        if let Some(cache_dir) = file_path.parent() {
+            std::fs::create_dir_all(cache_dir).context("failed to crate user cache directory")?;
+        }
 
-        let file = std::fs::File::create(file_path)?;
-        serde_yaml::to_writer(file, &self.history)?;
+        let text = match self.storage_format {
+            StorageFormat::Yaml => serde_yaml::to_string(self)?,
+            StorageFormat::Markdown => storage::to_markdown(self)?,
+        };
+        match &self.encrypted_for {
+            Some(recipient) => storage::encrypt(&file_path, &text, recipient)?,
+            None => std::fs::write(&file_path, text)?,
+        }
+        self.saved_path = Some(file_path);
         Ok(())
     }
 
+    /// transcript for sharing outside of gpt-cli
+    pub fn export(&self, format: ExportFormat, timestamps: &TimestampConfig) -> Result<String> {
+        match format {
+            ExportFormat::Md => Ok(export::to_markdown(self, timestamps)),
+            ExportFormat::Html => Ok(export::to_html(self, timestamps)),
+            ExportFormat::Json => export::to_json(self),
+        }
+    }
+
+    /// conversations without a project and those in every project folder
     pub fn get_all_saved_conversations() -> Result<Vec<PathBuf>> {
         let project_dirs = get_project_dirs()?;
         let cache_dir = project_dirs.cache_dir();
@@ -361,13 +1993,65 @@ impl ChatHistory {
                 files.push(path);
             }
         }
+
+        let projects_dir = cache_dir.join(projects::PROJECTS_DIR_NAME);
+        if projects_dir.is_dir() {
+            for project_dir in std::fs::read_dir(projects_dir)? {
+                let project_dir = project_dir?.path();
+                if !project_dir.is_dir() {
+                    continue;
+                }
+                for entry in std::fs::read_dir(project_dir)? {
+                    let path = entry?.path();
+                    if path.is_file() {
+                        files.push(path);
+                    }
+                }
+            }
+        }
         Ok(files)
     }
 
+    /// titles of saved conversations as they are in the file names, newest first
+    ///
+    /// the files aren't opened so this stays fast with many conversations
+    pub fn saved_titles() -> Result<Vec<String>> {
+        let mut files = Self::get_all_saved_conversations()?;
+        files.sort_by_key(|path| std::cmp::Reverse(path.file_name().map(ToOwned::to_owned)));
+        let mut titles: Vec<String> = vec![];
+        for title in files.iter().filter_map(|path| saved_title(path)) {
+            if !titles.contains(&title) {
+                titles.push(title);
+            }
+        }
+        Ok(titles)
+    }
+
+    /// newest saved conversation with this title
+    pub fn find_saved(title: &str) -> Result<Option<PathBuf>> {
+        let slug = storage::slugify(title);
+        let mut files: Vec<_> = Self::get_all_saved_conversations()?
+            .into_iter()
+            .filter(|path| saved_title(path).as_deref() == Some(slug.as_str()))
+            .collect();
+        files.sort_by_key(|path| path.file_name().map(ToOwned::to_owned));
+        Ok(files.pop())
+    }
+
     /// load from chat history file
     pub fn load_from_file(file_path: &Path) -> anyhow::Result<ChatHistory> {
-        let file = std::fs::File::open(file_path)?;
-        let chat_history: ChatHistory = serde_yaml::from_reader(file)?;
+        let storage_format = StorageFormat::of_path(file_path);
+        let text = if storage::is_encrypted(file_path) {
+            storage::decrypt(file_path)?
+        } else {
+            std::fs::read_to_string(file_path)?
+        };
+        let mut chat_history: ChatHistory = match storage_format {
+            StorageFormat::Yaml => serde_yaml::from_str(&text)?,
+            StorageFormat::Markdown => storage::from_markdown(&text)?,
+        };
+        chat_history.storage_format = storage_format;
+        chat_history.saved_path = Some(file_path.to_owned());
         Ok(chat_history)
     }
 
@@ -375,16 +2059,40 @@ impl ChatHistory {
         format!(
             "{INCREASING_TREND_EMOJI} Estimated usage {}/{} tokens",
             self.count_tokens(),
-            self.model_metadata.token_limit
+            self.model_metadata().token_limit
+        )
+    }
+
+    /// how much of the context window the conversation uses
+    pub fn context_bar(&self) -> String {
+        progress_bar(
+            self.count_tokens(),
+            self.model_metadata().token_limit as i64,
         )
     }
 
     pub fn token_usage_message(&self) -> Option<String> {
         self.token_usage().as_ref().map(|token_usage| {
+            let cost = self
+                .model_metadata()
+                .pricing
+                .map(|pricing| format!(", ${:.4}", pricing.cost(token_usage)))
+                .unwrap_or_default();
             format!(
-                "{INCREASING_TREND_EMOJI} Recorded usage {}/{} tokens",
-                token_usage.total_tokens, self.model_metadata.token_limit
+                "{INCREASING_TREND_EMOJI} Recorded usage {}/{} tokens{cost}",
+                token_usage.total_tokens,
+                self.model_metadata().token_limit
             )
         })
     }
 }
+
+/// tags are lowercase without spaces so `Rust`, `#rust` and `rust ` are the same tag
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim()
+        .trim_start_matches('#')
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+}
@@ -0,0 +1,173 @@
+use crate::{
+    chat_client::ChatClient,
+    chat_manager::{ChatHistory, CompletionParameters},
+    configuration::AppConfig,
+    conversation::count_text_tokens,
+    messages::ChatMessage,
+    projects,
+    response_cache::ResponseCache,
+    utils::{resolve_model, resolve_persona, DEFAULT_SYSTEM_INSTRUCTIONS_KEY, GPT_3_5_MODEL_NAME},
+};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// Conversation driven from Rust code instead of the terminal
+///
+/// uses the config, history files and request handling of the `gpt-cli` binary so saved
+/// conversations can be resumed with `gpt-cli --file <path>` and the other way around
+///
+/// ```no_run
+/// use gpt_cli::ChatSession;
+///
+/// # async fn run() -> anyhow::Result<()> {
+/// let mut session = ChatSession::new()?.with_persona("joi")?;
+/// let answer = session.send("What does #[must_use] do?").await?;
+/// println!("{answer}");
+///
+/// session
+///     .stream("Show an example", |content| {
+///         print!("{content}");
+///         Ok(())
+///     })
+///     .await?;
+/// let path = session.save().await?;
+///
+/// let mut resumed = ChatSession::load(&path)?;
+/// resumed.send("And on traits?").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ChatSession {
+    config: AppConfig,
+    client: ChatClient,
+    chat_history: ChatHistory,
+    parameters: CompletionParameters,
+}
+
+impl ChatSession {
+    /// new conversation with the user's config file
+    pub fn new() -> Result<Self> {
+        Self::with_config(AppConfig::load_user_config()?)
+    }
+
+    /// new conversation with the default model, persona and parameters of `config`
+    pub fn with_config(config: AppConfig) -> Result<Self> {
+        let model = resolve_model(
+            config
+                .default_model
+                .as_deref()
+                .unwrap_or(GPT_3_5_MODEL_NAME),
+            &config.models,
+        );
+        let persona = resolve_persona(
+            config
+                .default_persona
+                .as_deref()
+                .unwrap_or(DEFAULT_SYSTEM_INSTRUCTIONS_KEY),
+            &config.personas,
+        )?;
+        let mut chat_history = ChatHistory::new(model, persona)?;
+        chat_history.set_user_name(config.user_name.clone());
+        chat_history.set_storage_format(config.storage_format);
+        chat_history.set_project(projects::current_project(&config.projects));
+        Self::from_history(config, chat_history)
+    }
+
+    /// resume a conversation saved by `save` or by the `gpt-cli` binary
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::load_with_config(path, AppConfig::load_user_config()?)
+    }
+
+    pub fn load_with_config(path: &Path, config: AppConfig) -> Result<Self> {
+        let mut chat_history = ChatHistory::load_from_file(path)?;
+        chat_history.set_user_name(config.user_name.clone());
+        Self::from_history(config, chat_history)
+    }
+
+    fn from_history(config: AppConfig, chat_history: ChatHistory) -> Result<Self> {
+        let client = ChatClient::new(&config, config.provider)?;
+        let parameters = CompletionParameters {
+            temperature: config.default_temperature,
+            top_p: config.default_top_p,
+            response_cache: if config.response_cache.enabled {
+                Some(ResponseCache::new(&config.response_cache)?)
+            } else {
+                None
+            },
+            question_prefix: config.question_prefix.clone(),
+            question_suffix: config.question_suffix.clone(),
+            context_overflow: config.context_overflow,
+            logit_bias: config.logit_bias()?,
+            ..Default::default()
+        };
+        Ok(Self {
+            config,
+            client,
+            chat_history,
+            parameters,
+        })
+    }
+
+    /// switch to a model by its API name, models from config keep their token limit and pricing
+    pub fn with_model(mut self, name: &str) -> Self {
+        self.chat_history
+            .set_model_metadata(resolve_model(name, &self.config.models));
+        self
+    }
+
+    /// switch to a built in or configured persona
+    pub fn with_persona(mut self, name: &str) -> Result<Self> {
+        self.chat_history
+            .set_persona(resolve_persona(name, &self.config.personas)?);
+        Ok(self)
+    }
+
+    /// temperature, max_tokens and the other parameters of the following requests
+    pub fn parameters_mut(&mut self) -> &mut CompletionParameters {
+        &mut self.parameters
+    }
+
+    /// ask a question and wait for the whole answer
+    pub async fn send(&mut self, message: &str) -> Result<String> {
+        self.chat_history
+            .fit_context(count_text_tokens(message), &self.client, &self.parameters)
+            .await?;
+        self.chat_history
+            .next_message(message, &self.client, &self.parameters)
+            .await
+    }
+
+    /// ask a question, `on_content` gets the answer piece by piece as it arrives
+    ///
+    /// returns the whole answer, an error from `on_content` stops the answer
+    pub async fn stream(
+        &mut self,
+        message: &str,
+        on_content: impl FnMut(&str) -> Result<()>,
+    ) -> Result<String> {
+        self.chat_history
+            .fit_context(count_text_tokens(message), &self.client, &self.parameters)
+            .await?;
+        self.chat_history
+            .next_message_stream(message, &self.client, &self.parameters, on_content)
+            .await
+    }
+
+    /// save where the binary saves conversations, titles the conversation first if it has no title
+    pub async fn save(&mut self) -> Result<PathBuf> {
+        self.chat_history
+            .populate_title_if_empty(&self.client, &self.parameters)
+            .await?;
+        self.chat_history.save_to_file()?;
+        self.chat_history.file_path()
+    }
+
+    pub fn messages(&self) -> &[ChatMessage] {
+        self.chat_history.messages()
+    }
+
+    /// the whole conversation, e.g. for exports or token counts
+    pub fn chat_history(&self) -> &ChatHistory {
+        &self.chat_history
+    }
+}
@@ -0,0 +1,201 @@
+use regex::Regex;
+use std::{collections::HashMap, path::Path, sync::OnceLock};
+
+/// added to the system prompt in citations mode, never saved in history
+pub const CITATION_INSTRUCTIONS: &str = "Attached files have numbered lines. \
+Support every claim drawn from an attached file with a source marker naming the file and lines \
+right after the claim, like [src/main.rs:12-20] or [README.md:3]. Only cite files and lines \
+that were attached and say so when the attached files don't answer the question.";
+
+/// Source marker in an answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Citation {
+    pub path: String,
+    /// first and last cited line, 1-based and inclusive
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Citation {
+    pub fn marker(&self) -> String {
+        if self.start == self.end {
+            format!("{}:{}", self.path, self.start)
+        } else {
+            format!("{}:{}-{}", self.path, self.start, self.end)
+        }
+    }
+}
+
+fn marker_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"\[([^\[\]\s:]+):(\d+)(?:-(\d+))?\]").expect("citation pattern is valid")
+    })
+}
+
+/// source markers in the order they appear, each one once
+pub fn find(answer: &str) -> Vec<Citation> {
+    let mut citations: Vec<Citation> = vec![];
+    for captures in marker_pattern().captures_iter(answer) {
+        let Ok(start) = captures[2].parse() else {
+            continue;
+        };
+        let end = captures
+            .get(3)
+            .and_then(|end| end.as_str().parse().ok())
+            .unwrap_or(start);
+        let citation = Citation {
+            path: captures[1].to_owned(),
+            start,
+            end,
+        };
+        if !citations.contains(&citation) {
+            citations.push(citation);
+        }
+    }
+    citations
+}
+
+/// line numbers the model can cite, starting at the first attached line
+pub fn number_lines(content: &str, first_line: usize) -> String {
+    let last_line = first_line + content.lines().count().saturating_sub(1);
+    let width = last_line.to_string().len();
+    content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| format!("{:>width$}| {line}", first_line + index))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// numbered lines of the files attached in `messages`, keyed by path
+///
+/// attachments start with a `File: <path>` header and their lines are
+/// numbered by [number_lines], so markers are checked against what the
+/// model was shown rather than the file as it is on disk now
+fn attached_lines<'a>(messages: &[&'a str]) -> HashMap<&'a str, HashMap<usize, &'a str>> {
+    let mut attached: HashMap<&str, HashMap<usize, &str>> = HashMap::new();
+    for message in messages {
+        let mut path = None;
+        for line in message.lines() {
+            if let Some(header) = line.strip_prefix("File: ") {
+                let source = header.split(" (").next().unwrap_or(header);
+                path = Some(source);
+                attached.entry(source).or_default();
+                continue;
+            }
+            let Some(path) = path else {
+                continue;
+            };
+            let Some((number, text)) = line.split_once("| ") else {
+                continue;
+            };
+            if let Ok(number) = number.trim_start().parse() {
+                attached.entry(path).or_default().insert(number, text);
+            }
+        }
+    }
+    attached
+}
+
+/// one line per source marker of the answer with the cited text,
+/// markers that don't point into the attached files are flagged
+pub fn sources(answer: &str, messages: &[&str], hyperlinks: bool) -> Vec<String> {
+    let attached = attached_lines(messages);
+    find(answer)
+        .iter()
+        .map(|citation| {
+            let marker = citation.marker();
+            match cited_text(citation, &attached) {
+                Some(text) => {
+                    let path = Path::new(&citation.path).canonicalize();
+                    let marker = match path {
+                        Ok(path) if hyperlinks => format!(
+                            "\x1b]8;;file://{}\x1b\\{marker}\x1b]8;;\x1b\\",
+                            path.display()
+                        ),
+                        _ => marker,
+                    };
+                    format!("  [{marker}] {text}")
+                }
+                None => format!("  [{marker}] not found in the attached files"),
+            }
+        })
+        .collect()
+}
+
+/// first cited line, None when the file wasn't attached or the lines weren't part of it
+fn cited_text(
+    citation: &Citation,
+    attached: &HashMap<&str, HashMap<usize, &str>>,
+) -> Option<String> {
+    if citation.start == 0 || citation.start > citation.end {
+        return None;
+    }
+    let lines = attached.get(citation.path.as_str())?;
+    if !(citation.start..=citation.end).all(|number| lines.contains_key(&number)) {
+        return None;
+    }
+    Some(lines[&citation.start].trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_markers_once() {
+        let citations =
+            find("Parsed in [src/main.rs:12-20], see [README.md:3] and [src/main.rs:12-20]");
+        assert_eq!(
+            citations,
+            vec![
+                Citation {
+                    path: String::from("src/main.rs"),
+                    start: 12,
+                    end: 20,
+                },
+                Citation {
+                    path: String::from("README.md"),
+                    start: 3,
+                    end: 3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_links_and_plain_brackets() {
+        assert!(find("[link](https://example.com) and [1] and [a b:3]").is_empty());
+    }
+
+    #[test]
+    fn numbers_lines_from_the_first_attached_line() {
+        assert_eq!(number_lines("a\nb", 9), " 9| a\n10| b");
+    }
+
+    #[test]
+    fn flags_missing_files() {
+        assert_eq!(
+            sources("[does/not/exist.rs:1]", &[], false),
+            vec![String::from(
+                "  [does/not/exist.rs:1] not found in the attached files"
+            )]
+        );
+    }
+
+    #[test]
+    fn resolves_markers_against_attached_content() {
+        let attachment = format!(
+            "File: src/lib.rs (lines 4-5)\n```rust\n{}\n```",
+            number_lines("fn a() {}\nfn b() {}", 4)
+        );
+        assert_eq!(
+            sources("[src/lib.rs:5] and [src/lib.rs:5-6]", &[&attachment], false),
+            vec![
+                String::from("  [src/lib.rs:5] fn b() {}"),
+                String::from("  [src/lib.rs:5-6] not found in the attached files"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,34 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use dialoguer::console::Term;
+
+/// Puts text on the clipboard of the machine the terminal runs on
+///
+/// the system clipboard is opened on first use and kept open,
+/// on X11 copied text is only available while its owner is alive,
+/// builds without the clipboard feature always copy through the terminal
+#[derive(Default)]
+pub struct Clipboard {
+    #[cfg(feature = "clipboard")]
+    system: Option<arboard::Clipboard>,
+}
+
+impl Clipboard {
+    /// copy the text, returns which clipboard it went to
+    pub fn copy(&mut self, text: &str, term: &Term) -> Result<&'static str> {
+        // over SSH the system clipboard is the one of the remote machine
+        #[cfg(feature = "clipboard")]
+        if std::env::var_os("SSH_CONNECTION").is_none() {
+            if self.system.is_none() {
+                self.system = arboard::Clipboard::new().ok();
+            }
+            if let Some(system) = &mut self.system {
+                system.set_text(text.to_owned())?;
+                return Ok("clipboard");
+            }
+        }
+        // OSC 52, most terminals forward it to the local clipboard
+        term.write_str(&format!("\x1b]52;c;{}\x07", BASE64.encode(text)))?;
+        Ok("terminal clipboard")
+    }
+}
@@ -0,0 +1,52 @@
+/// Fenced code block of a response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    /// info string after the opening fence, e.g. rust
+    pub language: Option<String>,
+    pub code: String,
+}
+
+/// every fenced code block in the text, an unterminated block runs to the end
+pub fn extract_code_blocks(text: &str) -> Vec<CodeBlock> {
+    let mut blocks = vec![];
+    let mut current: Option<(Option<String>, Vec<&str>)> = None;
+    for line in text.lines() {
+        let Some(fence) = line.trim_start().strip_prefix("```") else {
+            if let Some((_, lines)) = &mut current {
+                lines.push(line);
+            }
+            continue;
+        };
+        match current.take() {
+            Some((language, lines)) => blocks.push(CodeBlock {
+                language,
+                code: lines.join("\n") + "\n",
+            }),
+            None => {
+                let language = fence.trim();
+                current = Some(((!language.is_empty()).then(|| language.to_owned()), vec![]));
+            }
+        }
+    }
+    if let Some((language, lines)) = current {
+        blocks.push(CodeBlock {
+            language,
+            code: lines.join("\n") + "\n",
+        });
+    }
+    blocks
+}
+
+/// program and flag that run code of a language given on the command line
+pub fn interpreter(language: Option<&str>) -> Option<(&'static str, &'static str)> {
+    // info strings like `python title="x.py"` only name the language first
+    let language = language?.split_whitespace().next()?.to_lowercase();
+    match language.as_str() {
+        "sh" | "shell" | "bash" | "zsh" | "console" => Some(("sh", "-c")),
+        "python" | "python3" | "py" => Some(("python3", "-c")),
+        "javascript" | "js" | "node" => Some(("node", "-e")),
+        "ruby" | "rb" => Some(("ruby", "-e")),
+        "perl" | "pl" => Some(("perl", "-e")),
+        _ => None,
+    }
+}
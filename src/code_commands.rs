@@ -0,0 +1,535 @@
+use crate::{
+    attachments::{self, AttachmentConfig},
+    chat_client::ChatClient,
+    chat_manager::{ChatHistory, CompletionParameters, ModelMetadata},
+    configuration::AppConfig,
+    conversation::count_text_tokens,
+    line_editor::LineEditor,
+    patch::{self, FilePatch},
+    render::RenderOptions,
+    storage::StorageFormat,
+    utils::{
+        generate_system_instructions, model_names, persona_names, DEFAULT_SYSTEM_INSTRUCTIONS_KEY,
+        ROBOT_EMOJI,
+    },
+};
+use anyhow::{Context, Result};
+use dialoguer::{console::Term, theme::ColorfulTheme};
+use regex::Regex;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// keep the end of long build logs, that's where the errors are
+const MAX_COMMAND_OUTPUT_CHARS: usize = 12_000;
+const MAX_REFERENCED_FILES: usize = 5;
+
+/// One-off conversation used by the code commands
+pub struct CodeSession<'a> {
+    client: &'a ChatClient,
+    term: &'a Term,
+    chat_history: ChatHistory,
+    render_options: RenderOptions,
+    parameters: CompletionParameters,
+    /// format saves are written in, None when saving is off
+    save: Option<StorageFormat>,
+}
+
+impl<'a> CodeSession<'a> {
+    pub fn new(
+        client: &'a ChatClient,
+        term: &'a Term,
+        model_metadata: ModelMetadata,
+        save: Option<StorageFormat>,
+    ) -> Result<Self> {
+        let mut chat_history = ChatHistory::new(
+            model_metadata,
+            generate_system_instructions()[DEFAULT_SYSTEM_INSTRUCTIONS_KEY].clone(),
+        )?;
+        if let Some(storage_format) = save {
+            chat_history.set_storage_format(storage_format);
+        }
+        Ok(Self {
+            client,
+            term,
+            chat_history,
+            render_options: RenderOptions::new(term, false, false),
+            parameters: CompletionParameters::default(),
+            save,
+        })
+    }
+
+    /// stream answer to terminal and return it
+    async fn ask(&mut self, question: &str) -> Result<String> {
+        self.term
+            .write_line(&format!("\n{ROBOT_EMOJI} ChatGPT:\n"))?;
+        let response = self
+            .chat_history
+            .next_message_stream_stdout(
+                question,
+                self.client,
+                self.term,
+                &self.render_options,
+                &self.parameters,
+            )
+            .await?;
+        self.chat_history
+            .populate_title_if_empty(self.client, &self.parameters)
+            .await?;
+        self.chat_history.show_title(self.term);
+        if self.save.is_some() {
+            self.chat_history.save_to_file()?;
+        }
+        Ok(response)
+    }
+
+    /// tokens left for attachments after the question is added
+    fn remaining_tokens(&self) -> i64 {
+        self.chat_history.model_metadata().token_limit as i64 - self.chat_history.count_tokens()
+    }
+}
+
+struct CommandOutput {
+    success: bool,
+    output: String,
+}
+
+fn run_command(command: &[String]) -> Result<CommandOutput> {
+    let (program, arguments) = command.split_first().context("No command to run")?;
+    let output = Command::new(program)
+        .args(arguments)
+        .output()
+        .with_context(|| format!("failed to run {program}"))?;
+
+    // errors usually go to stderr but test runners like to print to stdout
+    let mut combined = String::from_utf8_lossy(&output.stderr).into_owned();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !stdout.trim().is_empty() {
+        combined.push('\n');
+        combined.push_str(&stdout);
+    }
+    if combined.len() > MAX_COMMAND_OUTPUT_CHARS {
+        let mut start = combined.len() - MAX_COMMAND_OUTPUT_CHARS;
+        while !combined.is_char_boundary(start) {
+            start += 1;
+        }
+        combined = format!("[...]\n{}", &combined[start..]);
+    }
+    Ok(CommandOutput {
+        success: output.status.success(),
+        output: combined,
+    })
+}
+
+/// source files mentioned in compiler output like `src/main.rs:12:5`
+fn referenced_files(output: &str) -> Vec<String> {
+    let pattern = Regex::new(r"((?:\.{0,2}/)?[\w.-]+(?:/[\w.-]+)*\.[A-Za-z0-9]+):\d+")
+        .expect("invalid file reference pattern");
+    let mut seen = BTreeSet::new();
+    pattern
+        .captures_iter(output)
+        .filter_map(|captures| captures.get(1))
+        .map(|path| path.as_str().to_owned())
+        // absolute paths usually point into toolchains and dependencies
+        .filter(|path| !Path::new(path).is_absolute() && Path::new(path).is_file())
+        .filter(|path| seen.insert(path.clone()))
+        .take(MAX_REFERENCED_FILES)
+        .collect()
+}
+
+/// attach files referenced in the output as long as they fit in the context
+async fn referenced_attachments(
+    session: &CodeSession<'_>,
+    output: &str,
+    question_tokens: usize,
+) -> Result<Vec<String>> {
+    // leave room for the answer
+    let mut budget = session.remaining_tokens() / 2 - question_tokens as i64;
+    let mut messages = vec![];
+    for path in referenced_files(output) {
+        // the token budget is stricter than the configured size limits anyway
+        let Ok(loaded) = attachments::load_target(&path, &AttachmentConfig::default()).await else {
+            continue;
+        };
+        for attachment in loaded.attachments {
+            if attachment.tokens as i64 > budget {
+                session.term.write_line(&format!(
+                    "Not attaching {}, it doesn't fit in the context",
+                    attachment.label()
+                ))?;
+                continue;
+            }
+            budget -= attachment.tokens as i64;
+            session.term.write_line(&format!(
+                "Attaching {} ({} tokens)",
+                attachment.label(),
+                attachment.tokens
+            ))?;
+            messages.push(attachment.to_message());
+        }
+    }
+    Ok(messages)
+}
+
+/// run a failing command and iterate on fixes with the model
+///
+/// `/apply` applies the diff from the last answer and runs the command again
+pub async fn run_fix(
+    command: &[String],
+    config: &AppConfig,
+    client: &ChatClient,
+    term: &Term,
+    term_theme: &ColorfulTheme,
+    model_metadata: ModelMetadata,
+    save: Option<StorageFormat>,
+) -> Result<()> {
+    let command_line = command.join(" ");
+    let mut session = CodeSession::new(client, term, model_metadata, save)?;
+    let mut line_editor = LineEditor::new(
+        config.edit_mode,
+        &config.shortcuts,
+        config.question_history_size,
+        None,
+        persona_names(&config.personas),
+        model_names(&config.models),
+    )?;
+
+    term.write_line(&format!("Running {command_line}"))?;
+    let mut result = run_command(command)?;
+    if result.success {
+        term.write_line("Command succeeded, nothing to fix")?;
+        return Ok(());
+    }
+
+    let question = format!(
+        "This command failed:\n`{command_line}`\n\nOutput:\n```\n{}\n```",
+        result.output.trim_end()
+    );
+    let attached =
+        referenced_attachments(&session, &result.output, count_text_tokens(&question)).await?;
+    let mut question = std::iter::once(question)
+        .chain(attached)
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    question.push_str(
+        "\n\nExplain the cause in a few sentences and propose a fix as a unified diff \
+in a ```diff block with paths relative to the current directory.",
+    );
+    let mut response = session.ask(&question).await?;
+
+    term.write_line("Use /apply to apply the fix and run the command again, ask follow-up questions or press Ctrl-D to quit")?;
+    loop {
+        let Some(input) = line_editor.read_line("Fix:")? else {
+            return Ok(());
+        };
+        if input.trim().is_empty() {
+            continue;
+        }
+        if input.trim() != "/apply" {
+            response = session.ask(&input).await?;
+            continue;
+        }
+
+        let Some(diff) = patch::extract_diff_blocks(&response).pop() else {
+            term.write_line("Last answer has no diff to apply")?;
+            continue;
+        };
+        let patches = match patch::apply_diff(&diff) {
+            Ok(patches) => patches,
+            Err(error) => {
+                term.write_line(&format!("Can't apply fix: {error}"))?;
+                continue;
+            }
+        };
+        if !patch::confirm_and_write(&patches, term, term_theme)? {
+            continue;
+        }
+
+        term.write_line(&format!("Running {command_line}"))?;
+        result = run_command(command)?;
+        if result.success {
+            term.write_line("Command succeeded")?;
+            return Ok(());
+        }
+        response = session
+            .ask(&format!(
+                "The command still fails after applying the fix:\n```\n{}\n```",
+                result.output.trim_end()
+            ))
+            .await?;
+    }
+}
+
+/// Test conventions of the project a file belongs to
+struct TestFramework {
+    name: &'static str,
+    /// instructions passed to the model
+    conventions: &'static str,
+    test_path: PathBuf,
+}
+
+/// find the project root by walking up from the file and guess the test framework
+fn detect_test_framework(file: &Path) -> Option<TestFramework> {
+    let stem = file.file_stem()?.to_str()?;
+    let extension = file.extension().and_then(|extension| extension.to_str());
+    let directory = file.parent().unwrap_or_else(|| Path::new(""));
+
+    for root in directory.ancestors() {
+        let root_file = |name: &str| root.join(name);
+        if root_file("Cargo.toml").is_file() {
+            return Some(TestFramework {
+                name: "cargo test",
+                conventions: "Write Rust integration tests using #[test] functions and the crate's public API.",
+                test_path: root.join("tests").join(format!("{stem}.rs")),
+            });
+        }
+        if root_file("go.mod").is_file() {
+            return Some(TestFramework {
+                name: "go test",
+                conventions:
+                    "Write table driven Go tests in the same package using the testing package.",
+                test_path: directory.join(format!("{stem}_test.go")),
+            });
+        }
+        if root_file("package.json").is_file() {
+            let package = std::fs::read_to_string(root_file("package.json")).unwrap_or_default();
+            let (name, conventions) = if package.contains("\"vitest\"") {
+                (
+                    "vitest",
+                    "Write tests with vitest using describe, it and expect imported from vitest.",
+                )
+            } else if package.contains("\"mocha\"") {
+                (
+                    "mocha",
+                    "Write mocha tests using describe and it with node's assert module.",
+                )
+            } else {
+                ("jest", "Write jest tests using describe, it and expect.")
+            };
+            let extension = extension.unwrap_or("js");
+            return Some(TestFramework {
+                name,
+                conventions,
+                test_path: directory.join(format!("{stem}.test.{extension}")),
+            });
+        }
+        if [
+            "pyproject.toml",
+            "setup.py",
+            "setup.cfg",
+            "pytest.ini",
+            "requirements.txt",
+        ]
+        .iter()
+        .any(|name| root_file(name).is_file())
+        {
+            return Some(TestFramework {
+                name: "pytest",
+                conventions: "Write pytest tests as plain test_ functions using assert, use fixtures where they help.",
+                test_path: root.join("tests").join(format!("test_{stem}.py")),
+            });
+        }
+    }
+    None
+}
+
+/// contents of the first fenced code block in a response
+fn first_code_block(response: &str) -> Option<String> {
+    let mut lines = response
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("```"));
+    lines.next()?;
+    let block: Vec<_> = lines
+        .take_while(|line| !line.trim_start().starts_with("```"))
+        .collect();
+    Some(block.join("\n") + "\n")
+}
+
+/// ask for unit tests for a file and offer to write them where the project keeps tests
+pub async fn run_gen_tests(
+    file: &Path,
+    function: Option<&str>,
+    client: &ChatClient,
+    term: &Term,
+    term_theme: &ColorfulTheme,
+    model_metadata: ModelMetadata,
+    save: Option<StorageFormat>,
+) -> Result<()> {
+    let attachment =
+        attachments::load_target(&file.display().to_string(), &AttachmentConfig::default())
+            .await?
+            .attachments
+            .pop()
+            .context("Nothing to attach")?;
+    let mut session = CodeSession::new(client, term, model_metadata, save)?;
+    if attachment.tokens as i64 > session.remaining_tokens() / 2 {
+        anyhow::bail!(
+            "{} has {} tokens, attach a line range with /attach instead",
+            attachment.label(),
+            attachment.tokens
+        );
+    }
+
+    let framework = detect_test_framework(file);
+    let target = match function {
+        Some(function) => format!("the function `{function}`"),
+        None => String::from("the public functions"),
+    };
+    let conventions = match &framework {
+        Some(framework) => {
+            term.write_line(&format!("Detected {} project", framework.name))?;
+            framework.conventions
+        }
+        None => "Use the most common test framework for this language.",
+    };
+    let question = format!(
+        "{}\n\nWrite unit tests for {target} in this file. {conventions} \
+Cover edge cases and error paths. Answer with a single code block containing the complete test file.",
+        attachment.to_message()
+    );
+    let response = session.ask(&question).await?;
+
+    let (Some(framework), Some(tests)) = (framework, first_code_block(&response)) else {
+        return Ok(());
+    };
+    let test_file = FilePatch::replace(framework.test_path, tests)?;
+    patch::confirm_and_write(&[test_file], term, term_theme)?;
+    Ok(())
+}
+
+/// comment lines the document command is allowed to add
+fn is_comment_line(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty()
+        || ["//", "#", "/*", "*", "--", "\"\"\"", "'''"]
+            .iter()
+            .any(|prefix| line.starts_with(prefix))
+}
+
+/// add doc comments to public items without touching the code
+pub async fn run_document(
+    file: &Path,
+    client: &ChatClient,
+    term: &Term,
+    term_theme: &ColorfulTheme,
+    model_metadata: ModelMetadata,
+    save: Option<StorageFormat>,
+) -> Result<()> {
+    let attachment =
+        attachments::load_target(&file.display().to_string(), &AttachmentConfig::default())
+            .await?
+            .attachments
+            .pop()
+            .context("Nothing to attach")?;
+    let mut session = CodeSession::new(client, term, model_metadata, save)?;
+    // the whole file comes back so it has to fit twice
+    if attachment.tokens as i64 * 2 > session.remaining_tokens() {
+        anyhow::bail!(
+            "{} has {} tokens, too many to document in one go",
+            attachment.label(),
+            attachment.tokens
+        );
+    }
+
+    let question = format!(
+        "{}\n\nAdd doc comments to every public item in this file that doesn't have one, \
+using the idiomatic doc comment style of the language. Keep existing comments, code and \
+formatting exactly as they are. Answer with a single code block containing the complete file.",
+        attachment.to_message()
+    );
+    let response = session.ask(&question).await?;
+    let Some(documented) = first_code_block(&response) else {
+        term.write_line("Answer has no code block to apply")?;
+        return Ok(());
+    };
+
+    let original = std::fs::read_to_string(file)?;
+    let changes = diffy::create_patch(&original, &documented);
+    let lines = changes.hunks().iter().flat_map(|hunk| hunk.lines());
+    let mut removed = 0;
+    let mut added_code = 0;
+    for line in lines {
+        match line {
+            diffy::Line::Delete(_) => removed += 1,
+            diffy::Line::Insert(line) if !is_comment_line(line) => added_code += 1,
+            _ => {}
+        }
+    }
+    if removed > 0 {
+        term.write_line(&format!(
+            "Answer changed {removed} existing lines, not applying it"
+        ))?;
+        return Ok(());
+    }
+    if added_code > 0 {
+        term.write_line(&format!(
+            "Warning: answer adds {added_code} lines that don't look like comments"
+        ))?;
+    }
+
+    let documented_file = FilePatch::replace(file.to_owned(), documented)?;
+    patch::confirm_and_write(&[documented_file], term, term_theme)?;
+    Ok(())
+}
+
+/// ask for a scoped edit as a unified diff, retry once if it doesn't apply
+pub async fn run_refactor(
+    file: &Path,
+    instruction: &str,
+    client: &ChatClient,
+    term: &Term,
+    term_theme: &ColorfulTheme,
+    model_metadata: ModelMetadata,
+    save: Option<StorageFormat>,
+) -> Result<()> {
+    const ATTEMPTS: usize = 2;
+
+    let attachment =
+        attachments::load_target(&file.display().to_string(), &AttachmentConfig::default())
+            .await?
+            .attachments
+            .pop()
+            .context("Nothing to attach")?;
+    let mut session = CodeSession::new(client, term, model_metadata, save)?;
+    if attachment.tokens as i64 > session.remaining_tokens() / 2 {
+        anyhow::bail!(
+            "{} has {} tokens, attach a line range with /attach instead",
+            attachment.label(),
+            attachment.tokens
+        );
+    }
+
+    let mut question = format!(
+        "{}\n\n{instruction}\n\nOnly make changes needed for this. Answer with nothing but a \
+unified diff against {} in a single ```diff block, with correct hunk headers and enough context \
+lines to apply cleanly.",
+        attachment.to_message(),
+        attachment.source
+    );
+    for _ in 0..ATTEMPTS {
+        let response = session.ask(&question).await?;
+        let error = match patch::extract_diff_blocks(&response).pop() {
+            None => String::from("The answer didn't contain a ```diff block."),
+            Some(diff) => match patch::apply_diff(&diff) {
+                Ok(patches) if patches.iter().all(|patch| same_file(&patch.path, file)) => {
+                    patch::confirm_and_write(&patches, term, term_theme)?;
+                    return Ok(());
+                }
+                Ok(_) => format!("The diff changes files other than {}.", attachment.source),
+                Err(error) => format!("The diff doesn't apply: {error}."),
+            },
+        };
+        term.write_line(&error)?;
+        question = format!("{error} Answer again with only a corrected unified diff.");
+    }
+    anyhow::bail!("Model didn't produce a diff that applies")
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
@@ -0,0 +1,1505 @@
+use crate::{
+    attachments::{self, Attachment, AttachmentConfig},
+    chat_client::ChatClient,
+    chat_manager::{
+        AssistantMetadata, ChatHistory, CompletionParameters, ConversationSummary, PendingSummary,
+        PendingTitle, SamplingParameter,
+    },
+    clipboard::Clipboard,
+    code_blocks::interpreter,
+    configuration::AppConfig,
+    conversation::count_text_tokens,
+    dedup::Deduplicator,
+    export, file_versions, images, injection,
+    interrupt::STOP_COMMAND,
+    line_editor::{self, REGENERATE_COMMAND},
+    messages::{Rating, Role},
+    redaction::{self, SensitiveKind},
+    render::RenderOptions,
+    terminal_capture, todos,
+    utils::{
+        persona_names, resolve_model, resolve_persona, ChatGptModel, GPT_4_VISION_MODEL_NAME,
+        IMAGE_EMOJI, INCREASING_TREND_EMOJI, NOTE_EMOJI, ROBOT_EMOJI, SYSTEM_EMOJI, TAG_EMOJI,
+    },
+    webhooks::{WebhookEvent, Webhooks},
+};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use dialoguer::{console::Term, theme::ColorfulTheme, Confirm, Input, MultiSelect, Select};
+use std::path::PathBuf;
+
+/// screen captures are trimmed to this many tokens
+const SCREEN_TOKEN_BUDGET: usize = 2000;
+/// persona name for system prompts set with /system
+const CUSTOM_PERSONA: &str = "custom";
+
+/// name, arguments and description of every command, listed by /help and offered by the completer
+pub const COMMANDS: &[(&str, &str, &str)] = &[
+    ("/help", "", "Show this list"),
+    ("/history", "", "Print the conversation so far"),
+    ("/retry", "", "Regenerate the last response"),
+    (
+        "/stop",
+        "",
+        "Type while an answer streams to stop it, like Ctrl-C",
+    ),
+    ("/title", "[title]", "Generate a new title or set one"),
+    ("/model", "[name]", "Switch model for the following answers"),
+    ("/system", "[prompt]", "Show or replace the system prompt"),
+    ("/persona", "[name]", "Switch to another system prompt"),
+    (
+        "/params",
+        "",
+        "Tune temperature, top_p, max_tokens and penalties",
+    ),
+    ("/save", "", "Save the conversation now"),
+    ("/good", "[reason]", "Rate the last answer good"),
+    ("/bad", "[reason]", "Rate the last answer bad"),
+    (
+        "/note",
+        "<text>",
+        "Annotate the last message, notes are never sent",
+    ),
+    ("/tag", "[label]", "Tag the conversation or list its tags"),
+    ("/untag", "<label>", "Remove a tag"),
+    (
+        "/copy",
+        "[n]",
+        "Copy the nth code block of the last answer, the first by default",
+    ),
+    (
+        "/code",
+        "<n> [--copy|--save <path>|--run]",
+        "Print, copy, save or run the nth code block of the last answer",
+    ),
+    (
+        "/todos",
+        "",
+        "Extract tasks from the conversation into the task file",
+    ),
+    (
+        "/clear",
+        "",
+        "Start a new conversation with the same model and persona",
+    ),
+    (
+        "/open",
+        "<title>",
+        "Save this conversation and continue a saved one",
+    ),
+    ("/exit", "", "Leave the chat"),
+    (
+        "/readonly",
+        "",
+        "Toggle recording messages without sending them",
+    ),
+    (
+        "/rollback",
+        "<n>",
+        "Drop the messages after message n of /history, a backup is saved",
+    ),
+    (
+        "/trim",
+        "",
+        "Delete or summarize messages to free up context",
+    ),
+    ("/add", "<snippet>", "Add a snippet to the draft"),
+    (
+        "/attach",
+        "<paths, globs or urls>",
+        "Add files or URLs to the draft",
+    ),
+    (
+        "/image",
+        "<path or url>",
+        "Send an image with the next question",
+    ),
+    ("/screen", "", "Add recent terminal output to the draft"),
+    (
+        "/capture",
+        "[pane]",
+        "Add the content of another tmux pane to the draft",
+    ),
+    ("/preview", "", "Show the assembled draft"),
+    ("/edit", "", "Write the next question in $EDITOR"),
+    ("/send", "", "Send the draft"),
+    (
+        "/send-to",
+        "<connector> [transcript]",
+        "Post the last answer or the whole conversation to Slack, Matrix or email",
+    ),
+    ("/discard", "", "Discard the draft"),
+    (
+        "/citations",
+        "",
+        "Toggle asking for [file:line] sources on claims from attached files",
+    ),
+];
+
+/// Command typed at the question prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    History,
+    Retry,
+    /// only does something while an answer streams
+    Stop,
+    /// generate a title or set this one
+    Title(Option<String>),
+    /// pick a model or switch to this one
+    Model(Option<String>),
+    /// show the system prompt or replace it
+    System(Option<String>),
+    Persona(Option<String>),
+    Params,
+    Save,
+    Note(String),
+    /// list the tags or add this one
+    Tag(Option<String>),
+    Untag(String),
+    Rate {
+        rating: Rating,
+        reason: Option<String>,
+    },
+    /// 1 based number of the code block
+    Copy(usize),
+    /// 1 based number of the code block and what to do with it
+    Code {
+        number: usize,
+        action: CodeAction,
+    },
+    Todos,
+    Clear,
+    /// title of the saved conversation to continue
+    Open(String),
+    Exit,
+    ReadOnly,
+    /// number of the last message to keep
+    Rollback(usize),
+    Trim,
+    Add(String),
+    /// files, globs or URLs
+    Attach(Vec<String>),
+    /// path or URL of an image for the next question
+    Image(String),
+    Screen,
+    /// tmux pane to capture, the last one when missing
+    Capture(Option<String>),
+    Preview,
+    /// write the question in an external editor
+    Edit,
+    Send,
+    /// post the last answer or with `transcript` the whole conversation to a connector
+    SendTo {
+        connector: String,
+        transcript: bool,
+    },
+    Discard,
+    Citations,
+}
+
+/// What /code does with a code block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodeAction {
+    Print,
+    Copy,
+    Save(PathBuf),
+    Run,
+}
+
+/// parse a line starting with a command
+///
+/// anything else is None so questions can still start with a path
+pub fn parse(line: &str) -> Option<Result<Command>> {
+    let line = line.trim_end();
+    let (name, raw_argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let argument = Some(raw_argument.trim())
+        .filter(|argument| !argument.is_empty())
+        .map(str::to_owned);
+    let command = match name {
+        "/help" | "/?" => Command::Help,
+        "/history" => Command::History,
+        REGENERATE_COMMAND => Command::Retry,
+        STOP_COMMAND => Command::Stop,
+        "/title" => Command::Title(argument),
+        "/model" => Command::Model(argument),
+        "/system" => Command::System(argument),
+        "/persona" => Command::Persona(argument),
+        "/params" => Command::Params,
+        "/save" => Command::Save,
+        "/copy" => match argument.as_deref().map(str::parse).unwrap_or(Ok(1)) {
+            Ok(number) if number > 0 => Command::Copy(number),
+            _ => return Some(Err(anyhow::anyhow!("Usage: /copy [n]"))),
+        },
+        "/code" => return Some(parse_code(raw_argument)),
+        "/good" => Command::Rate {
+            rating: Rating::Good,
+            reason: argument,
+        },
+        "/bad" => Command::Rate {
+            rating: Rating::Bad,
+            reason: argument,
+        },
+        "/note" => return Some(argument.map(Command::Note).context("Usage: /note <text>")),
+        "/tag" => Command::Tag(argument),
+        "/untag" => {
+            return Some(
+                argument
+                    .map(Command::Untag)
+                    .context("Usage: /untag <label>"),
+            )
+        }
+        "/todos" => Command::Todos,
+        "/clear" => Command::Clear,
+        "/open" => return Some(argument.map(Command::Open).context("Usage: /open <title>")),
+        "/exit" | "/quit" => Command::Exit,
+        "/readonly" => Command::ReadOnly,
+        "/rollback" => match argument.as_deref().map(str::parse) {
+            Some(Ok(number)) => Command::Rollback(number),
+            _ => return Some(Err(anyhow::anyhow!("Usage: /rollback <n>"))),
+        },
+        "/trim" => Command::Trim,
+        // snippets keep their indentation
+        "/add" if !raw_argument.trim().is_empty() => Command::Add(raw_argument.to_owned()),
+        "/add" => return Some(Err(anyhow::anyhow!("Usage: /add <snippet>"))),
+        "/attach" => {
+            return Some(
+                argument
+                    .map(|argument| Command::Attach(attach_targets(&argument)))
+                    .context("Usage: /attach <paths, globs or urls>"),
+            )
+        }
+        "/image" => {
+            return Some(
+                argument
+                    .map(Command::Image)
+                    .context("Usage: /image <path or url>"),
+            )
+        }
+        "/screen" => Command::Screen,
+        "/capture" => Command::Capture(argument),
+        "/preview" => Command::Preview,
+        "/edit" => Command::Edit,
+        "/send" => Command::Send,
+        "/send-to" => {
+            let mut words = raw_argument.split_whitespace();
+            return Some(match (words.next(), words.next(), words.next()) {
+                (Some(connector), transcript @ (None | Some("transcript")), None) => {
+                    Ok(Command::SendTo {
+                        connector: connector.to_owned(),
+                        transcript: transcript.is_some(),
+                    })
+                }
+                _ => Err(anyhow::anyhow!("Usage: /send-to <connector> [transcript]")),
+            });
+        }
+        "/discard" => Command::Discard,
+        "/citations" => Command::Citations,
+        _ => return None,
+    };
+    Some(Ok(command))
+}
+
+fn parse_code(argument: &str) -> Result<Command> {
+    let usage = || anyhow::anyhow!("Usage: /code <n> [--copy|--save <path>|--run]");
+    let mut words = argument.split_whitespace();
+    let number = match words.next().map(str::parse) {
+        Some(Ok(number)) if number > 0 => number,
+        _ => return Err(usage()),
+    };
+    let action = match (words.next(), words.next(), words.next()) {
+        (None, _, _) => CodeAction::Print,
+        (Some("--copy"), None, _) => CodeAction::Copy,
+        (Some("--save"), Some(path), None) => CodeAction::Save(PathBuf::from(path)),
+        (Some("--run"), None, _) => CodeAction::Run,
+        _ => return Err(usage()),
+    };
+    Ok(Command::Code { number, action })
+}
+
+/// targets separated by whitespace unless the whole argument is an existing path with spaces
+fn attach_targets(argument: &str) -> Vec<String> {
+    if std::path::Path::new(argument).exists() {
+        return vec![argument.to_owned()];
+    }
+    argument.split_whitespace().map(str::to_owned).collect()
+}
+
+/// What the chat loop does after a command
+pub enum Outcome {
+    /// ask for the next input
+    Prompt,
+    /// send a question, regenerated questions skip the repeated question check
+    Ask {
+        question: String,
+        regenerate: bool,
+    },
+    Exit,
+}
+
+/// State of an interactive chat that commands act on besides the conversation
+pub struct Session<'a> {
+    pub config: &'a AppConfig,
+    pub client: &'a ChatClient,
+    pub term: &'a Term,
+    pub term_theme: &'a ColorfulTheme,
+    pub render_options: &'a RenderOptions,
+    pub webhooks: &'a Webhooks,
+    pub parameters: CompletionParameters,
+    /// save after every change, off with --no-save
+    pub save: bool,
+    /// input is only recorded while the conversation is read-only
+    pub read_only: bool,
+    /// snippets collected with /add and submitted together with /send
+    pub draft: Vec<String>,
+    /// titles are generated while the next question is typed
+    pub pending_title: Option<PendingTitle>,
+    /// summaries are updated in the background every few turns
+    pub pending_summary: Option<PendingSummary>,
+    /// messages already checked for secrets and personal data
+    pub privacy_checked: usize,
+    pub clipboard: Clipboard,
+}
+
+/// What to do with a conversation that contains sensitive data
+#[derive(Clone, Copy)]
+enum PrivacyAction {
+    Redact,
+    Encrypt,
+    Skip,
+    Save,
+}
+
+impl Session<'_> {
+    pub fn save(&mut self, chat_manager: &mut ChatHistory) -> Result<()> {
+        if self.save && self.config.privacy.enabled && !self.check_privacy(chat_manager)? {
+            self.save = false;
+        }
+        if self.save {
+            chat_manager.save_to_file()?;
+            self.webhooks
+                .notify(WebhookEvent::SessionSaved, chat_manager, None);
+        }
+        Ok(())
+    }
+
+    /// give the conversation a title that arrived in the background
+    pub fn poll_title(&mut self, chat_manager: &mut ChatHistory) -> Result<()> {
+        if let Some(title) = self.pending_title.as_mut().and_then(PendingTitle::try_take) {
+            self.pending_title = None;
+            self.finish_title(chat_manager, title)?;
+        }
+        if let Some(summary) = self
+            .pending_summary
+            .as_mut()
+            .and_then(PendingSummary::try_take)
+        {
+            self.pending_summary = None;
+            self.finish_summary(chat_manager, summary)?;
+        }
+        Ok(())
+    }
+
+    /// update the summary when enough turns passed since the last one,
+    /// read-only conversations keep their summary
+    pub fn update_summary(&mut self, chat_manager: &ChatHistory) -> Result<()> {
+        if self.pending_summary.is_none() && !self.read_only {
+            self.pending_summary = chat_manager.spawn_summary(self.client, &self.config.summary)?;
+        }
+        Ok(())
+    }
+
+    /// wait for the title, the summary and webhooks before the chat ends
+    pub async fn close(&mut self, chat_manager: &mut ChatHistory) -> Result<()> {
+        self.finish_pending(chat_manager).await?;
+        self.webhooks.finish().await;
+        Ok(())
+    }
+
+    /// wait for the title and the summary of this conversation
+    async fn finish_pending(&mut self, chat_manager: &mut ChatHistory) -> Result<()> {
+        if let Some(pending_title) = self.pending_title.take() {
+            let title = pending_title.wait().await;
+            self.finish_title(chat_manager, title)?;
+        }
+        if let Some(pending_summary) = self.pending_summary.take() {
+            let summary = pending_summary.wait().await;
+            self.finish_summary(chat_manager, summary)?;
+            if self.save && chat_manager.file_path()?.exists() {
+                chat_manager.save_to_file()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// ask what to do when new messages contain secrets or personal data,
+    /// returns whether the conversation may be saved
+    fn check_privacy(&mut self, chat_manager: &mut ChatHistory) -> Result<bool> {
+        let kinds = chat_manager.sensitive_data(self.privacy_checked);
+        self.privacy_checked = chat_manager.messages().len();
+        if kinds.is_empty() || chat_manager.is_encrypted() {
+            return Ok(true);
+        }
+        let found: Vec<&str> = kinds.iter().map(SensitiveKind::description).collect();
+        self.term.write_line(&format!(
+            "{SYSTEM_EMOJI} Conversation contains what looks like {}",
+            found.join(", ")
+        ))?;
+
+        let mut actions = vec![("Redact before saving", PrivacyAction::Redact)];
+        if self.config.gpg_recipient().is_some() {
+            actions.push(("Encrypt with gpg", PrivacyAction::Encrypt));
+        }
+        actions.push(("Don't save this session", PrivacyAction::Skip));
+        actions.push(("Save anyway", PrivacyAction::Save));
+        let labels: Vec<&str> = actions.iter().map(|(label, _)| *label).collect();
+        let selection = Select::with_theme(self.term_theme)
+            .with_prompt("Before saving")
+            .items(&labels)
+            .default(0)
+            .interact_on(self.term)?;
+
+        // the file saved before this check only has the plain text
+        let previous_file = chat_manager.file_path()?;
+        match actions[selection].1 {
+            PrivacyAction::Redact => chat_manager.redact(),
+            PrivacyAction::Encrypt => {
+                if let Some(recipient) = self.config.gpg_recipient() {
+                    chat_manager.encrypt_for(recipient.to_owned());
+                }
+            }
+            PrivacyAction::Skip => return Ok(false),
+            PrivacyAction::Save => return Ok(true),
+        }
+        if previous_file.exists() {
+            std::fs::remove_file(&previous_file)
+                .with_context(|| format!("failed to remove {}", previous_file.display()))?;
+        }
+        Ok(true)
+    }
+
+    fn finish_title(&self, chat_manager: &mut ChatHistory, title: Result<String>) -> Result<()> {
+        match title {
+            Ok(title) => chat_manager.rename(title, self.save),
+            // an untitled conversation is still saved, /title can retry
+            Err(error) => {
+                self.term
+                    .write_line(&format!("Failed to generate title: {error}"))?;
+                Ok(())
+            }
+        }
+    }
+
+    /// the summary is saved with the next answer
+    fn finish_summary(
+        &self,
+        chat_manager: &mut ChatHistory,
+        summary: Result<ConversationSummary>,
+    ) -> Result<()> {
+        match summary {
+            Ok(summary) => chat_manager.set_summary(summary),
+            // the next update covers these messages again
+            Err(error) => self
+                .term
+                .write_line(&format!("Failed to update summary: {error}"))?,
+        }
+        Ok(())
+    }
+
+    fn cancel_title(&mut self) {
+        if let Some(pending_title) = self.pending_title.take() {
+            pending_title.cancel();
+        }
+    }
+
+    /// tokens left in the context window once the draft is sent
+    fn remaining_tokens(&self, chat_manager: &ChatHistory) -> i64 {
+        chat_manager.model_metadata().token_limit as i64
+            - chat_manager.count_tokens()
+            - count_text_tokens(&self.draft.join("\n\n")) as i64
+    }
+}
+
+/// answer to commands that would send the conversation while it's read-only
+const READ_ONLY_MESSAGE: &str = "Conversation is read-only. Use /readonly to unlock it";
+
+pub async fn run(
+    command: Command,
+    chat_manager: &mut ChatHistory,
+    session: &mut Session<'_>,
+) -> Result<Outcome> {
+    let term = session.term;
+    match command {
+        Command::Help => {
+            for (name, arguments, description) in COMMANDS {
+                let usage = format!("{name} {arguments}");
+                term.write_line(&format!("  {:<24} {description}", usage.trim_end()))?;
+            }
+        }
+        Command::History => chat_manager.print_history(term, session.render_options)?,
+        Command::Stop => term.write_line(&format!(
+            "{SYSTEM_EMOJI} Nothing is streaming, type {STOP_COMMAND} while an answer streams to stop it"
+        ))?,
+        Command::Retry => {
+            if session.read_only {
+                term.write_line(READ_ONLY_MESSAGE)?;
+                return Ok(Outcome::Prompt);
+            }
+            if !matches!(
+                chat_manager.messages().last(),
+                Some(message) if message.role == Role::Assistant
+            ) {
+                term.write_line("No response to regenerate")?;
+                return Ok(Outcome::Prompt);
+            }
+            _ = chat_manager.pop_last_message();
+            let question = chat_manager.unsend_user_message().unwrap_or_default();
+            return Ok(Outcome::Ask {
+                question,
+                regenerate: true,
+            });
+        }
+        Command::Title(title) => {
+            session.cancel_title();
+            let title = match title {
+                Some(title) => {
+                    let title = title.replace(char::is_whitespace, "_");
+                    if title.contains(std::path::is_separator) {
+                        term.write_line("Title can't contain path separators")?;
+                        return Ok(Outcome::Prompt);
+                    }
+                    title
+                }
+                None if session.read_only => {
+                    term.write_line(READ_ONLY_MESSAGE)?;
+                    return Ok(Outcome::Prompt);
+                }
+                None => chat_manager.generate_new_title(session.client).await?,
+            };
+            chat_manager.rename(title, session.save)?;
+            chat_manager.show_title(term);
+            term.write_line(&format!(
+                "{SYSTEM_EMOJI} Conversation titled {}",
+                chat_manager
+                    .conversation_title()
+                    .unwrap_or_default()
+                    .replace('_', " ")
+            ))?;
+        }
+        Command::Model(name) => {
+            let name = match name {
+                Some(name) => name,
+                None => {
+                    let mut models: Vec<String> = ChatGptModel::value_variants()
+                        .iter()
+                        .map(|model| model.get_model_name().to_owned())
+                        .collect();
+                    for model in &session.config.models {
+                        if !models.contains(&model.name) {
+                            models.push(model.name.clone());
+                        }
+                    }
+                    let current = models
+                        .iter()
+                        .position(|model| model == &chat_manager.model_metadata().name)
+                        .unwrap_or_default();
+                    let selection = Select::with_theme(session.term_theme)
+                        .with_prompt("Select model")
+                        .items(&models)
+                        .default(current)
+                        .interact_on_opt(term)?;
+                    match selection {
+                        Some(selection) => models.swap_remove(selection),
+                        None => return Ok(Outcome::Prompt),
+                    }
+                }
+            };
+            // completion offers the --model spellings like gpt-4-8k
+            let name = ChatGptModel::from_str(&name, true)
+                .map(|model| model.get_model_name().to_owned())
+                .unwrap_or(name);
+            chat_manager.set_model_metadata(resolve_model(&name, &session.config.models));
+            term.write_line(&format!(
+                "{ROBOT_EMOJI} Switched to model {} ({} tokens)",
+                chat_manager.model_metadata().name,
+                chat_manager.model_metadata().token_limit
+            ))?;
+        }
+        Command::System(None) => {
+            let system_prompt = chat_manager
+                .messages()
+                .first()
+                .filter(|message| message.role.is_instruction())
+                .map(|message| message.content.as_str())
+                .unwrap_or_default();
+            term.write_line(&format!(
+                "{SYSTEM_EMOJI} System prompt of persona {}:\n{system_prompt}",
+                chat_manager.assistant_metadata().name
+            ))?;
+        }
+        Command::System(Some(system_prompt)) => {
+            chat_manager.set_persona(AssistantMetadata::new(CUSTOM_PERSONA, system_prompt));
+            term.write_line(&format!("{SYSTEM_EMOJI} System prompt replaced"))?;
+            session.save(chat_manager)?;
+        }
+        Command::Persona(name) => {
+            let config = session.config;
+            let name = match name {
+                Some(name) => name,
+                None => {
+                    let personas = persona_names(&config.personas);
+                    let current = personas
+                        .iter()
+                        .position(|persona| persona == &chat_manager.assistant_metadata().name)
+                        .unwrap_or_default();
+                    let selection = Select::with_theme(session.term_theme)
+                        .with_prompt("Select persona")
+                        .items(&personas)
+                        .default(current)
+                        .interact_on(term)?;
+                    personas[selection].clone()
+                }
+            };
+            match resolve_persona(&name, &config.personas) {
+                Ok(persona) => {
+                    chat_manager.set_persona(persona);
+                    term.write_line(&format!("{ROBOT_EMOJI} Switched to persona {name}"))?;
+                }
+                Err(error) => term.write_line(&error.to_string())?,
+            }
+        }
+        Command::Params => {
+            let changes = tune_parameters(&mut session.parameters, term, session.term_theme)?;
+            if !changes.is_empty() {
+                let note = format!("Sampling parameters changed: {}", changes.join(", "));
+                term.write_line(&format!("{SYSTEM_EMOJI} {note}"))?;
+                chat_manager.add_system_note(&note);
+                session.save(chat_manager)?;
+            }
+        }
+        Command::Save => {
+            if session.config.privacy.enabled && !session.check_privacy(chat_manager)? {
+                term.write_line("Conversation not saved")?;
+                return Ok(Outcome::Prompt);
+            }
+            chat_manager.save_to_file()?;
+            session
+                .webhooks
+                .notify(WebhookEvent::SessionSaved, chat_manager, None);
+            term.write_line(&format!(
+                "{SYSTEM_EMOJI} Saved to {}",
+                chat_manager.file_path()?.display()
+            ))?;
+        }
+        Command::Copy(number) => code_block(number, CodeAction::Copy, chat_manager, session)?,
+        Command::Code { number, action } => code_block(number, action, chat_manager, session)?,
+        Command::Note(note) => match chat_manager.add_note(&note) {
+            Some(number) => {
+                term.write_line(&format!("{NOTE_EMOJI} Note added to message {number}"))?;
+                session.save(chat_manager)?;
+            }
+            None => term.write_line("No message to annotate")?,
+        },
+        Command::Tag(None) if chat_manager.tags().is_empty() => {
+            term.write_line("No tags, add one with /tag <label>")?
+        }
+        Command::Tag(None) => term.write_line(&format!(
+            "{TAG_EMOJI} Tagged {}",
+            chat_manager.tags().join(", ")
+        ))?,
+        Command::Tag(Some(tag)) => {
+            if chat_manager.add_tag(&tag) {
+                term.write_line(&format!(
+                    "{TAG_EMOJI} Tagged {}",
+                    chat_manager.tags().join(", ")
+                ))?;
+                session.save(chat_manager)?;
+            } else {
+                term.write_line(&format!("Already tagged {tag}"))?;
+            }
+        }
+        Command::Untag(tag) => {
+            if chat_manager.remove_tag(&tag) {
+                term.write_line(&format!("{TAG_EMOJI} Removed tag {tag}"))?;
+                session.save(chat_manager)?;
+            } else {
+                term.write_line(&format!("Not tagged {tag}"))?;
+            }
+        }
+        Command::Rate { rating, reason } => match chat_manager.rate_last_answer(rating, reason) {
+            Some(number) => {
+                term.write_line(&format!(
+                    "{NOTE_EMOJI} Answer {number} rated {}",
+                    rating.as_str()
+                ))?;
+                session.save(chat_manager)?;
+            }
+            None => term.write_line("No answer to rate")?,
+        },
+        Command::Todos if session.read_only => term.write_line(READ_ONLY_MESSAGE)?,
+        Command::Todos => {
+            let todos = chat_manager.extract_todos(session.client).await?;
+            let path = todos::todo_file(session.config.todo_file.as_deref())?;
+            let added = todos::append_todos(&path, &todos)?;
+            for todo in &added {
+                term.write_line(&format!("  - {todo}"))?;
+            }
+            term.write_line(&format!(
+                "{SYSTEM_EMOJI} Added {} of {} tasks to {}",
+                added.len(),
+                todos.len(),
+                path.display()
+            ))?;
+        }
+        Command::Clear => {
+            session.cancel_title();
+            if let Some(pending_summary) = session.pending_summary.take() {
+                pending_summary.cancel();
+            }
+            chat_manager.clear();
+            term.set_title("gpt-cli");
+            term.write_line(&format!("{SYSTEM_EMOJI} New conversation"))?;
+        }
+        Command::Open(title) => {
+            let Some(path) = ChatHistory::find_saved(&title)? else {
+                term.write_line(&format!("No saved conversation titled {title}"))?;
+                return Ok(Outcome::Prompt);
+            };
+            session.finish_pending(chat_manager).await?;
+            // nothing to keep when no question was asked yet
+            if chat_manager
+                .messages()
+                .iter()
+                .any(|message| message.role == Role::User)
+            {
+                session.save(chat_manager)?;
+            }
+            let mut opened = ChatHistory::load_from_file(&path)?;
+            opened.set_user_name(chat_manager.user_name().map(str::to_owned));
+            *chat_manager = opened;
+            session.privacy_checked = chat_manager.messages().len();
+            chat_manager.show_title(term);
+            term.write_line(&format!(
+                "{SYSTEM_EMOJI} Opened {} with {} messages",
+                path.display(),
+                chat_manager.messages().len()
+            ))?;
+        }
+        Command::Exit => return Ok(Outcome::Exit),
+        Command::ReadOnly => {
+            session.read_only = !session.read_only;
+            if session.read_only {
+                term.write_line("Conversation is read-only. Messages are recorded but not sent")?;
+            } else {
+                term.write_line("Conversation unlocked. Notes are sent with the next question")?;
+            }
+        }
+        Command::Rollback(number) => {
+            let mut backup = match chat_manager.rollback(number) {
+                Ok(backup) => backup,
+                Err(error) => {
+                    term.write_line(&error.to_string())?;
+                    return Ok(Outcome::Prompt);
+                }
+            };
+            session.privacy_checked = session.privacy_checked.min(chat_manager.messages().len());
+            if session.save {
+                backup.save_to_file()?;
+                term.write_line(&format!(
+                    "Previous conversation kept in {}",
+                    backup.file_path()?.display()
+                ))?;
+            }
+            session.save(chat_manager)?;
+            term.write_line(&format!(
+                "{SYSTEM_EMOJI} Rolled back to message {number}. {}",
+                chat_manager.token_count_message()
+            ))?;
+        }
+        Command::Trim => {
+            // summarizing sends the selected messages, deleting them doesn't
+            let client = (!session.read_only).then_some(session.client);
+            trim_conversation(chat_manager, client, term, session.term_theme).await?;
+            session.save(chat_manager)?;
+        }
+        Command::Add(snippet) => {
+            session.draft.push(snippet);
+            term.write_line(&format!("Added snippet {} to draft", session.draft.len()))?;
+        }
+        Command::Attach(targets) => {
+            attach_to_draft(
+                &targets,
+                chat_manager,
+                &mut session.draft,
+                &session.config.attachments,
+                session.parameters.citations,
+                term,
+                Some(session.term_theme),
+            )
+            .await?;
+        }
+        Command::Image(target) => attach_image(&target, chat_manager, term)?,
+        Command::Screen => {
+            // leave room for the question and the answer
+            let budget = (session.remaining_tokens(chat_manager) / 2)
+                .clamp(0, SCREEN_TOKEN_BUDGET as i64) as usize;
+            match terminal_capture::capture_scrollback() {
+                Ok((source, scrollback)) => {
+                    let screen = terminal_capture::trim_to_tokens(&scrollback, budget);
+                    session
+                        .draft
+                        .push(format!("Recent output of my terminal:\n```\n{screen}\n```"));
+                    term.write_line(&format!(
+                        "Captured {} lines from {source} ({} tokens) as snippet {}. Use /send to submit",
+                        screen.lines().count(),
+                        count_text_tokens(&screen),
+                        session.draft.len()
+                    ))?;
+                }
+                Err(error) => term.write_line(&format!("Can't capture screen: {error}"))?,
+            }
+        }
+        Command::Capture(target) => {
+            let target = target.as_deref().unwrap_or(terminal_capture::LAST_PANE);
+            match terminal_capture::capture_pane(target) {
+                Ok(content) => {
+                    session.draft.push(format!(
+                        "Content of terminal pane {target}:\n```\n{}\n```",
+                        content.trim_end()
+                    ));
+                    term.write_line(&format!(
+                        "Captured pane {target} ({} tokens) as snippet {}. Use /send to submit",
+                        count_text_tokens(&content),
+                        session.draft.len()
+                    ))?;
+                }
+                Err(error) => term.write_line(&format!("Can't capture pane {target}: {error}"))?,
+            }
+        }
+        Command::Preview => {
+            let draft_message = session.draft.join("\n\n");
+            term.write_line("---------------------------------")?;
+            term.write_line(&draft_message)?;
+            term.write_line("")?;
+            term.write_line(&format!(
+                "{INCREASING_TREND_EMOJI} Draft has {} snippets and {} tokens",
+                session.draft.len(),
+                count_text_tokens(&draft_message)
+            ))?;
+            for image in chat_manager.pending_images() {
+                term.write_line(&format!("{IMAGE_EMOJI} [image: {}]", image.source))?;
+            }
+            term.write_line(&chat_manager.token_count_message())?;
+            term.write_line("---------------------------------")?;
+        }
+        Command::Discard => {
+            session.draft.clear();
+            chat_manager.discard_file_versions();
+            term.write_line("Draft discarded")?;
+        }
+        Command::Citations => {
+            session.parameters.citations = !session.parameters.citations;
+            if session.parameters.citations {
+                term.write_line(
+                    "Citations on. Files attached from now on have numbered lines and answers cite them",
+                )?;
+            } else {
+                term.write_line("Citations off")?;
+            }
+        }
+        Command::Edit => {
+            // the draft is edited together with the question
+            let text = line_editor::edit_text(&session.draft.join("\n\n"))?;
+            let question = text.trim();
+            if question.is_empty() {
+                term.write_line("Nothing written, question discarded")?;
+            } else if session.read_only {
+                chat_manager.add_user_message(question)?;
+                session.save(chat_manager)?;
+            } else {
+                session.draft.clear();
+                return Ok(Outcome::Ask {
+                    question: question.to_owned(),
+                    regenerate: false,
+                });
+            }
+        }
+        Command::SendTo { .. } if session.read_only => term.write_line(READ_ONLY_MESSAGE)?,
+        Command::SendTo {
+            connector: name,
+            transcript,
+        } => {
+            let Some(connector) = session.config.connectors.get(&name) else {
+                let mut names: Vec<&str> = session
+                    .config
+                    .connectors
+                    .keys()
+                    .map(String::as_str)
+                    .collect();
+                names.sort_unstable();
+                term.write_line(&format!(
+                    "No connector {name}, configured are: {}",
+                    names.join(", ")
+                ))?;
+                return Ok(Outcome::Prompt);
+            };
+            let text = if transcript {
+                export::to_markdown(chat_manager, &session.config.timestamps)
+            } else {
+                match chat_manager
+                    .messages()
+                    .iter()
+                    .rev()
+                    .find(|message| message.role == Role::Assistant)
+                {
+                    Some(message) => message.content.clone(),
+                    None => {
+                        term.write_line("No answer to send yet")?;
+                        return Ok(Outcome::Prompt);
+                    }
+                }
+            };
+            let text = if session.config.privacy.enabled {
+                redaction::redact(&text).into_owned()
+            } else {
+                text
+            };
+            let subject = chat_manager
+                .conversation_title()
+                .unwrap_or("gpt-cli conversation")
+                .replace('_', " ");
+            match connector.send(&subject, &text).await {
+                Ok(()) => term.write_line(&format!("{SYSTEM_EMOJI} Sent to {name}"))?,
+                Err(error) => term.write_line(&format!("Failed to send to {name}: {error:#}"))?,
+            }
+        }
+        Command::Send => {
+            if session.read_only {
+                term.write_line(READ_ONLY_MESSAGE)?;
+            } else if session.draft.is_empty() {
+                term.write_line("Draft is empty. Use /add to add snippets")?;
+            } else {
+                let question = session.draft.join("\n\n");
+                session.draft.clear();
+                return Ok(Outcome::Ask {
+                    question,
+                    regenerate: false,
+                });
+            }
+        }
+    }
+    Ok(Outcome::Prompt)
+}
+
+/// print, copy, save or run a code block of the last answer
+fn code_block(
+    number: usize,
+    action: CodeAction,
+    chat_manager: &ChatHistory,
+    session: &mut Session<'_>,
+) -> Result<()> {
+    let term = session.term;
+    let code_blocks = chat_manager.last_code_blocks();
+    let Some(code_block) = code_blocks.get(number - 1) else {
+        term.write_line(&format!(
+            "Last answer has {} code blocks",
+            code_blocks.len()
+        ))?;
+        return Ok(());
+    };
+    let language = code_block
+        .language
+        .as_deref()
+        .map(|language| format!("{language} "))
+        .unwrap_or_default();
+    match action {
+        CodeAction::Print => term.write_str(&code_block.code)?,
+        CodeAction::Copy => {
+            let clipboard = session.clipboard.copy(&code_block.code, term)?;
+            term.write_line(&format!(
+                "Copied {language}code block {number} ({} lines) to the {clipboard}",
+                code_block.code.lines().count()
+            ))?;
+        }
+        CodeAction::Save(path) => {
+            if path.exists()
+                && !Confirm::with_theme(session.term_theme)
+                    .with_prompt(format!("{} exists, overwrite it?", path.display()))
+                    .default(false)
+                    .interact_on(term)?
+            {
+                return Ok(());
+            }
+            if let Some(parent) = path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+            {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&path, &code_block.code)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            term.write_line(&format!(
+                "{SYSTEM_EMOJI} Saved {language}code block {number} to {}",
+                path.display()
+            ))?;
+        }
+        CodeAction::Run => {
+            let Some((program, flag)) = interpreter(code_block.language.as_deref()) else {
+                term.write_line(&format!(
+                    "Don't know how to run {language}code, /code {number} --save <path> writes it to a file"
+                ))?;
+                return Ok(());
+            };
+            term.write_str(&code_block.code)?;
+            // code from the model only runs once it has been read
+            if !Confirm::with_theme(session.term_theme)
+                .with_prompt(format!("Run this with {program}?"))
+                .default(false)
+                .interact_on(term)?
+            {
+                return Ok(());
+            }
+            let status = std::process::Command::new(program)
+                .arg(flag)
+                .arg(&code_block.code)
+                .status()
+                .with_context(|| format!("failed to run {program}"))?;
+            term.write_line(&format!(
+                "{SYSTEM_EMOJI} Code block {number} exited with {status}"
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// edit sampling parameters until done is picked, returns the changes made
+fn tune_parameters(
+    parameters: &mut CompletionParameters,
+    term: &Term,
+    term_theme: &ColorfulTheme,
+) -> Result<Vec<String>> {
+    let format_value = |value: Option<f32>| {
+        value
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| String::from("default"))
+    };
+    let before = parameters.clone();
+    let mut selection = 0;
+    loop {
+        let mut items: Vec<_> = SamplingParameter::ALL
+            .iter()
+            .map(|parameter| {
+                format!(
+                    "{}: {}",
+                    parameter.name(),
+                    format_value(parameters.sampling_parameter(*parameter))
+                )
+            })
+            .collect();
+        items.push(String::from("Done"));
+        selection = Select::with_theme(term_theme)
+            .with_prompt("Pick a parameter to change")
+            .items(&items)
+            .default(selection)
+            .interact_on(term)?;
+        let Some(&parameter) = SamplingParameter::ALL.get(selection) else {
+            break;
+        };
+
+        let (min, max) = parameter.range();
+        let value: String = Input::with_theme(term_theme)
+            .with_prompt(format!(
+                "{} between {min} and {max}, empty for default",
+                parameter.name()
+            ))
+            .with_initial_text(
+                parameters
+                    .sampling_parameter(parameter)
+                    .map(|value| value.to_string())
+                    .unwrap_or_default(),
+            )
+            .allow_empty(true)
+            .validate_with(|input: &String| -> Result<(), String> {
+                if input.trim().is_empty() {
+                    return Ok(());
+                }
+                match input.trim().parse::<f32>() {
+                    Ok(value) if (min..=max).contains(&value) => Ok(()),
+                    _ => Err(format!("Enter a number between {min} and {max}")),
+                }
+            })
+            .interact_text_on(term)?;
+        let value = value.trim();
+        parameters.set_sampling_parameter(parameter, value.parse().ok());
+    }
+
+    Ok(SamplingParameter::ALL
+        .iter()
+        .filter_map(|parameter| {
+            let old = before.sampling_parameter(*parameter);
+            let new = parameters.sampling_parameter(*parameter);
+            (old != new).then(|| {
+                format!(
+                    "{} {} -> {}",
+                    parameter.name(),
+                    format_value(old),
+                    format_value(new)
+                )
+            })
+        })
+        .collect())
+}
+
+/// send an image with the next question if the model can see it
+pub fn attach_image(target: &str, chat_manager: &mut ChatHistory, term: &Term) -> Result<()> {
+    let model = &chat_manager.model_metadata().name;
+    if !chat_manager.model_metadata().vision {
+        term.write_line(&format!(
+            "{model} doesn't accept images, switch with /model {GPT_4_VISION_MODEL_NAME} or set vision: true on the model in config"
+        ))?;
+        return Ok(());
+    }
+    match images::load_image(target) {
+        Ok(image) => {
+            chat_manager.attach_image(image);
+            term.write_line(&format!(
+                "{IMAGE_EMOJI} Attached {target}, it's sent with the next question"
+            ))?;
+        }
+        Err(error) => term.write_line(&format!("Can't attach image: {error}"))?,
+    }
+    Ok(())
+}
+
+/// load attachments into the draft, asks before adding a lot of context
+///
+/// with `citations` lines are numbered so answers can point at them,
+/// one-shot questions pass no theme and attach without asking
+pub async fn attach_to_draft(
+    targets: &[String],
+    chat_manager: &mut ChatHistory,
+    draft: &mut Vec<String>,
+    limits: &AttachmentConfig,
+    citations: bool,
+    term: &Term,
+    term_theme: Option<&ColorfulTheme>,
+) -> Result<()> {
+    let mut attachments = vec![];
+    for target in targets {
+        match attachments::load_target(target, limits).await {
+            Ok(loaded) => {
+                for (path, reason) in loaded.skipped {
+                    term.write_line(&format!("Skipped {}: {reason}", path.display()))?;
+                }
+                attachments.extend(loaded.attachments);
+            }
+            Err(error) => term.write_line(&format!("Can't attach {target}: {error}"))?,
+        }
+    }
+    if attachments.is_empty() {
+        return Ok(());
+    }
+    let total_bytes: usize = attachments
+        .iter()
+        .map(|attachment| attachment.content.len())
+        .sum();
+    if total_bytes > limits.max_total_bytes() {
+        term.write_line(&format!(
+            "Not attaching {} KiB, that's over the {} KiB total limit set by attachments.max_total_kib",
+            total_bytes.div_ceil(1024),
+            limits.max_total_kib
+        ))?;
+        return Ok(());
+    }
+
+    // files attached before only need the changes since then
+    for attachment in &mut attachments {
+        let Some(version) = attachment.version.clone() else {
+            continue;
+        };
+        let Some(earlier) = chat_manager
+            .attached_version(&version)
+            .filter(|earlier| earlier.blob != version.blob)
+            .cloned()
+        else {
+            continue;
+        };
+        if let Some(earlier_content) = file_versions::read_version(&earlier) {
+            attachment.diff_against(&earlier, &earlier_content);
+        }
+    }
+
+    if citations {
+        attachments.iter_mut().for_each(Attachment::number_lines);
+    }
+
+    for attachment in attachments
+        .iter()
+        .filter(|attachment| attachment.is_untrusted())
+    {
+        let findings = injection::detect_instructions(&attachment.content);
+        if !findings.is_empty() {
+            term.write_line(&format!(
+                "{SYSTEM_EMOJI} {} may be trying to instruct the model, it {}",
+                attachment.label(),
+                findings.join(", ")
+            ))?;
+        }
+    }
+
+    // repeated content is sent once and referenced after that
+    let mut deduplicator = Deduplicator::new(chat_manager.messages(), draft);
+    let mut saved_tokens = 0;
+    for attachment in &mut attachments {
+        let (content, saved) = deduplicator.deduplicate(&attachment.label(), &attachment.content);
+        if saved > 0 {
+            attachment.content = content;
+            attachment.tokens = count_text_tokens(&attachment.content);
+            saved_tokens += saved;
+        }
+    }
+
+    for attachment in &attachments {
+        match &attachment.diff_base {
+            Some(earlier) => term.write_line(&format!(
+                "  {} ({} tokens, changes since {})",
+                attachment.label(),
+                attachment.tokens,
+                earlier.label()
+            ))?,
+            None => term.write_line(&format!(
+                "  {} ({} tokens)",
+                attachment.label(),
+                attachment.tokens
+            ))?,
+        }
+    }
+    if saved_tokens > 0 {
+        term.write_line(&format!(
+            "Saved {saved_tokens} tokens by referencing content the conversation already has"
+        ))?;
+    }
+    let total_tokens: usize = attachments.iter().map(|attachment| attachment.tokens).sum();
+    let remaining_tokens = chat_manager.model_metadata().token_limit as i64
+        - chat_manager.count_tokens()
+        - count_text_tokens(&draft.join("\n\n")) as i64;
+    if total_tokens as i64 > remaining_tokens {
+        term.write_line(&format!(
+            "Attachments have {total_tokens} tokens but only {} are left in the context",
+            remaining_tokens.max(0)
+        ))?;
+        return Ok(());
+    }
+    if let Some(term_theme) = term_theme {
+        if total_tokens > attachments::CONFIRM_TOKEN_THRESHOLD
+            && !Confirm::with_theme(term_theme)
+                .with_prompt(format!(
+                    "Attach {} files with {total_tokens} tokens?",
+                    attachments.len()
+                ))
+                .default(false)
+                .interact_on(term)?
+        {
+            return Ok(());
+        }
+    }
+
+    draft.extend(attachments.iter().map(|attachment| attachment.to_message()));
+    for version in attachments
+        .iter()
+        .filter_map(|attachment| attachment.version.clone())
+    {
+        chat_manager.attach_file_version(version);
+    }
+    let hint = if term_theme.is_some() {
+        ". Use /send to submit"
+    } else {
+        ""
+    };
+    term.write_line(&format!(
+        "{INCREASING_TREND_EMOJI} Attached {total_tokens} tokens as {} snippets{hint}",
+        attachments.len()
+    ))?;
+    Ok(())
+}
+
+/// pick messages to delete or summarize to free up context,
+/// summarizing is refused without a client
+async fn trim_conversation(
+    chat_manager: &mut ChatHistory,
+    client: Option<&ChatClient>,
+    term: &Term,
+    term_theme: &ColorfulTheme,
+) -> Result<()> {
+    const TRIM_PREVIEW_CHARACTERS: usize = 60;
+    // system prompt can't be trimmed
+    let indices: Vec<usize> = (1..chat_manager.messages().len()).collect();
+    if indices.is_empty() {
+        term.write_line("Nothing to trim yet")?;
+        return Ok(());
+    }
+    let items: Vec<String> = indices
+        .iter()
+        .map(|&index| {
+            let message = &chat_manager.messages()[index];
+            let speaker = message.role.label();
+            let preview: String = message
+                .content
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .chars()
+                .take(TRIM_PREVIEW_CHARACTERS)
+                .collect();
+            format!(
+                "{index:>3} {speaker:<9} {:>6} tokens  {preview}",
+                chat_manager.message_tokens(index)
+            )
+        })
+        .collect();
+
+    let mut checked = vec![false; indices.len()];
+    loop {
+        let total_tokens = chat_manager.count_tokens();
+        let selection = MultiSelect::with_theme(term_theme)
+            .with_prompt(format!(
+                "Select messages to trim, conversation uses {total_tokens}/{} tokens",
+                chat_manager.model_metadata().token_limit
+            ))
+            .items(&items)
+            .defaults(&checked)
+            .interact_on_opt(term)?;
+        let Some(selection) = selection.filter(|selection| !selection.is_empty()) else {
+            return Ok(());
+        };
+        checked = (0..indices.len())
+            .map(|position| selection.contains(&position))
+            .collect();
+        let selected: Vec<usize> = selection
+            .iter()
+            .map(|position| indices[*position])
+            .collect();
+
+        let freed_tokens: usize = selected
+            .iter()
+            .map(|index| chat_manager.message_tokens(*index))
+            .sum();
+        let options = ["Delete", "Summarize", "Change selection", "Cancel"];
+        let action = Select::with_theme(term_theme)
+            .with_prompt(format!(
+                "{} messages with {freed_tokens} tokens selected, deleting them leaves {} tokens",
+                selected.len(),
+                (total_tokens - freed_tokens as i64).max(0)
+            ))
+            .items(&options)
+            .default(0)
+            .interact_on_opt(term)?;
+        match action {
+            Some(0) => chat_manager.remove_messages(&selected),
+            Some(1) => match client {
+                Some(client) => chat_manager.summarize_messages(&selected, client).await?,
+                None => {
+                    term.write_line(READ_ONLY_MESSAGE)?;
+                    continue;
+                }
+            },
+            Some(2) => continue,
+            _ => return Ok(()),
+        }
+        term.write_line(&format!(
+            "{INCREASING_TREND_EMOJI} Conversation now uses {}",
+            chat_manager.context_bar()
+        ))?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_ok(line: &str) -> Command {
+        parse(line).expect("is a command").expect("parses")
+    }
+
+    #[test]
+    fn questions_are_not_commands() {
+        assert!(parse("what does /help do?").is_none());
+        assert!(parse("/unknown").is_none());
+    }
+
+    #[test]
+    fn parses_arguments() {
+        assert_eq!(parse_ok("/help"), Command::Help);
+        assert_eq!(parse_ok("/stop"), Command::Stop);
+        assert_eq!(parse_ok("/title"), Command::Title(None));
+        assert_eq!(
+            parse_ok("/title  Borrow checker  "),
+            Command::Title(Some(String::from("Borrow checker")))
+        );
+        assert_eq!(
+            parse_ok("/bad too long"),
+            Command::Rate {
+                rating: Rating::Bad,
+                reason: Some(String::from("too long")),
+            }
+        );
+        assert_eq!(parse_ok("/copy"), Command::Copy(1));
+        assert_eq!(parse_ok("/copy 3"), Command::Copy(3));
+    }
+
+    #[test]
+    fn snippets_keep_their_indentation() {
+        assert_eq!(
+            parse_ok("/add     indented();"),
+            Command::Add(String::from("    indented();"))
+        );
+    }
+
+    #[test]
+    fn parses_code_actions() {
+        assert_eq!(
+            parse_ok("/code 2 --save out.rs"),
+            Command::Code {
+                number: 2,
+                action: CodeAction::Save(PathBuf::from("out.rs")),
+            }
+        );
+        assert_eq!(
+            parse_ok("/code 1"),
+            Command::Code {
+                number: 1,
+                action: CodeAction::Print,
+            }
+        );
+        assert!(parse("/code 0").unwrap().is_err());
+        assert!(parse("/code 1 --save").unwrap().is_err());
+    }
+
+    #[test]
+    fn reports_usage_for_missing_arguments() {
+        for line in [
+            "/note",
+            "/untag",
+            "/open",
+            "/rollback x",
+            "/copy 0",
+            "/add ",
+        ] {
+            let error = parse(line).unwrap().unwrap_err();
+            assert!(error.to_string().starts_with("Usage:"), "{line}: {error}");
+        }
+    }
+
+    #[test]
+    fn parses_send_to() {
+        assert_eq!(
+            parse_ok("/send-to mail transcript"),
+            Command::SendTo {
+                connector: String::from("mail"),
+                transcript: true,
+            }
+        );
+        assert!(parse("/send-to mail everything").unwrap().is_err());
+    }
+}
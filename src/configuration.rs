@@ -1,8 +1,28 @@
+use crate::{
+    anthropic::AnthropicConfig,
+    assistants::Backend,
+    attachments::AttachmentConfig,
+    chat_client::{AzureConfig, Provider},
+    chat_manager::{AssistantMetadata, ExampleExchange, ModelMetadata, SummaryConfig},
+    connectors::Connector,
+    conversation::{self, ContextOverflow},
+    line_editor::{EditMode, Shortcuts},
+    messages::validate_name,
+    projects::ProjectConfig,
+    redaction::PrivacyConfig,
+    render::DEFAULT_FLUSH_INTERVAL_MS,
+    response_cache::ResponseCacheConfig,
+    storage::{self, ProfileKey, StorageFormat},
+    telemetry::TelemetryConfig,
+    utils::TimestampConfig,
+    webhooks::WebhookConfig,
+};
 use anyhow::{Context, Result};
 use config::Config;
 use directories::ProjectDirs;
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_yaml::Value;
+use std::{collections::HashMap, path::PathBuf};
 
 const PROJECT_QUALIFIER: &str = "com";
 const PROJECT_ORGANIZATION: &str = "dmweis";
@@ -12,6 +32,13 @@ const GPT_CLI_CONFIG_FILE_NAME: &str = "config";
 const GPT_CLI_CONFIG_FILE_EXTENSION: &str = "yaml";
 
 pub const OPEN_AI_API_KEY_WEB_URL: &str = "https://platform.openai.com/account/api-keys";
+/// used when `api_base` isn't set
+pub const OPEN_AI_API_BASE: &str = "https://api.openai.com/v1";
+
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "gpt-cli";
+#[cfg(feature = "keyring")]
+const KEYRING_USER: &str = "open_ai_api_key";
 
 pub fn get_project_dirs() -> Result<ProjectDirs> {
     ProjectDirs::from(
@@ -28,23 +55,322 @@ fn get_config_file_path() -> Result<PathBuf> {
     Ok(config_dir_path.join(GPT_CLI_CONFIG_FILE_NAME))
 }
 
+/// YAML file the config is saved to
+pub fn config_file_path() -> Result<PathBuf> {
+    Ok(get_config_file_path()?.with_extension(GPT_CLI_CONFIG_FILE_EXTENSION))
+}
+
+/// Where the OpenAI API key is kept
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeySource {
+    /// open_ai_api_key in this file, the keyring is asked when it's empty
+    #[default]
+    File,
+    /// platform credential store written by `gpt-cli login`, open_ai_api_key is ignored
+    Keyring,
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_entry() -> Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).context("failed to open keyring")
+}
+
+#[cfg(feature = "keyring")]
+pub fn load_api_key_from_keyring() -> Result<String> {
+    keyring_entry()?
+        .get_password()
+        .context("OpenAI API key isn't in the keyring, run gpt-cli login")
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn load_api_key_from_keyring() -> Result<String> {
+    Err(crate::utils::feature_disabled("keyring", "The keyring"))
+}
+
+#[cfg(feature = "keyring")]
+pub fn save_api_key_to_keyring(api_key: &str) -> Result<()> {
+    keyring_entry()?
+        .set_password(api_key)
+        .context("failed to write API key to keyring")
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn save_api_key_to_keyring(_api_key: &str) -> Result<()> {
+    Err(crate::utils::feature_disabled("keyring", "The keyring"))
+}
+
+/// Persona from config, a system prompt alone or with example exchanges
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PersonaConfig {
+    SystemPrompt(String),
+    WithExamples {
+        system_prompt: String,
+        /// questions and answers new conversations start with as few-shot context
+        #[serde(default)]
+        examples: Vec<ExampleExchange>,
+    },
+}
+
+impl PersonaConfig {
+    pub fn to_assistant_metadata(&self, name: &str) -> AssistantMetadata {
+        match self {
+            PersonaConfig::SystemPrompt(system_prompt) => {
+                AssistantMetadata::new(name, system_prompt.clone())
+            }
+            PersonaConfig::WithExamples {
+                system_prompt,
+                examples,
+            } => AssistantMetadata {
+                examples: examples.clone(),
+                ..AssistantMetadata::new(name, system_prompt.clone())
+            },
+        }
+    }
+}
+
+/// Settings that differ between profiles like work and personal
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ProfileConfig {
+    /// gpg key conversations are encrypted for under this profile,
+    /// conversations encrypted for another key aren't opened
+    #[serde(default)]
+    pub gpg_recipient: Option<String>,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct AppConfig {
+    /// empty when the key is in the keyring
+    #[serde(default)]
     pub open_ai_api_key: String,
+    /// whether the key is read from this file or the keyring
+    #[serde(default)]
+    pub api_key_source: ApiKeySource,
+    /// keybindings for the question prompt
+    #[serde(default)]
+    pub edit_mode: EditMode,
+    #[serde(default)]
+    pub shortcuts: Shortcuts,
+    #[serde(default)]
+    pub response_cache: ResponseCacheConfig,
+    /// API used for conversations
+    #[serde(default)]
+    pub backend: Backend,
+    /// added before every question when it's sent, e.g. "answer in bullet points"
+    #[serde(default)]
+    pub question_prefix: Option<String>,
+    /// added after every question when it's sent, e.g. "respond only with code"
+    #[serde(default)]
+    pub question_suffix: Option<String>,
+    #[serde(default)]
+    pub timestamps: TimestampConfig,
+    /// name attached to your messages so shared transcripts show who asked
+    #[serde(default)]
+    pub user_name: Option<String>,
+    /// models usable with --model-name, e.g. new or fine-tuned models
+    #[serde(default)]
+    pub models: Vec<ModelMetadata>,
+    /// system prompts selectable with --persona and /persona, by name
+    #[serde(default)]
+    pub personas: HashMap<String, PersonaConfig>,
+    /// how many questions the prompt remembers between sessions
+    #[serde(default = "default_question_history_size")]
+    pub question_history_size: usize,
+    /// URLs that get JSON posted on conversation events
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    /// task file /todos appends to, a .md file gets a checklist and anything else todo.txt lines
+    #[serde(default)]
+    pub todo_file: Option<PathBuf>,
+    /// Slack, Matrix and email channels /send-to posts to, by name
+    #[serde(default)]
+    pub connectors: HashMap<String, Connector>,
+    /// export traces of API calls to an OpenTelemetry collector
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    /// truncate, summarize or error when a conversation nears the token limit
+    #[serde(default)]
+    pub context_overflow: ContextOverflow,
+    /// short summary of each conversation updated every few turns, shown in `view` and `history`
+    #[serde(default)]
+    pub summary: SummaryConfig,
+    /// how often streamed answers are written to the terminal, raise it for slow SSH sessions
+    #[serde(default = "default_stream_flush_interval_ms")]
+    pub stream_flush_interval_ms: u64,
+    /// OpenAI compatible server used instead of api.openai.com,
+    /// e.g. http://localhost:11434/v1 for Ollama or http://localhost:8080/v1 for llama.cpp
+    #[serde(default)]
+    pub api_base: Option<String>,
+    /// send chat requests to an Azure OpenAI resource instead of api.openai.com
+    #[serde(default)]
+    pub azure: Option<AzureConfig>,
+    /// provider used when --provider isn't given
+    #[serde(default)]
+    pub provider: Provider,
+    /// key for the anthropic provider
+    #[serde(default)]
+    pub anthropic: Option<AnthropicConfig>,
+    /// save new conversations as YAML or as readable markdown
+    #[serde(default)]
+    pub storage_format: StorageFormat,
+    /// words masked with --presentable besides the built-in profanity list
+    #[serde(default)]
+    pub presentable_blocklist: Vec<String>,
+    /// look for secrets and personal data before conversations are saved
+    #[serde(default)]
+    pub privacy: PrivacyConfig,
+    /// active profile, GPT_PROFILE or --profile switch to another one
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// settings of profiles like work and personal, by name
+    #[serde(default)]
+    pub profiles: HashMap<String, ProfileConfig>,
+    /// project names for directories, other directories use the `origin` remote of their git repository
+    #[serde(default)]
+    pub projects: Vec<ProjectConfig>,
+    /// API name of the model used when neither --model nor --model-name is given
+    #[serde(default)]
+    pub default_model: Option<String>,
+    /// temperature used when --temperature isn't given
+    #[serde(default)]
+    pub default_temperature: Option<f32>,
+    /// persona of new conversations when --persona isn't given
+    #[serde(default)]
+    pub default_persona: Option<String>,
+    /// top_p used when --top-p isn't given
+    #[serde(default)]
+    pub default_top_p: Option<f32>,
+    /// stream answers as they arrive, --stream and --no-stream override it
+    #[serde(default = "default_true")]
+    pub stream: bool,
+    /// save conversations as they go, --save and --no-save override it
+    #[serde(default = "default_true")]
+    pub auto_save: bool,
+    /// size limits for /attach, --attach and files the model reads
+    #[serde(default)]
+    pub attachments: AttachmentConfig,
+    /// bias from -100 to 100 per word for every answer, negative makes a word less likely
+    #[serde(default)]
+    pub logit_bias: HashMap<String, i32>,
+    /// words answers shouldn't contain, the same as a bias of -100
+    #[serde(default)]
+    pub banned_words: Vec<String>,
+    /// sent as OpenAI-Organization, for accounts that belong to several organizations
+    #[serde(default)]
+    pub organization_id: Option<String>,
+    /// sent as OpenAI-Project, for keys scoped to a project
+    #[serde(default)]
+    pub project_id: Option<String>,
+}
+
+fn default_question_history_size() -> usize {
+    1000
+}
+
+fn default_stream_flush_interval_ms() -> u64 {
+    DEFAULT_FLUSH_INTERVAL_MS
+}
+
+fn default_true() -> bool {
+    true
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
             open_ai_api_key: format!("Get token from {OPEN_AI_API_KEY_WEB_URL}",),
+            api_key_source: ApiKeySource::default(),
+            edit_mode: EditMode::default(),
+            shortcuts: Shortcuts::default(),
+            response_cache: ResponseCacheConfig::default(),
+            backend: Backend::default(),
+            question_prefix: None,
+            question_suffix: None,
+            timestamps: TimestampConfig::default(),
+            user_name: None,
+            models: vec![],
+            personas: HashMap::new(),
+            question_history_size: default_question_history_size(),
+            webhooks: vec![],
+            todo_file: None,
+            connectors: HashMap::new(),
+            telemetry: TelemetryConfig::default(),
+            context_overflow: ContextOverflow::default(),
+            summary: SummaryConfig::default(),
+            stream_flush_interval_ms: default_stream_flush_interval_ms(),
+            api_base: None,
+            azure: None,
+            provider: Provider::default(),
+            anthropic: None,
+            storage_format: StorageFormat::default(),
+            presentable_blocklist: vec![],
+            privacy: PrivacyConfig::default(),
+            profile: None,
+            profiles: HashMap::new(),
+            projects: vec![],
+            default_model: None,
+            default_temperature: None,
+            default_persona: None,
+            default_top_p: None,
+            stream: true,
+            auto_save: true,
+            attachments: AttachmentConfig::default(),
+            logit_bias: HashMap::new(),
+            banned_words: vec![],
+            organization_id: None,
+            project_id: None,
         }
     }
 }
 
 impl AppConfig {
+    /// gpg key conversations are encrypted for, the active profile's or privacy.gpg_recipient
+    pub fn gpg_recipient(&self) -> Option<&str> {
+        match self.active_profile() {
+            Some((_, profile)) => profile.gpg_recipient.as_deref(),
+            None => self.privacy.gpg_recipient.as_deref(),
+        }
+    }
+
+    fn active_profile(&self) -> Option<(&str, &ProfileConfig)> {
+        let name = self.profile.as_deref()?;
+        self.profiles.get(name).map(|profile| (name, profile))
+    }
+
+    /// base URL of OpenAI requests, `api_base` or api.openai.com, without a trailing slash
+    pub fn api_base(&self) -> &str {
+        self.api_base
+            .as_deref()
+            .unwrap_or(OPEN_AI_API_BASE)
+            .trim_end_matches('/')
+    }
+
+    /// organization and project headers for requests to OpenAI
+    pub fn openai_headers(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("OpenAI-Organization", &self.organization_id),
+            ("OpenAI-Project", &self.project_id),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value.as_deref()?)))
+        .collect()
+    }
+
+    /// logit_bias by token id with banned words at -100
+    pub fn logit_bias(&self) -> Result<HashMap<String, serde_json::Value>> {
+        conversation::logit_bias(
+            self.logit_bias
+                .iter()
+                .map(|(word, bias)| (word.as_str(), *bias))
+                .chain(self.banned_words.iter().map(|word| (word.as_str(), -100))),
+        )
+    }
+
     pub fn new(api_key: String) -> Self {
         Self {
             open_ai_api_key: api_key,
+            ..Default::default()
         }
     }
 
@@ -55,12 +381,33 @@ impl AppConfig {
             .add_source(config::Environment::with_prefix("GPT"))
             .build()?;
 
-        Ok(settings.try_deserialize::<AppConfig>()?)
+        let mut config = settings.try_deserialize::<AppConfig>()?;
+        if let Some(profile) = &config.profile {
+            if !config.profiles.contains_key(profile) {
+                anyhow::bail!("Profile {profile} isn't in the profiles section of the config");
+            }
+        }
+        // conversations of other profiles stay closed, whichever command loads them
+        storage::use_profile_key(
+            config
+                .active_profile()
+                .map(|(name, profile)| ProfileKey::new(name, profile.gpg_recipient.as_deref())),
+        );
+        match config.api_key_source {
+            ApiKeySource::Keyring => config.open_ai_api_key = load_api_key_from_keyring()?,
+            // servers configured with api_base often don't need a key
+            ApiKeySource::File if config.open_ai_api_key.is_empty() => {
+                if let Ok(api_key) = load_api_key_from_keyring() {
+                    config.open_ai_api_key = api_key;
+                }
+            }
+            ApiKeySource::File => {}
+        }
+        Ok(config)
     }
 
     pub fn save_user_config(&self) -> anyhow::Result<()> {
-        let config_file_path =
-            get_config_file_path()?.with_extension(GPT_CLI_CONFIG_FILE_EXTENSION);
+        let config_file_path = config_file_path()?;
 
         std::fs::create_dir_all(
             config_file_path
@@ -68,8 +415,154 @@ impl AppConfig {
                 .context("failed to get config file parent directory")?,
         )?;
 
+        // a key from the keyring never ends up in the file
+        let mut config = self.clone();
+        if config.api_key_source == ApiKeySource::Keyring {
+            config.open_ai_api_key.clear();
+        }
         let file = std::fs::File::create(config_file_path)?;
-        serde_yaml::to_writer(file, self)?;
+        serde_yaml::to_writer(file, &config)?;
         Ok(())
     }
 }
+
+/// Setting `gpt-cli config get` and `gpt-cli config set` work with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[value(rename_all = "snake_case")]
+pub enum ConfigKey {
+    DefaultModel,
+    DefaultTemperature,
+    DefaultTopP,
+    DefaultPersona,
+    Stream,
+    AutoSave,
+    ApiBase,
+    OrganizationId,
+    ProjectId,
+    Provider,
+    UserName,
+    StorageFormat,
+    EditMode,
+    ContextOverflow,
+}
+
+impl ConfigKey {
+    /// key in the config file
+    pub fn name(self) -> &'static str {
+        match self {
+            ConfigKey::DefaultModel => "default_model",
+            ConfigKey::DefaultTemperature => "default_temperature",
+            ConfigKey::DefaultTopP => "default_top_p",
+            ConfigKey::DefaultPersona => "default_persona",
+            ConfigKey::Stream => "stream",
+            ConfigKey::AutoSave => "auto_save",
+            ConfigKey::ApiBase => "api_base",
+            ConfigKey::OrganizationId => "organization_id",
+            ConfigKey::ProjectId => "project_id",
+            ConfigKey::Provider => "provider",
+            ConfigKey::UserName => "user_name",
+            ConfigKey::StorageFormat => "storage_format",
+            ConfigKey::EditMode => "edit_mode",
+            ConfigKey::ContextOverflow => "context_overflow",
+        }
+    }
+
+    /// value as it's written to the config file, refuses values the setting can't take
+    pub fn parse_value(self, value: &str) -> Result<Value> {
+        let value = value.trim();
+        match self {
+            ConfigKey::DefaultModel | ConfigKey::DefaultPersona => {
+                if value.is_empty() {
+                    anyhow::bail!("{} can't be empty", self.name());
+                }
+                Ok(Value::String(value.to_owned()))
+            }
+            ConfigKey::OrganizationId | ConfigKey::ProjectId => {
+                if value.is_empty() || !value.chars().all(|c| c.is_ascii_graphic()) {
+                    anyhow::bail!("{} can't be empty or contain spaces", self.name());
+                }
+                Ok(Value::String(value.to_owned()))
+            }
+            ConfigKey::UserName => {
+                validate_name(value)?;
+                Ok(Value::String(value.to_owned()))
+            }
+            ConfigKey::DefaultTemperature => {
+                let temperature: f32 = value
+                    .parse()
+                    .with_context(|| format!("{value} isn't a number"))?;
+                if !(0.0..=2.0).contains(&temperature) {
+                    anyhow::bail!("Temperature has to be between 0 and 2");
+                }
+                Ok(serde_yaml::to_value(temperature)?)
+            }
+            ConfigKey::DefaultTopP => {
+                let top_p: f32 = value
+                    .parse()
+                    .with_context(|| format!("{value} isn't a number"))?;
+                if !(0.0..=1.0).contains(&top_p) {
+                    anyhow::bail!("top_p has to be between 0 and 1");
+                }
+                Ok(serde_yaml::to_value(top_p)?)
+            }
+            ConfigKey::Stream | ConfigKey::AutoSave => {
+                let enabled: bool = value
+                    .parse()
+                    .with_context(|| format!("{} has to be true or false", self.name()))?;
+                Ok(Value::Bool(enabled))
+            }
+            ConfigKey::ApiBase => {
+                let url =
+                    reqwest::Url::parse(value).with_context(|| format!("{value} isn't a URL"))?;
+                if !matches!(url.scheme(), "http" | "https") {
+                    anyhow::bail!("api_base has to be an http or https URL");
+                }
+                Ok(Value::String(value.to_owned()))
+            }
+            ConfigKey::Provider => typed_value::<Provider>(self, value),
+            ConfigKey::StorageFormat => typed_value::<StorageFormat>(self, value),
+            ConfigKey::EditMode => typed_value::<EditMode>(self, value),
+            ConfigKey::ContextOverflow => typed_value::<ContextOverflow>(self, value),
+        }
+    }
+}
+
+/// value checked against the type of the setting, e.g. the variants of an enum
+fn typed_value<T: DeserializeOwned + Serialize>(key: ConfigKey, value: &str) -> Result<Value> {
+    let parsed: T = serde_yaml::from_value(Value::String(value.to_owned()))
+        .with_context(|| format!("{value} isn't a valid {}", key.name()))?;
+    Ok(serde_yaml::to_value(parsed)?)
+}
+
+/// write one setting into the config file and leave the rest of it alone
+///
+/// comments in the file don't survive this, `gpt-cli config edit` keeps them
+pub fn set_config_value(key: ConfigKey, value: Value) -> Result<PathBuf> {
+    let config_file_path = config_file_path()?;
+    let mut document = match std::fs::read_to_string(&config_file_path) {
+        Ok(text) => serde_yaml::from_str(&text)
+            .with_context(|| format!("failed to parse {}", config_file_path.display()))?,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Value::Null,
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("failed to read {}", config_file_path.display()))
+        }
+    };
+    // an empty file parses as null
+    if document.is_null() {
+        document = Value::Mapping(Default::default());
+    }
+    document
+        .as_mapping_mut()
+        .with_context(|| format!("{} isn't a YAML mapping", config_file_path.display()))?
+        .insert(Value::String(key.name().to_owned()), value);
+
+    std::fs::create_dir_all(
+        config_file_path
+            .parent()
+            .context("failed to get config file parent directory")?,
+    )?;
+    std::fs::write(&config_file_path, serde_yaml::to_string(&document)?)
+        .with_context(|| format!("failed to write {}", config_file_path.display()))?;
+    Ok(config_file_path)
+}
@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+#[cfg(feature = "email")]
+use lettre::{
+    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Channel that `/send-to` posts answers and transcripts to
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Connector {
+    /// incoming webhook of a Slack channel
+    Slack { webhook_url: String },
+    /// room on a Matrix homeserver, the token belongs to the account that posts
+    Matrix {
+        homeserver: String,
+        room_id: String,
+        access_token: String,
+    },
+    /// email sent through an SMTP server with STARTTLS
+    Smtp {
+        server: String,
+        #[serde(default = "default_smtp_port")]
+        port: u16,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+impl Connector {
+    /// post the text, `subject` is only used by email
+    pub async fn send(&self, subject: &str, text: &str) -> Result<()> {
+        match self {
+            Connector::Slack { webhook_url } => {
+                reqwest::Client::new()
+                    .post(webhook_url)
+                    .json(&json!({ "text": text }))
+                    .send()
+                    .await?
+                    .error_for_status()
+                    .context("Slack webhook failed")?;
+            }
+            Connector::Matrix {
+                homeserver,
+                room_id,
+                access_token,
+            } => {
+                // transaction ids only have to be unique for the access token
+                let transaction_id = chrono::Utc::now().timestamp_millis().to_string();
+                let mut url = reqwest::Url::parse(homeserver).context("Invalid homeserver URL")?;
+                url.path_segments_mut()
+                    .map_err(|_| anyhow::anyhow!("Invalid homeserver URL {homeserver}"))?
+                    .pop_if_empty()
+                    .extend([
+                        "_matrix",
+                        "client",
+                        "v3",
+                        "rooms",
+                        room_id,
+                        "send",
+                        "m.room.message",
+                        &transaction_id,
+                    ]);
+                reqwest::Client::new()
+                    .put(url)
+                    .bearer_auth(access_token)
+                    .json(&json!({ "msgtype": "m.text", "body": text }))
+                    .send()
+                    .await?
+                    .error_for_status()
+                    .context("Matrix homeserver refused the message")?;
+            }
+            #[cfg(not(feature = "email"))]
+            Connector::Smtp { .. } => {
+                // only email has a subject
+                _ = subject;
+                return Err(crate::utils::feature_disabled("email", "Sending email"));
+            }
+            #[cfg(feature = "email")]
+            Connector::Smtp {
+                server,
+                port,
+                username,
+                password,
+                from,
+                to,
+            } => {
+                let mut message = Message::builder()
+                    .from(from.parse().context("Invalid from address")?)
+                    .subject(subject)
+                    .header(ContentType::TEXT_PLAIN);
+                for recipient in to {
+                    message = message.to(recipient
+                        .parse()
+                        .with_context(|| format!("Invalid address {recipient}"))?);
+                }
+                let message = message.body(text.to_owned())?;
+
+                let mut transport =
+                    AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(server)?.port(*port);
+                if let Some(username) = username {
+                    transport = transport.credentials(Credentials::new(
+                        username.clone(),
+                        password.clone().unwrap_or_default(),
+                    ));
+                }
+                transport
+                    .build()
+                    .send(message)
+                    .await
+                    .context("SMTP server refused the email")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+/// words masked in presentable mode without any configuration
+const PROFANITY: &[&str] = &[
+    "arse",
+    "arsehole",
+    "ass",
+    "asshole",
+    "bastard",
+    "bitch",
+    "bollocks",
+    "bullshit",
+    "cock",
+    "crap",
+    "cunt",
+    "damn",
+    "dick",
+    "dickhead",
+    "fuck",
+    "goddamn",
+    "motherfucker",
+    "piss",
+    "prick",
+    "shit",
+    "slut",
+    "twat",
+    "wanker",
+    "whore",
+];
+/// endings that make a listed word match too, e.g. fucking for fuck
+const SUFFIXES: &[&str] = &["s", "es", "ed", "er", "ers", "ing", "y"];
+
+/// Masks profanity and blocked words in shown answers for --presentable
+#[derive(Debug, Clone, Default)]
+pub struct ContentFilter {
+    /// lowercase words to mask
+    words: Vec<String>,
+}
+
+impl ContentFilter {
+    /// built-in profanity list and the configured words
+    pub fn new(blocklist: &[String]) -> Self {
+        let mut words: Vec<String> = PROFANITY.iter().map(|word| (*word).to_owned()).collect();
+        words.extend(blocklist.iter().map(|word| word.trim().to_lowercase()));
+        words.retain(|word| !word.is_empty());
+        Self { words }
+    }
+
+    fn is_blocked(&self, word: &str) -> bool {
+        let word = word.to_lowercase();
+        self.words.iter().any(|blocked| {
+            word.strip_prefix(blocked.as_str())
+                .is_some_and(|suffix| suffix.is_empty() || SUFFIXES.contains(&suffix))
+        })
+    }
+
+    /// every blocked word replaced by its first letter and asterisks
+    pub fn filter(&self, text: &str) -> String {
+        let mut output = String::with_capacity(text.len());
+        let mut word_start = None;
+        for (index, character) in text.char_indices() {
+            if character.is_alphanumeric() {
+                word_start.get_or_insert(index);
+            } else {
+                if let Some(start) = word_start.take() {
+                    self.push_word(&text[start..index], &mut output);
+                }
+                output.push(character);
+            }
+        }
+        if let Some(start) = word_start {
+            self.push_word(&text[start..], &mut output);
+        }
+        output
+    }
+
+    fn push_word(&self, word: &str, output: &mut String) {
+        if !self.is_blocked(word) {
+            output.push_str(word);
+            return;
+        }
+        let mut characters = word.chars();
+        output.extend(characters.next());
+        output.extend(characters.map(|_| '*'));
+    }
+}
+
+/// Filters streamed text, a word is only written once it's complete
+#[derive(Debug, Clone, Default)]
+pub struct StreamFilter {
+    filter: ContentFilter,
+    pending: String,
+}
+
+impl StreamFilter {
+    pub fn new(filter: ContentFilter) -> Self {
+        Self {
+            filter,
+            pending: String::new(),
+        }
+    }
+
+    pub fn push(&mut self, delta: &str) -> String {
+        self.pending.push_str(delta);
+        let split_at = self
+            .pending
+            .char_indices()
+            .rev()
+            .find(|(_, character)| !character.is_alphanumeric())
+            .map(|(index, character)| index + character.len_utf8());
+        match split_at {
+            Some(split_at) => {
+                let rest = self.pending.split_off(split_at);
+                let complete = std::mem::replace(&mut self.pending, rest);
+                self.filter.filter(&complete)
+            }
+            None => String::new(),
+        }
+    }
+
+    pub fn finish(&mut self) -> String {
+        self.filter.filter(&std::mem::take(&mut self.pending))
+    }
+}
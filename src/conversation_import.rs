@@ -0,0 +1,58 @@
+use crate::{
+    attachments::fetch_text,
+    chat_manager::{ChatHistory, ModelMetadata},
+    messages::ChatMessage,
+    storage,
+};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// persona recorded for conversations that don't carry their own
+const IMPORTED_PERSONA: &str = "imported";
+
+/// one line of the chat format JSONL written by `sessions export-jsonl`
+#[derive(Deserialize)]
+struct ExportedConversation {
+    messages: Vec<ChatMessage>,
+}
+
+/// gist pages are HTML, their raw endpoint serves the first file
+fn raw_url(url: &str) -> String {
+    match url.strip_prefix("https://gist.github.com/") {
+        Some(path) if !path.contains("/raw") => format!(
+            "https://gist.githubusercontent.com/{}/raw",
+            path.trim_end_matches('/')
+        ),
+        _ => url.to_owned(),
+    }
+}
+
+/// fetch a shared transcript and turn it into local conversations
+///
+/// accepts a saved conversation file in either storage format or chat format JSONL, `model_metadata` is used
+/// for transcripts that don't record a model
+pub async fn import_url(url: &str, model_metadata: &ModelMetadata) -> Result<Vec<ChatHistory>> {
+    let text = fetch_text(&raw_url(url)).await?;
+
+    // saved conversations are a single YAML document
+    if let Ok(chat_history) = serde_yaml::from_str::<ChatHistory>(&text) {
+        return Ok(vec![chat_history]);
+    }
+    if let Ok(chat_history) = storage::from_markdown(&text) {
+        return Ok(vec![chat_history]);
+    }
+
+    let conversations = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let exported: ExportedConversation = serde_json::from_str(line)?;
+            ChatHistory::from_messages(model_metadata.clone(), IMPORTED_PERSONA, exported.messages)
+        })
+        .collect::<Result<Vec<_>>>()
+        .with_context(|| format!("{url} is neither a saved conversation nor chat format JSONL"))?;
+    if conversations.is_empty() {
+        anyhow::bail!("{url} doesn't contain any conversations");
+    }
+    Ok(conversations)
+}
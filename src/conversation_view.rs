@@ -0,0 +1,321 @@
+use crate::{
+    chat_manager::ChatHistory, code_blocks::extract_code_blocks, messages::Role,
+    render::highlight_spans, utils::TimestampConfig,
+};
+use anyhow::Result;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span, Text},
+    widgets::{Block, Paragraph, Wrap},
+    DefaultTerminal, Frame,
+};
+
+/// lines scrolled by page up and page down
+const PAGE: u16 = 10;
+/// the pinned summary is cut to this many lines
+const SUMMARY_MAX_LINES: u16 = 6;
+
+/// Pane scrolled by page up and page down
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum Focus {
+    #[default]
+    Transcript,
+    Reference,
+}
+
+/// Attached file or code block shown next to the transcript
+struct Reference {
+    title: String,
+    language: Option<String>,
+    code: String,
+}
+
+impl Reference {
+    /// the most recent attached file or code block of the conversation
+    fn last_in(chat_history: &ChatHistory) -> Option<Self> {
+        chat_history.messages().iter().rev().find_map(|message| {
+            let block = extract_code_blocks(&message.content).pop()?;
+            // attachments are fenced under a `File: <path>` header
+            let title = match message.role {
+                Role::User => message
+                    .content
+                    .lines()
+                    .rev()
+                    .find_map(|line| line.strip_prefix("File: "))
+                    .map(str::to_owned),
+                _ => None,
+            }
+            .unwrap_or_else(|| format!("Code block from {}", message.role.label()));
+            Some(Self {
+                title,
+                // longer fences leave backticks in front of the language
+                language: block
+                    .language
+                    .map(|language| language.trim_start_matches('`').to_owned()),
+                code: block.code,
+            })
+        })
+    }
+
+    fn text(&self) -> Text<'static> {
+        let highlighted = self
+            .language
+            .as_deref()
+            .and_then(|language| highlight_spans(&self.code, language));
+        match highlighted {
+            Some(lines) => Text::from(
+                lines
+                    .into_iter()
+                    .map(|spans| {
+                        Line::from(
+                            spans
+                                .into_iter()
+                                .map(|((red, green, blue), text)| {
+                                    Span::styled(
+                                        text,
+                                        Style::new().fg(Color::Rgb(red, green, blue)),
+                                    )
+                                })
+                                .collect::<Vec<_>>(),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            None => Text::from(self.code.clone()),
+        }
+    }
+}
+
+/// Transcript of a conversation under its pinned summary, with a side pane for the last
+/// attached file or code block
+#[derive(Default)]
+pub struct ConversationPane {
+    scroll: u16,
+    show_reference: bool,
+    reference_scroll: u16,
+    focus: Focus,
+}
+
+impl ConversationPane {
+    /// keys handled by the pane for the footer
+    pub const KEYS: &'static str = "f file  tab focus  pgup/pgdn scroll";
+
+    /// scroll back to the top, e.g. for another conversation
+    pub fn reset(&mut self) {
+        self.scroll = 0;
+        self.reference_scroll = 0;
+    }
+
+    pub fn shows_reference(&self) -> bool {
+        self.show_reference
+    }
+
+    /// returns false for keys the pane doesn't use
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::PageDown => self.scroll(true),
+            KeyCode::PageUp => self.scroll(false),
+            KeyCode::Char('f') => {
+                self.show_reference = !self.show_reference;
+                self.focus = if self.show_reference {
+                    Focus::Reference
+                } else {
+                    Focus::Transcript
+                };
+            }
+            KeyCode::Tab if self.show_reference => {
+                self.focus = match self.focus {
+                    Focus::Transcript => Focus::Reference,
+                    Focus::Reference => Focus::Transcript,
+                };
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn scroll(&mut self, down: bool) {
+        let scroll = match self.focus {
+            Focus::Transcript => &mut self.scroll,
+            Focus::Reference => &mut self.reference_scroll,
+        };
+        *scroll = if down {
+            scroll.saturating_add(PAGE)
+        } else {
+            scroll.saturating_sub(PAGE)
+        };
+    }
+
+    pub fn draw(
+        &self,
+        frame: &mut Frame,
+        area: Rect,
+        title: &str,
+        chat_history: Option<&ChatHistory>,
+        timestamps: &TimestampConfig,
+    ) {
+        let [transcript_area, reference_area] = if self.show_reference {
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(area)
+        } else {
+            Layout::horizontal([Constraint::Percentage(100), Constraint::Length(0)]).areas(area)
+        };
+
+        // the summary stays pinned above the transcript while it scrolls
+        let summary = chat_history
+            .and_then(ChatHistory::summary)
+            .map(|summary| summary.text.clone());
+        let summary_height = summary.as_ref().map_or(0, |summary| {
+            let width = transcript_area.width.saturating_sub(2).max(1) as usize;
+            let lines = summary
+                .lines()
+                .map(|line| line.chars().count().div_ceil(width).max(1))
+                .sum::<usize>();
+            (lines as u16).min(SUMMARY_MAX_LINES) + 2
+        });
+        let [summary_area, transcript_area] =
+            Layout::vertical([Constraint::Length(summary_height), Constraint::Min(0)])
+                .areas(transcript_area);
+        if let Some(summary) = summary {
+            frame.render_widget(
+                Paragraph::new(summary)
+                    .block(Block::bordered().title(" Summary "))
+                    .style(Style::new().fg(Color::Cyan))
+                    .wrap(Wrap { trim: true }),
+                summary_area,
+            );
+        }
+
+        let text = chat_history
+            .map(|chat_history| transcript(chat_history, timestamps))
+            .unwrap_or_default();
+        let focused = |focus| {
+            if self.show_reference && self.focus == focus {
+                Style::new().fg(Color::Yellow)
+            } else {
+                Style::new()
+            }
+        };
+        frame.render_widget(
+            Paragraph::new(text)
+                .block(
+                    Block::bordered()
+                        .title(format!(" {title} "))
+                        .border_style(focused(Focus::Transcript)),
+                )
+                .wrap(Wrap { trim: false })
+                .scroll((self.scroll, 0)),
+            transcript_area,
+        );
+
+        if self.show_reference {
+            let (title, text) = match chat_history.and_then(Reference::last_in) {
+                Some(reference) => (format!(" {} ", reference.title), reference.text()),
+                None => (
+                    String::from(" No attached file or code block "),
+                    Text::default(),
+                ),
+            };
+            // code isn't wrapped so indentation stays readable
+            frame.render_widget(
+                Paragraph::new(text)
+                    .block(
+                        Block::bordered()
+                            .title(title)
+                            .border_style(focused(Focus::Reference)),
+                    )
+                    .scroll((self.reference_scroll, 0)),
+                reference_area,
+            );
+        }
+    }
+}
+
+/// show a conversation full screen until q is pressed
+pub fn view(chat_history: &ChatHistory, timestamps: &TimestampConfig) -> Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = run(&mut terminal, chat_history, timestamps);
+    ratatui::restore();
+    result
+}
+
+fn run(
+    terminal: &mut DefaultTerminal,
+    chat_history: &ChatHistory,
+    timestamps: &TimestampConfig,
+) -> Result<()> {
+    let mut pane = ConversationPane::default();
+    let title = chat_history
+        .conversation_title()
+        .unwrap_or("untitled")
+        .replace('_', " ");
+    loop {
+        terminal.draw(|frame| {
+            let [main, footer] =
+                Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+            pane.draw(frame, main, &title, Some(chat_history), timestamps);
+            frame.render_widget(
+                Paragraph::new(format!("{}  q quit", ConversationPane::KEYS)),
+                footer,
+            );
+        })?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        if matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+            return Ok(());
+        }
+        pane.handle_key(key);
+    }
+}
+
+fn transcript(chat_history: &ChatHistory, timestamps: &TimestampConfig) -> Text<'static> {
+    let mut lines = vec![];
+    if let Some(date) = chat_history.conversation_start() {
+        lines.push(Line::from(Span::styled(
+            format!(
+                "{}, persona {}",
+                timestamps.display(&date),
+                chat_history.assistant_metadata().name
+            ),
+            Style::new().fg(Color::DarkGray),
+        )));
+        if let Some(directory) = chat_history.working_directory() {
+            lines.push(Line::from(Span::styled(
+                format!("started in {}", directory.display()),
+                Style::new().fg(Color::DarkGray),
+            )));
+        }
+        lines.push(Line::default());
+    }
+    for message in chat_history.messages() {
+        if message.role.is_instruction() {
+            continue;
+        }
+        let color = match message.role {
+            Role::User => Color::Blue,
+            Role::Assistant => Color::Green,
+            _ => Color::Yellow,
+        };
+        let label = match &message.name {
+            Some(name) => format!("{} ({name}):", message.role.label()),
+            None => format!("{}:", message.role.label()),
+        };
+        lines.push(Line::from(Span::styled(
+            label,
+            Style::new().fg(color).add_modifier(Modifier::BOLD),
+        )));
+        lines.extend(
+            message
+                .content
+                .lines()
+                .map(|line| Line::from(line.to_owned())),
+        );
+        lines.push(Line::default());
+    }
+    Text::from(lines)
+}
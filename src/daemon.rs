@@ -0,0 +1,408 @@
+use crate::{
+    chat_client::ChatClient,
+    chat_manager::{AssistantMetadata, ChatHistory, CompletionParameters, ModelMetadata},
+    configuration::get_project_dirs,
+    conversation::count_text_tokens,
+    line_editor::LineEditor,
+    messages::Role,
+    metrics::{self, Metrics},
+    render::{render_text, RenderOptions, ResponseRenderer},
+    storage::StorageFormat,
+    utils::{QUESTION_MARK_EMOJI, ROBOT_EMOJI, SYSTEM_EMOJI},
+};
+use anyhow::{Context, Result};
+use dialoguer::{console::Term, theme::ColorfulTheme, FuzzySelect};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{unix::OwnedWriteHalf, UnixListener, UnixStream},
+    sync::{mpsc, Mutex},
+    time::Instant,
+};
+
+const DAEMON_SOCKET_NAME: &str = "daemon.sock";
+const DEFAULT_SESSION_NAME: &str = "default";
+
+/// Requests sent from `gpt-cli attach` to the daemon, one JSON object per line
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    ListSessions,
+    Attach { session: String },
+    Ask { question: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct HistoryMessage {
+    role: Role,
+    content: String,
+}
+
+/// Replies from the daemon
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum DaemonMessage {
+    Sessions { names: Vec<String> },
+    History { messages: Vec<HistoryMessage> },
+    Content { content: String },
+    Done,
+    Error { message: String },
+}
+
+fn socket_path() -> Result<PathBuf> {
+    let data_dir = get_project_dirs()?.data_dir().to_owned();
+    std::fs::create_dir_all(&data_dir).context("failed to create data directory")?;
+    Ok(data_dir.join(DAEMON_SOCKET_NAME))
+}
+
+type Session = Arc<Mutex<ChatHistory>>;
+
+/// Shared by all attached clients
+struct DaemonState {
+    client: ChatClient,
+    model_metadata: ModelMetadata,
+    assistant_metadata: AssistantMetadata,
+    parameters: CompletionParameters,
+    /// format saves are written in, None when saving is off
+    save: Option<StorageFormat>,
+    sessions: Mutex<HashMap<String, Session>>,
+    metrics: Arc<Metrics>,
+}
+
+/// Keep sessions and the API client alive between terminals
+///
+/// Answers keep generating and get saved even if the attached terminal goes away,
+/// with `metrics_address` set usage is served there on `/metrics`
+pub async fn run_daemon(
+    client: ChatClient,
+    model_metadata: ModelMetadata,
+    assistant_metadata: AssistantMetadata,
+    parameters: CompletionParameters,
+    save: Option<StorageFormat>,
+    metrics_address: Option<&str>,
+    term: &Term,
+) -> Result<()> {
+    let socket_path = socket_path()?;
+    if UnixStream::connect(&socket_path).await.is_ok() {
+        anyhow::bail!("Daemon is already running on {}", socket_path.display());
+    }
+    // socket left behind by a daemon that didn't shut down cleanly
+    if socket_path.exists() {
+        std::fs::remove_file(&socket_path)?;
+    }
+
+    // check the API key once instead of on every attach
+    client
+        .check_credentials()
+        .await
+        .context("failed to authenticate with OpenAI")?;
+
+    let listener = UnixListener::bind(&socket_path)
+        .with_context(|| format!("failed to bind {}", socket_path.display()))?;
+    term.write_line(&format!(
+        "{SYSTEM_EMOJI} Daemon listening on {}. Press Ctrl-C to stop",
+        socket_path.display()
+    ))?;
+
+    let metrics = Arc::new(Metrics::default());
+    if let Some(metrics_address) = metrics_address {
+        let address = metrics_address.to_owned();
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(error) = metrics::serve_metrics(&address, metrics).await {
+                eprintln!("Metrics server failed: {error:?}");
+            }
+        });
+        term.write_line(&format!(
+            "{SYSTEM_EMOJI} Serving metrics on http://{metrics_address}/metrics"
+        ))?;
+    }
+
+    let state = Arc::new(DaemonState {
+        client,
+        model_metadata,
+        assistant_metadata,
+        parameters,
+        save,
+        sessions: Mutex::new(HashMap::new()),
+        metrics,
+    });
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            connection = listener.accept() => {
+                let (stream, _) = connection?;
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = handle_client(stream, state).await {
+                        eprintln!("Client connection failed: {error:?}");
+                    }
+                });
+            }
+        }
+    }
+
+    std::fs::remove_file(&socket_path)?;
+    Ok(())
+}
+
+async fn send(writer: &mut OwnedWriteHalf, message: &DaemonMessage) -> Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+async fn handle_client(stream: UnixStream, state: Arc<DaemonState>) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut attached: Option<Session> = None;
+
+    while let Some(line) = lines.next_line().await? {
+        match serde_json::from_str(&line)? {
+            ClientMessage::ListSessions => {
+                let mut names: Vec<_> = state.sessions.lock().await.keys().cloned().collect();
+                names.sort_unstable();
+                send(&mut writer, &DaemonMessage::Sessions { names }).await?;
+            }
+            ClientMessage::Attach { session } => {
+                let session = {
+                    let mut sessions = state.sessions.lock().await;
+                    match sessions.get(&session) {
+                        Some(session) => session.clone(),
+                        None => {
+                            let mut chat_history = ChatHistory::new(
+                                state.model_metadata.clone(),
+                                state.assistant_metadata.clone(),
+                            )?;
+                            if let Some(storage_format) = state.save {
+                                chat_history.set_storage_format(storage_format);
+                            }
+                            let chat_history = Arc::new(Mutex::new(chat_history));
+                            sessions.insert(session, chat_history.clone());
+                            chat_history
+                        }
+                    }
+                };
+                let messages = session
+                    .lock()
+                    .await
+                    .messages()
+                    .iter()
+                    .filter(|message| !message.role.is_instruction())
+                    .map(|message| HistoryMessage {
+                        role: message.role,
+                        content: message.content.clone(),
+                    })
+                    .collect();
+                send(&mut writer, &DaemonMessage::History { messages }).await?;
+                attached = Some(session);
+            }
+            ClientMessage::Ask { question } => {
+                let Some(session) = &attached else {
+                    send(
+                        &mut writer,
+                        &DaemonMessage::Error {
+                            message: String::from("Not attached to a session"),
+                        },
+                    )
+                    .await?;
+                    continue;
+                };
+                let (content_sender, mut content_receiver) = mpsc::unbounded_channel();
+                // generate on its own task so a closed terminal doesn't cancel the answer
+                let generation = tokio::spawn(generate(
+                    session.clone(),
+                    state.clone(),
+                    question,
+                    content_sender,
+                ));
+                while let Some(content) = content_receiver.recv().await {
+                    send(&mut writer, &DaemonMessage::Content { content }).await?;
+                }
+                let reply = match generation.await? {
+                    Ok(()) => DaemonMessage::Done,
+                    Err(error) => DaemonMessage::Error {
+                        message: error.to_string(),
+                    },
+                };
+                send(&mut writer, &reply).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn generate(
+    session: Session,
+    state: Arc<DaemonState>,
+    question: String,
+    content_sender: mpsc::UnboundedSender<String>,
+) -> Result<()> {
+    let mut chat_history = session.lock().await;
+    let model = chat_history.model_metadata().name.clone();
+    let started = Instant::now();
+    if let Some(trimmed) = chat_history
+        .fit_context(
+            count_text_tokens(&question),
+            &state.client,
+            &state.parameters,
+        )
+        .await?
+    {
+        _ = content_sender.send(format!("{SYSTEM_EMOJI} {trimmed}\n\n"));
+    }
+    let answer = chat_history
+        .next_message_stream(&question, &state.client, &state.parameters, |content| {
+            // the terminal may be gone, keep generating anyway
+            _ = content_sender.send(content.to_owned());
+            Ok(())
+        })
+        .await;
+    let answer = match answer {
+        Ok(answer) => answer,
+        Err(error) => {
+            state.metrics.record_error(&model, started.elapsed());
+            return Err(error);
+        }
+    };
+    // streamed answers don't report usage so tokens are estimated
+    let completion_tokens = count_text_tokens(&answer) as u64;
+    let prompt_tokens = (chat_history.count_tokens() as u64).saturating_sub(completion_tokens);
+    state
+        .metrics
+        .record_answer(&model, started.elapsed(), prompt_tokens, completion_tokens);
+    if state.save.is_some() {
+        chat_history.save_to_file()?;
+    }
+    Ok(())
+}
+
+/// Line-delimited JSON connection to the daemon
+struct DaemonConnection {
+    lines: tokio::io::Lines<BufReader<tokio::net::unix::OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl DaemonConnection {
+    async fn connect() -> Result<Self> {
+        let socket_path = socket_path()?;
+        let stream = UnixStream::connect(&socket_path)
+            .await
+            .context("Daemon isn't running, start it with gpt-cli daemon")?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        })
+    }
+
+    async fn send(&mut self, message: &ClientMessage) -> Result<()> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<DaemonMessage> {
+        let line = self
+            .lines
+            .next_line()
+            .await?
+            .context("Daemon closed the connection")?;
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+/// connect the terminal to a session kept by the daemon
+///
+/// Ctrl-D detaches, the session stays alive in the daemon
+pub async fn attach(
+    session: Option<&str>,
+    line_editor: &mut LineEditor,
+    term: &Term,
+    term_theme: &ColorfulTheme,
+    render_options: &RenderOptions,
+) -> Result<()> {
+    let mut connection = DaemonConnection::connect().await?;
+
+    let session = match session {
+        Some(session) => session.to_owned(),
+        None => {
+            connection.send(&ClientMessage::ListSessions).await?;
+            let DaemonMessage::Sessions { mut names } = connection.receive().await? else {
+                anyhow::bail!("Unexpected reply from daemon");
+            };
+            if names.is_empty() {
+                names.push(String::from(DEFAULT_SESSION_NAME));
+            }
+            let selection = FuzzySelect::with_theme(term_theme)
+                .with_prompt("Select session")
+                .items(&names)
+                .default(0)
+                .interact_on(term)?;
+            names.swap_remove(selection)
+        }
+    };
+
+    connection
+        .send(&ClientMessage::Attach {
+            session: session.clone(),
+        })
+        .await?;
+    let DaemonMessage::History { messages } = connection.receive().await? else {
+        anyhow::bail!("Unexpected reply from daemon");
+    };
+    for message in &messages {
+        match message.role {
+            Role::User => term.write_line(&format!(
+                "{QUESTION_MARK_EMOJI} User:\n{}\n",
+                message.content
+            ))?,
+            Role::Assistant => term.write_line(&format!(
+                "{ROBOT_EMOJI} ChatGPT:\n{}\n",
+                render_text(&message.content, term, render_options)
+            ))?,
+            role => term.write_line(&format!(
+                "{SYSTEM_EMOJI} {}:\n{}\n",
+                role.label(),
+                message.content
+            ))?,
+        }
+    }
+    term.write_line(&format!(
+        "{SYSTEM_EMOJI} Attached to session {session} with {} messages. Press Ctrl-D to detach",
+        messages.len()
+    ))?;
+
+    loop {
+        let Some(question) = line_editor.read_line("Question:")? else {
+            term.write_line(&format!(
+                "{SYSTEM_EMOJI} Detached, session {session} keeps running in the daemon"
+            ))?;
+            return Ok(());
+        };
+        if question.trim().is_empty() {
+            continue;
+        }
+
+        connection.send(&ClientMessage::Ask { question }).await?;
+        term.write_line(&format!("\n{ROBOT_EMOJI} ChatGPT:\n"))?;
+        let mut renderer = ResponseRenderer::new(term, render_options);
+        let error = loop {
+            match connection.receive().await? {
+                DaemonMessage::Content { content } => renderer.write(&content)?,
+                DaemonMessage::Done => break None,
+                DaemonMessage::Error { message } => break Some(message),
+                _ => anyhow::bail!("Unexpected reply from daemon"),
+            }
+        };
+        renderer.finish()?;
+        term.write_line("\n")?;
+        if let Some(message) = error {
+            term.write_line(&format!("Daemon failed to answer: {message}"))?;
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use crate::{conversation::count_text_tokens, messages::ChatMessage};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+/// blocks smaller than this stay, a reference wouldn't save much
+const MIN_BLOCK_TOKENS: usize = 50;
+
+/// Replaces text the conversation already contains with references to where it was first seen
+///
+/// content is compared in blocks separated by blank lines, ignoring trailing whitespace
+#[derive(Debug, Default)]
+pub struct Deduplicator {
+    /// hash of a block and where it was first seen
+    seen: HashMap<u64, String>,
+}
+
+impl Deduplicator {
+    /// knows the blocks of earlier messages and the draft
+    pub fn new(messages: &[ChatMessage], draft: &[String]) -> Self {
+        let mut deduplicator = Self::default();
+        for (number, message) in messages.iter().enumerate() {
+            deduplicator.remember(&format!("message {number}"), &message.content);
+        }
+        for (number, snippet) in draft.iter().enumerate() {
+            deduplicator.remember(&format!("snippet {} of the draft", number + 1), snippet);
+        }
+        deduplicator
+    }
+
+    fn remember(&mut self, source: &str, content: &str) {
+        self.seen
+            .entry(block_hash(content))
+            .or_insert_with(|| source.to_owned());
+        for block in blocks(content) {
+            self.seen
+                .entry(block_hash(block))
+                .or_insert_with(|| source.to_owned());
+        }
+    }
+
+    /// content with blocks seen before replaced by references, and the tokens that saves
+    ///
+    /// the content is remembered so later attachments can refer to it
+    pub fn deduplicate(&mut self, source: &str, content: &str) -> (String, usize) {
+        if let Some(first_seen) = self.seen.get(&block_hash(content)) {
+            let reference = format!("[identical to the content of {first_seen}, not repeated]");
+            let saved = count_text_tokens(content).saturating_sub(count_text_tokens(&reference));
+            return (reference, saved);
+        }
+
+        let mut deduplicated = String::with_capacity(content.len());
+        let mut saved = 0;
+        for block in blocks(content) {
+            let tokens = count_text_tokens(block);
+            match self.seen.get(&block_hash(block)) {
+                Some(first_seen) if tokens >= MIN_BLOCK_TOKENS => {
+                    let reference = format!(
+                        "[{} lines identical to {first_seen}, not repeated]",
+                        block.lines().count()
+                    );
+                    saved += tokens.saturating_sub(count_text_tokens(&reference));
+                    deduplicated.push_str(&reference);
+                }
+                _ => deduplicated.push_str(block),
+            }
+            deduplicated.push_str("\n\n");
+        }
+        self.remember(source, content);
+        (deduplicated.trim_end().to_owned(), saved)
+    }
+}
+
+/// text between blank lines
+fn blocks(content: &str) -> impl Iterator<Item = &str> {
+    content
+        .split("\n\n")
+        .map(|block| block.trim_matches('\n'))
+        .filter(|block| !block.trim().is_empty())
+}
+
+fn block_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for line in text.trim().lines() {
+        line.trim_end().hash(&mut hasher);
+    }
+    hasher.finish()
+}
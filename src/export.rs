@@ -0,0 +1,324 @@
+use crate::{
+    chat_manager::ChatHistory,
+    messages::{ChatMessage, Role},
+    utils::TimestampConfig,
+};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+
+/// folder inside the vault that holds the conversation notes
+const VAULT_FOLDER: &str = "gpt-cli";
+
+const PAGE_STYLE: &str = "body { font-family: sans-serif; max-width: 50em; margin: auto; padding: 1em; }
+.message { white-space: pre-wrap; border-left: 3px solid #ccc; padding-left: 1em; margin-bottom: 1em; }
+.message pre { white-space: pre; overflow-x: auto; background: #f5f5f5; padding: 0.5em; }
+.user { border-color: #4a90d9; }
+.assistant { border-color: #5cb85c; }
+.system { border-color: #999; color: #666; }
+.tool { border-color: #d9a44a; font-family: monospace; }
+.note { white-space: normal; background: #fff8dc; padding: 0.25em 0.5em; font-style: italic; }";
+
+/// Format of a shared transcript
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// markdown with a heading per message
+    Md,
+    /// standalone styled HTML page
+    Html,
+    /// title, model and messages as JSON
+    Json,
+}
+
+/// Conversation as it's exported to JSON
+#[derive(Serialize)]
+struct JsonExport<'a> {
+    title: Option<String>,
+    started: Option<String>,
+    model: &'a str,
+    persona: &'a str,
+    messages: &'a [ChatMessage],
+}
+
+/// Front matter of a conversation note in a vault
+#[derive(Serialize)]
+struct VaultFrontMatter<'a> {
+    title: String,
+    date: Option<String>,
+    model: &'a str,
+    persona: &'a str,
+    tags: &'a [String],
+}
+
+fn title(chat_history: &ChatHistory) -> String {
+    chat_history
+        .conversation_title()
+        .unwrap_or("untitled")
+        .replace('_', " ")
+}
+
+/// role and speaker shown above a message
+pub fn message_label(message: &ChatMessage) -> String {
+    match &message.name {
+        Some(name) => format!("{} ({name})", message.role.label()),
+        None => message.role.label().to_owned(),
+    }
+}
+
+/// start, model and persona of the conversation
+fn subtitle(chat_history: &ChatHistory, timestamps: &TimestampConfig) -> String {
+    let mut parts = vec![];
+    if let Some(date) = chat_history.conversation_start() {
+        parts.push(timestamps.display(&date));
+    }
+    parts.push(chat_history.model_metadata().name.clone());
+    let persona = &chat_history.assistant_metadata().name;
+    if !persona.is_empty() {
+        parts.push(format!("persona {persona}"));
+    }
+    parts.join(" · ")
+}
+
+/// every message as markdown, tool calls and outputs are fenced
+pub fn to_markdown(chat_history: &ChatHistory, timestamps: &TimestampConfig) -> String {
+    let mut markdown = format!(
+        "# {}\n\n*{}*\n\n",
+        title(chat_history),
+        subtitle(chat_history, timestamps)
+    );
+    for message in chat_history.messages() {
+        if message.content.is_empty() && message.tool_calls.is_empty() {
+            continue;
+        }
+        markdown.push_str(&format!("## {}\n\n", message_label(message)));
+        match message.role {
+            Role::Tool | Role::Function => {
+                markdown.push_str(&format!("```\n{}\n```\n\n", message.content.trim_end()))
+            }
+            _ if !message.content.is_empty() => {
+                markdown.push_str(message.content.trim_end());
+                markdown.push_str("\n\n");
+            }
+            _ => {}
+        }
+        for tool_call in &message.tool_calls {
+            markdown.push_str(&format!(
+                "Calls `{}`:\n\n```json\n{}\n```\n\n",
+                tool_call.name, tool_call.arguments
+            ));
+        }
+        for version in &message.files {
+            markdown.push_str(&format!("> **File:** `{}`\n\n", version.label()));
+        }
+        for note in &message.notes {
+            markdown.push_str(&format!("> **Note:** {note}\n\n"));
+        }
+    }
+    markdown
+}
+
+/// the conversation as a page that needs nothing else to display
+pub fn to_html(chat_history: &ChatHistory, timestamps: &TimestampConfig) -> String {
+    let title = title(chat_history);
+    let body = format!(
+        "<h1>{}</h1><p><small>{}</small></p>{}",
+        html_escape(&title),
+        html_escape(&subtitle(chat_history, timestamps)),
+        messages_html(chat_history)
+    );
+    html_page(&title, &body)
+}
+
+pub fn to_json(chat_history: &ChatHistory) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&JsonExport {
+        title: chat_history.conversation_title().map(str::to_owned),
+        started: chat_history
+            .conversation_start()
+            .map(|date| date.to_rfc3339()),
+        model: &chat_history.model_metadata().name,
+        persona: &chat_history.assistant_metadata().name,
+        messages: chat_history.messages(),
+    })?)
+}
+
+/// write conversations as notes into an Obsidian style vault, returns how many were written
+///
+/// notes link their tags so tag pages collect backlinks, with `daily_note` every
+/// conversation is also linked from the daily note of the day it started
+pub fn write_vault(
+    conversations: &[ChatHistory],
+    vault: &Path,
+    daily_note: bool,
+    timestamps: &TimestampConfig,
+) -> Result<usize> {
+    let folder = vault.join(VAULT_FOLDER);
+    std::fs::create_dir_all(&folder)
+        .with_context(|| format!("failed to create {}", folder.display()))?;
+    for chat_history in conversations {
+        let name = note_name(chat_history);
+        std::fs::write(
+            folder.join(format!("{name}.md")),
+            vault_note(chat_history, timestamps)?,
+        )?;
+
+        let Some(date) = chat_history.conversation_start().filter(|_| daily_note) else {
+            continue;
+        };
+        let daily_note_path = vault.join(format!("{}.md", date.format("%Y-%m-%d")));
+        let mut daily_entries = std::fs::read_to_string(&daily_note_path).unwrap_or_default();
+        let link = format!("[[{VAULT_FOLDER}/{name}|{}]]", title(chat_history));
+        // exporting again shouldn't link a conversation twice
+        if !daily_entries.contains(&link) {
+            if !daily_entries.is_empty() && !daily_entries.ends_with('\n') {
+                daily_entries.push('\n');
+            }
+            daily_entries.push_str(&format!("- {link}\n"));
+            std::fs::write(&daily_note_path, daily_entries)?;
+        }
+    }
+    Ok(conversations.len())
+}
+
+/// file name of a note, stable so exporting again replaces it
+fn note_name(chat_history: &ChatHistory) -> String {
+    let date = chat_history
+        .conversation_start()
+        .map(|date| date.format("%Y-%m-%d %H%M").to_string())
+        .unwrap_or_default();
+    // characters that break file names or wiki links
+    let title: String = title(chat_history)
+        .chars()
+        .filter(|character| !"/\\:[]#^|".contains(*character))
+        .collect();
+    format!("{date} {title}").trim().to_owned()
+}
+
+fn vault_note(chat_history: &ChatHistory, timestamps: &TimestampConfig) -> Result<String> {
+    let front_matter = serde_yaml::to_string(&VaultFrontMatter {
+        title: title(chat_history),
+        date: chat_history
+            .conversation_start()
+            .map(|date| date.to_rfc3339()),
+        model: &chat_history.model_metadata().name,
+        persona: &chat_history.assistant_metadata().name,
+        tags: chat_history.tags(),
+    })?;
+    let mut note = format!("---\n{front_matter}---\n\n");
+    if !chat_history.tags().is_empty() {
+        let links: Vec<String> = chat_history
+            .tags()
+            .iter()
+            .map(|tag| format!("[[{tag}]]"))
+            .collect();
+        note.push_str(&format!("Tags: {}\n\n", links.join(" ")));
+    }
+    note.push_str(&to_markdown(chat_history, timestamps));
+    Ok(note)
+}
+
+/// a heading and a block per message
+pub fn messages_html(chat_history: &ChatHistory) -> String {
+    let mut html = String::new();
+    for message in chat_history.messages() {
+        let class = match message.role {
+            Role::System | Role::Developer => "system",
+            Role::Assistant => "assistant",
+            Role::User => "user",
+            Role::Tool | Role::Function => "tool",
+        };
+        let mut content = content_html(&message.content);
+        for tool_call in &message.tool_calls {
+            content.push_str(&format!(
+                "Calls {}:<pre><code>{}</code></pre>",
+                html_escape(&tool_call.name),
+                html_escape(&tool_call.arguments)
+            ));
+        }
+        for version in &message.files {
+            content.push_str(&format!(
+                "<p class=\"note\">File: <code>{}</code></p>",
+                html_escape(&version.label())
+            ));
+        }
+        for note in &message.notes {
+            content.push_str(&format!(
+                "<p class=\"note\">Note: {}</p>",
+                html_escape(note)
+            ));
+        }
+        html.push_str(&format!(
+            "<h3>{}</h3><div class=\"message {class}\">{content}</div>",
+            html_escape(&message_label(message))
+        ));
+    }
+    html
+}
+
+/// escaped text with fenced code blocks turned into preformatted blocks
+fn content_html(content: &str) -> String {
+    let mut html = String::new();
+    let mut code_block: Option<String> = None;
+    for line in content.split_inclusive('\n') {
+        if let Some(fence) = line.trim_start().strip_prefix("```") {
+            match code_block.take() {
+                Some(code) => html.push_str(&format!("{code}</code></pre>")),
+                None => {
+                    let language = fence.trim();
+                    code_block = Some(if language.is_empty() {
+                        String::from("<pre><code>")
+                    } else {
+                        format!("<pre><code class=\"language-{}\">", html_escape(language))
+                    });
+                }
+            }
+            continue;
+        }
+        match &mut code_block {
+            Some(code) => code.push_str(&html_escape(line)),
+            None => html.push_str(&html_escape(line)),
+        }
+    }
+    // an unterminated fence still shows its code
+    if let Some(code) = code_block {
+        html.push_str(&format!("{code}</code></pre>"));
+    }
+    html
+}
+
+pub fn html_page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>
+<html><head><meta charset=\"utf-8\"><title>{}</title><style>{PAGE_STYLE}</style></head>
+<body>{body}</body></html>",
+        html_escape(title)
+    )
+}
+
+pub fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            html_escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+        assert_eq!(html_escape("plain ü"), "plain ü");
+    }
+}
@@ -0,0 +1,69 @@
+pub use crate::messages::FileVersion;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// whether the file differs from the attached version
+///
+/// None when the repository isn't on this machine, a deleted file counts as changed
+pub fn has_changed(version: &FileVersion) -> Option<bool> {
+    if !version.repository.is_dir() {
+        return None;
+    }
+    let current = git(&version.repository, &["hash-object", "--", &version.path]);
+    Some(current.as_deref() != Some(version.blob.as_str()))
+}
+
+/// version of a file tracked by git, None outside a repository or for untracked files
+pub fn file_version(path: &Path) -> Option<FileVersion> {
+    let path = path.canonicalize().ok()?;
+    let repository = PathBuf::from(git(path.parent()?, &["rev-parse", "--show-toplevel"])?)
+        .canonicalize()
+        .ok()?;
+    let relative_path = path
+        .strip_prefix(&repository)
+        .ok()?
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    // untracked files aren't part of any version of the code
+    git(
+        &repository,
+        &["ls-files", "--error-unmatch", "--", &relative_path],
+    )?;
+    // written to the object database so later attachments can be sent as a diff against it
+    let blob = git(&repository, &["hash-object", "-w", "--", &relative_path])?;
+    Some(FileVersion {
+        repository,
+        path: relative_path,
+        blob,
+    })
+}
+
+/// content of an earlier version, None when the repository no longer has it
+pub fn read_version(version: &FileVersion) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(&version.repository)
+        .args(["cat-file", "blob", &version.blob])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+/// trimmed output of a git command that succeeded
+fn git(directory: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(args)
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    (output.status.success() && !stdout.is_empty()).then_some(stdout)
+}
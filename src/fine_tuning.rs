@@ -0,0 +1,155 @@
+use crate::{chat_client::openai_http_client, configuration::AppConfig, utils::TimestampConfig};
+use anyhow::{Context, Result};
+use chrono::{Local, TimeZone};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct UploadedFile {
+    pub id: String,
+    pub filename: String,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FineTuningJob {
+    pub id: String,
+    pub model: String,
+    pub status: String,
+    pub created_at: i64,
+    pub fine_tuned_model: Option<String>,
+    pub trained_tokens: Option<u64>,
+    pub error: Option<FineTuningError>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FineTuningError {
+    pub message: Option<String>,
+}
+
+impl FineTuningJob {
+    pub fn summary(&self, timestamps: &TimestampConfig) -> String {
+        let created = Local
+            .timestamp_opt(self.created_at, 0)
+            .single()
+            .map(|created| timestamps.display(&created))
+            .unwrap_or_default();
+        let mut summary = format!(
+            "{} {} base {} created {created}",
+            self.id, self.status, self.model
+        );
+        if let Some(fine_tuned_model) = &self.fine_tuned_model {
+            summary.push_str(&format!(" model {fine_tuned_model}"));
+        }
+        if let Some(trained_tokens) = self.trained_tokens {
+            summary.push_str(&format!(" trained on {trained_tokens} tokens"));
+        }
+        if let Some(message) = self.error.as_ref().and_then(|error| error.message.as_ref()) {
+            summary.push_str(&format!(" error: {message}"));
+        }
+        summary
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JobList {
+    data: Vec<FineTuningJob>,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateJobRequest<'a> {
+    training_file: &'a str,
+    model: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suffix: Option<&'a str>,
+}
+
+/// async-openai doesn't cover the fine-tuning jobs API so we talk to it directly
+pub struct FineTuningClient {
+    http: reqwest::Client,
+    api_base: String,
+    api_key: String,
+}
+
+impl FineTuningClient {
+    pub fn new(config: &AppConfig) -> Result<Self> {
+        Ok(Self {
+            http: openai_http_client(config)?,
+            api_base: config.api_base().to_owned(),
+            api_key: config.open_ai_api_key.clone(),
+        })
+    }
+
+    /// upload JSONL training data
+    pub async fn upload_training_file(
+        &self,
+        file_name: &str,
+        jsonl: String,
+    ) -> Result<UploadedFile> {
+        let part = reqwest::multipart::Part::text(jsonl)
+            .file_name(file_name.to_owned())
+            .mime_str("application/jsonl")?;
+        let form = reqwest::multipart::Form::new()
+            .text("purpose", "fine-tune")
+            .part("file", part);
+        let response = self
+            .http
+            .post(format!("{}/files", self.api_base))
+            .bearer_auth(&self.api_key)
+            .multipart(form)
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+
+    pub async fn create_job(
+        &self,
+        training_file: &str,
+        model: &str,
+        suffix: Option<&str>,
+    ) -> Result<FineTuningJob> {
+        let request = CreateJobRequest {
+            training_file,
+            model,
+            suffix,
+        };
+        let response = self
+            .http
+            .post(format!("{}/fine_tuning/jobs", self.api_base))
+            .bearer_auth(&self.api_key)
+            .json(&request)
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+
+    pub async fn list_jobs(&self) -> Result<Vec<FineTuningJob>> {
+        let response = self
+            .http
+            .get(format!("{}/fine_tuning/jobs", self.api_base))
+            .bearer_auth(&self.api_key)
+            .query(&[("limit", "20")])
+            .send()
+            .await?;
+        let list: JobList = parse_response(response).await?;
+        Ok(list.data)
+    }
+
+    pub async fn job_status(&self, job_id: &str) -> Result<FineTuningJob> {
+        let response = self
+            .http
+            .get(format!("{}/fine_tuning/jobs/{job_id}", self.api_base))
+            .bearer_auth(&self.api_key)
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+}
+
+pub async fn parse_response<T: DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        anyhow::bail!("OpenAI API returned {status}: {body}");
+    }
+    serde_json::from_str(&body).context("failed to parse OpenAI API response")
+}
@@ -0,0 +1,366 @@
+use crate::{
+    chat_manager::ChatHistory, conversation_view::ConversationPane, projects::ResumeScope,
+    utils::TimestampConfig,
+};
+use anyhow::{Context, Result};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+use std::path::PathBuf;
+
+/// Saved conversation shown in the list
+struct Entry {
+    path: PathBuf,
+    chat_history: ChatHistory,
+    date: String,
+    tokens: i64,
+}
+
+impl Entry {
+    fn title(&self) -> String {
+        self.chat_history
+            .conversation_title()
+            .unwrap_or("untitled")
+            .replace('_', " ")
+    }
+
+    /// tags after the title, e.g. `  #rust #work`
+    fn tag_list(&self) -> String {
+        self.chat_history
+            .tags()
+            .iter()
+            .map(|tag| format!("  #{tag}"))
+            .collect()
+    }
+
+    fn matches(&self, filter: &str) -> bool {
+        filter.is_empty()
+            || self.title().to_lowercase().contains(filter)
+            || self
+                .chat_history
+                .tags()
+                .iter()
+                .any(|tag| tag.contains(filter))
+            || self
+                .chat_history
+                .model_metadata()
+                .name
+                .to_lowercase()
+                .contains(filter)
+            || self
+                .chat_history
+                .working_directory()
+                .is_some_and(|directory| {
+                    directory.to_string_lossy().to_lowercase().contains(filter)
+                })
+    }
+}
+
+enum Mode {
+    Browse,
+    /// typing a filter for titles, tags, models and directories
+    Filter,
+    /// typing a new title
+    Rename(String),
+    ConfirmDelete,
+}
+
+/// Full screen browser for saved conversations
+struct Browser<'a> {
+    entries: Vec<Entry>,
+    /// indices into entries that match the filter
+    visible: Vec<usize>,
+    list_state: ListState,
+    filter: String,
+    mode: Mode,
+    /// preview of the selected conversation
+    pane: ConversationPane,
+    status: Option<String>,
+    timestamps: &'a TimestampConfig,
+}
+
+/// what to do after a key press
+enum Action {
+    Continue,
+    Quit,
+    Resume(PathBuf),
+}
+
+/// browse saved conversations, returns the conversation to resume
+///
+/// only conversations in the scope are listed
+pub fn browse(timestamps: &TimestampConfig, scope: &ResumeScope) -> Result<Option<PathBuf>> {
+    let mut entries: Vec<_> = ChatHistory::get_all_saved_conversations()?
+        .into_iter()
+        .filter_map(|path| {
+            // skip files we can't parse instead of refusing to open
+            let chat_history = ChatHistory::load_from_file(&path).ok()?;
+            if !scope.contains(&chat_history) {
+                return None;
+            }
+            let date = chat_history
+                .conversation_start()
+                .map(|date| timestamps.display(&date))
+                .unwrap_or_default();
+            let tokens = chat_history.count_tokens();
+            Some(Entry {
+                path,
+                chat_history,
+                date,
+                tokens,
+            })
+        })
+        .collect();
+    if entries.is_empty() {
+        anyhow::bail!("No saved conversations in {}", scope.description());
+    }
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.chat_history.conversation_start()));
+
+    let mut browser = Browser {
+        visible: (0..entries.len()).collect(),
+        entries,
+        list_state: ListState::default().with_selected(Some(0)),
+        filter: String::new(),
+        mode: Mode::Browse,
+        pane: ConversationPane::default(),
+        status: None,
+        timestamps,
+    };
+    let mut terminal = ratatui::try_init()?;
+    let result = browser.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl Browser<'_> {
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<Option<PathBuf>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match self.handle_key(key)? {
+                Action::Continue => {}
+                Action::Quit => return Ok(None),
+                Action::Resume(path) => return Ok(Some(path)),
+            }
+        }
+    }
+
+    fn selected_index(&self) -> Option<usize> {
+        self.visible.get(self.list_state.selected()?).copied()
+    }
+
+    fn selected(&self) -> Option<&Entry> {
+        self.entries.get(self.selected_index()?)
+    }
+
+    fn select(&mut self, index: usize) {
+        self.list_state.select(if self.visible.is_empty() {
+            None
+        } else {
+            Some(index.min(self.visible.len() - 1))
+        });
+        self.pane.reset();
+    }
+
+    fn refresh_visible(&mut self) {
+        let filter = self.filter.to_lowercase();
+        self.visible = (0..self.entries.len())
+            .filter(|index| self.entries[*index].matches(&filter))
+            .collect();
+    }
+
+    fn apply_filter(&mut self) {
+        self.refresh_visible();
+        self.select(0);
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Result<Action> {
+        self.status = None;
+        match &mut self.mode {
+            Mode::Browse => return self.handle_browse_key(key),
+            Mode::Filter => match key.code {
+                KeyCode::Enter => self.mode = Mode::Browse,
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.apply_filter();
+                    self.mode = Mode::Browse;
+                }
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                    self.apply_filter();
+                }
+                KeyCode::Char(character) => {
+                    self.filter.push(character);
+                    self.apply_filter();
+                }
+                _ => {}
+            },
+            Mode::Rename(title) => match key.code {
+                KeyCode::Enter => {
+                    let title = title.trim().replace(char::is_whitespace, "_");
+                    self.mode = Mode::Browse;
+                    self.rename(title)?;
+                }
+                KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Backspace => {
+                    title.pop();
+                }
+                KeyCode::Char(character) => title.push(character),
+                _ => {}
+            },
+            Mode::ConfirmDelete => {
+                if key.code == KeyCode::Char('y') {
+                    self.delete()?;
+                }
+                self.mode = Mode::Browse;
+            }
+        }
+        Ok(Action::Continue)
+    }
+
+    fn handle_browse_key(&mut self, key: KeyEvent) -> Result<Action> {
+        if self.pane.handle_key(key) {
+            return Ok(Action::Continue);
+        }
+        let selected = self.list_state.selected().unwrap_or_default();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(Action::Quit),
+            KeyCode::Down | KeyCode::Char('j') => self.select(selected + 1),
+            KeyCode::Up | KeyCode::Char('k') => self.select(selected.saturating_sub(1)),
+            KeyCode::Home | KeyCode::Char('g') => self.select(0),
+            KeyCode::End | KeyCode::Char('G') => self.select(usize::MAX),
+            KeyCode::Char('/') => self.mode = Mode::Filter,
+            KeyCode::Char('r') => {
+                if let Some(entry) = self.selected() {
+                    let title = entry
+                        .chat_history
+                        .conversation_title()
+                        .unwrap_or_default()
+                        .to_owned();
+                    self.mode = Mode::Rename(title);
+                }
+            }
+            KeyCode::Char('d') if self.selected().is_some() => {
+                self.mode = Mode::ConfirmDelete;
+            }
+            KeyCode::Enter => {
+                if let Some(entry) = self.selected() {
+                    return Ok(Action::Resume(entry.path.clone()));
+                }
+            }
+            _ => {}
+        }
+        Ok(Action::Continue)
+    }
+
+    fn rename(&mut self, title: String) -> Result<()> {
+        let Some(index) = self.selected_index() else {
+            return Ok(());
+        };
+        if title.is_empty() || title.contains(std::path::is_separator) {
+            self.status = Some(String::from(
+                "Title can't be empty or contain path separators",
+            ));
+            return Ok(());
+        }
+        let entry = &mut self.entries[index];
+        entry.chat_history.rename(title, true)?;
+        entry.path = entry.chat_history.file_path()?;
+        self.status = Some(String::from("Renamed"));
+        Ok(())
+    }
+
+    fn delete(&mut self) -> Result<()> {
+        let Some(index) = self.selected_index() else {
+            return Ok(());
+        };
+        let entry = self.entries.remove(index);
+        std::fs::remove_file(&entry.path)
+            .with_context(|| format!("failed to remove {}", entry.path.display()))?;
+        self.refresh_visible();
+        self.select(self.list_state.selected().unwrap_or_default());
+        self.status = Some(format!("Deleted {}", entry.title()));
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, footer] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        // the side pane takes room from the list
+        let list_width = if self.pane.shows_reference() { 30 } else { 40 };
+        let [list_area, preview_area] = Layout::horizontal([
+            Constraint::Percentage(list_width),
+            Constraint::Percentage(100 - list_width),
+        ])
+        .areas(main);
+
+        let dim = Style::new().fg(Color::DarkGray);
+        let items: Vec<_> = self
+            .visible
+            .iter()
+            .map(|index| {
+                let entry = &self.entries[*index];
+                ListItem::new(vec![
+                    Line::from(vec![
+                        Span::styled(entry.title(), Style::new().add_modifier(Modifier::BOLD)),
+                        Span::styled(entry.tag_list(), Style::new().fg(Color::Cyan)),
+                    ]),
+                    Line::from(Span::styled(
+                        format!(
+                            "{}  {}  {} tokens",
+                            entry.date,
+                            entry.chat_history.model_metadata().name,
+                            entry.tokens
+                        ),
+                        dim,
+                    )),
+                ])
+            })
+            .collect();
+        let title = if self.filter.is_empty() {
+            format!(" Conversations ({}) ", self.visible.len())
+        } else {
+            format!(
+                " Conversations ({}/{}) matching {} ",
+                self.visible.len(),
+                self.entries.len(),
+                self.filter
+            )
+        };
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.list_state);
+
+        self.pane.draw(
+            frame,
+            preview_area,
+            "Preview",
+            self.selected().map(|entry| &entry.chat_history),
+            self.timestamps,
+        );
+
+        let footer_text = match &self.mode {
+            Mode::Browse => self.status.clone().unwrap_or_else(|| {
+                format!(
+                    "↑↓ select  enter resume  r rename  d delete  / filter  {}  q quit",
+                    ConversationPane::KEYS
+                )
+            }),
+            Mode::Filter => format!("Filter: {}", self.filter),
+            Mode::Rename(title) => format!("New title: {title}"),
+            Mode::ConfirmDelete => String::from("Delete this conversation? y/n"),
+        };
+        frame.render_widget(Paragraph::new(footer_text), footer);
+    }
+}
@@ -0,0 +1,408 @@
+use crate::{
+    chat_manager::{normalize_tag, ChatHistory},
+    export::{html_escape, html_page, messages_html},
+    storage,
+    utils::TimestampConfig,
+};
+use anyhow::{Context, Result};
+use dialoguer::console::Term;
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+/// Serve saved conversations as read-only HTML pages
+///
+/// This is deliberately tiny and only answers GET requests
+pub async fn serve_history(address: &str, timestamps: TimestampConfig, term: &Term) -> Result<()> {
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("failed to bind history server to {address}"))?;
+
+    term.write_line(&format!("Serving conversation history on http://{address}"))?;
+
+    let timestamps = Arc::new(timestamps);
+    let index = Arc::new(Mutex::new(HistoryIndex::default()));
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let timestamps = timestamps.clone();
+        let index = index.clone();
+        tokio::spawn(async move {
+            if let Err(error) = handle_connection(stream, &timestamps, &index).await {
+                eprintln!("Failed to handle request: {error:?}");
+            }
+        });
+    }
+}
+
+/// Saved conversation as the server lists it
+enum Entry {
+    Conversation(Box<ChatHistory>),
+    /// encrypted files are never decrypted by the server, gpg could ask for a passphrase
+    Encrypted,
+    /// files that can't be parsed are left out of the list
+    Invalid,
+}
+
+/// Conversations loaded once and again only when their file changes
+#[derive(Default)]
+struct HistoryIndex {
+    entries: HashMap<PathBuf, (SystemTime, Entry)>,
+}
+
+impl HistoryIndex {
+    /// pick up new, changed and deleted files, newest conversations first
+    fn refresh(&mut self) -> Result<Vec<(&PathBuf, &Entry)>> {
+        let paths = ChatHistory::get_all_saved_conversations()?;
+        self.entries.retain(|path, _| paths.contains(path));
+        for path in paths {
+            let modified = std::fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            if self
+                .entries
+                .get(&path)
+                .is_some_and(|(loaded, _)| *loaded == modified)
+            {
+                continue;
+            }
+            let entry = if storage::is_encrypted(&path) {
+                Entry::Encrypted
+            } else {
+                match ChatHistory::load_from_file(&path) {
+                    Ok(chat_history) => Entry::Conversation(Box::new(chat_history)),
+                    Err(_) => Entry::Invalid,
+                }
+            };
+            self.entries.insert(path, (modified, entry));
+        }
+
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(_, (_, entry))| !matches!(entry, Entry::Invalid))
+            .map(|(path, (modified, entry))| (path, modified, entry))
+            .collect();
+        entries.sort_by(|(_, a_modified, a), (_, b_modified, b)| {
+            let start = |entry: &Entry| match entry {
+                Entry::Conversation(chat_history) => chat_history.conversation_start(),
+                _ => None,
+            };
+            start(b)
+                .cmp(&start(a))
+                .then_with(|| b_modified.cmp(a_modified))
+        });
+        Ok(entries
+            .into_iter()
+            .map(|(path, _, entry)| (path, entry))
+            .collect())
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    timestamps: &TimestampConfig,
+    index: &Mutex<HistoryIndex>,
+) -> Result<()> {
+    let mut reader = BufReader::new(&mut stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+
+    // drain headers, we don't need any of them
+    loop {
+        let mut header = String::new();
+        let read = reader.read_line(&mut header).await?;
+        if read == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+
+    let (status, body) = if method != "GET" {
+        (
+            "405 Method Not Allowed",
+            html_page("Error", "<p>Only GET is supported</p>"),
+        )
+    } else {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut index = index.lock().unwrap_or_else(|error| error.into_inner());
+        match path {
+            "/" => (
+                "200 OK",
+                render_index(
+                    &mut index,
+                    query_param(query, "q").as_deref(),
+                    query_param(query, "tag").as_deref(),
+                    timestamps,
+                )?,
+            ),
+            "/conversation" => match query_param(query, "file") {
+                Some(file_name) => match render_conversation(&mut index, &file_name, timestamps)? {
+                    Some(body) => ("200 OK", body),
+                    None => (
+                        "404 Not Found",
+                        html_page("Not found", "<p>Conversation not found</p>"),
+                    ),
+                },
+                None => (
+                    "400 Bad Request",
+                    html_page("Error", "<p>Missing file parameter</p>"),
+                ),
+            },
+            _ => (
+                "404 Not Found",
+                html_page("Not found", "<p>Page not found</p>"),
+            ),
+        }
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+fn render_index(
+    index: &mut HistoryIndex,
+    search: Option<&str>,
+    tag: Option<&str>,
+    timestamps: &TimestampConfig,
+) -> Result<String> {
+    let search = search.unwrap_or_default();
+    let search_lowercase = search.to_lowercase();
+    let tag = tag.map(normalize_tag).filter(|tag| !tag.is_empty());
+    let entries = index.refresh()?;
+
+    let mut body = format!(
+        "<h1>Conversations</h1>
+<form action=\"/\" method=\"get\"><input type=\"search\" name=\"q\" value=\"{}\" placeholder=\"Search\">{} <button type=\"submit\">Search</button></form>",
+        html_escape(search),
+        tag.as_deref()
+            .map(|tag| format!(
+                "<input type=\"hidden\" name=\"tag\" value=\"{}\">",
+                html_escape(tag)
+            ))
+            .unwrap_or_default()
+    );
+
+    let tags: BTreeSet<&str> = entries
+        .iter()
+        .filter_map(|(_, entry)| match entry {
+            Entry::Conversation(chat_history) => Some(chat_history.tags()),
+            _ => None,
+        })
+        .flatten()
+        .map(String::as_str)
+        .collect();
+    if !tags.is_empty() {
+        body.push_str("<p>");
+        for available in &tags {
+            let link = format!(
+                "<a href=\"/?tag={}\">#{}</a> ",
+                url_encode(available),
+                html_escape(available)
+            );
+            if Some(*available) == tag.as_deref() {
+                body.push_str(&format!("<strong>{link}</strong>"));
+            } else {
+                body.push_str(&link);
+            }
+        }
+        if tag.is_some() {
+            body.push_str("<a href=\"/\">all</a>");
+        }
+        body.push_str("</p>");
+    }
+
+    body.push_str("<ul>");
+    for (path, entry) in entries {
+        let file_name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_str()
+            .unwrap_or_default();
+        let chat_history = match entry {
+            Entry::Conversation(chat_history) => chat_history,
+            // nothing to search or filter by without decrypting
+            Entry::Encrypted if search.is_empty() && tag.is_none() => {
+                body.push_str(&format!(
+                    "<li>{} <small>encrypted, open it with gpt-cli --file</small></li>",
+                    html_escape(file_name)
+                ));
+                continue;
+            }
+            Entry::Encrypted | Entry::Invalid => continue,
+        };
+        if !search_lowercase.is_empty() && !matches_search(chat_history, &search_lowercase) {
+            continue;
+        }
+        if tag.as_deref().is_some_and(|tag| !chat_history.has_tag(tag)) {
+            continue;
+        }
+        let title = chat_history.conversation_title().unwrap_or("untitled");
+        let date = chat_history
+            .conversation_start()
+            .map(|date| timestamps.display(&date))
+            .unwrap_or_default();
+        let tags: String = chat_history
+            .tags()
+            .iter()
+            .map(|tag| format!(" #{}", html_escape(tag)))
+            .collect();
+        body.push_str(&format!(
+            "<li><a href=\"/conversation?file={}\">{}</a> <small>{} {}{tags}</small></li>",
+            url_encode(file_name),
+            html_escape(&title.replace('_', " ")),
+            html_escape(&date),
+            html_escape(&chat_history.model_metadata().name),
+        ));
+    }
+    body.push_str("</ul>");
+
+    Ok(html_page("Conversations", &body))
+}
+
+fn matches_search(chat_history: &ChatHistory, search_lowercase: &str) -> bool {
+    chat_history
+        .conversation_title()
+        .map(|title| title.to_lowercase().contains(search_lowercase))
+        .unwrap_or_default()
+        || chat_history
+            .messages()
+            .iter()
+            .any(|message| message.content.to_lowercase().contains(search_lowercase))
+}
+
+fn render_conversation(
+    index: &mut HistoryIndex,
+    file_name: &str,
+    timestamps: &TimestampConfig,
+) -> Result<Option<String>> {
+    // only serve files from the conversation list so paths can't escape the cache dir
+    let chat_history = match index
+        .refresh()?
+        .into_iter()
+        .find(|(path, _)| path.file_name().and_then(|name| name.to_str()) == Some(file_name))
+        .map(|(_, entry)| entry)
+    {
+        Some(Entry::Conversation(chat_history)) => chat_history,
+        Some(Entry::Encrypted) => {
+            return Ok(Some(html_page(
+                "Encrypted",
+                &format!(
+                    "<p><a href=\"/\">&larr; All conversations</a></p><p>{} is encrypted, open it with gpt-cli --file</p>",
+                    html_escape(file_name)
+                ),
+            )))
+        }
+        _ => return Ok(None),
+    };
+    let title = chat_history
+        .conversation_title()
+        .unwrap_or("untitled")
+        .replace('_', " ");
+
+    let started = chat_history
+        .conversation_start()
+        .map(|date| timestamps.display(&date))
+        .unwrap_or_default();
+
+    let mut body = format!(
+        "<p><a href=\"/\">&larr; All conversations</a></p><h1>{}</h1><p><small>{} {}</small></p>",
+        html_escape(&title),
+        html_escape(&started),
+        html_escape(&chat_history.model_metadata().name),
+    );
+    body.push_str(&messages_html(chat_history));
+
+    Ok(Some(html_page(&title, &body)))
+}
+
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| url_decode(value))
+}
+
+fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' => decoded.push(b' '),
+            b'%' if index + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[index + 1..index + 3]).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        index += 2;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_query_values() {
+        assert_eq!(url_decode("borrow+checker%21"), "borrow checker!");
+        assert_eq!(url_decode("%C3%BCber"), "über");
+    }
+
+    #[test]
+    fn keeps_invalid_escapes() {
+        assert_eq!(url_decode("100%"), "100%");
+        assert_eq!(url_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let value = "tag & title/ü 100%";
+        assert_eq!(url_decode(&url_encode(value)), value);
+    }
+
+    #[test]
+    fn finds_query_params() {
+        assert_eq!(
+            query_param("q=hash+map&tag=rust", "tag").as_deref(),
+            Some("rust")
+        );
+        assert_eq!(query_param("q=hash+map", "tag"), None);
+    }
+}
@@ -0,0 +1,53 @@
+use crate::messages::Image;
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::path::Path;
+
+/// OpenAI refuses larger images
+const MAX_IMAGE_BYTES: u64 = 20 * 1024 * 1024;
+
+fn media_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// image from a local file or a URL the model fetches itself
+///
+/// files are embedded as base64 data URLs so saved conversations still show them to the model
+pub fn load_image(target: &str) -> Result<Image> {
+    if target.starts_with("http://") || target.starts_with("https://") {
+        return Ok(Image {
+            source: target.to_owned(),
+            url: target.to_owned(),
+        });
+    }
+    let path = Path::new(target);
+    let media_type = media_type(path)
+        .with_context(|| format!("{target} isn't a PNG, JPEG, GIF or WebP image"))?;
+    let size = std::fs::metadata(path)
+        .with_context(|| format!("failed to read {target}"))?
+        .len();
+    if size > MAX_IMAGE_BYTES {
+        anyhow::bail!(
+            "{target} is {} MB, images can be at most {} MB",
+            size / 1024 / 1024,
+            MAX_IMAGE_BYTES / 1024 / 1024
+        );
+    }
+    let bytes = std::fs::read(path).with_context(|| format!("failed to read {target}"))?;
+    Ok(Image {
+        source: target.to_owned(),
+        url: format!("data:{media_type};base64,{}", BASE64.encode(bytes)),
+    })
+}
+
+/// media type and base64 data of a data URL
+pub fn data_url_parts(url: &str) -> Option<(&str, &str)> {
+    url.strip_prefix("data:")?.split_once(";base64,")
+}
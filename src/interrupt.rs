@@ -0,0 +1,127 @@
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use dialoguer::console::Term;
+use std::{
+    io::IsTerminal,
+    sync::{Mutex, OnceLock},
+    thread::JoinHandle,
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+
+/// typed while an answer streams to stop it
+pub const STOP_COMMAND: &str = "/stop";
+/// exit code of a process ended by Ctrl-C
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+/// how long typed input is waited for before checking whether the answer is done
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// token of the work Ctrl-C stops right now
+///
+/// tokio keeps its SIGINT handler until the process ends, so it's installed once on first use
+/// and ends the process like the default handler when there is nothing to stop
+fn current() -> &'static Mutex<Option<CancellationToken>> {
+    static CURRENT: OnceLock<Mutex<Option<CancellationToken>>> = OnceLock::new();
+    CURRENT.get_or_init(|| {
+        tokio::spawn(async {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                let token = current().lock().expect("interrupt lock poisoned").clone();
+                match token {
+                    // a second Ctrl-C ends an answer that doesn't stop
+                    Some(token) if !token.is_cancelled() => token.cancel(),
+                    _ => {
+                        _ = Term::stdout().show_cursor();
+                        std::process::exit(INTERRUPTED_EXIT_CODE);
+                    }
+                }
+            }
+        });
+        Mutex::new(None)
+    })
+}
+
+/// Work Ctrl-C stops instead of ending the process, until it's dropped
+///
+/// one at a time, the session only streams one answer
+pub struct Stoppable {
+    token: CancellationToken,
+    /// reader of /stop and the token that ends it
+    input: Option<(CancellationToken, JoinHandle<()>)>,
+}
+
+impl Stoppable {
+    pub fn new() -> Self {
+        let token = CancellationToken::new();
+        *current().lock().expect("interrupt lock poisoned") = Some(token.clone());
+        Self { token, input: None }
+    }
+
+    /// also stop when /stop is typed, only reads input from a terminal
+    pub fn with_stop_command(mut self) -> Self {
+        if !std::io::stdin().is_terminal() {
+            return self;
+        }
+        let finished = CancellationToken::new();
+        let input = std::thread::spawn({
+            let token = self.token.clone();
+            let finished = finished.clone();
+            move || read_stop_command(&token, &finished)
+        });
+        self.input = Some((finished, input));
+        self
+    }
+
+    /// cancelled by Ctrl-C or /stop
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+impl Default for Stoppable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Stoppable {
+    fn drop(&mut self) {
+        *current().lock().expect("interrupt lock poisoned") = None;
+        if let Some((finished, input)) = self.input.take() {
+            finished.cancel();
+            // joined so keys typed after the answer go to the prompt
+            _ = input.join();
+        }
+    }
+}
+
+/// reads typed lines until one is /stop, the terminal isn't in raw mode so lines end with Enter
+///
+/// shares crossterm's event reader with the line editor, other input is dropped
+fn read_stop_command(token: &CancellationToken, finished: &CancellationToken) {
+    let mut line = String::new();
+    while !finished.is_cancelled() && !token.is_cancelled() {
+        match event::poll(INPUT_POLL_INTERVAL) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(_) => return,
+        }
+        let Ok(Event::Key(key)) = event::read() else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char(character) => line.push(character),
+            KeyCode::Backspace => {
+                line.pop();
+            }
+            KeyCode::Enter => {
+                if line.trim() == STOP_COMMAND {
+                    token.cancel();
+                }
+                line.clear();
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use crate::messages::Role;
+use anyhow::Result;
+use async_openai::types::Usage;
+use serde::Serialize;
+
+/// Line printed with --json, one JSON object per line
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonLine<'a> {
+    /// part of a streamed answer as it arrives
+    Delta { role: Role, content: &'a str },
+    /// the whole answer once it's done
+    Answer {
+        role: Role,
+        content: &'a str,
+        finish_reason: Option<&'a str>,
+        /// not reported for streamed answers by every API
+        usage: Option<Usage>,
+    },
+}
+
+impl JsonLine<'_> {
+    pub fn print(&self) -> Result<()> {
+        println!("{}", serde_json::to_string(self)?);
+        Ok(())
+    }
+}
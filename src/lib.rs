@@ -0,0 +1,70 @@
+//! Chat with OpenAI compatible models from the terminal or from Rust code
+//!
+//! [`ChatSession`] drives conversations without a terminal, it uses the same config file and
+//! saved conversations as the `gpt-cli` binary. The modules are what the binary is built from.
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! let mut session = gpt_cli::ChatSession::new()?;
+//! let answer = session.send("Name three uses of a hash map").await?;
+//! println!("{answer}");
+//! session.save().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod anthropic;
+pub mod assistants;
+pub mod attachments;
+pub mod batch;
+pub mod chat_client;
+pub mod chat_manager;
+pub mod chat_session;
+pub mod citations;
+pub mod clipboard;
+pub mod code_blocks;
+pub mod code_commands;
+pub mod commands;
+pub mod configuration;
+pub mod connectors;
+pub mod content_filter;
+pub mod conversation_import;
+#[cfg(feature = "tui")]
+pub mod conversation_view;
+#[cfg(unix)]
+pub mod daemon;
+pub mod dedup;
+pub mod export;
+pub mod file_versions;
+pub mod fine_tuning;
+#[cfg(feature = "tui")]
+pub mod history_browser;
+pub mod history_server;
+pub mod images;
+pub mod injection;
+pub mod interrupt;
+pub mod json_output;
+pub mod line_editor;
+#[cfg(unix)]
+pub mod metrics;
+pub mod model_router;
+pub mod patch;
+pub mod projects;
+#[cfg(feature = "audio")]
+pub mod realtime;
+pub mod render;
+pub mod response_buffer;
+pub mod response_cache;
+pub mod search;
+pub mod session_fifo;
+pub mod storage;
+pub mod telemetry;
+pub mod terminal_capture;
+pub mod todos;
+pub mod tools;
+pub mod training_data;
+pub mod utils;
+pub mod webhooks;
+
+pub use chat_session::ChatSession;
+pub use gpt_cli_core::{conversation, messages, redaction};
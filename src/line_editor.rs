@@ -0,0 +1,426 @@
+use crate::{
+    chat_manager::ChatHistory, commands::COMMANDS, configuration::get_project_dirs,
+    utils::ChatGptModel,
+};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use reedline::{
+    default_emacs_keybindings, default_vi_insert_keybindings, default_vi_normal_keybindings,
+    ColumnarMenu, Completer, DefaultPrompt, DefaultPromptSegment, EditCommand,
+    EditMode as ReedlineEditMode, Emacs, FileBackedHistory, History, HistoryItem, KeyCode,
+    KeyModifiers, Keybindings, MenuBuilder, Reedline, ReedlineEvent, ReedlineMenu, Signal, Span,
+    Suggestion, ValidationResult, Validator, Vi,
+};
+use serde::{Deserialize, Serialize};
+use std::{path::Path, process::Command};
+
+const QUESTION_HISTORY_FILE_NAME: &str = "question_history.txt";
+const COMPLETION_MENU_NAME: &str = "completion_menu";
+/// line that ends a question in multi-line mode
+pub const MULTILINE_TERMINATOR: &str = ";;";
+
+/// Command sent by the regenerate shortcut
+pub const REGENERATE_COMMAND: &str = "/retry";
+
+/// Keybinding scheme for the question prompt
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EditMode {
+    /// readline style emacs bindings
+    #[default]
+    Emacs,
+    /// vi bindings with normal and insert mode
+    Vi,
+}
+
+/// Keyboard shortcuts for the question prompt
+///
+/// Keys are written as `alt-r`, `ctrl-g` or `f5`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Shortcuts {
+    #[serde(default = "default_regenerate_shortcut")]
+    pub regenerate: String,
+}
+
+impl Default for Shortcuts {
+    fn default() -> Self {
+        Self {
+            regenerate: default_regenerate_shortcut(),
+        }
+    }
+}
+
+/// ctrl-r is left to reverse history search
+fn default_regenerate_shortcut() -> String {
+    String::from("alt-r")
+}
+
+fn parse_shortcut(shortcut: &str) -> Result<(KeyModifiers, KeyCode)> {
+    let shortcut = shortcut.to_lowercase();
+    let mut parts: Vec<&str> = shortcut.split('-').collect();
+    let key = parts.pop().unwrap_or_default();
+
+    let mut modifiers = KeyModifiers::NONE;
+    for modifier in parts {
+        modifiers |= match modifier {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => anyhow::bail!("Unknown modifier {modifier:?} in shortcut {shortcut:?}"),
+        };
+    }
+
+    let mut characters = key.chars();
+    let key_code = match (characters.next(), characters.next()) {
+        (Some(character), None) => KeyCode::Char(character),
+        _ => match key {
+            "enter" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "esc" => KeyCode::Esc,
+            _ => match key.strip_prefix('f').and_then(|number| number.parse().ok()) {
+                Some(number) => KeyCode::F(number),
+                None => anyhow::bail!("Unknown key {key:?} in shortcut {shortcut:?}"),
+            },
+        },
+    };
+    Ok((modifiers, key_code))
+}
+
+/// write `initial` to a temporary file, open it in `$VISUAL` or `$EDITOR` and return what was saved
+pub fn edit_text(initial: &str) -> Result<String> {
+    let path = std::env::temp_dir().join(format!("gpt-cli-question-{}.md", std::process::id()));
+    std::fs::write(&path, initial)?;
+    let edited = open_in_editor(&path);
+    let text = std::fs::read_to_string(&path);
+    _ = std::fs::remove_file(&path);
+    edited?;
+    Ok(text?)
+}
+
+/// open a file in `$VISUAL` or `$EDITOR` and wait until the editor is closed
+pub fn open_in_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().context("EDITOR is empty")?;
+    let status = Command::new(program)
+        .args(parts)
+        .arg(path)
+        .status()
+        .with_context(|| format!("failed to run {program}"))?;
+    if !status.success() {
+        anyhow::bail!("{program} exited with {status}");
+    }
+    Ok(())
+}
+
+/// Line editor used for reading user questions
+pub struct LineEditor {
+    editor: Reedline,
+    /// shown on the right side of the prompt
+    status: String,
+    /// Enter adds lines until one is `;;`
+    multiline: bool,
+}
+
+impl LineEditor {
+    /// Create line editor
+    ///
+    /// Uses the persistent global history unless `conversation_history` is set,
+    /// repeating the previous question doesn't add another entry,
+    /// `personas` are offered when completing `/persona` and `models` from the config
+    /// besides the built in ones when completing `/model`
+    pub fn new(
+        edit_mode: EditMode,
+        shortcuts: &Shortcuts,
+        history_size: usize,
+        conversation_history: Option<&[String]>,
+        personas: Vec<String>,
+        models: Vec<String>,
+    ) -> Result<Self> {
+        let (regenerate_modifiers, regenerate_key) = parse_shortcut(&shortcuts.regenerate)?;
+        let add_shortcuts = |keybindings: &mut Keybindings| {
+            keybindings.add_binding(
+                regenerate_modifiers,
+                regenerate_key,
+                ReedlineEvent::ExecuteHostCommand(REGENERATE_COMMAND.to_owned()),
+            );
+        };
+
+        let edit_mode: Box<dyn ReedlineEditMode> = match edit_mode {
+            EditMode::Emacs => {
+                let mut keybindings = default_emacs_keybindings();
+                add_common_keybindings(&mut keybindings);
+                add_shortcuts(&mut keybindings);
+                Box::new(Emacs::new(keybindings))
+            }
+            EditMode::Vi => {
+                let mut insert_keybindings = default_vi_insert_keybindings();
+                let mut normal_keybindings = default_vi_normal_keybindings();
+                add_common_keybindings(&mut insert_keybindings);
+                add_shortcuts(&mut insert_keybindings);
+                add_shortcuts(&mut normal_keybindings);
+                Box::new(Vi::new(insert_keybindings, normal_keybindings))
+            }
+        };
+
+        let history = match conversation_history {
+            Some(conversation_history) => {
+                let mut history = FileBackedHistory::new(history_size)?;
+                let mut previous = None;
+                for line in conversation_history {
+                    if previous != Some(line) {
+                        history.save(HistoryItem::from_command_line(line))?;
+                    }
+                    previous = Some(line);
+                }
+                history
+            }
+            None => {
+                let history_path = get_project_dirs()?
+                    .data_dir()
+                    .join(QUESTION_HISTORY_FILE_NAME);
+                FileBackedHistory::with_file(history_size, history_path)?
+            }
+        };
+
+        let completion_menu = ColumnarMenu::default().with_name(COMPLETION_MENU_NAME);
+
+        let editor = Reedline::create()
+            .with_edit_mode(edit_mode)
+            .with_history(Box::new(history))
+            .with_validator(Box::new(QuestionValidator { multiline: false }))
+            .with_completer(Box::new(QuestionCompleter { personas, models }))
+            .with_menu(ReedlineMenu::EngineCompleter(Box::new(completion_menu)))
+            .use_bracketed_paste(true);
+        Ok(Self {
+            editor,
+            status: String::new(),
+            multiline: false,
+        })
+    }
+
+    /// keep reading lines until one is `;;` so pasted code doesn't need Alt+Enter
+    ///
+    /// slash commands are still sent on Enter
+    pub fn with_multiline(mut self, multiline: bool) -> Self {
+        self.editor = self
+            .editor
+            .with_validator(Box::new(QuestionValidator { multiline }));
+        self.multiline = multiline;
+        self
+    }
+
+    /// set what's shown on the right of the next prompts
+    pub fn set_status(&mut self, status: String) {
+        self.status = status;
+    }
+
+    /// Read one line from the user
+    ///
+    /// Returns `None` when the user wants to quit with Ctrl-D
+    pub fn read_line(&mut self, prompt: &str) -> Result<Option<String>> {
+        let prompt = DefaultPrompt::new(
+            DefaultPromptSegment::Basic(format!("{prompt} ")),
+            if self.status.is_empty() {
+                DefaultPromptSegment::Empty
+            } else {
+                DefaultPromptSegment::Basic(self.status.clone())
+            },
+        );
+        loop {
+            match self.editor.read_line(&prompt)? {
+                Signal::Success(line) if self.multiline => {
+                    let question = line.trim_end();
+                    let question = question
+                        .strip_suffix(MULTILINE_TERMINATOR)
+                        .unwrap_or(question);
+                    return Ok(Some(question.trim_end().to_owned()));
+                }
+                // drop the backslashes used to continue lines
+                Signal::Success(line) => return Ok(Some(line.replace("\\\n", "\n"))),
+                // Ctrl-C only clears the current line
+                Signal::CtrlC => continue,
+                Signal::CtrlD => return Ok(None),
+            }
+        }
+    }
+}
+
+fn add_common_keybindings(keybindings: &mut Keybindings) {
+    keybindings.add_binding(
+        KeyModifiers::NONE,
+        KeyCode::Tab,
+        ReedlineEvent::UntilFound(vec![
+            ReedlineEvent::Menu(COMPLETION_MENU_NAME.to_owned()),
+            ReedlineEvent::MenuNext,
+        ]),
+    );
+    keybindings.add_binding(
+        KeyModifiers::ALT,
+        KeyCode::Enter,
+        ReedlineEvent::Edit(vec![EditCommand::InsertNewline]),
+    );
+}
+
+/// Keeps the prompt open while a code fence is unclosed or a line ends in `\`
+///
+/// in multi-line mode it stays open until the last line is `;;`
+struct QuestionValidator {
+    multiline: bool,
+}
+
+impl Validator for QuestionValidator {
+    fn validate(&self, line: &str) -> ValidationResult {
+        if self.multiline {
+            let terminated = line
+                .lines()
+                .last()
+                .is_some_and(|last| last.trim() == MULTILINE_TERMINATOR);
+            let command = line.starts_with('/') && !line.contains('\n');
+            return if terminated || command || line.trim().is_empty() {
+                ValidationResult::Complete
+            } else {
+                ValidationResult::Incomplete
+            };
+        }
+        let open_fence = line
+            .lines()
+            .filter(|line| line.trim_start().starts_with("```"))
+            .count()
+            % 2
+            == 1;
+        if open_fence || line.ends_with('\\') {
+            ValidationResult::Incomplete
+        } else {
+            ValidationResult::Complete
+        }
+    }
+}
+
+/// Completes slash commands, their arguments and paths
+struct QuestionCompleter {
+    personas: Vec<String>,
+    /// models from the config
+    models: Vec<String>,
+}
+
+impl Completer for QuestionCompleter {
+    fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        let line = &line[..pos];
+        let word_start = line
+            .rfind(char::is_whitespace)
+            .map(|index| index + 1)
+            .unwrap_or_default();
+        let word = &line[word_start..];
+        let span = Span::new(word_start, pos);
+
+        if word_start == 0 && word.starts_with('/') && !word[1..].contains('/') {
+            return COMMANDS
+                .iter()
+                .filter(|(command, _, _)| command.starts_with(word))
+                .map(|(command, _, description)| Suggestion {
+                    value: (*command).to_owned(),
+                    description: Some((*description).to_owned()),
+                    span,
+                    append_whitespace: true,
+                    ..Default::default()
+                })
+                .collect();
+        }
+
+        // arguments of commands that take a known set of values
+        let command = line.split_whitespace().next().unwrap_or_default();
+        if word_start > 0 {
+            match command {
+                "/attach" => return complete_path(word, span),
+                "/persona" => return complete_from(self.personas.iter().cloned(), word, span),
+                "/model" => {
+                    let models = ChatGptModel::value_variants()
+                        .iter()
+                        .filter_map(|model| model.to_possible_value())
+                        .map(|value| value.get_name().to_owned())
+                        .chain(self.models.iter().cloned());
+                    return complete_from(models, word, span);
+                }
+                // listed on every completion so renamed and new conversations show up
+                "/open" => {
+                    let titles = ChatHistory::saved_titles().unwrap_or_default();
+                    return complete_from(titles, word, span);
+                }
+                _ => {}
+            }
+        }
+
+        if word.starts_with('/')
+            || word.starts_with("./")
+            || word.starts_with("../")
+            || word.starts_with("~/")
+        {
+            return complete_path(word, span);
+        }
+
+        vec![]
+    }
+}
+
+fn complete_from(
+    candidates: impl IntoIterator<Item = impl Into<String>>,
+    word: &str,
+    span: Span,
+) -> Vec<Suggestion> {
+    candidates
+        .into_iter()
+        .map(Into::into)
+        .filter(|candidate: &String| candidate.starts_with(word))
+        .map(|candidate| Suggestion {
+            value: candidate,
+            span,
+            append_whitespace: true,
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn complete_path(word: &str, span: Span) -> Vec<Suggestion> {
+    let (directory, file_prefix) = match word.rfind('/') {
+        Some(index) => (&word[..=index], &word[index + 1..]),
+        None => ("", word),
+    };
+
+    let expanded_directory = match directory.strip_prefix("~/") {
+        Some(rest) => match directories::UserDirs::new() {
+            Some(user_dirs) => user_dirs.home_dir().join(rest),
+            None => return vec![],
+        },
+        None => Path::new(directory).to_owned(),
+    };
+
+    let Ok(entries) = std::fs::read_dir(&expanded_directory) else {
+        return vec![];
+    };
+
+    let mut suggestions: Vec<Suggestion> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_str()?.to_owned();
+            if !file_name.starts_with(file_prefix) {
+                return None;
+            }
+            let is_dir = entry
+                .file_type()
+                .map(|kind| kind.is_dir())
+                .unwrap_or_default();
+            let suffix = if is_dir { "/" } else { "" };
+            Some(Suggestion {
+                value: format!("{directory}{file_name}{suffix}"),
+                span,
+                append_whitespace: !is_dir,
+                ..Default::default()
+            })
+        })
+        .collect();
+    suggestions.sort_by(|a, b| a.value.cmp(&b.value));
+    suggestions
+}
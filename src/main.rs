@@ -1,37 +1,90 @@
-mod chat_manager;
-mod cli_history;
-mod configuration;
-mod utils;
+#[cfg(unix)]
+use gpt_cli::daemon;
+#[cfg(feature = "audio")]
+use gpt_cli::realtime;
+use gpt_cli::{
+    assistants, batch, chat_client, chat_manager, citations, clipboard, code_commands, commands,
+    configuration, content_filter, conversation, conversation_import, export, fine_tuning,
+    history_server, images, interrupt, json_output, line_editor, messages, model_router, projects,
+    render, response_cache, search, session_fifo, storage, telemetry, terminal_capture, tools,
+    training_data, utils, webhooks,
+};
+#[cfg(feature = "tui")]
+use gpt_cli::{conversation_view, history_browser};
 
 use anyhow::Context;
-use async_openai::Client;
+use assistants::{AssistantsClient, Backend};
+use batch::BatchClient;
+use chat_client::{ChatClient, Provider};
+use chat_manager::CompletionParameters;
 use clap::{Parser, Subcommand};
-use cli_history::InMemoryHistory;
-use configuration::{AppConfig, OPEN_AI_API_KEY_WEB_URL};
-use dialoguer::{console::Term, theme::ColorfulTheme, FuzzySelect, Input, Password};
-use std::path::PathBuf;
+use clipboard::Clipboard;
+use configuration::{ApiKeySource, AppConfig, ConfigKey, OPEN_AI_API_KEY_WEB_URL};
+use content_filter::{ContentFilter, StreamFilter};
+use conversation::count_text_tokens;
+use dialoguer::{console::Term, theme::ColorfulTheme, FuzzySelect, MultiSelect, Password, Select};
+use export::ExportFormat;
+use fine_tuning::FineTuningClient;
+use interrupt::Stoppable;
+use json_output::JsonLine;
+use line_editor::{LineEditor, MULTILINE_TERMINATOR};
+use projects::ResumeScope;
+use render::{render_text, RenderOptions};
+use response_cache::ResponseCache;
+use std::{
+    collections::BTreeMap,
+    io::{IsTerminal, Read},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use storage::StorageFormat;
+use tools::{ToolRegistry, ToolsClient};
+use training_data::ConversationFilter;
 use utils::{
-    generate_system_instructions, ChatGptModel, DEFAULT_SYSTEM_INSTRUCTIONS_KEY, ROBOT_EMOJI,
+    generate_system_instructions, model_names, persona_names, resolve_model, resolve_persona,
+    ChatGptModel, DEFAULT_SYSTEM_INSTRUCTIONS_KEY, GPT_3_5_MODEL_NAME, MAGNIFYING_GLASS_EMOJI,
+    QUESTION_MARK_EMOJI, REALTIME_MODEL_NAME, ROBOT_EMOJI, SYSTEM_EMOJI,
 };
+use webhooks::{WebhookEvent, Webhooks};
 
 #[derive(Parser)]
 #[command()]
 struct Cli {
-    /// model to select
-    #[arg(long, value_enum, default_value = "gpt-3-5")]
-    model: ChatGptModel,
+    /// model to select, gpt-3-5 unless default_model is set in config
+    #[arg(long, value_enum)]
+    model: Option<ChatGptModel>,
+    /// use any model by its API name instead of --model, e.g. a fine-tuned model
+    ///
+    /// token limit and pricing come from the models section in config
+    #[arg(long)]
+    model_name: Option<String>,
     /// load from file
     #[arg(long)]
     file: Option<PathBuf>,
     /// list files
     #[arg(long)]
     select_file: bool,
+    /// resume the latest conversation started in this directory, with --select-file pick one of them
+    #[arg(long)]
+    here: bool,
+    /// offer conversations of every project to resume, not only the current one's
+    #[arg(long)]
+    all_projects: bool,
+    /// only offer conversations to resume with this tag, repeat for several
+    #[arg(long)]
+    tag: Vec<String>,
     /// don't save conversation history
     #[arg(long)]
     no_save: bool,
+    /// save conversation history even if auto_save is off in config
+    #[arg(long, conflicts_with = "no_save")]
+    save: bool,
     /// disable streaming
     #[arg(long)]
     no_stream: bool,
+    /// stream answers even if stream is off in config
+    #[arg(long, conflicts_with = "no_stream")]
+    stream: bool,
 
     /// What sampling temperature to use, between 0 and 2. Higher values like 0.8 will make the output more random, while lower values like 0.2 will make it more focused and deterministic.
     ///
@@ -45,16 +98,371 @@ struct Cli {
     #[arg(long)]
     top_p: Option<f32>,
 
+    /// longest answer in tokens
+    #[arg(long)]
+    max_tokens: Option<u16>,
+
+    /// between -2 and 2, positive values push the model towards new topics
+    #[arg(long, allow_hyphen_values = true)]
+    presence_penalty: Option<f32>,
+
+    /// between -2 and 2, positive values make the model repeat itself less
+    #[arg(long, allow_hyphen_values = true)]
+    frequency_penalty: Option<f32>,
+
+    /// abort responses that take longer than this many seconds
+    ///
+    /// whatever was streamed before the deadline is kept
+    #[arg(long)]
+    response_timeout: Option<u64>,
+
+    /// don't use the response cache even if it's enabled in config
+    #[arg(long)]
+    no_cache: bool,
+
+    /// don't turn URLs and file paths in answers into terminal hyperlinks
+    #[arg(long)]
+    no_hyperlinks: bool,
+
+    /// name put on your messages so shared transcripts show who asked, overrides user_name in config
+    #[arg(long)]
+    name: Option<String>,
+
+    /// profile from the profiles section of the config, conversations encrypted for the key of
+    /// another profile can't be opened
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// system prompt for new conversations, built in or from the personas section in config
+    #[arg(long)]
+    persona: Option<String>,
+
+    /// print answers as plain text instead of rendering markdown
+    #[arg(long)]
+    raw: bool,
+
+    /// print answers as newline-delimited JSON for other programs, streamed answers token by token
+    ///
+    /// only for questions given on the command line or stdin
+    #[arg(long)]
+    json: bool,
+
+    /// mask profanity and the words in presentable_blocklist for live demos and classrooms
+    #[arg(long)]
+    presentable: bool,
+
+    /// number the lines of attached files and ask for [file:line] sources on claims drawn from them
+    #[arg(long)]
+    citations: bool,
+
+    /// Enter adds lines to the question until a line with only ;; sends it
+    #[arg(long)]
+    multiline: bool,
+
+    /// answer questions written into a named pipe instead of reading the prompt
+    ///
+    /// other commands can append to the conversation with `echo "question" > <pipe>`
+    #[arg(long)]
+    listen: bool,
+
+    /// attach files, URLs or globs like "src/**/*.rs" to the first question
+    #[arg(long)]
+    attach: Vec<String>,
+
+    /// send an image file or URL with the first question, the model needs vision
+    #[arg(long)]
+    image: Vec<String>,
+
+    /// route simple questions to --model and escalate the rest to --escalation-model
+    ///
+    /// start a question with !! to always use the escalation model
+    #[arg(long)]
+    auto_model: bool,
+    /// model used for questions --auto-model considers hard
+    #[arg(long, value_enum, default_value = "gpt-4-8k")]
+    escalation_model: ChatGptModel,
+
+    /// send every answer to a second model for a short critique
+    #[arg(long)]
+    verify: bool,
+
+    /// let the model run shell commands you confirm and read files
+    ///
+    /// answers aren't streamed while tools are enabled
+    #[arg(long)]
+    tools: bool,
+
+    /// company whose API answers, overrides provider in config
+    #[arg(long, value_enum)]
+    provider: Option<Provider>,
+    /// model used to verify answers, defaults to the model answering with the active provider
+    #[arg(long, value_enum)]
+    verify_model: Option<ChatGptModel>,
+
+    /// ask a single question, print the answer to stdout and exit, same as `gpt-cli ask`
+    #[arg(long)]
+    prompt: Option<String>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+impl Cli {
+    fn model_metadata(&self, config: &AppConfig) -> chat_manager::ModelMetadata {
+        // flags win over the configured default
+        let name = match (&self.model_name, &self.model, &config.default_model) {
+            (Some(model_name), _, _) => model_name.as_str(),
+            (None, Some(model), _) => model.get_model_name(),
+            (None, None, Some(default_model)) => default_model.as_str(),
+            (None, None, None) => GPT_3_5_MODEL_NAME,
+        };
+        resolve_model(name, &config.models)
+    }
+
+    fn model(&self) -> ChatGptModel {
+        self.model.unwrap_or_default()
+    }
+
+    fn temperature(&self, config: &AppConfig) -> Option<f32> {
+        self.temperature.or(config.default_temperature)
+    }
+
+    fn top_p(&self, config: &AppConfig) -> Option<f32> {
+        self.top_p.or(config.default_top_p)
+    }
+
+    /// --stream and --no-stream win over stream in config
+    fn streams(&self, config: &AppConfig) -> bool {
+        !self.no_stream && (self.stream || config.stream)
+    }
+
+    /// --save and --no-save win over auto_save in config
+    fn saves(&self, config: &AppConfig) -> bool {
+        !self.no_save && (self.save || config.auto_save)
+    }
+
+    fn chat_client(&self, config: &AppConfig) -> anyhow::Result<ChatClient> {
+        ChatClient::new(config, self.provider.unwrap_or(config.provider))
+    }
+
+    fn content_filter(&self, config: &AppConfig) -> Option<ContentFilter> {
+        self.presentable
+            .then(|| ContentFilter::new(&config.presentable_blocklist))
+    }
+
+    /// format new conversations are saved in, None when they aren't saved
+    fn storage(&self, config: &AppConfig) -> Option<StorageFormat> {
+        self.saves(config).then_some(config.storage_format)
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
+    /// ask a single question, print the answer to stdout and exit
+    ///
+    /// piped stdin is appended to the question, e.g. `gpt-cli ask explain this error < build.log`,
+    /// chat flags go before `ask`
+    Ask {
+        /// words of the question, quoting is optional
+        question: Vec<String>,
+    },
     /// login
     Login,
     /// create default config
     CreateConfig,
+    /// read, change or open the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// list the models the configured endpoint serves
+    Models,
+    /// browse saved conversations in a web browser
+    ServeHistory {
+        /// address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        address: String,
+    },
+    /// spoken conversation using the realtime API, transcripts are saved like text conversations
+    Talk {
+        /// realtime model to talk to
+        #[arg(long, default_value = REALTIME_MODEL_NAME)]
+        realtime_model: String,
+        /// voice used for answers
+        #[arg(long, default_value = "alloy")]
+        voice: String,
+    },
+    /// run a failing build command and iterate on a fix
+    Fix {
+        /// command to run, e.g. gpt-cli fix -- cargo build
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// generate unit tests for a file following the project's test conventions
+    GenTests {
+        file: PathBuf,
+        /// only test this function
+        #[arg(long)]
+        function: Option<String>,
+    },
+    /// add doc comments to public items, changes are shown as a diff before writing
+    Document { file: PathBuf },
+    /// ask for a minimal edit as a diff that is validated and confirmed before writing
+    Refactor {
+        file: PathBuf,
+        /// what to change, e.g. "extract the parsing into its own function"
+        instruction: String,
+    },
+    /// keep sessions and the API connection alive in the background
+    Daemon {
+        /// serve Prometheus metrics on this address, e.g. 127.0.0.1:9090
+        #[arg(long)]
+        metrics_address: Option<String>,
+    },
+    /// connect this terminal to a session kept by the daemon
+    Attach {
+        /// session name, pick from running sessions if missing
+        session: Option<String>,
+    },
+    /// open the chat in a tmux pane next to the current one or focus it if it's open
+    Tmux {
+        /// arguments for the chat, e.g. gpt-cli tmux -- --model gpt-4-8k
+        #[arg(last = true)]
+        arguments: Vec<String>,
+    },
+    /// show a saved conversation full screen, the most recently saved one by default
+    View {
+        /// conversation file
+        file: Option<PathBuf>,
+    },
+    /// browse, preview, rename, delete and resume saved conversations
+    History,
+    /// find saved conversations containing every word of a query and resume one
+    Search {
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+    /// import a shared transcript from a URL as a local conversation
+    ///
+    /// takes saved conversation files and chat format JSONL, gist links are fetched raw
+    Import { url: String },
+    /// export a saved conversation to share it
+    Export {
+        /// saved conversation file, every saved conversation when exporting to a vault
+        #[arg(required_unless_present = "vault")]
+        file: Option<PathBuf>,
+        #[arg(long, value_enum, default_value = "md")]
+        format: ExportFormat,
+        /// write to file instead of stdout
+        #[arg(long, short, conflicts_with = "vault")]
+        output: Option<PathBuf>,
+        /// write conversations as notes with front matter into an Obsidian style vault
+        #[arg(long)]
+        vault: Option<PathBuf>,
+        /// link exported conversations from the daily note of the day they started
+        #[arg(long, requires = "vault")]
+        daily_note: bool,
+        /// keep secrets and personal data instead of redacting them
+        #[arg(long)]
+        no_redact: bool,
+    },
+    /// work with saved conversations
+    Sessions {
+        #[command(subcommand)]
+        command: SessionsCommand,
+    },
+    /// manage fine-tuned models
+    Finetune {
+        #[command(subcommand)]
+        command: FinetuneCommand,
+    },
+    /// answer many prompts at half the price within a day with the Batch API
+    Batch {
+        #[command(subcommand)]
+        command: BatchCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionsCommand {
+    /// export conversations as chat format JSONL for fine-tuning or evaluation
+    ExportJsonl {
+        /// only export conversations matching all filters, e.g. model=gpt-4, title=rust or rating=good
+        #[arg(long)]
+        filter: Vec<ConversationFilter>,
+        /// write to file instead of stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// keep secrets and personal data instead of redacting them
+        #[arg(long)]
+        no_redact: bool,
+    },
+    /// conversations, answers and their ratings per model
+    Stats,
+    /// saved conversations, newest first
+    List {
+        /// only conversations of this project, of the current directory's project without a name
+        #[arg(long)]
+        project: Option<Option<String>>,
+        /// only conversations with this tag, repeat for several
+        #[arg(long)]
+        tag: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// print the current value of a setting
+    Get { key: ConfigKey },
+    /// check a value and write the setting into the config file
+    Set { key: ConfigKey, value: String },
+    /// open the config file in $VISUAL or $EDITOR
+    Edit,
+    /// print where the config file is
+    Path,
+}
+
+#[derive(Subcommand)]
+enum FinetuneCommand {
+    /// upload selected conversations as training data
+    Upload,
+    /// create fine-tuning job from an uploaded training file
+    Create {
+        /// ID of the uploaded training file
+        training_file: String,
+        /// base model to fine-tune
+        #[arg(long, default_value = GPT_3_5_MODEL_NAME)]
+        model: String,
+        /// suffix added to the fine-tuned model name
+        #[arg(long)]
+        suffix: Option<String>,
+    },
+    /// list recent fine-tuning jobs
+    List,
+    /// show status of a fine-tuning job
+    Status {
+        /// ID of the fine-tuning job
+        job_id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum BatchCommand {
+    /// submit a file with one prompt per line, --model, --persona and --temperature apply
+    Submit { prompts: PathBuf },
+    /// show progress of a batch
+    Status {
+        /// ID printed by submit
+        batch_id: String,
+    },
+    /// write the answers of a finished batch as JSONL, one line per prompt
+    Fetch {
+        /// ID printed by submit
+        batch_id: String,
+        /// write to file instead of stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
 }
 
 // #[derive(Args)]
@@ -63,9 +471,12 @@ enum Commands {
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let mut cli = Cli::parse();
+    // every command loads the config itself, GPT_PROFILE is read by all of them
+    if let Some(profile) = &cli.profile {
+        std::env::set_var("GPT_PROFILE", profile);
+    }
 
     let term = Term::stdout();
-    let mut history = InMemoryHistory::default();
     let term_theme = ColorfulTheme::default();
 
     match cli.command {
@@ -74,7 +485,13 @@ async fn main() -> anyhow::Result<()> {
             let api_key: String = Password::with_theme(&term_theme)
                 .with_prompt("API key:")
                 .interact_on(&term)?;
-            let config = AppConfig::new(api_key);
+            let mut config = AppConfig::new(api_key);
+            match configuration::save_api_key_to_keyring(&config.open_ai_api_key) {
+                Ok(()) => config.api_key_source = ApiKeySource::Keyring,
+                Err(error) => term.write_line(&format!(
+                    "Keyring unavailable, the key is saved in the config file: {error:#}"
+                ))?,
+            }
             config.save_user_config()?;
             term.write_line("Login successful")?;
             return Ok(());
@@ -85,104 +502,1066 @@ async fn main() -> anyhow::Result<()> {
             config_new.save_user_config()?;
             return Ok(());
         }
-        None => {}
+        Some(Commands::Config { ref command }) => {
+            match command {
+                ConfigCommand::Get { key } => {
+                    let config = AppConfig::load_user_config()?;
+                    match serde_yaml::to_value(&config)?.get(key.name()) {
+                        Some(serde_yaml::Value::String(value)) => println!("{value}"),
+                        Some(serde_yaml::Value::Null) | None => {
+                            eprintln!("{} isn't set", key.name())
+                        }
+                        Some(value) => print!("{}", serde_yaml::to_string(value)?),
+                    }
+                }
+                ConfigCommand::Set { key, value } => {
+                    let parsed = key.parse_value(value)?;
+                    // personas can come from the config itself
+                    if *key == ConfigKey::DefaultPersona {
+                        let config = AppConfig::load_user_config().unwrap_or_default();
+                        resolve_persona(value.trim(), &config.personas)?;
+                    }
+                    let path = configuration::set_config_value(*key, parsed)?;
+                    term.write_line(&format!("Set {} in {}", key.name(), path.display()))?;
+                }
+                ConfigCommand::Edit => {
+                    let path = configuration::config_file_path()?;
+                    if !path.exists() {
+                        AppConfig::default().save_user_config()?;
+                    }
+                    line_editor::open_in_editor(&path)?;
+                    if let Err(error) = AppConfig::load_user_config() {
+                        term.write_line(&format!("{} has errors: {error:#}", path.display()))?;
+                    }
+                }
+                ConfigCommand::Path => println!("{}", configuration::config_file_path()?.display()),
+            }
+            return Ok(());
+        }
+        Some(Commands::Models) => {
+            let config = AppConfig::load_user_config()?;
+            let mut models = cli.chat_client(&config)?.list_models().await?;
+            models.sort_unstable();
+            for model in models {
+                println!("{model}");
+            }
+            return Ok(());
+        }
+        #[cfg(feature = "tui")]
+        Some(Commands::View { ref file }) => {
+            let config = AppConfig::load_user_config().unwrap_or_default();
+            let path = match file {
+                Some(file) => file.clone(),
+                None => chat_manager::ChatHistory::get_all_saved_conversations()?
+                    .into_iter()
+                    .max_by_key(|path| {
+                        path.metadata()
+                            .and_then(|metadata| metadata.modified())
+                            .ok()
+                    })
+                    .context("No saved conversations")?,
+            };
+            let chat_history = chat_manager::ChatHistory::load_from_file(&path)?;
+            conversation_view::view(&chat_history, &config.timestamps)?;
+            return Ok(());
+        }
+        #[cfg(not(feature = "tui"))]
+        Some(Commands::View { .. }) => {
+            return Err(utils::feature_disabled("tui", "gpt-cli view"));
+        }
+        #[cfg(feature = "tui")]
+        Some(Commands::History) => {
+            let config = AppConfig::load_user_config().unwrap_or_default();
+            let scope =
+                ResumeScope::new(cli.here, cli.all_projects, &config.projects)?.with_tags(&cli.tag);
+            match history_browser::browse(&config.timestamps, &scope)? {
+                // resume like --file
+                Some(path) => cli.file = Some(path),
+                None => return Ok(()),
+            }
+        }
+        #[cfg(not(feature = "tui"))]
+        Some(Commands::History) => {
+            return Err(utils::feature_disabled("tui", "gpt-cli history"));
+        }
+        Some(Commands::Search { ref query }) => {
+            let config = AppConfig::load_user_config().unwrap_or_default();
+            let scope =
+                ResumeScope::new(cli.here, cli.all_projects, &config.projects)?.with_tags(&cli.tag);
+            let query = query.join(" ");
+            let conversations = chat_manager::ChatHistory::get_all_saved_conversations()?
+                .into_iter()
+                .filter_map(|path| {
+                    let chat_history = chat_manager::ChatHistory::load_from_file(&path).ok()?;
+                    Some((path, chat_history))
+                })
+                .filter(|(_, chat_history)| scope.contains(chat_history))
+                .collect();
+            let hits = search::search(conversations, &query)?;
+            if hits.is_empty() {
+                term.write_line(&format!(
+                    "No conversations in {} contain {query}",
+                    scope.description()
+                ))?;
+                return Ok(());
+            }
+            let mut labels = vec![];
+            for hit in &hits {
+                let date = hit
+                    .chat_history
+                    .conversation_start()
+                    .map(|date| config.timestamps.display(&date))
+                    .unwrap_or_default();
+                let label = format!(
+                    "{date}  {}",
+                    hit.chat_history
+                        .conversation_title()
+                        .unwrap_or("untitled")
+                        .replace('_', " ")
+                );
+                term.write_line(&format!("{MAGNIFYING_GLASS_EMOJI} {label}"))?;
+                for (number, snippet) in &hit.snippets {
+                    term.write_line(&format!("    {number}. {snippet}"))?;
+                }
+                labels.push(label);
+            }
+            if !term.is_term() {
+                return Ok(());
+            }
+            let selection = Select::with_theme(&term_theme)
+                .with_prompt("Resume conversation")
+                .items(&labels)
+                .default(0)
+                .interact_on_opt(&term)?;
+            match selection {
+                // resume like --file
+                Some(index) => cli.file = Some(hits[index].path.clone()),
+                None => return Ok(()),
+            }
+        }
+        Some(Commands::ServeHistory { ref address }) => {
+            let config = AppConfig::load_user_config()?;
+            history_server::serve_history(address, config.timestamps, &term).await?;
+            return Ok(());
+        }
+        #[cfg(feature = "audio")]
+        Some(Commands::Talk {
+            ref realtime_model,
+            ref voice,
+        }) => {
+            let config = AppConfig::load_user_config()?;
+            let client = cli.chat_client(&config)?;
+            // text model is used for titles and for continuing the conversation in text later
+            let mut chat_manager = chat_manager::ChatHistory::new(
+                cli.model_metadata(&config),
+                generate_system_instructions()[DEFAULT_SYSTEM_INSTRUCTIONS_KEY].clone(),
+            )?;
+            chat_manager.set_storage_format(config.storage_format);
+            realtime::talk(&config, realtime_model, voice, &mut chat_manager, &term).await?;
+            if chat_manager.messages().len() > 1 && cli.saves(&config) {
+                chat_manager.populate_title(&client).await?;
+                chat_manager.save_to_file()?;
+            }
+            return Ok(());
+        }
+        #[cfg(not(feature = "audio"))]
+        Some(Commands::Talk { .. }) => {
+            return Err(utils::feature_disabled("audio", "gpt-cli talk"));
+        }
+        Some(Commands::Fix { ref command }) => {
+            let config = AppConfig::load_user_config()?;
+            let client = cli.chat_client(&config)?;
+            code_commands::run_fix(
+                command,
+                &config,
+                &client,
+                &term,
+                &term_theme,
+                cli.model_metadata(&config),
+                cli.storage(&config),
+            )
+            .await?;
+            return Ok(());
+        }
+        Some(Commands::GenTests {
+            ref file,
+            ref function,
+        }) => {
+            let config = AppConfig::load_user_config()?;
+            let client = cli.chat_client(&config)?;
+            code_commands::run_gen_tests(
+                file,
+                function.as_deref(),
+                &client,
+                &term,
+                &term_theme,
+                cli.model_metadata(&config),
+                cli.storage(&config),
+            )
+            .await?;
+            return Ok(());
+        }
+        Some(Commands::Document { ref file }) => {
+            let config = AppConfig::load_user_config()?;
+            let client = cli.chat_client(&config)?;
+            code_commands::run_document(
+                file,
+                &client,
+                &term,
+                &term_theme,
+                cli.model_metadata(&config),
+                cli.storage(&config),
+            )
+            .await?;
+            return Ok(());
+        }
+        Some(Commands::Refactor {
+            ref file,
+            ref instruction,
+        }) => {
+            let config = AppConfig::load_user_config()?;
+            let client = cli.chat_client(&config)?;
+            code_commands::run_refactor(
+                file,
+                instruction,
+                &client,
+                &term,
+                &term_theme,
+                cli.model_metadata(&config),
+                cli.storage(&config),
+            )
+            .await?;
+            return Ok(());
+        }
+        Some(Commands::Tmux { ref arguments }) => {
+            terminal_capture::open_chat_pane(arguments)?;
+            return Ok(());
+        }
+        #[cfg(unix)]
+        Some(Commands::Daemon {
+            ref metrics_address,
+        }) => {
+            let config = AppConfig::load_user_config()?;
+            daemon::run_daemon(
+                cli.chat_client(&config)?,
+                cli.model_metadata(&config),
+                generate_system_instructions()[DEFAULT_SYSTEM_INSTRUCTIONS_KEY].clone(),
+                CompletionParameters {
+                    temperature: cli.temperature(&config),
+                    top_p: cli.top_p(&config),
+                    max_tokens: cli.max_tokens,
+                    presence_penalty: cli.presence_penalty,
+                    frequency_penalty: cli.frequency_penalty,
+                    response_timeout: cli.response_timeout.map(Duration::from_secs),
+                    response_cache: None,
+                    question_prefix: config.question_prefix.clone(),
+                    question_suffix: config.question_suffix.clone(),
+                    context_overflow: config.context_overflow,
+                    logit_bias: config.logit_bias()?,
+                    // daemon sessions have no attachments to cite
+                    citations: false,
+                },
+                cli.storage(&config),
+                metrics_address.as_deref(),
+                &term,
+            )
+            .await?;
+            return Ok(());
+        }
+        #[cfg(unix)]
+        Some(Commands::Attach { ref session }) => {
+            let config = AppConfig::load_user_config()?;
+            daemon::attach(
+                session.as_deref(),
+                &mut LineEditor::new(
+                    config.edit_mode,
+                    &config.shortcuts,
+                    config.question_history_size,
+                    None,
+                    persona_names(&config.personas),
+                    model_names(&config.models),
+                )?,
+                &term,
+                &term_theme,
+                &RenderOptions::new(&term, cli.no_hyperlinks, cli.raw)
+                    .with_flush_interval(Duration::from_millis(config.stream_flush_interval_ms))
+                    .with_content_filter(cli.content_filter(&config)),
+            )
+            .await?;
+            return Ok(());
+        }
+        #[cfg(not(unix))]
+        Some(Commands::Daemon { .. } | Commands::Attach { .. }) => {
+            anyhow::bail!("Daemon mode is only supported on unix");
+        }
+        Some(Commands::Import { ref url }) => {
+            // public transcripts don't need an API key
+            let config = AppConfig::load_user_config().unwrap_or_default();
+            let mut conversations =
+                conversation_import::import_url(url, &cli.model_metadata(&config)).await?;
+            for chat_history in &mut conversations {
+                chat_history.set_storage_format(config.storage_format);
+                chat_history.save_to_file()?;
+                term.write_line(&format!(
+                    "Imported {} with {} messages",
+                    chat_history
+                        .conversation_title()
+                        .unwrap_or("untitled conversation")
+                        .replace('_', " "),
+                    chat_history.messages().len()
+                ))?;
+            }
+            term.write_line("Resume with gpt-cli --select-file")?;
+            return Ok(());
+        }
+        Some(Commands::Export {
+            ref file,
+            format,
+            ref output,
+            ref vault,
+            daily_note,
+            no_redact,
+        }) => {
+            // exporting doesn't talk to the API so a key isn't needed
+            let config = AppConfig::load_user_config().unwrap_or_default();
+            let load = |path: &Path| {
+                chat_manager::ChatHistory::load_from_file(path).map(|mut chat_history| {
+                    if !no_redact {
+                        chat_history.redact();
+                    }
+                    chat_history
+                })
+            };
+            if let Some(vault) = vault {
+                let conversations = match file {
+                    Some(file) => vec![load(file)?],
+                    None => chat_manager::ChatHistory::get_all_saved_conversations()?
+                        .iter()
+                        .filter_map(|path| load(path).ok())
+                        .collect(),
+                };
+                let count =
+                    export::write_vault(&conversations, vault, daily_note, &config.timestamps)?;
+                term.write_line(&format!(
+                    "Exported {count} conversations to {}",
+                    vault.display()
+                ))?;
+                return Ok(());
+            }
+            let file = file.as_ref().context("Missing conversation file")?;
+            let exported = load(file)?.export(format, &config.timestamps)?;
+            match output {
+                Some(output) => {
+                    std::fs::write(output, exported)?;
+                    term.write_line(&format!("Exported to {}", output.display()))?;
+                }
+                None => print!("{exported}"),
+            }
+            return Ok(());
+        }
+        Some(Commands::Sessions {
+            command:
+                SessionsCommand::ExportJsonl {
+                    ref filter,
+                    ref output,
+                    no_redact,
+                },
+        }) => {
+            let conversations = chat_manager::ChatHistory::get_all_saved_conversations()?
+                .iter()
+                .filter_map(|path| chat_manager::ChatHistory::load_from_file(path).ok())
+                .filter(|chat_history| filter.iter().all(|filter| filter.matches(chat_history)))
+                .collect::<Vec<_>>();
+            let jsonl = training_data::conversations_to_jsonl(&conversations, !no_redact)?;
+            match output {
+                Some(output) => {
+                    std::fs::write(output, jsonl)?;
+                    term.write_line(&format!(
+                        "Exported {} conversations to {}",
+                        conversations.len(),
+                        output.display()
+                    ))?;
+                }
+                None => print!("{jsonl}"),
+            }
+            return Ok(());
+        }
+        Some(Commands::Sessions {
+            command: SessionsCommand::Stats,
+        }) => {
+            // conversations, answers, good and bad ratings
+            let mut stats: BTreeMap<String, [usize; 4]> = BTreeMap::new();
+            for chat_history in chat_manager::ChatHistory::get_all_saved_conversations()?
+                .iter()
+                .filter_map(|path| chat_manager::ChatHistory::load_from_file(path).ok())
+            {
+                let model_stats = stats
+                    .entry(chat_history.model_metadata().name.clone())
+                    .or_default();
+                model_stats[0] += 1;
+                model_stats[1] += chat_history
+                    .messages()
+                    .iter()
+                    .filter(|message| message.role == messages::Role::Assistant)
+                    .count();
+                model_stats[2] += chat_history.count_ratings(messages::Rating::Good);
+                model_stats[3] += chat_history.count_ratings(messages::Rating::Bad);
+            }
+            println!(
+                "{:<32} {:>13} {:>8} {:>6} {:>6}",
+                "model", "conversations", "answers", "good", "bad"
+            );
+            for (model, [conversations, answers, good, bad]) in stats {
+                println!("{model:<32} {conversations:>13} {answers:>8} {good:>6} {bad:>6}");
+            }
+            return Ok(());
+        }
+        Some(Commands::Sessions {
+            command:
+                SessionsCommand::List {
+                    ref project,
+                    ref tag,
+                },
+        }) => {
+            let config = AppConfig::load_user_config().unwrap_or_default();
+            let project = match project {
+                Some(Some(project)) => Some(project.clone()),
+                Some(None) => Some(
+                    projects::current_project(&config.projects)
+                        .context("Current directory isn't part of a project")?,
+                ),
+                None => None,
+            };
+            let mut conversations: Vec<_> =
+                chat_manager::ChatHistory::get_all_saved_conversations()?
+                    .into_iter()
+                    .filter_map(|path| {
+                        let chat_history = chat_manager::ChatHistory::load_from_file(&path).ok()?;
+                        Some((path, chat_history))
+                    })
+                    .filter(|(_, chat_history)| {
+                        project.is_none() || chat_history.project() == project.as_deref()
+                    })
+                    .filter(|(_, chat_history)| tag.iter().all(|tag| chat_history.has_tag(tag)))
+                    .collect();
+            conversations.sort_by_key(|(_, chat_history)| {
+                std::cmp::Reverse(chat_history.conversation_start())
+            });
+            for (path, chat_history) in conversations {
+                let date = chat_history
+                    .conversation_start()
+                    .map(|date| config.timestamps.display(&date))
+                    .unwrap_or_default();
+                let tags = if chat_history.tags().is_empty() {
+                    String::new()
+                } else {
+                    format!("  [{}]", chat_history.tags().join(", "))
+                };
+                println!(
+                    "{date}  {}  {}{tags}  {}",
+                    chat_history.project().unwrap_or("-"),
+                    chat_history
+                        .conversation_title()
+                        .unwrap_or("untitled")
+                        .replace('_', " "),
+                    path.display()
+                );
+            }
+            return Ok(());
+        }
+        Some(Commands::Finetune { ref command }) => {
+            let config = AppConfig::load_user_config()?;
+            run_finetune_command(command, &config, &term, &term_theme).await?;
+            return Ok(());
+        }
+        Some(Commands::Batch { ref command }) => {
+            let config = AppConfig::load_user_config()?;
+            run_batch_command(command, &cli, &config, &term).await?;
+            return Ok(());
+        }
+        Some(Commands::Ask { .. }) | None => {}
     }
 
-    if cli.select_file {
-        let files = chat_manager::ChatHistory::get_all_saved_conversations()?;
-        let file_names: Vec<_> = files
-            .iter()
+    let config = AppConfig::load_user_config()?;
+
+    // tags alone offer the tagged conversations to pick from
+    let select_file = cli.select_file || (!cli.tag.is_empty() && !cli.here);
+    if cli.file.is_none() && (select_file || cli.here) {
+        let scope =
+            ResumeScope::new(cli.here, cli.all_projects, &config.projects)?.with_tags(&cli.tag);
+        // files that don't load are still listed when every conversation is offered
+        let mut conversations: Vec<_> = chat_manager::ChatHistory::get_all_saved_conversations()?
+            .into_iter()
             .map(|path| {
-                path.file_name()
-                    .unwrap_or_default()
-                    .to_str()
-                    .unwrap_or_default()
+                let chat_history = chat_manager::ChatHistory::load_from_file(&path).ok();
+                (path, chat_history)
+            })
+            .filter(|(_, chat_history)| match chat_history {
+                Some(chat_history) => scope.contains(chat_history),
+                None => scope.is_unrestricted(),
             })
             .collect();
-        let selection = FuzzySelect::with_theme(&term_theme)
-            .with_prompt("Select file")
-            .items(&file_names)
-            .default(0)
-            .interact_on(&term)?;
-        cli.file = Some(
-            files
-                .get(selection)
-                .context("Selected wrong item form file list")?
-                .to_owned(),
-        );
+        if cli.here {
+            conversations.sort_by_key(|(_, chat_history)| {
+                std::cmp::Reverse(
+                    chat_history
+                        .as_ref()
+                        .and_then(chat_manager::ChatHistory::conversation_start),
+                )
+            });
+        }
+
+        if conversations.is_empty() && !scope.is_unrestricted() {
+            term.write_line(&format!(
+                "{SYSTEM_EMOJI} No conversations in {}, starting a new one",
+                scope.description()
+            ))?;
+        } else if select_file {
+            let file_names: Vec<_> = conversations
+                .iter()
+                .map(|(path, chat_history)| {
+                    let file_name = path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_str()
+                        .unwrap_or_default();
+                    let mut item = file_name.to_owned();
+                    if let Some(chat_history) = chat_history {
+                        if !chat_history.tags().is_empty() {
+                            item.push_str(&format!("  [{}]", chat_history.tags().join(", ")));
+                        }
+                        if let Some(working_directory) = chat_history.working_directory() {
+                            item.push_str(&format!("  {}", working_directory.display()));
+                        }
+                    }
+                    item
+                })
+                .collect();
+            let selection = FuzzySelect::with_theme(&term_theme)
+                .with_prompt("Select file")
+                .items(&file_names)
+                .default(0)
+                .interact_on(&term)?;
+            cli.file = Some(conversations.swap_remove(selection).0);
+        } else {
+            // --here alone picks up the latest conversation of the project
+            cli.file = Some(conversations.swap_remove(0).0);
+        }
         // weird mutating the cli args
     }
 
-    let config = AppConfig::load_user_config()?;
+    let _telemetry = telemetry::init(&config.telemetry)?;
 
-    let client = Client::new().with_api_key(&config.open_ai_api_key);
-
-    let system_messages = generate_system_instructions();
+    let client = cli.chat_client(&config)?;
+    let assistants_client = (config.backend == Backend::Assistants)
+        .then(|| AssistantsClient::new(&config))
+        .transpose()?;
+    if cli.tools && assistants_client.is_some() {
+        anyhow::bail!("--tools isn't supported with the assistants backend");
+    }
+    // runs have no logit_bias so it would be dropped without a word
+    if assistants_client.is_some()
+        && !(config.logit_bias.is_empty() && config.banned_words.is_empty())
+    {
+        anyhow::bail!("logit_bias and banned_words aren't supported with the assistants backend");
+    }
+    if config.azure.is_some() && (cli.tools || assistants_client.is_some()) {
+        anyhow::bail!("Tools and the assistants backend aren't supported with Azure");
+    }
+    if cli.provider.unwrap_or(config.provider) == Provider::Anthropic
+        && (cli.tools || assistants_client.is_some())
+    {
+        anyhow::bail!("Tools and the assistants backend aren't supported with Anthropic");
+    }
+    let tools_client = cli.tools.then(|| ToolsClient::new(&config)).transpose()?;
+    let tools = ToolRegistry::with_builtin_tools(&config.attachments);
 
-    let mut chat_manager = if let Some(path) = cli.file {
-        chat_manager::ChatHistory::load_from_file(&path)?
+    let resumed = cli.file.is_some();
+    let mut chat_manager = if let Some(path) = &cli.file {
+        chat_manager::ChatHistory::load_from_file(path)?
     } else {
         chat_manager::ChatHistory::new(
-            cli.model.to_model_metadata(),
-            system_messages[DEFAULT_SYSTEM_INSTRUCTIONS_KEY].clone(),
+            cli.model_metadata(&config),
+            resolve_persona(
+                cli.persona
+                    .as_deref()
+                    .or(config.default_persona.as_deref())
+                    .unwrap_or(DEFAULT_SYSTEM_INSTRUCTIONS_KEY),
+                &config.personas,
+            )?,
         )?
     };
+    let user_name = cli.name.clone().or_else(|| config.user_name.clone());
+    if let Some(user_name) = &user_name {
+        messages::validate_name(user_name)?;
+    }
+    chat_manager.set_user_name(user_name);
+    if !resumed {
+        chat_manager.set_storage_format(config.storage_format);
+        chat_manager.set_project(projects::current_project(&config.projects));
+    }
 
-    term.write_line("Write /? to get help")?;
+    let parameters = CompletionParameters {
+        temperature: cli.temperature(&config),
+        top_p: cli.top_p(&config),
+        max_tokens: cli.max_tokens,
+        presence_penalty: cli.presence_penalty,
+        frequency_penalty: cli.frequency_penalty,
+        response_timeout: cli.response_timeout.map(Duration::from_secs),
+        response_cache: if config.response_cache.enabled && !cli.no_cache {
+            Some(ResponseCache::new(&config.response_cache)?)
+        } else {
+            None
+        },
+        question_prefix: config.question_prefix.clone(),
+        question_suffix: config.question_suffix.clone(),
+        context_overflow: config.context_overflow,
+        logit_bias: config.logit_bias()?,
+        citations: cli.citations,
+    };
+    let webhooks = Webhooks::new(&config.webhooks);
 
-    loop {
-        let mut user_question: String = Input::with_theme(&term_theme)
-            .with_prompt("Question:")
-            .history_with(&mut history)
-            .interact_text_on(&term)?;
+    // one-shot mode for scripts and pipelines, nothing interactive runs
+    let stdin_piped = !std::io::stdin().is_terminal();
+    let one_shot_question = cli.prompt.take().or_else(|| match cli.command.take() {
+        Some(Commands::Ask { question }) => Some(question.join(" ")),
+        _ => None,
+    });
+    if one_shot_question.is_some() || (stdin_piped && !cli.listen) {
+        if cli.tools && stdin_piped {
+            anyhow::bail!("--tools asks before running commands, ask without piping stdin");
+        }
+        let mut question = one_shot_question.unwrap_or_default();
+        if stdin_piped {
+            let mut piped = String::new();
+            std::io::stdin().read_to_string(&mut piped)?;
+            if !piped.trim().is_empty() {
+                if !question.is_empty() {
+                    question.push_str("\n\n");
+                }
+                question.push_str(piped.trim_end());
+            }
+        }
+        if question.trim().is_empty() {
+            anyhow::bail!("No question given on the command line or stdin");
+        }
+        if !cli.attach.is_empty() {
+            // only the answer goes to stdout
+            let mut draft = vec![];
+            commands::attach_to_draft(
+                &cli.attach,
+                &mut chat_manager,
+                &mut draft,
+                &config.attachments,
+                parameters.citations,
+                &Term::stderr(),
+                None,
+            )
+            .await?;
+            draft.push(question);
+            question = draft.join("\n\n");
+        }
+        for image in &cli.image {
+            if !chat_manager.model_metadata().vision {
+                anyhow::bail!(
+                    "{} doesn't accept images",
+                    chat_manager.model_metadata().name
+                );
+            }
+            chat_manager.attach_image(images::load_image(image)?);
+        }
+        if let Some(trimmed) = chat_manager
+            .fit_context(count_text_tokens(&question), &client, &parameters)
+            .await?
+        {
+            eprintln!("{SYSTEM_EMOJI} {trimmed}");
+        }
+        webhooks.notify(WebhookEvent::MessageSent, &chat_manager, Some(&question));
+        let answer = if let Some(assistants_client) = &assistants_client {
+            chat_manager
+                .next_message_assistants(&question, &client, assistants_client, &parameters)
+                .await?
+        } else if let Some(tools_client) = &tools_client {
+            chat_manager
+                .next_message_tools(&question, tools_client, &tools, &parameters)
+                .await?
+        } else if cli.json && cli.streams(&config) {
+            let mut stream_filter = cli.content_filter(&config).map(StreamFilter::new);
+            let answer = chat_manager
+                .next_message_stream(&question, &client, &parameters, |delta| {
+                    let content = match &mut stream_filter {
+                        Some(stream_filter) => stream_filter.push(delta),
+                        None => delta.to_owned(),
+                    };
+                    if content.is_empty() {
+                        return Ok(());
+                    }
+                    JsonLine::Delta {
+                        role: messages::Role::Assistant,
+                        content: &content,
+                    }
+                    .print()
+                })
+                .await?;
+            if let Some(stream_filter) = &mut stream_filter {
+                let rest = stream_filter.finish();
+                if !rest.is_empty() {
+                    JsonLine::Delta {
+                        role: messages::Role::Assistant,
+                        content: &rest,
+                    }
+                    .print()?;
+                }
+            }
+            answer
+        } else {
+            chat_manager
+                .next_message(&question, &client, &parameters)
+                .await?
+        };
+        webhooks.notify(WebhookEvent::ResponseReceived, &chat_manager, Some(&answer));
+        let shown = match cli.content_filter(&config) {
+            Some(content_filter) => content_filter.filter(&answer),
+            None => answer,
+        };
+        if cli.json {
+            JsonLine::Answer {
+                role: chat_manager
+                    .messages()
+                    .last()
+                    .map_or(messages::Role::Assistant, |message| message.role),
+                content: &shown,
+                finish_reason: chat_manager.finish_reason(),
+                usage: chat_manager.token_usage(),
+            }
+            .print()?;
+        } else {
+            println!("{shown}");
+        }
+        if cli.saves(&config) {
+            chat_manager
+                .populate_title_if_empty(&client, &parameters)
+                .await?;
+            chat_manager.save_to_file()?;
+            webhooks.notify(WebhookEvent::SessionSaved, &chat_manager, None);
+        }
+        webhooks.finish().await;
+        return Ok(());
+    }
 
-        if &user_question == "/?" {
-            let options = UserActions::all_str();
+    if cli.json {
+        anyhow::bail!("--json needs a question on the command line or stdin");
+    }
 
-            let selection = FuzzySelect::with_theme(&term_theme)
-                .with_prompt("Select action")
-                .items(&options)
-                .default(0)
-                .interact_on_opt(&term)?;
-            let selection = selection.and_then(|index| UserActions::all().get(index));
-            match selection {
-                Some(UserActions::ReturnToChat) => continue,
-                Some(UserActions::RecreateTitle) => {
-                    chat_manager.populate_title(&client).await?;
-                    continue;
+    // resumed conversations recall their own questions instead of the global history
+    let mut line_editor = LineEditor::new(
+        config.edit_mode,
+        &config.shortcuts,
+        config.question_history_size,
+        resumed.then(|| chat_manager.input_history()),
+        persona_names(&config.personas),
+        model_names(&config.models),
+    )?
+    .with_multiline(cli.multiline);
+
+    let render_options = RenderOptions::new(&term, cli.no_hyperlinks, cli.raw)
+        .with_flush_interval(Duration::from_millis(config.stream_flush_interval_ms))
+        .with_content_filter(cli.content_filter(&config));
+
+    chat_manager.print_banner(&term, &parameters, &config.timestamps)?;
+
+    if cli.listen {
+        let mut session_fifo = session_fifo::SessionFifo::create()?;
+        term.write_line(&format!(
+            "Listening for questions on {}. Press Ctrl-C to stop",
+            session_fifo.path().display()
+        ))?;
+        loop {
+            let waiting = Stoppable::new();
+            let question = tokio::select! {
+                _ = waiting.token().cancelled() => {
+                    webhooks.finish().await;
+                    return Ok(());
                 }
-                Some(UserActions::RegenerateResponse) => {
-                    // ugly...
-                    _ = chat_manager.pop_last_message();
-                    user_question = chat_manager.pop_last_message().unwrap_or_default().content;
-                    // keep going to create new message
+                question = session_fifo.next_line() => question?,
+            };
+            drop(waiting);
+            term.write_line(&format!("\n{QUESTION_MARK_EMOJI} Question:\n{question}"))?;
+            match chat_manager
+                .fit_context(count_text_tokens(&question), &client, &parameters)
+                .await
+            {
+                Ok(Some(trimmed)) => term.write_line(&format!("{SYSTEM_EMOJI} {trimmed}"))?,
+                Ok(None) => {}
+                Err(error) => {
+                    term.write_line(&format!("Failed to answer: {error}"))?;
+                    continue;
                 }
-                Some(UserActions::PrintChatHistory) => {
-                    chat_manager.print_history(&term)?;
+            }
+            term.write_line(&format!("\n{ROBOT_EMOJI} ChatGPT:\n"))?;
+            webhooks.notify(WebhookEvent::MessageSent, &chat_manager, Some(&question));
+            // a failed request shouldn't end the session other processes rely on
+            match chat_manager
+                .next_message_stream_stdout(&question, &client, &term, &render_options, &parameters)
+                .await
+            {
+                Ok(response) => webhooks.notify(
+                    WebhookEvent::ResponseReceived,
+                    &chat_manager,
+                    Some(&response),
+                ),
+                Err(error) => {
+                    term.write_line(&format!("Failed to answer: {error}"))?;
                     continue;
                 }
-                None => continue,
+            }
+            chat_manager
+                .populate_title_if_empty(&client, &parameters)
+                .await?;
+            chat_manager.show_title(&term);
+            if cli.saves(&config) {
+                chat_manager.save_to_file()?;
+                webhooks.notify(WebhookEvent::SessionSaved, &chat_manager, None);
+            }
+        }
+    }
+    term.write_line("Write /help to see commands")?;
+    if cli.multiline {
+        term.write_line(&format!(
+            "Finish questions with a line containing only {MULTILINE_TERMINATOR}"
+        ))?;
+    }
+
+    let mut session = commands::Session {
+        config: &config,
+        client: &client,
+        term: &term,
+        term_theme: &term_theme,
+        render_options: &render_options,
+        webhooks: &webhooks,
+        parameters,
+        save: cli.saves(&config),
+        read_only: false,
+        draft: vec![],
+        pending_title: None,
+        pending_summary: None,
+        privacy_checked: 0,
+        clipboard: Clipboard::default(),
+    };
+    if !cli.attach.is_empty() {
+        commands::attach_to_draft(
+            &cli.attach,
+            &mut chat_manager,
+            &mut session.draft,
+            &config.attachments,
+            session.parameters.citations,
+            &term,
+            Some(&term_theme),
+        )
+        .await?;
+    }
+    for image in &cli.image {
+        commands::attach_image(image, &mut chat_manager, &term)?;
+    }
+
+    loop {
+        session.poll_title(&mut chat_manager)?;
+        let prompt = if session.read_only {
+            "Note:"
+        } else if !session.draft.is_empty() {
+            "Draft:"
+        } else {
+            "Question:"
+        };
+        line_editor.set_status(chat_manager.context_bar());
+        let Some(user_question) = line_editor.read_line(prompt)? else {
+            session.close(&mut chat_manager).await?;
+            return Ok(());
+        };
+        if user_question.trim().is_empty() {
+            continue;
+        }
+        chat_manager.record_input(&user_question);
+
+        let (mut user_question, regenerating) = match commands::parse(&user_question) {
+            Some(Ok(command)) => {
+                match commands::run(command, &mut chat_manager, &mut session).await? {
+                    commands::Outcome::Prompt => continue,
+                    commands::Outcome::Ask {
+                        question,
+                        regenerate,
+                    } => (question, regenerate),
+                    commands::Outcome::Exit => {
+                        session.close(&mut chat_manager).await?;
+                        return Ok(());
+                    }
+                }
+            }
+            Some(Err(error)) => {
+                term.write_line(&error.to_string())?;
+                continue;
+            }
+            None if session.read_only => {
+                chat_manager.add_user_message(&user_question)?;
+                session.save(&mut chat_manager)?;
+                continue;
+            }
+            None if !session.draft.is_empty() => {
+                // don't send partial context by accident while composing
+                session.draft.push(user_question);
+                term.write_line(&format!(
+                    "Added snippet {} to draft. Use /send to submit",
+                    session.draft.len()
+                ))?;
+                continue;
+            }
+            None => (user_question, false),
+        };
+
+        if !regenerating {
+            if let Some(index) = chat_manager.find_similar_question(&user_question) {
+                let options = ["Resend anyway", "View previous answer", "Cancel"];
+                let selection = Select::with_theme(&term_theme)
+                    .with_prompt(format!("You asked this in message {index}"))
+                    .items(&options)
+                    .default(0)
+                    .interact_on_opt(&term)?;
+                match selection {
+                    Some(0) => {}
+                    Some(1) => {
+                        let answer = chat_manager
+                            .answer_to(index)
+                            .unwrap_or("No answer recorded for that message");
+                        term.write_line(&format!("\n{ROBOT_EMOJI} ChatGPT:\n"))?;
+                        term.write_line(&render_text(answer, &term, &render_options))?;
+                        term.write_line("")?;
+                        continue;
+                    }
+                    _ => continue,
+                }
+            }
+        }
+
+        if cli.auto_model {
+            let route = model_router::route(
+                &user_question,
+                cli.model(),
+                cli.escalation_model,
+                chat_manager.count_tokens(),
+            );
+            term.write_line(&format!(
+                "{ROBOT_EMOJI} {} handles this turn ({})",
+                route.model, route.reason
+            ))?;
+            chat_manager.route_turn(if route.model == cli.model() {
+                cli.model_metadata(&config)
+            } else {
+                resolve_model(route.model.get_model_name(), &config.models)
+            });
+            user_question = route.question.to_owned();
+        }
+
+        match chat_manager
+            .fit_context(
+                count_text_tokens(&user_question),
+                &client,
+                &session.parameters,
+            )
+            .await
+        {
+            Ok(Some(trimmed)) => term.write_line(&format!("{SYSTEM_EMOJI} {trimmed}"))?,
+            Ok(None) => {}
+            // the question stays in the prompt history so it can be sent again after /trim
+            Err(error) => {
+                term.write_line(&format!("{SYSTEM_EMOJI} {error}"))?;
+                continue;
             }
         }
 
         term.write_line(&format!("\n{ROBOT_EMOJI} ChatGPT:\n"))?;
+        webhooks.notify(
+            WebhookEvent::MessageSent,
+            &chat_manager,
+            Some(&user_question),
+        );
 
-        if !cli.no_stream {
-            let _response = chat_manager
+        // failed questions are taken back, they stay in the prompt history to send again
+        let response = if let Some(assistants_client) = &assistants_client {
+            // assistant runs are polled so there is nothing to stream
+            let response = match chat_manager
+                .next_message_assistants(
+                    &user_question,
+                    &client,
+                    assistants_client,
+                    &session.parameters,
+                )
+                .await
+            {
+                Ok(response) => response,
+                Err(error) => {
+                    term.write_line(&format!("\n{SYSTEM_EMOJI} Failed to answer: {error}\n"))?;
+                    continue;
+                }
+            };
+
+            term.write_line(&render_text(&response, &term, &render_options))?;
+            term.write_line("")?;
+            if let Some(token_usage) = chat_manager.token_usage_message() {
+                term.write_line(&token_usage)?;
+            }
+            term.write_line(&chat_manager.token_count_message())?;
+            term.write_line("")?;
+            response
+        } else if let Some(tools_client) = &tools_client {
+            let response = match chat_manager
+                .next_message_tools(&user_question, tools_client, &tools, &session.parameters)
+                .await
+            {
+                Ok(response) => response,
+                Err(error) => {
+                    term.write_line(&format!("\n{SYSTEM_EMOJI} Failed to answer: {error}\n"))?;
+                    continue;
+                }
+            };
+
+            term.write_line(&render_text(&response, &term, &render_options))?;
+            term.write_line("")?;
+            if let Some(token_usage) = chat_manager.token_usage_message() {
+                term.write_line(&token_usage)?;
+            }
+            term.write_line(&chat_manager.token_count_message())?;
+            term.write_line("")?;
+            response
+        } else if cli.streams(&config) {
+            match chat_manager
                 .next_message_stream_stdout(
                     &user_question,
                     &client,
                     &term,
-                    cli.temperature,
-                    cli.top_p,
+                    &render_options,
+                    &session.parameters,
                 )
-                .await?;
+                .await
+            {
+                Ok(response) => response,
+                Err(error) => {
+                    term.write_line(&format!("\n{SYSTEM_EMOJI} Failed to answer: {error}\n"))?;
+                    continue;
+                }
+            }
         } else {
-            let response = chat_manager
-                .next_message(&user_question, &client, cli.temperature, cli.top_p)
-                .await?;
+            let response = match chat_manager
+                .next_message(&user_question, &client, &session.parameters)
+                .await
+            {
+                Ok(response) => response,
+                Err(error) => {
+                    term.write_line(&format!("\n{SYSTEM_EMOJI} Failed to answer: {error}\n"))?;
+                    continue;
+                }
+            };
 
-            term.write_line(&response)?;
+            term.write_line(&render_text(&response, &term, &render_options))?;
             term.write_line("")?;
             // print usage
             if let Some(token_usage) = chat_manager.token_usage_message() {
@@ -190,42 +1569,181 @@ async fn main() -> anyhow::Result<()> {
             }
             term.write_line(&chat_manager.token_count_message())?;
             term.write_line("")?;
+            response
+        };
+        webhooks.notify(
+            WebhookEvent::ResponseReceived,
+            &chat_manager,
+            Some(&response),
+        );
+        if session.parameters.citations {
+            let attached: Vec<&str> = chat_manager
+                .messages()
+                .iter()
+                .filter(|message| message.role == messages::Role::User)
+                .map(|message| message.content.as_str())
+                .collect();
+            let sources = citations::sources(&response, &attached, render_options.hyperlinks);
+            if !sources.is_empty() {
+                term.write_line(&format!("{MAGNIFYING_GLASS_EMOJI} Sources:"))?;
+                for source in sources {
+                    term.write_line(&source)?;
+                }
+            }
+        }
+        let code_blocks = chat_manager.last_code_blocks().len();
+        if code_blocks > 0 {
+            term.write_line(&format!(
+                "{code_blocks} code blocks, /code <n> [--copy|--save <path>|--run] extracts one"
+            ))?;
         }
 
-        if !cli.no_save {
-            chat_manager.save_to_file()?;
+        if cli.verify {
+            let verify_model = match cli.verify_model {
+                Some(model) => resolve_model(model.get_model_name(), &config.models),
+                None => chat_manager.model_metadata().clone(),
+            };
+            let critique = chat_manager
+                .verify_last_response(&client, &verify_model)
+                .await?;
+            term.write_line(&format!(
+                "{MAGNIFYING_GLASS_EMOJI} Verification by {}:\n{critique}\n",
+                verify_model.name
+            ))?;
+        }
+
+        if session.pending_title.is_none() {
+            session.pending_title =
+                chat_manager.spawn_title(&client, &session.parameters, &term)?;
         }
+        session.update_summary(&chat_manager)?;
+
+        session.save(&mut chat_manager)?;
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum UserActions {
-    ReturnToChat,
-    RecreateTitle,
-    RegenerateResponse,
-    PrintChatHistory,
-}
+async fn run_finetune_command(
+    command: &FinetuneCommand,
+    config: &AppConfig,
+    term: &Term,
+    term_theme: &ColorfulTheme,
+) -> anyhow::Result<()> {
+    let client = FineTuningClient::new(config)?;
+    match command {
+        FinetuneCommand::Upload => {
+            let files = chat_manager::ChatHistory::get_all_saved_conversations()?;
+            let file_names: Vec<_> = files
+                .iter()
+                .map(|path| {
+                    path.file_name()
+                        .unwrap_or_default()
+                        .to_str()
+                        .unwrap_or_default()
+                })
+                .collect();
+            let selection = MultiSelect::with_theme(term_theme)
+                .with_prompt("Select conversations to train on")
+                .items(&file_names)
+                .interact_on(term)?;
+            if selection.is_empty() {
+                term.write_line("No conversations selected")?;
+                return Ok(());
+            }
 
-impl UserActions {
-    fn as_str(&self) -> &'static str {
-        match self {
-            UserActions::ReturnToChat => "Return to chat",
-            UserActions::RecreateTitle => "Recreate title",
-            UserActions::RegenerateResponse => "Regenerate response",
-            UserActions::PrintChatHistory => "Print chat history",
-        }
-    }
+            let conversations = selection
+                .iter()
+                .map(|index| chat_manager::ChatHistory::load_from_file(&files[*index]))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let jsonl = training_data::conversations_to_jsonl(&conversations, true)?;
+            if jsonl.is_empty() {
+                term.write_line("Selected conversations have no answers to train on")?;
+                return Ok(());
+            }
 
-    fn all() -> &'static [UserActions] {
-        &[
-            UserActions::ReturnToChat,
-            UserActions::RecreateTitle,
-            UserActions::RegenerateResponse,
-            UserActions::PrintChatHistory,
-        ]
+            let file_name = format!(
+                "gpt-cli-training-{}.jsonl",
+                utils::now().format("%Y%m%d%H%M%S")
+            );
+            let uploaded = client.upload_training_file(&file_name, jsonl).await?;
+            term.write_line(&format!(
+                "Uploaded {} ({} bytes) as {}",
+                uploaded.filename, uploaded.bytes, uploaded.id
+            ))?;
+            term.write_line(&format!(
+                "Start training with: gpt-cli finetune create {}",
+                uploaded.id
+            ))?;
+        }
+        FinetuneCommand::Create {
+            training_file,
+            model,
+            suffix,
+        } => {
+            let job = client
+                .create_job(training_file, model, suffix.as_deref())
+                .await?;
+            term.write_line(&job.summary(&config.timestamps))?;
+        }
+        FinetuneCommand::List => {
+            for job in client.list_jobs().await? {
+                term.write_line(&job.summary(&config.timestamps))?;
+            }
+        }
+        FinetuneCommand::Status { job_id } => {
+            term.write_line(&client.job_status(job_id).await?.summary(&config.timestamps))?;
+        }
     }
+    Ok(())
+}
 
-    fn all_str() -> Vec<&'static str> {
-        Self::all().iter().map(|opt| opt.as_str()).collect()
+async fn run_batch_command(
+    command: &BatchCommand,
+    cli: &Cli,
+    config: &AppConfig,
+    term: &Term,
+) -> anyhow::Result<()> {
+    let client = BatchClient::new(config)?;
+    match command {
+        BatchCommand::Submit { prompts } => {
+            let prompts = std::fs::read_to_string(prompts)
+                .with_context(|| format!("failed to read {}", prompts.display()))?;
+            let chat_history = chat_manager::ChatHistory::new(
+                cli.model_metadata(config),
+                resolve_persona(
+                    cli.persona
+                        .as_deref()
+                        .or(config.default_persona.as_deref())
+                        .unwrap_or(DEFAULT_SYSTEM_INSTRUCTIONS_KEY),
+                    &config.personas,
+                )?,
+            )?;
+            let jsonl = batch::batch_input(&prompts, &chat_history, cli.temperature(config))?;
+            let file_name = format!(
+                "gpt-cli-batch-{}.jsonl",
+                utils::now().format("%Y%m%d%H%M%S")
+            );
+            let batch = client.submit(&file_name, jsonl).await?;
+            term.write_line(&batch.summary(&config.timestamps))?;
+            term.write_line(&format!(
+                "Collect the answers with: gpt-cli batch fetch {}",
+                batch.id
+            ))?;
+        }
+        BatchCommand::Status { batch_id } => {
+            term.write_line(&client.status(batch_id).await?.summary(&config.timestamps))?;
+        }
+        BatchCommand::Fetch { batch_id, output } => {
+            let mut jsonl = String::new();
+            for result in client.fetch(batch_id).await? {
+                jsonl.push_str(&serde_json::to_string(&result)?);
+                jsonl.push('\n');
+            }
+            match output {
+                Some(output) => std::fs::write(output, jsonl)
+                    .with_context(|| format!("failed to write {}", output.display()))?,
+                None => print!("{jsonl}"),
+            }
+        }
     }
+    Ok(())
 }
@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+/// upper bounds of the latency histogram in seconds, answers take seconds rather than milliseconds
+const LATENCY_BUCKETS: [f64; 8] = [0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+#[derive(Debug, Default)]
+struct ModelMetrics {
+    requests: u64,
+    errors: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    /// requests per bucket, not cumulative
+    latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_sum: f64,
+}
+
+/// Request counters per model in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    models: Mutex<BTreeMap<String, ModelMetrics>>,
+}
+
+impl Metrics {
+    /// count an answered request
+    pub fn record_answer(
+        &self,
+        model: &str,
+        latency: Duration,
+        prompt_tokens: u64,
+        completion_tokens: u64,
+    ) {
+        self.record(model, latency, |metrics| {
+            metrics.prompt_tokens += prompt_tokens;
+            metrics.completion_tokens += completion_tokens;
+        });
+    }
+
+    /// count a request that failed
+    pub fn record_error(&self, model: &str, latency: Duration) {
+        self.record(model, latency, |metrics| metrics.errors += 1);
+    }
+
+    fn record(&self, model: &str, latency: Duration, update: impl FnOnce(&mut ModelMetrics)) {
+        let mut models = self.models.lock().expect("metrics lock poisoned");
+        let metrics = models.entry(model.to_owned()).or_default();
+        metrics.requests += 1;
+        let latency = latency.as_secs_f64();
+        metrics.latency_sum += latency;
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| latency <= *bound) {
+            metrics.latency_buckets[bucket] += 1;
+        }
+        update(metrics);
+    }
+
+    fn render(&self) -> String {
+        let models = self.models.lock().expect("metrics lock poisoned");
+        let mut text = String::new();
+        // writing to a string can't fail
+        _ = writeln!(
+            text,
+            "# HELP gpt_cli_requests_total Completion requests sent"
+        );
+        _ = writeln!(text, "# TYPE gpt_cli_requests_total counter");
+        for (model, metrics) in models.iter() {
+            _ = writeln!(
+                text,
+                "gpt_cli_requests_total{{model=\"{}\"}} {}",
+                escape_label(model),
+                metrics.requests
+            );
+        }
+        _ = writeln!(
+            text,
+            "# HELP gpt_cli_request_errors_total Completion requests that failed"
+        );
+        _ = writeln!(text, "# TYPE gpt_cli_request_errors_total counter");
+        for (model, metrics) in models.iter() {
+            _ = writeln!(
+                text,
+                "gpt_cli_request_errors_total{{model=\"{}\"}} {}",
+                escape_label(model),
+                metrics.errors
+            );
+        }
+        _ = writeln!(
+            text,
+            "# HELP gpt_cli_tokens_total Tokens of answered requests, estimated with the local tokenizer"
+        );
+        _ = writeln!(text, "# TYPE gpt_cli_tokens_total counter");
+        for (model, metrics) in models.iter() {
+            let model = escape_label(model);
+            _ = writeln!(
+                text,
+                "gpt_cli_tokens_total{{model=\"{model}\",kind=\"prompt\"}} {}",
+                metrics.prompt_tokens
+            );
+            _ = writeln!(
+                text,
+                "gpt_cli_tokens_total{{model=\"{model}\",kind=\"completion\"}} {}",
+                metrics.completion_tokens
+            );
+        }
+        _ = writeln!(
+            text,
+            "# HELP gpt_cli_request_duration_seconds Time until the whole answer arrived"
+        );
+        _ = writeln!(text, "# TYPE gpt_cli_request_duration_seconds histogram");
+        for (model, metrics) in models.iter() {
+            let model = escape_label(model);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(metrics.latency_buckets) {
+                cumulative += count;
+                _ = writeln!(
+                    text,
+                    "gpt_cli_request_duration_seconds_bucket{{model=\"{model}\",le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            _ = writeln!(
+                text,
+                "gpt_cli_request_duration_seconds_bucket{{model=\"{model}\",le=\"+Inf\"}} {}",
+                metrics.requests
+            );
+            _ = writeln!(
+                text,
+                "gpt_cli_request_duration_seconds_sum{{model=\"{model}\"}} {}",
+                metrics.latency_sum
+            );
+            _ = writeln!(
+                text,
+                "gpt_cli_request_duration_seconds_count{{model=\"{model}\"}} {}",
+                metrics.requests
+            );
+        }
+        text
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serve `/metrics` for Prometheus to scrape
+pub async fn serve_metrics(address: &str, metrics: Arc<Metrics>) -> Result<()> {
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("failed to bind metrics server to {address}"))?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(error) = handle_connection(stream, &metrics).await {
+                eprintln!("Failed to handle metrics request: {error:?}");
+            }
+        });
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, metrics: &Metrics) -> Result<()> {
+    let mut reader = BufReader::new(&mut stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+
+    // drain headers, we don't need any of them
+    loop {
+        let mut header = String::new();
+        let read = reader.read_line(&mut header).await?;
+        if read == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let (status, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", metrics.render()),
+        ("GET", _) => ("404 Not Found", String::from("Not found\n")),
+        _ => (
+            "405 Method Not Allowed",
+            String::from("Only GET is supported\n"),
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
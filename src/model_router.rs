@@ -0,0 +1,71 @@
+use crate::{conversation::count_text_tokens, utils::ChatGptModel};
+
+/// questions starting with this always go to the expensive model
+pub const ESCALATE_HINT: &str = "!!";
+/// longer questions are usually worth the better model
+const LONG_QUESTION_TOKENS: usize = 200;
+const REASONING_WORDS: &[&str] = &[
+    "why",
+    "explain",
+    "prove",
+    "design",
+    "debug",
+    "refactor",
+    "compare",
+    "optimize",
+    "review",
+    "architecture",
+];
+const CODE_MARKERS: &[&str] = &[
+    "```", "fn ", "def ", "class ", "=>", "();", "#include", "import ",
+];
+
+/// Model picked for one turn and why
+pub struct ModelRoute<'a> {
+    pub model: ChatGptModel,
+    pub reason: &'static str,
+    /// question with the routing hint removed
+    pub question: &'a str,
+}
+
+/// send simple questions to the cheap model and escalate the rest
+pub fn route<'a>(
+    question: &'a str,
+    cheap: ChatGptModel,
+    expensive: ChatGptModel,
+    context_tokens: i64,
+) -> ModelRoute<'a> {
+    let escalate = |reason: &'static str, question: &'a str| ModelRoute {
+        model: expensive,
+        reason,
+        question,
+    };
+
+    if let Some(question) = question.strip_prefix(ESCALATE_HINT) {
+        return escalate("requested with !!", question.trim_start());
+    }
+
+    let question_tokens = count_text_tokens(question);
+    if context_tokens + question_tokens as i64 > cheap.get_model_token_limit() as i64 / 2 {
+        return escalate("long conversation", question);
+    }
+    if question_tokens > LONG_QUESTION_TOKENS {
+        return escalate("long question", question);
+    }
+    if CODE_MARKERS.iter().any(|marker| question.contains(marker)) {
+        return escalate("contains code", question);
+    }
+    let lowercase = question.to_lowercase();
+    if lowercase
+        .split(|character: char| !character.is_alphanumeric())
+        .any(|word| REASONING_WORDS.contains(&word))
+    {
+        return escalate("needs reasoning", question);
+    }
+
+    ModelRoute {
+        model: cheap,
+        reason: "simple question",
+        question,
+    }
+}
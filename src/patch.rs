@@ -0,0 +1,289 @@
+use anyhow::{Context, Result};
+use dialoguer::{console::Term, theme::ColorfulTheme, Confirm};
+use std::path::{Component, Path, PathBuf};
+
+const DEV_NULL: &str = "/dev/null";
+
+/// Change to one file described by a unified diff
+#[derive(Debug, Clone)]
+pub struct FilePatch {
+    pub path: PathBuf,
+    /// None when the file is created
+    pub original: Option<String>,
+    /// None when the file is deleted
+    pub modified: Option<String>,
+}
+
+/// contents of all ```diff blocks in a response
+pub fn extract_diff_blocks(response: &str) -> Vec<String> {
+    let mut blocks = vec![];
+    let mut current: Option<Vec<&str>> = None;
+    for line in response.lines() {
+        let trimmed = line.trim_start();
+        match &mut current {
+            None if trimmed.starts_with("```diff") || trimmed.starts_with("```patch") => {
+                current = Some(vec![]);
+            }
+            Some(lines) if trimmed.starts_with("```") => {
+                blocks.push(lines.join("\n") + "\n");
+                current = None;
+            }
+            Some(lines) => lines.push(line),
+            None => {}
+        }
+    }
+    blocks
+}
+
+/// strip the `a/` and `b/` git likes to put in front of paths
+fn patch_path(header: &str) -> Option<&str> {
+    // timestamps are separated by a tab
+    let path = header.split('\t').next()?.trim();
+    if path == DEV_NULL {
+        return None;
+    }
+    Some(
+        path.strip_prefix("a/")
+            .or_else(|| path.strip_prefix("b/"))
+            .unwrap_or(path),
+    )
+}
+
+/// refuse paths outside the current directory
+///
+/// diffs come from the model so `../`, absolute paths and symlinks pointing
+/// out of the directory are all rejected, files that don't exist yet are
+/// checked through the closest parent that does
+fn check_inside_current_dir(path: &Path) -> Result<()> {
+    let outside = || anyhow::anyhow!("{} is outside the current directory", path.display());
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(outside());
+    }
+    let current_dir = std::env::current_dir()?.canonicalize()?;
+    let joined = current_dir.join(path);
+    let existing = joined
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(&current_dir);
+    if !existing.canonicalize()?.starts_with(&current_dir) {
+        return Err(outside());
+    }
+    Ok(())
+}
+
+/// split a multi-file diff into one diff per file
+fn split_files(diff: &str) -> Vec<String> {
+    let mut files: Vec<String> = vec![];
+    let lines: Vec<&str> = diff.lines().collect();
+    for (index, line) in lines.iter().enumerate() {
+        let starts_file = line.starts_with("--- ")
+            && lines
+                .get(index + 1)
+                .is_some_and(|next| next.starts_with("+++ "));
+        if starts_file {
+            files.push(String::new());
+        }
+        // git headers like `diff --git` and `index` are dropped
+        if let Some(file) = files.last_mut() {
+            if !(line.starts_with("diff --git") || line.starts_with("index ")) {
+                file.push_str(line);
+                file.push('\n');
+            }
+        }
+    }
+    files
+}
+
+/// apply a unified diff against files in the current directory
+///
+/// nothing is written, the new contents are returned so they can be reviewed first.
+/// diffs touching files outside the current directory are refused
+pub fn apply_diff(diff: &str) -> Result<Vec<FilePatch>> {
+    let mut patches = vec![];
+    for file_diff in split_files(diff) {
+        let patch = diffy::Patch::from_str(&file_diff)
+            .map_err(|error| anyhow::anyhow!("failed to parse diff: {error}"))?;
+        let original_path = patch.original().and_then(patch_path);
+        let modified_path = patch.modified().and_then(patch_path);
+        let path = PathBuf::from(
+            modified_path
+                .or(original_path)
+                .context("diff doesn't name a file")?,
+        );
+        for file in [original_path, modified_path].into_iter().flatten() {
+            check_inside_current_dir(Path::new(file))?;
+        }
+
+        let original = match original_path {
+            Some(original_path) => Some(
+                std::fs::read_to_string(original_path)
+                    .with_context(|| format!("failed to read {original_path}"))?,
+            ),
+            None => None,
+        };
+        let modified = match modified_path {
+            Some(_) => Some(
+                diffy::apply(original.as_deref().unwrap_or_default(), &patch).map_err(|error| {
+                    anyhow::anyhow!("diff doesn't apply to {}: {error}", path.display())
+                })?,
+            ),
+            None => None,
+        };
+        patches.push(FilePatch {
+            path,
+            original,
+            modified,
+        });
+    }
+    if patches.is_empty() {
+        anyhow::bail!("no file changes found in diff");
+    }
+    Ok(patches)
+}
+
+impl FilePatch {
+    /// patch that replaces the file, existing content is read so the change can be reviewed
+    pub fn replace(path: PathBuf, modified: String) -> Result<Self> {
+        let original = if path.exists() {
+            Some(std::fs::read_to_string(&path)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            path,
+            original,
+            modified: Some(modified),
+        })
+    }
+
+    fn action(&self) -> &'static str {
+        match (&self.original, &self.modified) {
+            (None, _) => "Create",
+            (_, None) => "Delete",
+            _ => "Modify",
+        }
+    }
+}
+
+/// show a colored diff of every change and only write after confirmation
+///
+/// deleting files has to be confirmed explicitly, returns whether the changes were written
+pub fn confirm_and_write(
+    patches: &[FilePatch],
+    term: &Term,
+    term_theme: &ColorfulTheme,
+) -> Result<bool> {
+    let formatter = diffy::PatchFormatter::new().with_color();
+    for patch in patches {
+        term.write_line(&format!("{} {}", patch.action(), patch.path.display()))?;
+        let diff = diffy::create_patch(
+            patch.original.as_deref().unwrap_or_default(),
+            patch.modified.as_deref().unwrap_or_default(),
+        );
+        term.write_line(&formatter.fmt_patch(&diff).to_string())?;
+    }
+    if patches.is_empty()
+        || !Confirm::with_theme(term_theme)
+            .with_prompt(format!("Write changes to {} files?", patches.len()))
+            .default(!patches.iter().any(|patch| patch.modified.is_none()))
+            .interact_on(term)?
+    {
+        return Ok(false);
+    }
+    write_patches(patches)?;
+    for patch in patches {
+        term.write_line(&format!("Wrote {}", patch.path.display()))?;
+    }
+    Ok(true)
+}
+
+/// write patched files to disk, nothing is written when a file is outside the current directory
+fn write_patches(patches: &[FilePatch]) -> Result<()> {
+    for patch in patches {
+        check_inside_current_dir(&patch.path)?;
+    }
+    for patch in patches {
+        match &patch.modified {
+            Some(modified) => {
+                if let Some(parent) = patch.path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&patch.path, modified)?;
+            }
+            None => std::fs::remove_file(&patch.path)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_diff_blocks() {
+        let response =
+            "Change this:\n```diff\n--- a/x\n+++ b/x\n```\nand\n```rust\nfn main() {}\n```";
+        assert_eq!(extract_diff_blocks(response), vec!["--- a/x\n+++ b/x\n"]);
+    }
+
+    #[test]
+    fn strips_git_prefixes_and_dev_null() {
+        assert_eq!(patch_path("a/src/main.rs\t2024-01-01"), Some("src/main.rs"));
+        assert_eq!(patch_path("b/src/main.rs"), Some("src/main.rs"));
+        assert_eq!(patch_path(DEV_NULL), None);
+    }
+
+    #[test]
+    fn splits_files_without_git_headers() {
+        let diff = "diff --git a/x b/x\nindex 1..2\n--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b\n--- a/y\n+++ b/y\n@@ -1 +1 @@\n-c\n+d\n";
+        assert_eq!(
+            split_files(diff),
+            vec![
+                "--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b\n",
+                "--- a/y\n+++ b/y\n@@ -1 +1 @@\n-c\n+d\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn creates_new_files() {
+        let patches =
+            apply_diff("--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1,2 @@\n+one\n+two\n").unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path, PathBuf::from("new.txt"));
+        assert_eq!(patches[0].original, None);
+        assert_eq!(patches[0].modified.as_deref(), Some("one\ntwo\n"));
+        assert_eq!(patches[0].action(), "Create");
+    }
+
+    #[test]
+    fn rejects_diffs_that_change_nothing_or_dont_apply() {
+        assert!(apply_diff("no diff here").is_err());
+        let error = apply_diff(
+            "--- a/Cargo.toml\n+++ b/Cargo.toml\n@@ -1 +1 @@\n-not the first line\n+changed\n",
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("doesn't apply"), "{error}");
+        assert!(
+            apply_diff("--- a/does/not/exist\n+++ b/does/not/exist\n@@ -1 +1 @@\n-a\n+b\n")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn refuses_files_outside_the_current_directory() {
+        for diff in [
+            "--- a/../outside.txt\n+++ b/../outside.txt\n@@ -1 +1 @@\n-a\n+b\n",
+            "--- /dev/null\n+++ /tmp/outside.txt\n@@ -0,0 +1 @@\n+a\n",
+            "--- /dev/null\n+++ b/src/../../outside.txt\n@@ -0,0 +1 @@\n+a\n",
+        ] {
+            let error = apply_diff(diff).unwrap_err();
+            assert!(error.to_string().contains("outside"), "{error}");
+        }
+        assert!(check_inside_current_dir(Path::new("src/new/module.rs")).is_ok());
+    }
+}
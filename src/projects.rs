@@ -0,0 +1,143 @@
+use crate::chat_manager::ChatHistory;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// folder in the cache dir with one folder of conversations per project
+pub const PROJECTS_DIR_NAME: &str = "projects";
+
+/// Name for the conversations started in a directory
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ProjectConfig {
+    pub directory: PathBuf,
+    pub name: String,
+}
+
+/// project a directory belongs to
+///
+/// the configured name of the closest directory wins over the `origin` remote of its git repository
+pub fn detect_project(directory: &Path, configured: &[ProjectConfig]) -> Option<String> {
+    configured
+        .iter()
+        .filter(|project| directory.starts_with(&project.directory))
+        .max_by_key(|project| project.directory.components().count())
+        .map(|project| project.name.clone())
+        .or_else(|| git_remote(directory).and_then(|remote| project_from_remote(&remote)))
+}
+
+fn git_remote(directory: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(["remote", "get-url", "origin"])
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// owner and repository of a remote URL, e.g. dmweis/gpt-cli
+///
+/// works for https and ssh URLs and the scp like git@github.com:owner/repository form
+pub fn project_from_remote(remote: &str) -> Option<String> {
+    let remote = remote.trim().trim_end_matches('/');
+    let remote = remote.strip_suffix(".git").unwrap_or(remote);
+    let path = match remote.split_once("://") {
+        Some((_, rest)) => rest.split_once('/')?.1,
+        None => remote.rsplit_once(':').map_or(remote, |(_, path)| path),
+    };
+    let segments: Vec<&str> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    match segments.as_slice() {
+        [] => None,
+        [repository] => Some((*repository).to_owned()),
+        [.., owner, repository] => Some(format!("{owner}/{repository}")),
+    }
+}
+
+/// folder name of a project, slashes would nest folders
+pub fn project_dir_name(project: &str) -> String {
+    project
+        .chars()
+        .map(|character| match character {
+            '/' | '\\' | ':' => '_',
+            character => character,
+        })
+        .collect()
+}
+
+/// project of the directory gpt-cli runs in
+pub fn current_project(configured: &[ProjectConfig]) -> Option<String> {
+    let directory = std::env::current_dir().ok()?;
+    detect_project(&directory, configured)
+}
+
+/// Conversations offered by --select-file, --here and `gpt-cli history`
+///
+/// inside a project only its conversations are offered unless every project is asked for
+pub struct ResumeScope {
+    /// only conversations started in this directory
+    here: Option<PathBuf>,
+    project: Option<String>,
+    /// only conversations with every one of these tags
+    tags: Vec<String>,
+}
+
+impl ResumeScope {
+    pub fn new(here: bool, all_projects: bool, configured: &[ProjectConfig]) -> Result<Self> {
+        Ok(Self {
+            here: here.then(std::env::current_dir).transpose()?,
+            project: if all_projects {
+                None
+            } else {
+                current_project(configured)
+            },
+            tags: vec![],
+        })
+    }
+
+    /// only offer conversations tagged with all of these
+    pub fn with_tags(mut self, tags: &[String]) -> Self {
+        self.tags = tags.to_vec();
+        self
+    }
+
+    /// every conversation is offered
+    pub fn is_unrestricted(&self) -> bool {
+        self.here.is_none() && self.project.is_none() && self.tags.is_empty()
+    }
+
+    pub fn contains(&self, chat_history: &ChatHistory) -> bool {
+        let in_directory = match &self.here {
+            Some(here) => chat_history.started_in(here),
+            None => true,
+        };
+        let in_project = match &self.project {
+            Some(project) => chat_history.project() == Some(project.as_str()),
+            None => true,
+        };
+        let tagged = self.tags.iter().all(|tag| chat_history.has_tag(tag));
+        in_directory && in_project && tagged
+    }
+
+    /// where conversations were looked for, e.g. to say that there are none
+    pub fn description(&self) -> String {
+        let location = match (&self.here, &self.project) {
+            (Some(here), _) => here.display().to_string(),
+            (None, Some(project)) => format!("project {project}"),
+            (None, None) => String::from("any project"),
+        };
+        if self.tags.is_empty() {
+            location
+        } else {
+            format!("{location} tagged {}", self.tags.join(", "))
+        }
+    }
+}
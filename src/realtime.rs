@@ -0,0 +1,294 @@
+use crate::{
+    chat_manager::ChatHistory,
+    configuration::AppConfig,
+    utils::{QUESTION_MARK_EMOJI, ROBOT_EMOJI, SYSTEM_EMOJI},
+};
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use dialoguer::console::Term;
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, http::HeaderValue, Message};
+
+const TRANSCRIPTION_MODEL: &str = "whisper-1";
+/// the realtime API speaks 24kHz mono PCM16 in both directions
+const REALTIME_SAMPLE_RATE: u32 = 24_000;
+
+type PlaybackBuffer = Arc<Mutex<VecDeque<f32>>>;
+
+/// Spoken conversation with a realtime model
+///
+/// Both sides are transcribed into the chat history so the conversation can be
+/// saved, searched and resumed as text
+pub async fn talk(
+    config: &AppConfig,
+    model: &str,
+    voice: &str,
+    chat_history: &mut ChatHistory,
+    term: &Term,
+) -> Result<()> {
+    // same server as the chat requests, over a websocket
+    let api_base = config.api_base().replacen("http", "ws", 1);
+    let mut request = format!("{api_base}/realtime?model={model}").into_client_request()?;
+    let headers = request.headers_mut();
+    headers.insert(
+        "Authorization",
+        HeaderValue::from_str(&format!("Bearer {}", config.open_ai_api_key))?,
+    );
+    headers.insert("OpenAI-Beta", HeaderValue::from_static("realtime=v1"));
+    for (name, value) in config.openai_headers() {
+        headers.insert(name, HeaderValue::from_str(value)?);
+    }
+
+    let (socket, _) = tokio_tungstenite::connect_async(request)
+        .await
+        .context("failed to connect to realtime API")?;
+    let (mut sender, mut receiver) = socket.split();
+
+    let system_prompt = chat_history
+        .messages()
+        .first()
+        .map(|message| message.content.clone())
+        .unwrap_or_default();
+    let session_update = json!({
+        "type": "session.update",
+        "session": {
+            "instructions": system_prompt,
+            "voice": voice,
+            "input_audio_transcription": { "model": TRANSCRIPTION_MODEL },
+            "turn_detection": { "type": "server_vad" },
+        }
+    });
+    sender
+        .send(Message::Text(session_update.to_string()))
+        .await?;
+
+    // audio streams are not Send so they stay on this task and only talk through channels
+    let (microphone_sender, mut microphone_receiver) = mpsc::unbounded_channel();
+    let _input_stream = start_microphone(microphone_sender)?;
+    let playback: PlaybackBuffer = Default::default();
+    let (_output_stream, output_sample_rate) = start_speaker(playback.clone())?;
+
+    term.write_line(&format!(
+        "{SYSTEM_EMOJI} Listening, start talking. Press Ctrl-C to stop"
+    ))?;
+
+    let mut transcript = Transcript::default();
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            Some(samples) = microphone_receiver.recv() => {
+                let append = json!({
+                    "type": "input_audio_buffer.append",
+                    "audio": BASE64.encode(pcm16_bytes(&samples)),
+                });
+                sender.send(Message::Text(append.to_string())).await?;
+            }
+            message = receiver.next() => {
+                let Some(message) = message else {
+                    break;
+                };
+                let Message::Text(text) = message? else {
+                    continue;
+                };
+                let event: Value = serde_json::from_str(&text)?;
+                handle_event(&event, &mut transcript, &playback, output_sample_rate, term)?;
+                transcript.flush_into(chat_history, term)?;
+            }
+        }
+    }
+
+    _ = sender.send(Message::Close(None)).await;
+    Ok(())
+}
+
+fn handle_event(
+    event: &Value,
+    transcript: &mut Transcript,
+    playback: &PlaybackBuffer,
+    output_sample_rate: u32,
+    term: &Term,
+) -> Result<()> {
+    let item_id = event["item_id"].as_str().unwrap_or_default();
+    match event["type"].as_str().unwrap_or_default() {
+        "conversation.item.created" => {
+            let item = &event["item"];
+            if let (Some(id), Some(role)) = (item["id"].as_str(), item["role"].as_str()) {
+                transcript.item_created(id, role == "user");
+            }
+        }
+        "conversation.item.input_audio_transcription.completed"
+        | "response.audio_transcript.done" => {
+            let text = event["transcript"].as_str().unwrap_or_default();
+            transcript.item_transcribed(item_id, text.trim());
+        }
+        "conversation.item.input_audio_transcription.failed" => {
+            transcript.item_transcribed(item_id, "");
+        }
+        "response.audio.delta" => {
+            let audio = BASE64.decode(event["delta"].as_str().unwrap_or_default())?;
+            let samples: Vec<f32> = audio
+                .chunks_exact(2)
+                .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / i16::MAX as f32)
+                .collect();
+            let samples = resample(&samples, REALTIME_SAMPLE_RATE, output_sample_rate);
+            playback
+                .lock()
+                .expect("playback buffer poisoned")
+                .extend(samples);
+        }
+        "input_audio_buffer.speech_started" => {
+            // let the user interrupt the answer
+            playback.lock().expect("playback buffer poisoned").clear();
+        }
+        "error" => {
+            let message = event["error"]["message"]
+                .as_str()
+                .unwrap_or("unknown error");
+            term.write_line(&format!("{SYSTEM_EMOJI} Realtime API error: {message}"))?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Conversation items in the order the server created them
+///
+/// Transcripts arrive out of order, user speech is often transcribed after
+/// the answer already started
+#[derive(Default)]
+struct Transcript {
+    items: VecDeque<TranscriptItem>,
+}
+
+struct TranscriptItem {
+    id: String,
+    from_user: bool,
+    text: Option<String>,
+}
+
+impl Transcript {
+    fn item_created(&mut self, id: &str, from_user: bool) {
+        self.items.push_back(TranscriptItem {
+            id: id.to_owned(),
+            from_user,
+            text: None,
+        });
+    }
+
+    fn item_transcribed(&mut self, id: &str, text: &str) {
+        if let Some(item) = self.items.iter_mut().find(|item| item.id == id) {
+            item.text = Some(text.to_owned());
+        }
+    }
+
+    /// move finished items into history while keeping order
+    fn flush_into(&mut self, chat_history: &mut ChatHistory, term: &Term) -> Result<()> {
+        while self.items.front().is_some_and(|item| item.text.is_some()) {
+            let item = self.items.pop_front().expect("front item checked");
+            let text = item.text.unwrap_or_default();
+            if text.is_empty() {
+                continue;
+            }
+            if item.from_user {
+                term.write_line(&format!("{QUESTION_MARK_EMOJI} You: {text}"))?;
+                chat_history.add_user_message(&text)?;
+            } else {
+                term.write_line(&format!("{ROBOT_EMOJI} ChatGPT: {text}"))?;
+                chat_history.add_assistant_message(&text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn start_microphone(sender: mpsc::UnboundedSender<Vec<f32>>) -> Result<cpal::Stream> {
+    let device = cpal::default_host()
+        .default_input_device()
+        .context("no microphone found")?;
+    let config = device.default_input_config()?;
+    let channels = config.channels() as usize;
+    let sample_rate = config.sample_rate().0;
+    if config.sample_format() != cpal::SampleFormat::F32 {
+        anyhow::bail!(
+            "microphone sample format {} is not supported",
+            config.sample_format()
+        );
+    }
+
+    let stream = device.build_input_stream(
+        &config.into(),
+        move |data: &[f32], _| {
+            let mono: Vec<f32> = data
+                .chunks(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                .collect();
+            _ = sender.send(resample(&mono, sample_rate, REALTIME_SAMPLE_RATE));
+        },
+        |error| eprintln!("Microphone error: {error}"),
+        None,
+    )?;
+    stream.play()?;
+    Ok(stream)
+}
+
+fn start_speaker(playback: PlaybackBuffer) -> Result<(cpal::Stream, u32)> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .context("no speaker found")?;
+    let config = device.default_output_config()?;
+    let channels = config.channels() as usize;
+    let sample_rate = config.sample_rate().0;
+    if config.sample_format() != cpal::SampleFormat::F32 {
+        anyhow::bail!(
+            "speaker sample format {} is not supported",
+            config.sample_format()
+        );
+    }
+
+    let stream = device.build_output_stream(
+        &config.into(),
+        move |data: &mut [f32], _| {
+            let mut playback = playback.lock().expect("playback buffer poisoned");
+            for frame in data.chunks_mut(channels) {
+                let sample = playback.pop_front().unwrap_or_default();
+                frame.fill(sample);
+            }
+        },
+        |error| eprintln!("Speaker error: {error}"),
+        None,
+    )?;
+    stream.play()?;
+    Ok((stream, sample_rate))
+}
+
+/// linear interpolation is plenty for speech
+fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let ratio = from_rate as f64 / to_rate as f64;
+    let output_len = (samples.len() as f64 / ratio) as usize;
+    (0..output_len)
+        .map(|index| {
+            let position = index as f64 * ratio;
+            let before = position.floor() as usize;
+            let after = (before + 1).min(samples.len() - 1);
+            let fraction = (position - before as f64) as f32;
+            samples[before] * (1.0 - fraction) + samples[after] * fraction
+        })
+        .collect()
+}
+
+fn pcm16_bytes(samples: &[f32]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|sample| ((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+        .collect()
+}
@@ -0,0 +1,798 @@
+use crate::content_filter::{ContentFilter, StreamFilter};
+use anyhow::Result;
+use dialoguer::console::{measure_text_width, style, Style, Term};
+#[cfg(feature = "markdown")]
+use std::sync::OnceLock;
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+#[cfg(feature = "markdown")]
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Theme, ThemeSet},
+    parsing::SyntaxSet,
+    util::as_24_bit_terminal_escaped,
+};
+
+#[cfg(feature = "markdown")]
+const CODE_THEME: &str = "base16-ocean.dark";
+/// short enough to look live, long enough to batch a few deltas
+pub const DEFAULT_FLUSH_INTERVAL_MS: u64 = 30;
+
+/// Options for how assistant output is written to the terminal
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    /// emit OSC-8 hyperlinks for URLs and existing file paths
+    pub hyperlinks: bool,
+    /// style markdown and highlight code blocks, always off without the markdown feature
+    pub markdown: bool,
+    /// streamed output is written at most this often, zero writes every delta
+    pub flush_interval: Duration,
+    /// masks words in presentable mode
+    pub content_filter: Option<ContentFilter>,
+}
+
+impl RenderOptions {
+    pub fn new(term: &Term, disable_hyperlinks: bool, raw: bool) -> Self {
+        Self {
+            hyperlinks: !disable_hyperlinks && hyperlinks_supported(term),
+            markdown: cfg!(feature = "markdown") && !raw && term.is_term(),
+            flush_interval: Duration::from_millis(DEFAULT_FLUSH_INTERVAL_MS),
+            content_filter: None,
+        }
+    }
+
+    pub fn with_flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    pub fn with_content_filter(mut self, content_filter: Option<ContentFilter>) -> Self {
+        self.content_filter = content_filter;
+        self
+    }
+}
+
+/// Best effort detection of terminals that understand OSC-8 hyperlinks
+///
+/// There is no reliable way to query this so we go by the environment
+pub fn hyperlinks_supported(term: &Term) -> bool {
+    if !term.is_term() {
+        return false;
+    }
+    if let Ok(term_program) = std::env::var("TERM_PROGRAM") {
+        if matches!(
+            term_program.as_str(),
+            "iTerm.app" | "WezTerm" | "vscode" | "Hyper" | "ghostty"
+        ) {
+            return true;
+        }
+    }
+    if let Ok(vte_version) = std::env::var("VTE_VERSION") {
+        // gnome terminal and friends support hyperlinks since VTE 0.50
+        if vte_version.parse::<u32>().unwrap_or_default() >= 5000 {
+            return true;
+        }
+    }
+    std::env::var_os("KITTY_WINDOW_ID").is_some()
+        || std::env::var_os("WT_SESSION").is_some()
+        || std::env::var("TERM")
+            .map(|term| term.contains("kitty") || term.contains("alacritty"))
+            .unwrap_or_default()
+}
+
+/// Renders a complete response
+pub fn render_text(text: &str, term: &Term, options: &RenderOptions) -> String {
+    let mut formatter = Formatter::new(options, terminal_width(term));
+    let mut output = formatter.push(text);
+    output.push_str(&formatter.finish());
+    output
+}
+
+fn terminal_width(term: &Term) -> usize {
+    let (_rows, columns) = term.size();
+    columns as usize
+}
+
+/// Renders a response as it's being streamed
+///
+/// Output is batched and written once per flush interval, a write per delta
+/// flickers and is slow over SSH
+pub struct ResponseRenderer<'a> {
+    term: &'a Term,
+    formatter: Formatter<'a>,
+    flush_interval: Duration,
+    /// formatted output that wasn't written yet
+    unflushed: String,
+    last_flush: Instant,
+}
+
+impl<'a> ResponseRenderer<'a> {
+    pub fn new(term: &'a Term, options: &'a RenderOptions) -> Self {
+        Self {
+            term,
+            formatter: Formatter::new(options, terminal_width(term)),
+            flush_interval: options.flush_interval,
+            unflushed: String::new(),
+            last_flush: Instant::now(),
+        }
+    }
+
+    pub fn write(&mut self, delta: &str) -> Result<()> {
+        self.unflushed.push_str(&self.formatter.push(delta));
+        if self.last_flush.elapsed() >= self.flush_interval {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.unflushed.is_empty() {
+            self.term.write_str(&self.unflushed)?;
+            self.unflushed.clear();
+        }
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+
+    /// write out anything that is still held back
+    pub fn finish(&mut self) -> Result<()> {
+        self.unflushed.push_str(&self.formatter.finish());
+        self.flush()
+    }
+}
+
+/// Incremental formatter shared by the streaming and complete renderers
+///
+/// Text is held back until a word is complete so that links and markdown markers
+/// can be detected, lines that look like table rows are held back until the table ends
+/// and code blocks are highlighted one complete line at a time
+struct Formatter<'a> {
+    options: &'a RenderOptions,
+    width: usize,
+    /// holds back words until they can be checked against the content filter
+    stream_filter: Option<StreamFilter>,
+    /// text of the current line that hasn't been written yet
+    pending: String,
+    /// part of the current line has already been written
+    line_started: bool,
+    table: Vec<String>,
+    code_block: Option<CodeBlock>,
+    /// code blocks opened so far, shown next to the fence for /code
+    code_blocks: usize,
+    /// inline markdown state of the current line
+    heading: bool,
+    bold: bool,
+    code_span: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(options: &'a RenderOptions, width: usize) -> Self {
+        Self {
+            options,
+            width,
+            stream_filter: options.content_filter.clone().map(StreamFilter::new),
+            pending: String::new(),
+            line_started: false,
+            table: vec![],
+            code_block: None,
+            code_blocks: 0,
+            heading: false,
+            bold: false,
+            code_span: false,
+        }
+    }
+
+    fn push(&mut self, text: &str) -> String {
+        let mut output = String::new();
+        match &mut self.stream_filter {
+            Some(stream_filter) => self.pending.push_str(&stream_filter.push(text)),
+            None => self.pending.push_str(text),
+        }
+        while let Some(newline) = self.pending.find('\n') {
+            let line: String = self.pending.drain(..=newline).collect();
+            self.line(line.trim_end_matches('\n'), true, &mut output);
+        }
+        self.partial(&mut output);
+        output
+    }
+
+    fn finish(&mut self) -> String {
+        let mut output = String::new();
+        if let Some(stream_filter) = &mut self.stream_filter {
+            let rest = stream_filter.finish();
+            self.pending.push_str(&rest);
+        }
+        if !self.pending.is_empty() {
+            let line = std::mem::take(&mut self.pending);
+            self.line(&line, false, &mut output);
+        }
+        self.flush_table(&mut output);
+        output
+    }
+
+    fn line(&mut self, line: &str, newline: bool, output: &mut String) {
+        let continued = std::mem::take(&mut self.line_started);
+        let mut text = line;
+        if !continued {
+            if let Some(code) = self.code_line(line) {
+                self.flush_table(output);
+                output.push_str(&code);
+                if newline {
+                    output.push('\n');
+                }
+                return;
+            }
+            if is_table_row(line) {
+                // table is written out in one go once it ends
+                self.table.push(line.to_owned());
+                return;
+            }
+            self.flush_table(output);
+            text = self.start_line(line, output);
+        }
+        output.push_str(&self.inline(text));
+        // inline markdown doesn't carry over to the next line
+        self.heading = false;
+        self.bold = false;
+        self.code_span = false;
+        if newline {
+            output.push('\n');
+        }
+    }
+
+    /// write out as much of an incomplete line as is safe
+    fn partial(&mut self, output: &mut String) {
+        if self.pending.is_empty() {
+            return;
+        }
+        if !self.line_started {
+            if self.code_block.is_some() {
+                // code is only highlighted once the line is complete
+                return;
+            }
+            let trimmed = self.pending.trim_start();
+            if trimmed.is_empty()
+                || trimmed.starts_with('|')
+                || (self.options.markdown && trimmed.starts_with('`'))
+            {
+                // might be the start of a table row or a code fence
+                return;
+            }
+            self.flush_table(output);
+        }
+
+        let split_at = if self.options.hyperlinks || self.options.markdown {
+            match self
+                .pending
+                .char_indices()
+                .rev()
+                .find(|(_, character)| character.is_whitespace())
+            {
+                Some((index, whitespace)) => index + whitespace.len_utf8(),
+                None => return,
+            }
+        } else {
+            self.pending.len()
+        };
+
+        let rest = self.pending.split_off(split_at);
+        let pending = std::mem::replace(&mut self.pending, rest);
+        let text = if self.line_started {
+            pending.as_str()
+        } else {
+            self.start_line(&pending, output)
+        };
+        let text = self.inline(text);
+        output.push_str(&text);
+        self.line_started = true;
+    }
+
+    fn flush_table(&mut self, output: &mut String) {
+        if self.table.is_empty() {
+            return;
+        }
+        let rows = std::mem::take(&mut self.table);
+        output.push_str(&render_table(&rows, self.width));
+    }
+
+    /// fences and lines inside code blocks, None for everything else
+    fn code_line(&mut self, line: &str) -> Option<String> {
+        if !self.options.markdown {
+            return None;
+        }
+        let trimmed = line.trim();
+        let fence = trimmed.len() - trimmed.trim_start_matches('`').len();
+        match &mut self.code_block {
+            Some(code_block) => {
+                if fence >= code_block.fence && fence == trimmed.len() {
+                    self.code_block = None;
+                    return Some(style(line).dim().to_string());
+                }
+                Some(code_block.highlight(line))
+            }
+            None if fence >= 3 => {
+                self.code_block = Some(CodeBlock::new(fence, &trimmed[fence..]));
+                self.code_blocks += 1;
+                Some(format!(
+                    "{} {}",
+                    style(line).dim(),
+                    style(format!("[{}]", self.code_blocks)).dim().bold()
+                ))
+            }
+            None => None,
+        }
+    }
+
+    /// render block level markdown at the start of a line, returns the rest of the line
+    fn start_line<'b>(&mut self, line: &'b str, output: &mut String) -> &'b str {
+        if !self.options.markdown {
+            return line;
+        }
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+
+        let hashes = trimmed.len() - trimmed.trim_start_matches('#').len();
+        if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+            self.heading = true;
+            return &trimmed[hashes + 1..];
+        }
+        for bullet in ["- ", "* ", "+ "] {
+            if let Some(rest) = trimmed.strip_prefix(bullet) {
+                output.push_str(indent);
+                output.push_str("• ");
+                return rest;
+            }
+        }
+        if let Some(rest) = trimmed.strip_prefix("> ") {
+            output.push_str(indent);
+            output.push_str(&style("│ ").dim().to_string());
+            return rest;
+        }
+        let rule = trimmed.trim_end();
+        if rule.len() >= 3
+            && ['-', '*', '_']
+                .iter()
+                .any(|marker| rule.chars().all(|character| character == *marker))
+        {
+            output.push_str(&style("─".repeat(self.width.min(40))).dim().to_string());
+            return "";
+        }
+        line
+    }
+
+    /// bold text and inline code, hyperlinks inside of them
+    fn inline(&mut self, text: &str) -> String {
+        if !self.options.markdown {
+            return self.styled(text);
+        }
+        let mut output = String::new();
+        let mut span_start = 0;
+        let mut index = 0;
+        while index < text.len() {
+            let rest = &text[index..];
+            let marker = if !self.code_span && rest.starts_with("**") {
+                2
+            } else if rest.starts_with('`') {
+                1
+            } else {
+                index += rest.chars().next().map(char::len_utf8).unwrap_or(1);
+                continue;
+            };
+            output.push_str(&self.styled(&text[span_start..index]));
+            if marker == 2 {
+                self.bold = !self.bold;
+            } else {
+                self.code_span = !self.code_span;
+            }
+            index += marker;
+            span_start = index;
+        }
+        output.push_str(&self.styled(&text[span_start..]));
+        output
+    }
+
+    fn styled(&self, text: &str) -> String {
+        if text.is_empty() {
+            return String::new();
+        }
+        let text = if self.options.hyperlinks {
+            linkify(text)
+        } else {
+            text.to_owned()
+        };
+        let mut text_style = Style::new();
+        if self.heading {
+            text_style = text_style.bold().cyan();
+        }
+        if self.bold {
+            text_style = text_style.bold();
+        }
+        if self.code_span {
+            text_style = text_style.yellow();
+        }
+        text_style.apply_to(text).to_string()
+    }
+}
+
+/// Fenced code block being highlighted
+struct CodeBlock {
+    /// number of backticks that opened the block
+    fence: usize,
+    /// None when the language is unknown or colors are disabled
+    #[cfg(feature = "markdown")]
+    highlighter: Option<HighlightLines<'static>>,
+}
+
+#[cfg(not(feature = "markdown"))]
+impl CodeBlock {
+    fn new(fence: usize, _language: &str) -> Self {
+        Self { fence }
+    }
+
+    fn highlight(&mut self, line: &str) -> String {
+        line.to_owned()
+    }
+}
+
+#[cfg(feature = "markdown")]
+impl CodeBlock {
+    fn new(fence: usize, language: &str) -> Self {
+        let language = language.split_whitespace().next().unwrap_or_default();
+        let highlighter = (!language.is_empty() && dialoguer::console::colors_enabled())
+            .then(|| syntax_set().find_syntax_by_token(language))
+            .flatten()
+            .map(|syntax| HighlightLines::new(syntax, code_theme()));
+        Self { fence, highlighter }
+    }
+
+    fn highlight(&mut self, line: &str) -> String {
+        let Some(highlighter) = &mut self.highlighter else {
+            return line.to_owned();
+        };
+        // syntaxes are loaded with newlines so the line is highlighted with its newline
+        let line = format!("{line}\n");
+        match highlighter.highlight_line(&line, syntax_set()) {
+            Ok(ranges) => format!(
+                "{}\x1b[0m",
+                as_24_bit_terminal_escaped(&ranges, false).trim_end_matches('\n')
+            ),
+            Err(_) => line.trim_end_matches('\n').to_owned(),
+        }
+    }
+}
+
+/// Piece of a highlighted line with its 24-bit color
+pub type ColoredSpan = ((u8, u8, u8), String);
+
+/// lines of code split into colored spans for full screen views,
+/// None when the language is unknown
+#[cfg(feature = "markdown")]
+pub fn highlight_spans(code: &str, language: &str) -> Option<Vec<Vec<ColoredSpan>>> {
+    let syntax = syntax_set().find_syntax_by_token(language)?;
+    let mut highlighter = HighlightLines::new(syntax, code_theme());
+    code.lines()
+        .map(|line| {
+            let line = format!("{line}\n");
+            let ranges = highlighter.highlight_line(&line, syntax_set()).ok()?;
+            Some(
+                ranges
+                    .into_iter()
+                    .map(|(style, text)| {
+                        let color = style.foreground;
+                        (
+                            (color.r, color.g, color.b),
+                            text.trim_end_matches('\n').to_owned(),
+                        )
+                    })
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "markdown"))]
+pub fn highlight_spans(_code: &str, _language: &str) -> Option<Vec<Vec<ColoredSpan>>> {
+    None
+}
+
+/// loading syntaxes takes a moment so they are only loaded for the first code block
+#[cfg(feature = "markdown")]
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+#[cfg(feature = "markdown")]
+fn code_theme() -> &'static Theme {
+    static THEME: OnceLock<Theme> = OnceLock::new();
+    THEME.get_or_init(|| {
+        ThemeSet::load_defaults()
+            .themes
+            .remove(CODE_THEME)
+            .unwrap_or_default()
+    })
+}
+
+/// Wrap URLs and paths to existing files in OSC-8 hyperlinks
+fn linkify(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut word_start = None;
+    for (index, character) in text.char_indices() {
+        if character.is_whitespace() {
+            if let Some(start) = word_start.take() {
+                output.push_str(&linkify_word(&text[start..index]));
+            }
+            output.push(character);
+        } else if word_start.is_none() {
+            word_start = Some(index);
+        }
+    }
+    if let Some(start) = word_start {
+        output.push_str(&linkify_word(&text[start..]));
+    }
+    output
+}
+
+fn linkify_word(word: &str) -> String {
+    // markdown and prose tend to wrap links in quotes, brackets and punctuation
+    let trimmed_start = word.trim_start_matches(['(', '[', '<', '"', '\'', '`']);
+    let trimmed = trimmed_start
+        .trim_end_matches([')', ']', '>', '"', '\'', '`', '.', ',', ';', ':', '!', '?']);
+    if trimmed.is_empty() {
+        return word.to_owned();
+    }
+
+    let Some(uri) = link_target(trimmed) else {
+        return word.to_owned();
+    };
+
+    let prefix_len = word.len() - trimmed_start.len();
+    let prefix = &word[..prefix_len];
+    let suffix = &word[prefix_len + trimmed.len()..];
+    format!("{prefix}\x1b]8;;{uri}\x1b\\{trimmed}\x1b]8;;\x1b\\{suffix}")
+}
+
+fn link_target(word: &str) -> Option<String> {
+    if word.starts_with("https://") || word.starts_with("http://") {
+        return Some(word.to_owned());
+    }
+    if !word.contains('/') && !word.contains('.') {
+        return None;
+    }
+    // allow path:line and path:line:column references
+    let path = word.split(':').next().unwrap_or(word);
+    let path = Path::new(path);
+    if !path.exists() {
+        return None;
+    }
+    let absolute_path = path.canonicalize().ok()?;
+    Some(format!("file://{}", absolute_path.display()))
+}
+
+fn is_table_row(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.len() > 1 && trimmed.starts_with('|')
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+fn split_table_row(line: &str) -> Vec<String> {
+    let trimmed = line.trim();
+    let trimmed = trimmed.strip_prefix('|').unwrap_or(trimmed);
+    let trimmed = trimmed.strip_suffix('|').unwrap_or(trimmed);
+    trimmed
+        .split('|')
+        .map(|cell| cell.trim().to_owned())
+        .collect()
+}
+
+fn parse_alignment(cell: &str) -> Option<Alignment> {
+    let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+    if dashes.is_empty() || !dashes.chars().all(|character| character == '-') {
+        return None;
+    }
+    match (cell.starts_with(':'), cell.ends_with(':')) {
+        (true, true) => Some(Alignment::Center),
+        (false, true) => Some(Alignment::Right),
+        _ => Some(Alignment::Left),
+    }
+}
+
+/// Render a markdown table with box drawing characters fitted to the terminal width
+///
+/// Falls back to the raw lines if this doesn't look like a valid table
+fn render_table(lines: &[String], terminal_width: usize) -> String {
+    let raw = || {
+        lines
+            .iter()
+            .map(|line| format!("{line}\n"))
+            .collect::<String>()
+    };
+
+    let rows: Vec<Vec<String>> = lines.iter().map(|line| split_table_row(line)).collect();
+    let Some(alignments) = rows.get(1).and_then(|separator| {
+        separator
+            .iter()
+            .map(|cell| parse_alignment(cell))
+            .collect::<Option<Vec<_>>>()
+    }) else {
+        return raw();
+    };
+
+    let header = &rows[0];
+    let body = &rows[2..];
+    let column_count = alignments.len().max(header.len());
+    if column_count == 0 {
+        return raw();
+    }
+
+    let cell = |row: &Vec<String>, column: usize| row.get(column).cloned().unwrap_or_default();
+
+    let mut widths: Vec<usize> = (0..column_count)
+        .map(|column| {
+            std::iter::once(header)
+                .chain(body.iter())
+                .map(|row| measure_text_width(&cell(row, column)))
+                .max()
+                .unwrap_or_default()
+                .max(1)
+        })
+        .collect();
+
+    // borders take one character per column plus one, padding two per column
+    let overhead = column_count * 3 + 1;
+    let available = terminal_width
+        .saturating_sub(overhead)
+        .max(column_count * 3);
+    while widths.iter().sum::<usize>() > available {
+        let (widest, width) = widths
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by_key(|(_, width)| *width)
+            .unwrap_or_default();
+        if width <= 3 {
+            break;
+        }
+        widths[widest] -= 1;
+    }
+
+    let border = |left: &str, middle: &str, right: &str| {
+        let segments: Vec<String> = widths.iter().map(|width| "─".repeat(width + 2)).collect();
+        format!("{left}{}{right}\n", segments.join(middle))
+    };
+
+    let render_row = |row: &Vec<String>| {
+        let wrapped: Vec<Vec<String>> = (0..column_count)
+            .map(|column| wrap_text(&cell(row, column), widths[column]))
+            .collect();
+        let height = wrapped.iter().map(Vec::len).max().unwrap_or(1);
+        let mut output = String::new();
+        for line_index in 0..height {
+            output.push('│');
+            for (column, lines) in wrapped.iter().enumerate() {
+                let text = lines
+                    .get(line_index)
+                    .map(String::as_str)
+                    .unwrap_or_default();
+                let alignment = alignments.get(column).copied().unwrap_or(Alignment::Left);
+                output.push(' ');
+                output.push_str(&pad(text, widths[column], alignment));
+                output.push_str(" │");
+            }
+            output.push('\n');
+        }
+        output
+    };
+
+    let mut output = border("┌", "┬", "┐");
+    output.push_str(&render_row(header));
+    output.push_str(&border("├", "┼", "┤"));
+    for row in body {
+        output.push_str(&render_row(row));
+    }
+    output.push_str(&border("└", "┴", "┘"));
+    output
+}
+
+fn pad(text: &str, width: usize, alignment: Alignment) -> String {
+    let padding = width.saturating_sub(measure_text_width(text));
+    match alignment {
+        Alignment::Left => format!("{text}{}", " ".repeat(padding)),
+        Alignment::Right => format!("{}{text}", " ".repeat(padding)),
+        Alignment::Center => {
+            let left = padding / 2;
+            format!("{}{text}{}", " ".repeat(left), " ".repeat(padding - left))
+        }
+    }
+}
+
+/// Wrap text on word boundaries, breaking words that don't fit on their own
+fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word.to_owned();
+        loop {
+            let separator = usize::from(!current.is_empty());
+            if measure_text_width(&current) + separator + measure_text_width(&word) <= width {
+                if !current.is_empty() {
+                    current.push(' ');
+                }
+                current.push_str(&word);
+                break;
+            }
+            if !current.is_empty() {
+                lines.push(std::mem::take(&mut current));
+                continue;
+            }
+            // word is too long for an empty line, break it up
+            let mut head = String::new();
+            let mut tail = word.chars();
+            for character in tail.by_ref() {
+                head.push(character);
+                if measure_text_width(&head) >= width {
+                    break;
+                }
+            }
+            lines.push(head);
+            word = tail.collect();
+            if word.is_empty() {
+                break;
+            }
+        }
+    }
+    if !current.is_empty() || lines.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(table: &str) -> Vec<String> {
+        table.lines().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn renders_aligned_columns() {
+        let table = lines("| a | b |\n|:--|--:|\n| x | long |");
+        assert_eq!(
+            render_table(&table, 80),
+            "┌───┬──────┐\n\
+             │ a │    b │\n\
+             ├───┼──────┤\n\
+             │ x │ long │\n\
+             └───┴──────┘\n"
+        );
+    }
+
+    #[test]
+    fn wraps_cells_to_the_terminal_width() {
+        let table = lines("| word |\n|---|\n| hello world |");
+        assert_eq!(
+            render_table(&table, 11),
+            "┌─────────┐\n\
+             │ word    │\n\
+             ├─────────┤\n\
+             │ hello   │\n\
+             │ world   │\n\
+             └─────────┘\n"
+        );
+    }
+
+    #[test]
+    fn keeps_tables_without_a_separator() {
+        let table = lines("| a | b |\n| x | y |");
+        assert_eq!(render_table(&table, 80), "| a | b |\n| x | y |\n");
+    }
+}
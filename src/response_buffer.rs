@@ -0,0 +1,113 @@
+use crate::configuration::get_project_dirs;
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// responses longer than this move from memory into a file
+const SPILL_THRESHOLD_BYTES: usize = 1024 * 1024;
+/// lines of a spilled response kept in history so the conversation still reads
+const EXCERPT_LINES: usize = 50;
+const SPILL_DIR_NAME: &str = "responses";
+
+/// Streamed response text that moves to a file once it gets too big to keep in memory
+#[derive(Default)]
+pub struct ResponseBuffer {
+    content: String,
+    spill: Option<Spill>,
+}
+
+struct Spill {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    excerpt: String,
+    lines: usize,
+}
+
+impl ResponseBuffer {
+    /// append streamed content, returns true when this moved the response into a file
+    pub fn push(&mut self, delta: &str) -> Result<bool> {
+        if let Some(spill) = &mut self.spill {
+            spill.writer.write_all(delta.as_bytes())?;
+            spill.lines += delta.matches('\n').count();
+            return Ok(false);
+        }
+        self.content.push_str(delta);
+        if self.content.len() <= SPILL_THRESHOLD_BYTES {
+            return Ok(false);
+        }
+        self.spill = Some(Spill::create(&std::mem::take(&mut self.content))?);
+        Ok(true)
+    }
+
+    /// content kept in memory, empty once the response was spilled
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn spill_file(&self) -> Option<&Path> {
+        self.spill.as_ref().map(|spill| spill.path.as_path())
+    }
+
+    /// text for history and the file holding the whole response if it was spilled
+    ///
+    /// spilled responses keep an excerpt and a note where the rest is
+    pub fn finish(self) -> Result<(String, Option<PathBuf>)> {
+        let Some(mut spill) = self.spill else {
+            return Ok((self.content, None));
+        };
+        spill.writer.flush()?;
+        let content = format!(
+            "{}\n\n[Response continues, all {} lines are in {}]",
+            spill.excerpt,
+            spill.lines + 1,
+            spill.path.display()
+        );
+        Ok((content, Some(spill.path)))
+    }
+}
+
+impl Spill {
+    fn create(content: &str) -> Result<Self> {
+        let directory = get_project_dirs()?.data_dir().join(SPILL_DIR_NAME);
+        std::fs::create_dir_all(&directory).context("failed to create responses directory")?;
+        let path = directory.join(format!(
+            "{}.md",
+            Local::now().format("%Y-%m-%dT%H-%M-%S%.3f")
+        ));
+        let file =
+            File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(content.as_bytes())?;
+        Ok(Self {
+            path,
+            writer,
+            excerpt: content
+                .lines()
+                .take(EXCERPT_LINES)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            lines: content.matches('\n').count(),
+        })
+    }
+}
+
+/// show a file in `$PAGER`, less by default
+pub fn page(path: &Path) -> Result<()> {
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| String::from("less -R"));
+    let mut parts = pager.split_whitespace();
+    let program = parts.next().context("PAGER is empty")?;
+    let status = Command::new(program)
+        .args(parts)
+        .arg(path)
+        .status()
+        .with_context(|| format!("failed to run {program}"))?;
+    if !status.success() {
+        anyhow::bail!("{program} exited with {status}");
+    }
+    Ok(())
+}
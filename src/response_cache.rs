@@ -0,0 +1,102 @@
+use crate::configuration::get_project_dirs;
+use anyhow::{Context, Result};
+use async_openai::types::CreateChatCompletionRequest;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{path::PathBuf, time::Duration};
+
+const RESPONSE_CACHE_DIR_NAME: &str = "responses";
+
+/// Settings for the local response cache
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ResponseCacheConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// how long cached responses stay valid
+    #[serde(default = "default_ttl_seconds")]
+    pub ttl_seconds: u64,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ttl_seconds: default_ttl_seconds(),
+        }
+    }
+}
+
+fn default_ttl_seconds() -> u64 {
+    // one day
+    60 * 60 * 24
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct CachedResponse {
+    created: DateTime<Local>,
+    content: String,
+}
+
+/// Cache of responses keyed by a hash of the whole request
+///
+/// Identical messages with identical parameters return the stored response
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    directory: PathBuf,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    pub fn new(config: &ResponseCacheConfig) -> Result<Self> {
+        let directory = get_project_dirs()?
+            .cache_dir()
+            .join(RESPONSE_CACHE_DIR_NAME);
+        std::fs::create_dir_all(&directory).context("failed to create response cache directory")?;
+        Ok(Self {
+            directory,
+            ttl: Duration::from_secs(config.ttl_seconds),
+        })
+    }
+
+    /// cache key for a request, hash of the whole serialized request
+    pub fn key(request: &CreateChatCompletionRequest) -> Result<String> {
+        let serialized = serde_json::to_vec(request)?;
+        let hash = Sha256::digest(&serialized);
+        Ok(hash.iter().map(|byte| format!("{byte:02x}")).collect())
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{key}.json"))
+    }
+
+    /// look up response, expired entries are removed
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        let path = self.entry_path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = std::fs::File::open(&path)?;
+        // treat broken entries as missing
+        let Ok(cached) = serde_json::from_reader::<_, CachedResponse>(file) else {
+            std::fs::remove_file(&path)?;
+            return Ok(None);
+        };
+        let age = (Local::now() - cached.created).to_std().unwrap_or_default();
+        if age > self.ttl {
+            std::fs::remove_file(&path)?;
+            return Ok(None);
+        }
+        Ok(Some(cached.content))
+    }
+
+    pub fn put(&self, key: &str, content: &str) -> Result<()> {
+        let cached = CachedResponse {
+            created: Local::now(),
+            content: content.to_owned(),
+        };
+        let file = std::fs::File::create(self.entry_path(key))?;
+        serde_json::to_writer(file, &cached)?;
+        Ok(())
+    }
+}
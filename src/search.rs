@@ -0,0 +1,120 @@
+use crate::chat_manager::ChatHistory;
+use anyhow::Result;
+use dialoguer::console::style;
+use regex::{Regex, RegexBuilder};
+use std::path::PathBuf;
+
+/// characters of context shown on each side of a match
+const SNIPPET_CONTEXT: usize = 60;
+/// snippets shown per conversation
+const MAX_SNIPPETS: usize = 3;
+/// a word in the title counts as much as this many in messages
+const TITLE_WEIGHT: usize = 5;
+
+/// Saved conversation that contains every word of a query
+pub struct SearchHit {
+    pub path: PathBuf,
+    pub chat_history: ChatHistory,
+    /// how well it matches, more matches and matches in the title rank higher
+    pub score: usize,
+    /// message number and the text around the match with matches highlighted
+    pub snippets: Vec<(usize, String)>,
+}
+
+/// conversations containing every word of the query, best matches first
+///
+/// words are matched case insensitively in the title and the messages
+pub fn search(conversations: Vec<(PathBuf, ChatHistory)>, query: &str) -> Result<Vec<SearchHit>> {
+    let words: Vec<Regex> = query
+        .split_whitespace()
+        .map(|word| {
+            RegexBuilder::new(&regex::escape(word))
+                .case_insensitive(true)
+                .build()
+        })
+        .collect::<Result<_, _>>()?;
+    if words.is_empty() {
+        anyhow::bail!("Search for at least one word");
+    }
+    let any_word = RegexBuilder::new(
+        &query
+            .split_whitespace()
+            .map(regex::escape)
+            .collect::<Vec<_>>()
+            .join("|"),
+    )
+    .case_insensitive(true)
+    .build()?;
+
+    let mut hits = vec![];
+    for (path, chat_history) in conversations {
+        let title = chat_history.conversation_title().unwrap_or_default();
+        let found_everywhere = words.iter().all(|word| {
+            word.is_match(title)
+                || chat_history
+                    .messages()
+                    .iter()
+                    .any(|message| word.is_match(&message.content))
+        });
+        if !found_everywhere {
+            continue;
+        }
+
+        let mut score = any_word.find_iter(title).count() * TITLE_WEIGHT;
+        let mut snippets = vec![];
+        for (number, message) in chat_history.messages().iter().enumerate() {
+            let matches = any_word.find_iter(&message.content).count();
+            score += matches;
+            if matches > 0 && snippets.len() < MAX_SNIPPETS {
+                snippets.push((number, snippet(&message.content, &any_word)));
+            }
+        }
+        hits.push(SearchHit {
+            path,
+            chat_history,
+            score,
+            snippets,
+        });
+    }
+    hits.sort_by(|a, b| {
+        b.score.cmp(&a.score).then_with(|| {
+            b.chat_history
+                .conversation_start()
+                .cmp(&a.chat_history.conversation_start())
+        })
+    });
+    Ok(hits)
+}
+
+/// one line around the first match with every match highlighted
+fn snippet(content: &str, words: &Regex) -> String {
+    let Some(first) = words.find(content) else {
+        return String::new();
+    };
+    let start = floor_char_boundary(content, first.start().saturating_sub(SNIPPET_CONTEXT));
+    let end = floor_char_boundary(content, (first.end() + SNIPPET_CONTEXT).min(content.len()));
+    let excerpt = content[start..end].replace(['\n', '\r', '\t'], " ");
+
+    let mut highlighted = String::new();
+    if start > 0 {
+        highlighted.push('…');
+    }
+    let mut last = 0;
+    for found in words.find_iter(&excerpt) {
+        highlighted.push_str(&excerpt[last..found.start()]);
+        highlighted.push_str(&style(found.as_str()).yellow().bold().to_string());
+        last = found.end();
+    }
+    highlighted.push_str(&excerpt[last..]);
+    if end < content.len() {
+        highlighted.push('…');
+    }
+    highlighted
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
@@ -0,0 +1,74 @@
+use crate::configuration::get_project_dirs;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, BufReader, Lines},
+};
+
+const SESSION_FIFO_NAME: &str = "session.fifo";
+
+/// Named pipe other processes write questions into
+///
+/// `echo "question" > session.fifo` appends to the live conversation
+pub struct SessionFifo {
+    path: PathBuf,
+    lines: Option<Lines<BufReader<File>>>,
+}
+
+impl SessionFifo {
+    /// create the pipe in the data directory, a stale pipe from a crashed session is replaced
+    pub fn create() -> Result<Self> {
+        let data_dir = get_project_dirs()?.data_dir().to_owned();
+        std::fs::create_dir_all(&data_dir).context("failed to create data directory")?;
+        let path = data_dir.join(SESSION_FIFO_NAME);
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+        }
+        make_fifo(&path)?;
+        Ok(Self { path, lines: None })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// wait for the next non-empty line written to the pipe
+    pub async fn next_line(&mut self) -> Result<String> {
+        loop {
+            let lines = match &mut self.lines {
+                Some(lines) => lines,
+                None => {
+                    // opening blocks until a writer shows up
+                    let file = File::open(&self.path).await?;
+                    self.lines.insert(BufReader::new(file).lines())
+                }
+            };
+            match lines.next_line().await? {
+                Some(line) if line.trim().is_empty() => continue,
+                Some(line) => return Ok(line),
+                // all writers closed the pipe, wait for the next one
+                None => self.lines = None,
+            }
+        }
+    }
+}
+
+impl Drop for SessionFifo {
+    fn drop(&mut self) {
+        _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+fn make_fifo(path: &Path) -> Result<()> {
+    use nix::sys::stat::Mode;
+    // only the owner may write questions into the session
+    nix::unistd::mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR)
+        .with_context(|| format!("failed to create named pipe {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn make_fifo(_path: &Path) -> Result<()> {
+    anyhow::bail!("named pipe sessions are only supported on unix")
+}
@@ -0,0 +1,341 @@
+use crate::{chat_manager::ChatHistory, export::message_label, messages::ChatMessage};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    time::SystemTime,
+};
+
+/// key of the messages in a serialized conversation
+const MESSAGES_KEY: &str = "history";
+const FRONT_MATTER_DELIMITER: &str = "---\n";
+/// start of the hidden line in front of every message, the rest of the line holds its fields as JSON
+const MESSAGE_MARKER: &str = "<!-- gpt-cli message ";
+const MESSAGE_MARKER_END: &str = " -->\n";
+/// appended to the name of conversations saved with gpg
+pub const ENCRYPTED_EXTENSION: &str = "gpg";
+/// longest title part of a file name, model titles can be whole sentences
+const MAX_SLUG_LENGTH: usize = 64;
+/// hex characters of a conversation id
+const CONVERSATION_ID_LENGTH: usize = 12;
+/// armor comment of encrypted conversations naming the key they are encrypted for
+const KEY_COMMENT: &str = "gpt-cli key ";
+
+/// profile whose key may open encrypted conversations, see `use_profile_key`
+static PROFILE_KEY: RwLock<Option<ProfileKey>> = RwLock::new(None);
+
+/// Active profile and the gpg key of its conversations
+#[derive(Debug, Clone)]
+pub struct ProfileKey {
+    profile: String,
+    recipient: Option<String>,
+}
+
+impl ProfileKey {
+    pub fn new(profile: &str, recipient: Option<&str>) -> Self {
+        Self {
+            profile: profile.to_owned(),
+            recipient: recipient.map(str::to_owned),
+        }
+    }
+}
+
+/// only open encrypted conversations of this profile's key, None opens any the keyring can
+pub fn use_profile_key(profile_key: Option<ProfileKey>) {
+    *PROFILE_KEY
+        .write()
+        .unwrap_or_else(|error| error.into_inner()) = profile_key;
+}
+
+/// key new conversations are encrypted for, the active profile's
+pub fn profile_recipient() -> Option<String> {
+    PROFILE_KEY
+        .read()
+        .unwrap_or_else(|error| error.into_inner())
+        .as_ref()?
+        .recipient
+        .clone()
+}
+
+/// How conversations are written to the cache directory
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageFormat {
+    /// the whole conversation as YAML
+    #[default]
+    Yaml,
+    /// readable transcript with the metadata as YAML front matter
+    Markdown,
+}
+
+impl StorageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            StorageFormat::Yaml => "yaml",
+            StorageFormat::Markdown => "md",
+        }
+    }
+
+    /// format of a saved conversation, anything that isn't markdown is YAML
+    pub fn of_path(path: &Path) -> Self {
+        let path = if is_encrypted(path) {
+            path.with_extension("")
+        } else {
+            path.to_owned()
+        };
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("md") => StorageFormat::Markdown,
+            _ => StorageFormat::Yaml,
+        }
+    }
+}
+
+/// title as a file name part, lowercase words joined by underscores
+///
+/// anything besides letters, digits and dashes separates words, empty when nothing is left
+pub fn slugify(title: &str) -> String {
+    let slug = title
+        .split(|character: char| !(character.is_alphanumeric() || character == '-'))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+        .to_lowercase();
+    match slug.char_indices().nth(MAX_SLUG_LENGTH) {
+        Some((end, _)) => slug[..end].trim_end_matches(['_', '-']).to_owned(),
+        None => slug,
+    }
+}
+
+/// random enough id that stays with a conversation across saves and renames
+pub fn new_conversation_id() -> String {
+    // conversations cloned in the same instant still get different ids
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let seed = format!(
+        "{nanos}-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    Sha256::digest(seed.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>()[..CONVERSATION_ID_LENGTH]
+        .to_owned()
+}
+
+/// transcript with a heading per message, `from_markdown` reads it back
+pub fn to_markdown(chat_history: &ChatHistory) -> Result<String> {
+    let serde_yaml::Value::Mapping(mut front_matter) = serde_yaml::to_value(chat_history)? else {
+        anyhow::bail!("Conversation isn't a mapping");
+    };
+    front_matter.remove(MESSAGES_KEY);
+
+    let mut markdown = String::from(FRONT_MATTER_DELIMITER);
+    markdown.push_str(&serde_yaml::to_string(&front_matter)?);
+    markdown.push_str(FRONT_MATTER_DELIMITER);
+    markdown.push('\n');
+    for message in chat_history.messages() {
+        let serde_json::Value::Object(mut fields) = serde_json::to_value(message)? else {
+            anyhow::bail!("Message isn't an object");
+        };
+        fields.remove("content");
+        markdown.push_str(&format!(
+            "{MESSAGE_MARKER}{}{MESSAGE_MARKER_END}## {}\n\n{}\n\n",
+            serde_json::Value::Object(fields),
+            message_label(message),
+            message.content
+        ));
+    }
+    Ok(markdown)
+}
+
+/// read a transcript written by `to_markdown`
+pub fn from_markdown(text: &str) -> Result<ChatHistory> {
+    let (front_matter, body) = text
+        .strip_prefix(FRONT_MATTER_DELIMITER)
+        .and_then(|text| text.split_once(&format!("\n{FRONT_MATTER_DELIMITER}")))
+        .context("Markdown conversation doesn't start with front matter")?;
+    let serde_yaml::Value::Mapping(mut conversation) = serde_yaml::from_str(front_matter)? else {
+        anyhow::bail!("Front matter isn't a mapping");
+    };
+
+    // the newline in front of each marker belongs to the previous message
+    let body = format!("\n{}", body.trim_start_matches('\n'));
+    let chunks: Vec<&str> = body.split(&format!("\n{MESSAGE_MARKER}")).skip(1).collect();
+    let mut messages = vec![];
+    for (index, chunk) in chunks.iter().enumerate() {
+        let (fields, rest) = chunk
+            .split_once(MESSAGE_MARKER_END)
+            .with_context(|| format!("Message {index} has an unterminated marker"))?;
+        let (_heading, content) = rest
+            .split_once("\n\n")
+            .with_context(|| format!("Message {index} is missing its heading"))?;
+        let content = if index + 1 == chunks.len() {
+            content
+                .strip_suffix("\n\n")
+                .or_else(|| content.strip_suffix('\n'))
+                .unwrap_or(content)
+        } else {
+            content.strip_suffix('\n').unwrap_or(content)
+        };
+
+        let mut fields: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(fields)
+                .with_context(|| format!("Message {index} has invalid fields"))?;
+        fields.insert(String::from("content"), content.into());
+        let message: ChatMessage = serde_json::from_value(fields.into())?;
+        messages.push(message);
+    }
+
+    conversation.insert(MESSAGES_KEY.into(), serde_yaml::to_value(messages)?);
+    Ok(serde_yaml::from_value(serde_yaml::Value::Mapping(
+        conversation,
+    ))?)
+}
+
+pub fn is_encrypted(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == ENCRYPTED_EXTENSION)
+}
+
+/// write the text encrypted for a gpg key
+///
+/// the file is ASCII armored with the key in a header so it can be checked without decrypting
+pub fn encrypt(path: &Path, text: &str, recipient: &str) -> Result<()> {
+    let mut gpg = Command::new("gpg")
+        .args([
+            "--batch",
+            "--yes",
+            "--armor",
+            "--comment",
+            &format!("{KEY_COMMENT}{recipient}"),
+            "--encrypt",
+            "--recipient",
+            recipient,
+            "--output",
+        ])
+        .arg(path)
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to run gpg")?;
+    gpg.stdin
+        .take()
+        .context("gpg has no stdin")?
+        .write_all(text.as_bytes())?;
+    let status = gpg.wait()?;
+    if !status.success() {
+        anyhow::bail!("gpg failed to encrypt {} with {status}", path.display());
+    }
+    Ok(())
+}
+
+/// key named in the header of an encrypted conversation, files from before profiles have none
+pub fn encrypted_for(path: &Path) -> Option<String> {
+    let file = std::fs::read(path).ok()?;
+    let text = String::from_utf8_lossy(&file);
+    let armor = text.split("\n\n").next()?;
+    armor
+        .lines()
+        .find_map(|line| line.strip_prefix("Comment: ")?.strip_prefix(KEY_COMMENT))
+        .map(|recipient| recipient.trim().to_owned())
+}
+
+/// read a file written by `encrypt`, fails when the key is locked
+/// or belongs to another profile than the active one
+pub fn decrypt(path: &Path) -> Result<String> {
+    if let Some(profile_key) = PROFILE_KEY
+        .read()
+        .unwrap_or_else(|error| error.into_inner())
+        .as_ref()
+    {
+        if let Some(recipient) = encrypted_for(path) {
+            if profile_key.recipient.as_deref() != Some(recipient.as_str()) {
+                anyhow::bail!(
+                    "{} is encrypted for {recipient}, which isn't the key of profile {}",
+                    path.display(),
+                    profile_key.profile
+                );
+            }
+        }
+    }
+    let output = Command::new("gpg")
+        .args(["--batch", "--quiet", "--decrypt"])
+        .arg(path)
+        .stderr(Stdio::null())
+        .output()
+        .context("Failed to run gpg")?;
+    if !output.status.success() {
+        anyhow::bail!("gpg failed to decrypt {}", path.display());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chat_manager::AssistantMetadata, utils::resolve_model};
+
+    #[test]
+    fn slugifies_titles() {
+        assert_eq!(
+            slugify("Fix the  borrow checker!"),
+            "fix_the_borrow_checker"
+        );
+        assert_eq!(slugify("Ünïcode and re-use"), "ünïcode_and_re-use");
+        assert_eq!(slugify("?!"), "");
+    }
+
+    #[test]
+    fn shortens_long_slugs_at_a_word_boundary() {
+        let slug = slugify(&"word ".repeat(20));
+        assert!(slug.chars().count() <= MAX_SLUG_LENGTH);
+        assert!(slug.ends_with("word"));
+    }
+
+    #[test]
+    fn conversation_ids_are_unique_hex() {
+        let first = new_conversation_id();
+        let second = new_conversation_id();
+        assert_eq!(first.len(), CONVERSATION_ID_LENGTH);
+        assert!(first.chars().all(|character| character.is_ascii_hexdigit()));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn markdown_round_trips() {
+        let mut chat_history = ChatHistory::new(
+            resolve_model("gpt-4", &[]),
+            AssistantMetadata::new("default", String::from("Be brief")),
+        )
+        .unwrap();
+        chat_history.set_conversation_title(String::from("Round trip"));
+        chat_history
+            .add_user_message("## Not a heading\n\nsecond paragraph\n")
+            .unwrap();
+        chat_history
+            .add_assistant_message("```rust\nfn main() {}\n```")
+            .unwrap();
+
+        let markdown = to_markdown(&chat_history).unwrap();
+        let read_back = from_markdown(&markdown).unwrap();
+        assert_eq!(
+            serde_yaml::to_string(&read_back).unwrap(),
+            serde_yaml::to_string(&chat_history).unwrap()
+        );
+    }
+
+    #[test]
+    fn markdown_needs_front_matter() {
+        assert!(from_markdown("## User\n\nhello").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+#[cfg(feature = "telemetry")]
+use anyhow::Context;
+use anyhow::Result;
+#[cfg(feature = "telemetry")]
+use opentelemetry::KeyValue;
+#[cfg(feature = "telemetry")]
+use opentelemetry_otlp::WithExportConfig;
+#[cfg(feature = "telemetry")]
+use opentelemetry_sdk::{runtime, trace, Resource};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "telemetry")]
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Settings for exporting traces of API calls over OTLP
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TelemetryConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// OTLP gRPC endpoint of the collector
+    #[serde(default = "default_endpoint")]
+    pub endpoint: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: default_endpoint(),
+        }
+    }
+}
+
+fn default_endpoint() -> String {
+    String::from("http://localhost:4317")
+}
+
+/// Exports the spans that are still buffered when dropped
+pub struct TelemetryGuard;
+
+#[cfg(feature = "telemetry")]
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+}
+
+/// start exporting spans when enabled in config, the guard has to live until exit
+#[cfg(not(feature = "telemetry"))]
+pub fn init(config: &TelemetryConfig) -> Result<Option<TelemetryGuard>> {
+    if config.enabled {
+        return Err(crate::utils::feature_disabled(
+            "telemetry",
+            "Exporting traces",
+        ));
+    }
+    Ok(None)
+}
+
+/// start exporting spans when enabled in config, the guard has to live until exit
+#[cfg(feature = "telemetry")]
+pub fn init(config: &TelemetryConfig) -> Result<Option<TelemetryGuard>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(config.endpoint.clone()),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                env!("CARGO_PKG_NAME"),
+            )])),
+        )
+        .install_batch(runtime::Tokio)
+        .context("failed to set up OTLP exporter")?;
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()
+        .context("failed to install tracing subscriber")?;
+    Ok(Some(TelemetryGuard))
+}
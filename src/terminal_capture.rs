@@ -0,0 +1,141 @@
+use crate::conversation::count_text_tokens;
+use anyhow::{Context, Result};
+use std::process::Command;
+
+/// title of the pane the chat runs in so it can be found again
+const CHAT_PANE_TITLE: &str = "gpt-cli";
+/// pane that was active before the current one
+pub const LAST_PANE: &str = "{last}";
+/// how far back to look, trimmed to the token budget afterwards
+const SCROLLBACK_LINES: &str = "-2000";
+
+fn inside_tmux() -> bool {
+    std::env::var_os("TMUX").is_some()
+}
+
+fn tmux(arguments: &[&str]) -> Result<String> {
+    run("tmux", arguments)
+}
+
+/// focus the chat pane in the current tmux window, creating it if there is none
+pub fn open_chat_pane(arguments: &[String]) -> Result<()> {
+    if !inside_tmux() {
+        anyhow::bail!("Not running inside tmux");
+    }
+
+    let panes = tmux(&["list-panes", "-F", "#{pane_id} #{pane_title}"])?;
+    let existing = panes
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .find(|(_, title)| *title == CHAT_PANE_TITLE)
+        .map(|(pane_id, _)| pane_id.to_owned());
+    if let Some(pane_id) = existing {
+        tmux(&["select-pane", "-t", &pane_id])?;
+        return Ok(());
+    }
+
+    let executable = std::env::current_exe()?;
+    let mut split = vec![
+        "split-window",
+        "-h",
+        "-l",
+        "40%",
+        "-P",
+        "-F",
+        "#{pane_id}",
+        "--",
+    ];
+    let executable = executable.to_string_lossy();
+    split.push(&executable);
+    split.extend(arguments.iter().map(String::as_str));
+    let pane_id = tmux(&split)?;
+    tmux(&["select-pane", "-t", pane_id.trim(), "-T", CHAT_PANE_TITLE])?;
+    Ok(())
+}
+
+/// visible content of a tmux pane
+pub fn capture_pane(target: &str) -> Result<String> {
+    if !inside_tmux() {
+        anyhow::bail!("Capturing panes needs tmux");
+    }
+    tmux(&["capture-pane", "-p", "-J", "-t", target])
+}
+
+fn run(program: &str, arguments: &[&str]) -> Result<String> {
+    let output = Command::new(program)
+        .args(arguments)
+        .output()
+        .with_context(|| format!("failed to run {program}"))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// scrollback of the terminal gpt-cli runs in
+///
+/// returns which terminal it came from and the text
+pub fn capture_scrollback() -> Result<(&'static str, String)> {
+    if let (true, Ok(pane)) = (inside_tmux(), std::env::var("TMUX_PANE")) {
+        let text = tmux(&[
+            "capture-pane",
+            "-p",
+            "-J",
+            "-S",
+            SCROLLBACK_LINES,
+            "-t",
+            &pane,
+        ])?;
+        return Ok(("tmux", text));
+    }
+    // needs allow_remote_control in kitty.conf
+    if let Ok(window) = std::env::var("KITTY_WINDOW_ID") {
+        let text = run(
+            "kitty",
+            &[
+                "@",
+                "get-text",
+                "--extent",
+                "all",
+                "--match",
+                &format!("id:{window}"),
+            ],
+        )?;
+        return Ok(("kitty", text));
+    }
+    if let Ok(pane) = std::env::var("WEZTERM_PANE") {
+        let text = run(
+            "wezterm",
+            &[
+                "cli",
+                "get-text",
+                "--pane-id",
+                &pane,
+                "--start-line",
+                SCROLLBACK_LINES,
+            ],
+        )?;
+        return Ok(("wezterm", text));
+    }
+    anyhow::bail!("Capturing the screen needs tmux, kitty or wezterm")
+}
+
+/// keep the newest lines that fit in the token budget
+pub fn trim_to_tokens(text: &str, budget: usize) -> String {
+    let mut kept = vec![];
+    let mut tokens = 0;
+    for line in text.trim_end().lines().rev() {
+        // each line costs at least its newline
+        let line_tokens = count_text_tokens(line) + 1;
+        if tokens + line_tokens > budget {
+            break;
+        }
+        tokens += line_tokens;
+        kept.push(line);
+    }
+    kept.reverse();
+    kept.join("\n")
+}
@@ -0,0 +1,98 @@
+use crate::configuration::get_project_dirs;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// task file used when `todo_file` isn't configured
+const DEFAULT_TODO_FILE_NAME: &str = "todo.txt";
+/// written by the model when the conversation has nothing to do
+pub const NO_TODOS: &str = "NONE";
+
+/// task file /todos appends to
+pub fn todo_file(configured: Option<&Path>) -> Result<PathBuf> {
+    match configured {
+        Some(path) => Ok(path.to_owned()),
+        None => Ok(get_project_dirs()?.data_dir().join(DEFAULT_TODO_FILE_NAME)),
+    }
+}
+
+/// task without list markers, todo.txt dates and priorities or trailing punctuation
+///
+/// entries that are the same after this count as duplicates
+fn normalize(entry: &str) -> String {
+    let mut entry = entry.trim();
+    for marker in ["- [ ]", "- [x]", "* [ ]", "* [x]", "- ", "* ", "x "] {
+        if let Some(rest) = entry.strip_prefix(marker) {
+            entry = rest.trim_start();
+            break;
+        }
+    }
+    // todo.txt priority like (A)
+    if entry.len() > 3 && entry.starts_with('(') && entry.as_bytes()[2] == b')' {
+        entry = entry[3..].trim_start();
+    }
+    // todo.txt creation and completion dates
+    while entry
+        .get(..10)
+        .is_some_and(|date| chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok())
+    {
+        entry = entry[10..].trim_start();
+    }
+    entry
+        .trim_end_matches(['.', '!'])
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// tasks in a model answer, one per line with or without list markers
+pub fn parse_todos(answer: &str) -> Vec<String> {
+    answer
+        .lines()
+        .map(|line| {
+            line.trim()
+                .trim_start_matches(['-', '*', '•'])
+                .trim_start_matches("[ ]")
+                .trim()
+        })
+        .filter(|line| !line.is_empty() && *line != NO_TODOS)
+        .map(str::to_owned)
+        .collect()
+}
+
+/// append tasks that aren't in the file yet, returns the ones that were added
+///
+/// a `.md` file gets a checklist, any other file todo.txt lines with today's date
+pub fn append_todos(path: &Path, todos: &[String]) -> Result<Vec<String>> {
+    let mut text = std::fs::read_to_string(path).unwrap_or_default();
+    let mut known: Vec<String> = text.lines().map(normalize).collect();
+    let markdown = path.extension().is_some_and(|extension| extension == "md");
+    let today = chrono::Local::now().format("%Y-%m-%d");
+
+    let mut added = vec![];
+    for todo in todos {
+        let normalized = normalize(todo);
+        if normalized.is_empty() || known.contains(&normalized) {
+            continue;
+        }
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        if markdown {
+            text.push_str(&format!("- [ ] {todo}\n"));
+        } else {
+            text.push_str(&format!("{today} {todo}\n"));
+        }
+        known.push(normalized);
+        added.push(todo.clone());
+    }
+
+    if !added.is_empty() {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, text)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(added)
+}
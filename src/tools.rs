@@ -0,0 +1,395 @@
+use crate::{
+    attachments::{self, AttachmentConfig},
+    chat_client::openai_http_client,
+    chat_manager::CompletionParameters,
+    configuration::AppConfig,
+    fine_tuning::parse_response,
+    injection,
+    messages::{ChatMessage, Role, ToolCall},
+    utils::TOOL_EMOJI,
+};
+use anyhow::{Context, Result};
+use async_openai::types::Usage;
+use dialoguer::{console::Term, theme::ColorfulTheme, Confirm};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, future::Future};
+use tokio::process::Command;
+
+/// rounds of tool calls one question may take before giving up
+pub const MAX_TOOL_ROUNDS: usize = 10;
+/// longer tool output is cut so a single call can't fill the context window
+const MAX_TOOL_OUTPUT_CHARS: usize = 16 * 1024;
+
+type ToolHandler = Box<dyn Fn(Value) -> BoxFuture<'static, Result<String>> + Send + Sync>;
+
+/// Function the model can call
+struct Tool {
+    name: String,
+    description: String,
+    /// JSON schema of the arguments
+    parameters: Value,
+    handler: ToolHandler,
+}
+
+/// Tools offered to the model
+#[derive(Default)]
+pub struct ToolRegistry {
+    tools: Vec<Tool>,
+}
+
+impl ToolRegistry {
+    /// shell commands confirmed by the user and reading files within the attachment limits
+    pub fn with_builtin_tools(limits: &AttachmentConfig) -> Self {
+        let mut registry = Self::default();
+        registry.register(
+            "run_shell_command",
+            "Run a shell command on the user's machine, the user confirms every command. Returns the exit status and output",
+            json!({
+                "type": "object",
+                "properties": {
+                    "command": {
+                        "type": "string",
+                        "description": "command passed to sh -c"
+                    }
+                },
+                "required": ["command"]
+            }),
+            run_shell_command,
+        );
+        registry.register(
+            "read_file",
+            "Read a text file in the user's current directory, files outside it can't be read",
+            json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "path of the file, append :start-end to read only those lines"
+                    }
+                },
+                "required": ["path"]
+            }),
+            {
+                let limits = limits.clone();
+                move |arguments| read_file(arguments, limits.clone())
+            },
+        );
+        registry
+    }
+
+    /// offer a tool to the model, `handler` receives the parsed arguments
+    pub fn register<F, Fut>(&mut self, name: &str, description: &str, parameters: Value, handler: F)
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        self.tools.push(Tool {
+            name: name.to_owned(),
+            description: description.to_owned(),
+            parameters,
+            handler: Box::new(move |arguments| Box::pin(handler(arguments))),
+        });
+    }
+
+    fn definitions(&self) -> Vec<ToolDefinition<'_>> {
+        self.tools
+            .iter()
+            .map(|tool| ToolDefinition {
+                kind: "function",
+                function: FunctionDefinition {
+                    name: &tool.name,
+                    description: &tool.description,
+                    parameters: &tool.parameters,
+                },
+            })
+            .collect()
+    }
+
+    /// run a tool call and return its output
+    ///
+    /// failures are reported to the model so it can correct itself
+    pub async fn call(&self, tool_call: &ToolCall) -> String {
+        let result = match self.tools.iter().find(|tool| tool.name == tool_call.name) {
+            Some(tool) => match serde_json::from_str(&tool_call.arguments) {
+                Ok(arguments) => (tool.handler)(arguments).await,
+                Err(error) => Err(anyhow::Error::new(error).context("invalid arguments")),
+            },
+            None => Err(anyhow::anyhow!("Unknown tool {}", tool_call.name)),
+        };
+        let mut output = result.unwrap_or_else(|error| format!("Error: {error:#}"));
+        if output.len() > MAX_TOOL_OUTPUT_CHARS {
+            let mut end = MAX_TOOL_OUTPUT_CHARS;
+            while !output.is_char_boundary(end) {
+                end -= 1;
+            }
+            output.truncate(end);
+            output.push_str("\n[...]");
+        }
+        // command output and files can carry text written to steer the model
+        let findings = injection::detect_instructions(&output);
+        if !findings.is_empty() {
+            _ = Term::stderr().write_line(&format!(
+                "{TOOL_EMOJI} Output of {} may be trying to instruct the model, it {}",
+                tool_call.name,
+                findings.join(", ")
+            ));
+        }
+        injection::wrap_untrusted(&format!("the {} tool", tool_call.name), &output)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ShellCommandArguments {
+    command: String,
+}
+
+async fn run_shell_command(arguments: Value) -> Result<String> {
+    let ShellCommandArguments { command } = serde_json::from_value(arguments)?;
+    // stderr keeps the prompt out of answers printed for pipelines
+    let term = Term::stderr();
+    term.write_line(&format!("{TOOL_EMOJI} Model wants to run:\n{command}"))?;
+    let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Run this command?")
+        .default(false)
+        .interact_on(&term)?;
+    if !confirmed {
+        return Ok(String::from("The user declined to run this command"));
+    }
+
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .output()
+        .await
+        .with_context(|| format!("failed to run {command}"))?;
+    Ok(format!(
+        "Exit status: {}\nstdout:\n{}\nstderr:\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadFileArguments {
+    path: String,
+}
+
+async fn read_file(arguments: Value, limits: AttachmentConfig) -> Result<String> {
+    let ReadFileArguments { path } = serde_json::from_value(arguments)?;
+    // only local files, a URL could carry conversation content off the machine
+    if path.contains("://") {
+        anyhow::bail!("Only local files can be read");
+    }
+    if path.contains(['*', '?', '[']) {
+        anyhow::bail!("Read one file at a time, globs aren't supported");
+    }
+    // keys and config files elsewhere in the home directory would be sent to the provider
+    let current_dir = std::env::current_dir()?.canonicalize()?;
+    let file = attachments::target_file(&path)
+        .canonicalize()
+        .with_context(|| format!("{path} doesn't exist"))?;
+    if !file.starts_with(&current_dir) {
+        anyhow::bail!("Only files in the current directory can be read");
+    }
+    Term::stderr().write_line(&format!("{TOOL_EMOJI} Model is reading {path}"))?;
+    let loaded = attachments::load_target(&path, &limits).await?;
+    Ok(loaded
+        .attachments
+        .iter()
+        .map(attachments::Attachment::to_message)
+        .collect::<Vec<_>>()
+        .join("\n\n"))
+}
+
+#[derive(Debug, Serialize)]
+struct ToolDefinition<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: FunctionDefinition<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct FunctionDefinition<'a> {
+    name: &'a str,
+    description: &'a str,
+    parameters: &'a Value,
+}
+
+/// tool call as the API sends and expects it
+#[derive(Debug, Serialize, Deserialize)]
+struct ApiToolCall {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    function: ApiFunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ApiFunctionCall {
+    name: String,
+    arguments: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RequestMessage<'a> {
+    role: &'static str,
+    content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ApiToolCall>,
+}
+
+impl<'a> From<&'a ChatMessage> for RequestMessage<'a> {
+    fn from(message: &'a ChatMessage) -> Self {
+        let mut request_message = RequestMessage {
+            role: "user",
+            content: message.content.clone(),
+            name: None,
+            tool_call_id: None,
+            tool_calls: vec![],
+        };
+        match (message.role, &message.tool_call_id) {
+            (Role::System | Role::Developer, _) => request_message.role = "system",
+            (Role::User, _) => request_message.name = message.name.as_deref(),
+            (Role::Assistant, _) => {
+                request_message.role = "assistant";
+                request_message.tool_calls = message
+                    .tool_calls
+                    .iter()
+                    .map(|tool_call| ApiToolCall {
+                        id: tool_call.id.clone(),
+                        kind: String::from("function"),
+                        function: ApiFunctionCall {
+                            name: tool_call.name.clone(),
+                            arguments: tool_call.arguments.clone(),
+                        },
+                    })
+                    .collect();
+            }
+            (Role::Tool, Some(tool_call_id)) => {
+                request_message.role = "tool";
+                request_message.tool_call_id = Some(tool_call_id);
+            }
+            // results without a call to answer can only be passed as text
+            (Role::Tool | Role::Function, _) => {
+                request_message.content = message.tool_output_text()
+            }
+        }
+        request_message
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ToolCompletionRequest<'a> {
+    model: &'a str,
+    messages: Vec<RequestMessage<'a>>,
+    tools: Vec<ToolDefinition<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    logit_bias: &'a HashMap<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseMessage {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ApiToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+    message: ResponseMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolCompletionResponse {
+    choices: Vec<Choice>,
+    usage: Option<Usage>,
+}
+
+/// Answer of the model, either content or tools to run first
+pub struct ToolCompletion {
+    pub content: String,
+    pub tool_calls: Vec<ToolCall>,
+    pub usage: Option<Usage>,
+}
+
+// async-openai predates tool calls so these requests are made by hand
+pub struct ToolsClient {
+    http: reqwest::Client,
+    api_base: String,
+    api_key: String,
+}
+
+impl ToolsClient {
+    pub fn new(config: &AppConfig) -> Result<Self> {
+        Ok(Self {
+            http: openai_http_client(config)?,
+            api_base: config.api_base().to_owned(),
+            api_key: config.open_ai_api_key.clone(),
+        })
+    }
+
+    /// one chat completion with the registered tools offered
+    pub async fn complete(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+        tools: &ToolRegistry,
+        parameters: &CompletionParameters,
+    ) -> Result<ToolCompletion> {
+        let request = ToolCompletionRequest {
+            model,
+            messages: messages.iter().map(RequestMessage::from).collect(),
+            tools: tools.definitions(),
+            temperature: parameters.temperature,
+            top_p: parameters.top_p,
+            max_tokens: parameters.max_tokens,
+            presence_penalty: parameters.presence_penalty,
+            frequency_penalty: parameters.frequency_penalty,
+            logit_bias: &parameters.logit_bias,
+        };
+        let response = self
+            .http
+            .post(format!("{}/chat/completions", self.api_base))
+            .bearer_auth(&self.api_key)
+            .json(&request)
+            .send()
+            .await?;
+        let response: ToolCompletionResponse = parse_response(response).await?;
+        let message = response
+            .choices
+            .into_iter()
+            .next()
+            .context("No first choice on response")?
+            .message;
+        Ok(ToolCompletion {
+            content: message.content.unwrap_or_default(),
+            tool_calls: message
+                .tool_calls
+                .into_iter()
+                .map(|tool_call| ToolCall {
+                    id: tool_call.id,
+                    name: tool_call.function.name,
+                    arguments: tool_call.function.arguments,
+                })
+                .collect(),
+            usage: response.usage,
+        })
+    }
+}
@@ -0,0 +1,127 @@
+use crate::{
+    chat_manager::ChatHistory,
+    messages::{Rating, Role},
+    redaction::redact,
+};
+use anyhow::Result;
+use serde::Serialize;
+use std::{borrow::Cow, str::FromStr};
+
+#[derive(Serialize)]
+struct TrainingMessage<'a> {
+    role: &'a str,
+    content: Cow<'a, str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    /// 0 keeps answers rated bad as context without training on them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weight: Option<u8>,
+}
+
+#[derive(Serialize)]
+struct TrainingExample<'a> {
+    messages: Vec<TrainingMessage<'a>>,
+}
+
+/// Filter for selecting conversations written as `key=value`
+#[derive(Debug, Clone)]
+pub enum ConversationFilter {
+    /// exact model name
+    Model(String),
+    /// persona name
+    Persona(String),
+    /// case insensitive part of the title
+    Title(String),
+    /// at least one answer was rated like this
+    Rating(Rating),
+}
+
+impl FromStr for ConversationFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(filter: &str) -> Result<Self> {
+        let Some((key, value)) = filter.split_once('=') else {
+            anyhow::bail!("Filter {filter:?} should look like key=value");
+        };
+        let value = value.to_owned();
+        match key {
+            "model" => Ok(Self::Model(value)),
+            "persona" => Ok(Self::Persona(value)),
+            "title" => Ok(Self::Title(value.to_lowercase())),
+            "rating" => match value.as_str() {
+                "good" => Ok(Self::Rating(Rating::Good)),
+                "bad" => Ok(Self::Rating(Rating::Bad)),
+                _ => anyhow::bail!("Unknown rating {value:?}, expected good or bad"),
+            },
+            _ => anyhow::bail!(
+                "Unknown filter key {key:?}, expected model, persona, title or rating"
+            ),
+        }
+    }
+}
+
+impl ConversationFilter {
+    pub fn matches(&self, chat_history: &ChatHistory) -> bool {
+        match self {
+            Self::Model(model) => &chat_history.model_metadata().name == model,
+            Self::Persona(persona) => &chat_history.assistant_metadata().name == persona,
+            Self::Title(title) => chat_history
+                .conversation_title()
+                .map(|conversation_title| conversation_title.to_lowercase().contains(title))
+                .unwrap_or_default(),
+            Self::Rating(rating) => chat_history.count_ratings(*rating) > 0,
+        }
+    }
+}
+
+/// Convert a conversation to one line of chat format JSONL
+///
+/// Conversations without an assistant answer are skipped
+pub fn conversation_to_jsonl_line(
+    chat_history: &ChatHistory,
+    redact_sensitive: bool,
+) -> Result<Option<String>> {
+    let messages: Vec<_> = chat_history
+        .messages()
+        .iter()
+        .map(|message| TrainingMessage {
+            role: match message.role {
+                // fine-tuning files predate developer messages
+                Role::Developer => Role::System.as_str(),
+                role => role.as_str(),
+            },
+            content: if redact_sensitive {
+                redact(&message.content)
+            } else {
+                Cow::Borrowed(message.content.as_str())
+            },
+            name: message.name.as_deref(),
+            weight: message
+                .feedback
+                .as_ref()
+                .filter(|feedback| feedback.rating == Rating::Bad)
+                .map(|_| 0),
+        })
+        .collect();
+
+    if !messages.iter().any(|message| message.role == "assistant") {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_json::to_string(&TrainingExample { messages })?))
+}
+
+/// Convert conversations to chat format JSONL for fine-tuning or evaluation
+pub fn conversations_to_jsonl<'a>(
+    conversations: impl IntoIterator<Item = &'a ChatHistory>,
+    redact_sensitive: bool,
+) -> Result<String> {
+    let mut output = String::new();
+    for chat_history in conversations {
+        if let Some(line) = conversation_to_jsonl_line(chat_history, redact_sensitive)? {
+            output.push_str(&line);
+            output.push('\n');
+        }
+    }
+    Ok(output)
+}
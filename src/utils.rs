@@ -1,8 +1,14 @@
-use chrono::{DateTime, Local};
+use anyhow::Context;
+use chrono::{DateTime, Local, TimeZone};
+use clap::ValueEnum;
 use dialoguer::console::Emoji;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt::Write};
 
-use crate::chat_manager::{AssistantMetadata, ModelMetadata};
+use crate::{
+    chat_manager::{AssistantMetadata, ModelMetadata},
+    configuration::PersonaConfig,
+};
 
 pub const CHAT_GPT_KNOWLEDGE_CUTOFF: &str = "September 2021";
 
@@ -17,11 +23,47 @@ pub const GPT_4_8K_MODEL_TOKEN_LIMIT: u32 = 8192;
 pub const GPT_4_32K_MODEL_NAME: &str = "gpt-4-32k";
 pub const GPT_4_32K_MODEL_TOKEN_LIMIT: u32 = 32768;
 
+/// <https://platform.openai.com/docs/guides/vision>
+pub const GPT_4_VISION_MODEL_NAME: &str = "gpt-4-vision-preview";
+pub const GPT_4_VISION_MODEL_TOKEN_LIMIT: u32 = 128000;
+
+/// <https://docs.anthropic.com/en/docs/models-overview>, need `--provider anthropic`
+pub const CLAUDE_3_OPUS_MODEL_NAME: &str = "claude-3-opus-20240229";
+pub const CLAUDE_3_SONNET_MODEL_NAME: &str = "claude-3-sonnet-20240229";
+pub const CLAUDE_3_HAIKU_MODEL_NAME: &str = "claude-3-haiku-20240307";
+pub const CLAUDE_3_MODEL_TOKEN_LIMIT: u32 = 200000;
+
+/// default model for `gpt-cli talk`
+pub const REALTIME_MODEL_NAME: &str = "gpt-4o-realtime-preview";
+
 // Emojis
 pub const ROBOT_EMOJI: Emoji = Emoji("🤖", "");
 pub const QUESTION_MARK_EMOJI: Emoji = Emoji("❓", "");
 pub const SYSTEM_EMOJI: Emoji = Emoji("ℹ️ ", "");
 pub const INCREASING_TREND_EMOJI: Emoji = Emoji("📈", "");
+pub const MAGNIFYING_GLASS_EMOJI: Emoji = Emoji("🔍", "");
+pub const STOPWATCH_EMOJI: Emoji = Emoji("⏱️ ", "");
+pub const TOOL_EMOJI: Emoji = Emoji("🔧", "");
+pub const NOTE_EMOJI: Emoji = Emoji("📝", "");
+pub const IMAGE_EMOJI: Emoji = Emoji("🖼️ ", "");
+pub const TAG_EMOJI: Emoji = Emoji("🏷️ ", "");
+const PROGRESS_FILLED: Emoji = Emoji("▓", "#");
+const PROGRESS_EMPTY: Emoji = Emoji("░", "-");
+const PROGRESS_BAR_WIDTH: usize = 10;
+
+/// compact bar like ▓▓▓▓▓▓░░░░ 62%, falls back to ASCII on terminals without unicode
+pub fn progress_bar(used: i64, limit: i64) -> String {
+    let fraction = (used as f64 / limit.max(1) as f64).clamp(0.0, 1.0);
+    let filled = (fraction * PROGRESS_BAR_WIDTH as f64).round() as usize;
+    format!(
+        "{}{} {:.0}%",
+        PROGRESS_FILLED.to_string().repeat(filled),
+        PROGRESS_EMPTY
+            .to_string()
+            .repeat(PROGRESS_BAR_WIDTH - filled),
+        fraction * 100.0
+    )
+}
 
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Copy, Default, clap::ValueEnum, PartialEq, Eq, Hash)]
@@ -30,6 +72,10 @@ pub enum ChatGptModel {
     GPT_3_5,
     GPT_4_8k,
     GPT_4_32k,
+    GPT_4_VISION,
+    CLAUDE_3_OPUS,
+    CLAUDE_3_SONNET,
+    CLAUDE_3_HAIKU,
 }
 
 impl std::fmt::Display for ChatGptModel {
@@ -44,6 +90,10 @@ impl ChatGptModel {
             ChatGptModel::GPT_3_5 => GPT_3_5_MODEL_NAME,
             ChatGptModel::GPT_4_8k => GPT_4_8K_MODEL_NAME,
             ChatGptModel::GPT_4_32k => GPT_4_32K_MODEL_NAME,
+            ChatGptModel::GPT_4_VISION => GPT_4_VISION_MODEL_NAME,
+            ChatGptModel::CLAUDE_3_OPUS => CLAUDE_3_OPUS_MODEL_NAME,
+            ChatGptModel::CLAUDE_3_SONNET => CLAUDE_3_SONNET_MODEL_NAME,
+            ChatGptModel::CLAUDE_3_HAIKU => CLAUDE_3_HAIKU_MODEL_NAME,
         }
     }
 
@@ -52,15 +102,57 @@ impl ChatGptModel {
             ChatGptModel::GPT_3_5 => GPT_3_5_MODEL_TOKEN_LIMIT,
             ChatGptModel::GPT_4_8k => GPT_4_8K_MODEL_TOKEN_LIMIT,
             ChatGptModel::GPT_4_32k => GPT_4_32K_MODEL_TOKEN_LIMIT,
+            ChatGptModel::GPT_4_VISION => GPT_4_VISION_MODEL_TOKEN_LIMIT,
+            ChatGptModel::CLAUDE_3_OPUS
+            | ChatGptModel::CLAUDE_3_SONNET
+            | ChatGptModel::CLAUDE_3_HAIKU => CLAUDE_3_MODEL_TOKEN_LIMIT,
         }
     }
 
+    /// accepts images in messages
+    pub fn supports_vision(&self) -> bool {
+        matches!(
+            self,
+            ChatGptModel::GPT_4_VISION
+                | ChatGptModel::CLAUDE_3_OPUS
+                | ChatGptModel::CLAUDE_3_SONNET
+                | ChatGptModel::CLAUDE_3_HAIKU
+        )
+    }
+
     pub fn to_model_metadata(self) -> ModelMetadata {
         ModelMetadata {
             name: self.get_model_name().to_owned(),
             token_limit: self.get_model_token_limit(),
+            pricing: None,
+            vision: self.supports_vision(),
         }
     }
+
+    fn from_model_name(name: &str) -> Option<Self> {
+        Self::value_variants()
+            .iter()
+            .copied()
+            .find(|model| model.get_model_name() == name)
+    }
+}
+
+/// token limit assumed for models that are neither built in nor defined in config
+const UNKNOWN_MODEL_TOKEN_LIMIT: u32 = 8192;
+
+/// metadata for a model by its API name, models defined in config take precedence
+pub fn resolve_model(name: &str, configured_models: &[ModelMetadata]) -> ModelMetadata {
+    configured_models
+        .iter()
+        .find(|model| model.name == name)
+        .cloned()
+        .or_else(|| ChatGptModel::from_model_name(name).map(ChatGptModel::to_model_metadata))
+        .unwrap_or_else(|| ModelMetadata {
+            name: name.to_owned(),
+            token_limit: UNKNOWN_MODEL_TOKEN_LIMIT,
+            pricing: None,
+            vision: false,
+        })
 }
 
 pub fn now() -> DateTime<Local> {
@@ -71,6 +163,56 @@ pub fn now_rfc3339() -> String {
     now().to_rfc3339()
 }
 
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// How dates are shown in the terminal and exported transcripts
+///
+/// file names always use RFC3339 so they sort the same everywhere
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct TimestampConfig {
+    /// strftime pattern, e.g. "%d.%m.%Y %H:%M"
+    pub format: String,
+    /// IANA timezone like "Europe/Prague", local time when unset
+    pub timezone: Option<String>,
+}
+
+impl Default for TimestampConfig {
+    fn default() -> Self {
+        Self {
+            format: String::from(DEFAULT_TIMESTAMP_FORMAT),
+            timezone: None,
+        }
+    }
+}
+
+impl TimestampConfig {
+    pub fn display<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> String {
+        // unknown timezones fall back to local time rather than failing
+        match self
+            .timezone
+            .as_deref()
+            .and_then(|timezone| timezone.parse::<chrono_tz::Tz>().ok())
+        {
+            Some(timezone) => format_time(&time.with_timezone(&timezone), &self.format),
+            None => format_time(&time.with_timezone(&Local), &self.format),
+        }
+    }
+}
+
+fn format_time<Tz: TimeZone>(time: &DateTime<Tz>, format: &str) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let mut formatted = String::new();
+    // chrono reports invalid patterns as a formatting error instead of returning a result
+    if write!(formatted, "{}", time.format(format)).is_err() {
+        formatted.clear();
+        _ = write!(formatted, "{}", time.format(DEFAULT_TIMESTAMP_FORMAT));
+    }
+    formatted
+}
+
 pub const DEFAULT_SYSTEM_INSTRUCTIONS_KEY: &str = "default";
 
 pub fn generate_system_instructions() -> HashMap<&'static str, AssistantMetadata> {
@@ -80,21 +222,72 @@ pub fn generate_system_instructions() -> HashMap<&'static str, AssistantMetadata
 
     instructions.insert(
         DEFAULT_SYSTEM_INSTRUCTIONS_KEY,
-        AssistantMetadata::new(format!(
-            "You are ChatGPT, a large language model trained by OpenAI. 
+        AssistantMetadata::new(
+            DEFAULT_SYSTEM_INSTRUCTIONS_KEY,
+            format!(
+                "You are ChatGPT, a large language model trained by OpenAI. 
 Answer as concisely as possible. Knowledge cutoff year {} Current date and time: {}",
-            CHAT_GPT_KNOWLEDGE_CUTOFF, current_time_str
-        )),
+                CHAT_GPT_KNOWLEDGE_CUTOFF, current_time_str
+            ),
+        ),
     );
 
     instructions.insert(
         "joi",
-        AssistantMetadata::new(format!(
-            "You are Joi. The cheerful and helpful AI assistant. Answer as concisely as possible.
+        AssistantMetadata::new(
+            "joi",
+            format!(
+                "You are Joi. The cheerful and helpful AI assistant. Answer as concisely as possible.
 Knowledge cutoff year {} Current date and time: {}",
-            CHAT_GPT_KNOWLEDGE_CUTOFF, current_time_str
-        )),
+                CHAT_GPT_KNOWLEDGE_CUTOFF, current_time_str
+            ),
+        ),
     );
 
     instructions
 }
+
+/// error for subsystems left out of this build
+pub fn feature_disabled(feature: &str, what: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "{what} is not available, gpt-cli was built without the {feature} feature. Rebuild with `--features {feature}`"
+    )
+}
+
+/// persona by name, personas defined in config take precedence over built in ones
+pub fn resolve_persona(
+    name: &str,
+    configured_personas: &HashMap<String, PersonaConfig>,
+) -> anyhow::Result<AssistantMetadata> {
+    if let Some(persona) = configured_personas.get(name) {
+        return Ok(persona.to_assistant_metadata(name));
+    }
+    generate_system_instructions()
+        .remove(name)
+        .with_context(|| {
+            format!(
+                "Unknown persona {name}, available: {}",
+                persona_names(configured_personas).join(", ")
+            )
+        })
+}
+
+/// names of the models in the config
+pub fn model_names(configured_models: &[ModelMetadata]) -> Vec<String> {
+    configured_models
+        .iter()
+        .map(|model| model.name.clone())
+        .collect()
+}
+
+/// names of built in and configured personas, sorted
+pub fn persona_names(configured_personas: &HashMap<String, PersonaConfig>) -> Vec<String> {
+    let mut names: Vec<_> = generate_system_instructions()
+        .into_keys()
+        .map(str::to_owned)
+        .chain(configured_personas.keys().cloned())
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
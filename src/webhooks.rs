@@ -0,0 +1,94 @@
+use crate::{chat_manager::ChatHistory, utils::now_rfc3339};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// Conversation events webhooks can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    MessageSent,
+    ResponseReceived,
+    SessionSaved,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// every event is posted when empty
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+}
+
+/// Posts JSON describing conversation events to the configured URLs
+pub struct Webhooks {
+    client: reqwest::Client,
+    webhooks: Vec<WebhookConfig>,
+    pending: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Webhooks {
+    pub fn new(webhooks: &[WebhookConfig]) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            webhooks: webhooks.to_vec(),
+            pending: Mutex::new(vec![]),
+        }
+    }
+
+    /// post the event in the background so a slow endpoint never holds up the conversation
+    pub fn notify(&self, event: WebhookEvent, chat_history: &ChatHistory, content: Option<&str>) {
+        let urls: Vec<&str> = self
+            .webhooks
+            .iter()
+            .filter(|webhook| webhook.events.is_empty() || webhook.events.contains(&event))
+            .map(|webhook| webhook.url.as_str())
+            .collect();
+        if urls.is_empty() {
+            return;
+        }
+
+        let payload = json!({
+            "event": event,
+            "timestamp": now_rfc3339(),
+            "conversation": {
+                "title": chat_history.conversation_title(),
+                "started": chat_history.conversation_start().map(|start| start.to_rfc3339()),
+                "model": chat_history.model_metadata().name,
+                "persona": chat_history.assistant_metadata().name,
+                "messages": chat_history.messages().len(),
+            },
+            "content": content,
+            "estimated_tokens": chat_history.count_tokens(),
+            "usage": chat_history.token_usage(),
+        });
+
+        let mut pending = self.pending.lock().expect("webhook lock poisoned");
+        pending.retain(|handle| !handle.is_finished());
+        for url in urls {
+            let client = self.client.clone();
+            let payload = payload.clone();
+            let url = url.to_owned();
+            pending.push(tokio::spawn(async move {
+                let result = client
+                    .post(&url)
+                    .json(&payload)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                if let Err(error) = result {
+                    eprintln!("Webhook {url} failed: {error}");
+                }
+            }));
+        }
+    }
+
+    /// wait for events that are still being posted, called before exiting
+    pub async fn finish(&self) {
+        let pending = std::mem::take(&mut *self.pending.lock().expect("webhook lock poisoned"));
+        for handle in pending {
+            _ = handle.await;
+        }
+    }
+}